@@ -12,10 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{array, fs, path::PathBuf};
+use std::{array, fs, io::BufReader, net::TcpListener, path::PathBuf, process};
 
 use clap::Parser;
-use risc0_zkvm::{Executor, ExecutorEnv};
+use risc0_zkvm::{
+    binfmt::hash::page_hasher_by_name, Executor, ExecutorEnv, FloatSupport, GdbOutcome, GdbStub,
+    ReferenceTrace,
+};
+use tracing_subscriber::{filter::LevelFilter, EnvFilter};
 
 /// Runs a RISC-V ELF binary within the RISC Zero ZKVM.
 #[derive(Parser)]
@@ -44,10 +48,95 @@ struct Args {
     /// Add environment vairables in the form of NAME=value.
     #[clap(long, action = clap::ArgAction::Append)]
     env: Vec<String>,
+
+    /// Size of guest memory in bytes.
+    #[clap(long)]
+    mem_size: Option<u64>,
+
+    /// Hash function used to commit to guest memory, e.g. `sha-256-cuda` to
+    /// hash on a GPU, or `blake3` for fast host-only runs that will not be
+    /// proven.
+    #[clap(long)]
+    page_hasher: Option<String>,
+
+    /// Stop the guest once it has used this many cycles.
+    #[clap(long)]
+    session_limit: Option<usize>,
+
+    /// Emulate misaligned loads and stores instead of stopping the guest with
+    /// a fault.
+    #[clap(long)]
+    emulate_misaligned: bool,
+
+    /// Seed the guest's random bytes, so that runs are reproducible.
+    #[clap(long)]
+    random_seed: Option<u64>,
+
+    /// Run a 32-bit RV32 guest.
+    #[clap(long)]
+    rv32: bool,
+
+    /// Stop the guest with a fault if it writes to a page it may execute.
+    #[clap(long)]
+    write_xor_execute: bool,
+
+    /// Report loads from guest memory that was never initialized.
+    #[clap(long)]
+    detect_uninit: bool,
+
+    /// Only allow the floating-point instructions that can be proven.
+    #[clap(long)]
+    provable_float: bool,
+
+    /// Wait for a GDB remote connection on this address, e.g.
+    /// `localhost:1234`, and debug the guest before running it.
+    #[clap(long)]
+    gdb: Option<String>,
+
+    /// Check the guest against a spike commit log, written by `spike -l
+    /// --log-commits`, stopping at the first difference.
+    #[clap(long, conflicts_with = "qemu_trace")]
+    spike_trace: Option<PathBuf>,
+
+    /// Check the guest against QEMU CPU state dumps, written by
+    /// `qemu-riscv64 -one-insn-per-tb -d cpu`, stopping at the first
+    /// difference.
+    #[clap(long)]
+    qemu_trace: Option<PathBuf>,
+
+    /// Profile the guest, writing the cycles spent in each call stack to this
+    /// file in collapsed-stack format, e.g. for `inferno-flamegraph`.
+    #[clap(long)]
+    profile: Option<PathBuf>,
+
+    /// Write the host calls the guest makes to this file as CSV.
+    #[clap(long)]
+    syscalls: Option<PathBuf>,
+
+    /// Write the guest's code coverage to this file in lcov format.
+    #[clap(long)]
+    coverage: Option<PathBuf>,
+
+    /// When profiling, only count every this many cycles.
+    #[clap(long, default_value_t = 1)]
+    profile_sample_period: u64,
+
+    /// Arguments passed to the guest after its name, following `--`.
+    #[clap(last = true)]
+    guest_args: Vec<String>,
 }
 
 fn main() {
-    env_logger::init();
+    // Filter events per module with RUST_LOG, e.g.
+    // `RUST_LOG=risc0_zkvm::exec::monitor=trace`.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::WARN.into())
+                .from_env_lossy(),
+        )
+        .with_writer(std::io::stderr)
+        .init();
 
     let args = Args::parse();
     let elf_contents = fs::read(&args.elf).unwrap();
@@ -70,12 +159,90 @@ fn main() {
         builder.env_var(name, value);
     }
 
+    if let Some(mem_size) = args.mem_size {
+        builder.mem_size(mem_size);
+    }
+
+    // The guest is named by its file name alone, so that its arguments, which
+    // are part of the image, do not depend on where the ELF was read from.
+    let name = args
+        .elf
+        .file_name()
+        .map_or_else(|| "guest".into(), |name| name.to_string_lossy());
+    let mut guest_args = vec![name.to_string()];
+    guest_args.extend(args.guest_args.iter().cloned());
+    builder.args(&guest_args);
+
+    if let Some(name) = args.page_hasher.as_ref() {
+        let hasher =
+            page_hasher_by_name(name).unwrap_or_else(|| panic!("Unsupported page hasher: {name}"));
+        builder.page_hasher(hasher);
+    }
+
+    builder
+        .emulate_misaligned(args.emulate_misaligned)
+        .detect_uninit(args.detect_uninit)
+        .write_xor_execute(args.write_xor_execute)
+        .rv32(args.rv32);
+    if args.provable_float {
+        builder.float_support(FloatSupport::Provable);
+    }
+
+    if let Some(seed) = args.random_seed {
+        builder.random_seed(seed);
+    }
+
+    if let Some(limit) = args.session_limit {
+        builder.session_limit(limit);
+    }
+
+    if let Some(path) = args.profile.as_ref() {
+        builder
+            .profile(path)
+            .profile_sample_period(args.profile_sample_period);
+    }
+
+    if let Some(path) = args.coverage.as_ref() {
+        builder.coverage(path);
+    }
+
     if let Some(input) = args.initial_input.as_ref() {
         builder.stdin(fs::File::open(input).unwrap());
     }
 
-    let env = builder.build();
+    let env = builder.build().unwrap();
     let mut exec = Executor::from_elf(env, &elf_contents, memory_data).unwrap();
+
+    if let Some(addr) = args.gdb.as_ref() {
+        let listener = TcpListener::bind(addr).unwrap();
+        eprintln!("Waiting for GDB on {}", listener.local_addr().unwrap());
+        let (stream, _) = listener.accept().unwrap();
+        match GdbStub::new(&mut exec, stream).serve().unwrap() {
+            GdbOutcome::Detached => {}
+            GdbOutcome::Killed | GdbOutcome::Exited(_) => return,
+        }
+    }
+
+    let reference = match (&args.spike_trace, &args.qemu_trace) {
+        (Some(path), _) => Some(ReferenceTrace::from_spike(BufReader::new(
+            fs::File::open(path).unwrap(),
+        ))),
+        (_, Some(path)) => Some(ReferenceTrace::from_qemu(BufReader::new(
+            fs::File::open(path).unwrap(),
+        ))),
+        _ => None,
+    };
+    if let Some(reference) = reference {
+        match exec.diff_against(&reference.unwrap()).unwrap() {
+            Some(divergence) => {
+                eprintln!("Diverged from the reference: {divergence}");
+                process::exit(1);
+            }
+            None => eprintln!("Matched the reference up to PC 0x{:08x}", exec.pc),
+        }
+        return;
+    }
+
     let session = match exec.run() {
         Ok(session) => session,
         Err(err) => {
@@ -88,6 +255,33 @@ fn main() {
         }
     };
 
+    for load in exec.uninit_loads() {
+        eprintln!(
+            "Uninitialized load of {} bytes from 0x{:x} at PC 0x{:08x}",
+            load.len, load.addr, load.pc
+        );
+    }
+
+    if let Some(path) = args.syscalls.as_ref() {
+        session
+            .write_syscalls(fs::File::create(path).unwrap())
+            .unwrap();
+    }
+
+    if args.verbose > 0 {
+        eprintln!(
+            "Executed {} instructions:\n{}",
+            session.opcode_stats.total(),
+            session.opcode_stats
+        );
+        for (name, stats) in session.region_stats.iter() {
+            eprintln!(
+                "Region {name}: {} cycles over {} runs",
+                stats.cycles, stats.count
+            );
+        }
+    }
+
     // let receipt = session.prove(hal.as_ref(), &eval).unwrap();
 
     // let receipt_data = encode_receipt(&receipt);
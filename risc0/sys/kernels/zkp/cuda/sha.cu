@@ -34,3 +34,14 @@ void sha_fold(ShaDigest* out,
     out[idx] = shaHashPair(in[2 * idx], in[2 * idx + 1]);
   }
 }
+
+extern "C" __global__
+void sha_pages(ShaDigest* out,
+               const uint32_t* pages,
+               uint32_t count,
+               uint32_t pageWords) {
+  uint32_t idx = blockDim.x * blockIdx.x + threadIdx.x;
+  if (idx < count) {
+    out[idx] = shaHash(pages + idx * pageWords, pageWords, 1, false);
+  }
+}
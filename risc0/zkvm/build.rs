@@ -18,4 +18,10 @@ fn main() {
         std::env::set_var("PROTOC", protobuf_src::protoc());
         prost_build::compile_protos(&["src/exec/profile.proto"], &["src/exec/"]).unwrap();
     }
+
+    #[cfg(feature = "cuda")]
+    {
+        let path = std::env::var("DEP_RISC0_SYS_CUDA_KERNELS_ZKP").unwrap();
+        println!("cargo:rustc-env=ZKVM_CUDA_KERNELS={path}");
+    }
 }
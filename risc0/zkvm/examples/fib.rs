@@ -54,7 +54,10 @@ where
     <<H as Hal>::HashSuite as HashSuite<BabyBear>>::HashFn: ControlId,
     E: EvalCheck<H>,
 {
-    let env = ExecutorEnv::builder().add_input(&[iterations]).build();
+    let env = ExecutorEnv::builder()
+        .add_input(&[iterations])
+        .build()
+        .unwrap();
     let mut exec = Executor::from_elf(env, FIB_ELF).unwrap();
     let session = exec.run().unwrap();
     let receipt = session.prove(hal, eval).unwrap();
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use super::WORD_SIZE;
-use crate::DOUBLE_WORD_SIZE;
+use crate::{DOUBLE_WORD_SIZE, PAGE_SIZE};
 
 pub const MEM_BITS: usize = 28;
 pub const MEM_SIZE: usize = 1 << MEM_BITS;
@@ -67,3 +67,25 @@ pub const TEXT: Region = Region::new(0x0700_0000, mb(80));
 pub const SYSTEM: Region = Region::new(0x0C00_0000, mb(16));
 pub const PAGE_TABLE: Region = Region::new(0x0D00_0000, mb(16));
 pub const PRE_LOAD: Region = Region::new(0x0D70_0000, mb(9));
+
+// A page between the regions above and the stack, which grows down towards it
+// from STACK_INITIAL_ADDRESS. The host faults on any access to it, so that a
+// stack overflow is caught rather than overwriting the preloaded data.
+pub const STACK_GUARD: Region = Region::new(PRE_LOAD.end(), PAGE_SIZE);
+
+// The floating-point registers f0 to f31, as little-endian double words,
+// followed by fcsr in the low word of one more. Like the integer registers
+// before it, this lives in the system region so that the image commits to it.
+pub const FLOAT_REGISTERS: Region = Region::new(SYSTEM.start() + kb(2), 33 * DOUBLE_WORD_SIZE);
+
+// The heap chosen by the host, as two little-endian double words: the start
+// address followed by the size in bytes. This lives in the system region,
+// after the saved registers. sys_alloc_words allocates from it once HEAP is
+// used up.
+pub const HEAP_INFO: Region = Region::new(SYSTEM.start() + kb(4), 2 * DOUBLE_WORD_SIZE);
+
+// The NUL-terminated program arguments, which argv points into. argc and argv
+// themselves are at STACK_INITIAL_ADDRESS, as the Linux ABI lays them out at
+// the initial stack pointer, followed by an empty environment and auxiliary
+// vector.
+pub const ARGS: Region = Region::new(SYSTEM.start() + kb(8), kb(8));
@@ -14,18 +14,75 @@
 
 #[cfg(target_os = "zkvm")]
 use core::arch::asm;
-use core::{cmp::min, ptr::null_mut};
+use core::ptr::null_mut;
 
 use crate::WORD_SIZE;
 
 pub mod ecall {
     pub const HALT: u64 = 0;
     pub const OUTPUT: u64 = 1;
+    /// Call a host callback by name: a0/a1 hold the name pointer and
+    /// length, a2/a3 the pointer and length of the data sent to the host,
+    /// and a4/a5 those of the buffer for its reply. Writes as much of the
+    /// reply as fits, and returns its full length in a0, as [GETENV] does.
     pub const SOFTWARE: u64 = 2;
+    /// Report a panic: a0/a1 hold the message pointer and length, a2/a3 the
+    /// source file pointer and length (or zero), and a4 the line number.
+    pub const PANIC: u64 = 3;
+    /// Read an environment variable: a0/a1 hold the name pointer and length,
+    /// a2/a3 the buffer pointer and length. Returns the length of the value
+    /// in a0, or u64::MAX if it is not set.
+    pub const GETENV: u64 = 4;
+    /// Read the cycle count of the session so far, including paging, into
+    /// a0, as the `cycle` CSR does.
+    pub const CYCLE_COUNT: u64 = 5;
+    /// Run a BLS12-381 operation: a0 holds one of [super::bls12_381], and
+    /// a1-a3 its operands. Returns 0 in a0, or u64::MAX if the operation is
+    /// unknown or an operand is not a valid point or scalar.
+    pub const BLS12_381: u64 = 6;
+    /// Multiply or add big integers modulo a third: a0 holds one of
+    /// [super::bigint], a1 the result pointer, a2/a3 the operand pointers,
+    /// a4 the modulus pointer and a5 the width in bits, 256 or 384. Returns
+    /// 0 in a0, or u64::MAX if the operation or width is unknown.
+    pub const BIGINT: u64 = 7;
+    /// Compress SHA-256 blocks: a0 holds the output state pointer, a1 the
+    /// input state pointer, a2/a3 pointers to the two halves of the first
+    /// block and a4 the number of blocks, each 64 bytes after the last.
+    /// States are digests, in big-endian words.
+    pub const SHA: u64 = 8;
+    /// Hash elements of the Pallas base field with Poseidon: a0 holds the
+    /// output pointer, a1 the pointer to the elements and a2 their number,
+    /// from 2 to [super::MAX_POSEIDON_ELEMENTS]. Elements are 32
+    /// little-endian bytes. Returns 0 in a0, or u64::MAX if an element is
+    /// not canonical.
+    pub const POSEIDON: u64 = 9;
+    /// Log a message: a0 holds one of [super::log_level], and a1/a2 the
+    /// message pointer and length.
+    pub const LOG: u64 = 10;
+    /// Enter a profiling region: a0/a1 hold the name pointer and length, and
+    /// a2 an id, by which [REGION_END] leaves it.
+    pub const REGION_START: u64 = 11;
+    /// Leave the profiling region whose id is in a0.
+    pub const REGION_END: u64 = 12;
+    /// Assume that another guest halted with exit code 0: a0 holds a pointer
+    /// to its image ID, and a1 a pointer to the digest of its journal, chained
+    /// one byte at a time as its segments bind it. The host ends execution
+    /// with an error unless it was given that claim as an assumption.
+    pub const VERIFY: u64 = 13;
+    /// Run a BN254 operation: a0 holds one of [super::bn254], and a1-a3 its
+    /// operands, as for [BLS12_381].
+    pub const BN254: u64 = 14;
 
     pub const FCNTL: u64 = 25;
     pub const OPEN: u64 = 56;
     pub const CLOSE: u64 = 57;
+    /// Move the offset of a host-provided file: a0 holds the file
+    /// descriptor, a1 the offset and a2 whence. Returns the new offset in a0.
+    pub const LSEEK: u64 = 62;
+    /// Read from a host-provided file: a0 holds the file descriptor and a1/a2
+    /// the buffer pointer and length. Returns the number of bytes read in a0,
+    /// which is 0 at the end of the file.
+    pub const READ: u64 = 63;
     pub const WRITE: u64 = 64;
     pub const EXIT: u64 = 94;
 
@@ -35,6 +92,10 @@ pub mod ecall {
     pub const SIGPROCMASK: u64 = 135;
 
     pub const GETRLIMIT: u64 = 163;
+    /// Fill a buffer with random bytes: a0/a1 hold the buffer pointer and
+    /// length, and a2 flags, which are ignored. Returns the number of bytes
+    /// written in a0, which may be fewer than requested.
+    pub const GETRANDOM: u64 = 278;
     pub const GETTID: u64 = 178;
     pub const GETAFFINITY: u64 = 123;
     pub const MMAP: u64 = 222;
@@ -50,6 +111,51 @@ pub mod halt {
     pub const SPLIT: u64 = 2;
 }
 
+/// The operations of [ecall::BLS12_381]. Points are uncompressed, 96 bytes in
+/// G1 and 192 in G2, and scalars are 32 little-endian bytes.
+pub mod bls12_381 {
+    /// Add the G1 points at a2 and a3, writing the sum to a1.
+    pub const G1_ADD: u64 = 0;
+    /// Multiply the G1 point at a2 by the scalar at a3, writing the product
+    /// to a1.
+    pub const G1_MUL: u64 = 1;
+    /// Add the G2 points at a2 and a3, writing the sum to a1.
+    pub const G2_ADD: u64 = 2;
+    /// Multiply the G2 point at a2 by the scalar at a3, writing the product
+    /// to a1.
+    pub const G2_MUL: u64 = 3;
+    /// Check the a2 pairs of a G1 and a G2 point at a1, returning 1 in a0 if
+    /// the product of their pairings is the identity and 0 if not.
+    pub const PAIRING_CHECK: u64 = 4;
+}
+
+/// The operations of [ecall::BN254], numbered and laid out as those of
+/// [bls12_381]. Points and scalars are encoded as by the Ethereum precompiles
+/// (EIP-196 and EIP-197): coordinates are 32 big-endian bytes, 64 in G1 and
+/// 128 in G2 with the imaginary part of each first, the point at infinity is
+/// all zeros, and scalars are 32 big-endian bytes, reduced by the call.
+pub mod bn254 {
+    pub use super::bls12_381::{G1_ADD, G1_MUL, G2_ADD, G2_MUL, PAIRING_CHECK};
+}
+
+/// The operations of [ecall::BIGINT]. Integers are little-endian, and a zero
+/// modulus stands for 2 to the width.
+pub mod bigint {
+    /// Multiply the operands modulo the modulus.
+    pub const OP_MUL: u64 = 0;
+    /// Add the operands modulo the modulus.
+    pub const OP_ADD: u64 = 1;
+}
+
+/// The levels of [ecall::LOG], numbered as those of the `log` crate.
+pub mod log_level {
+    pub const ERROR: u64 = 1;
+    pub const WARN: u64 = 2;
+    pub const INFO: u64 = 3;
+    pub const DEBUG: u64 = 4;
+    pub const TRACE: u64 = 5;
+}
+
 pub mod reg_abi {
     pub const REG_ZERO: usize = 0; // zero constant
     pub const REG_RA: usize = 1; // return address
@@ -91,6 +197,13 @@ pub mod reg_abi {
 pub const DIGEST_WORDS: usize = 8;
 pub const DIGEST_BYTES: usize = WORD_SIZE * DIGEST_WORDS;
 
+/// The most SHA-256 blocks a single `sha` host call may compress, so that
+/// it fits in a segment.
+pub const MAX_SHA_COMPRESS_BLOCKS: usize = 1000;
+
+/// The most field elements a single `poseidon` host call may hash.
+pub const MAX_POSEIDON_ELEMENTS: usize = 1024;
+
 // TODO: We can probably use ffi::CStr::from_bytes_with_nul once it's
 // const-stablized instead of rolling our own structure:
 // https://github.com/rust-lang/rust/issues/101719
@@ -279,7 +392,7 @@ pub unsafe extern "C" fn sys_sha_compress(
     {
         asm!(
             "ecall",
-            in("t0") ecall::SHA,
+            in("a7") ecall::SHA,
             in("a0") out_state,
             in("a1") in_state,
             in("a2") block1_ptr,
@@ -291,49 +404,310 @@ pub unsafe extern "C" fn sys_sha_compress(
     unimplemented!()
 }
 
+/// Compresses the `count` blocks at `buf` into `in_state`, storing the
+/// result to `out_state`, in as many host calls as it takes.
 #[inline(always)]
 pub unsafe fn sys_sha_buffer(
     out_state: *mut [u32; DIGEST_WORDS],
     in_state: *const [u32; DIGEST_WORDS],
     buf: *const u8,
-    count: u32,
+    count: usize,
 ) {
     #[cfg(target_os = "zkvm")]
     {
+        let (mut in_state, mut buf, mut count) = (in_state, buf, count);
+        while count > 0 {
+            let chunk = count.min(MAX_SHA_COMPRESS_BLOCKS);
+            asm!(
+                "ecall",
+                in("a7") ecall::SHA,
+                in("a0") out_state,
+                in("a1") in_state,
+                in("a2") buf,
+                in("a3") buf.add(DIGEST_BYTES),
+                in("a4") chunk,
+            );
+            in_state = out_state as *const _;
+            buf = buf.add(chunk * 2 * DIGEST_BYTES);
+            count -= chunk;
+        }
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sys_rand(recv_buf: *mut u32, words: usize) {
+    sys_getrandom(recv_buf.cast(), words * WORD_SIZE);
+}
+
+/// Fills the `len` bytes at `buf` with random bytes from the host.
+///
+/// The bytes are recorded along with the other host calls, so that the
+/// execution can be replayed and proven.
+#[no_mangle]
+pub unsafe extern "C" fn sys_getrandom(buf: *mut u8, len: usize) {
+    #[cfg(target_os = "zkvm")]
+    {
+        let (mut buf, mut len) = (buf, len);
+        while len > 0 {
+            let written: usize;
+            asm!(
+                "ecall",
+                in("a7") ecall::GETRANDOM,
+                inout("a0") buf => written,
+                in("a1") len,
+                in("a2") 0,
+            );
+            buf = buf.add(written);
+            len -= written;
+        }
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Runs the BLS12-381 operation `op` on the host, with the operands `a1` to
+/// `a3` described in [bls12_381], and returns its result.
+#[no_mangle]
+pub unsafe extern "C" fn sys_bls12_381(op: u64, a1: usize, a2: usize, a3: usize) -> u64 {
+    #[cfg(target_os = "zkvm")]
+    {
+        let result: u64;
         asm!(
             "ecall",
-            in("t0") ecall::SHA,
-            in("a0") out_state,
-            in("a1") in_state,
-            in("a2") buf,
-            in("a3") buf.add(DIGEST_BYTES),
-            in("a4") count,
+            in("a7") ecall::BLS12_381,
+            inout("a0") op => result,
+            in("a1") a1,
+            in("a2") a2,
+            in("a3") a3,
         );
+        result
     }
     #[cfg(not(target_os = "zkvm"))]
     unimplemented!()
 }
 
+/// Runs the BN254 operation `op` on the host, with the operands `a1` to `a3`
+/// described in [bn254], and returns its result.
 #[no_mangle]
-pub unsafe extern "C" fn sys_rand(recv_buf: *mut u32, words: usize) {
-    syscall_0(nr::SYS_RANDOM, recv_buf, words);
+pub unsafe extern "C" fn sys_bn254(op: u64, a1: usize, a2: usize, a3: usize) -> u64 {
+    #[cfg(target_os = "zkvm")]
+    {
+        let result: u64;
+        asm!(
+            "ecall",
+            in("a7") ecall::BN254,
+            inout("a0") op => result,
+            in("a1") a1,
+            in("a2") a2,
+            in("a3") a3,
+        );
+        result
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Calls the host callback `name` with the `to_host_len` bytes at `to_host`,
+/// writing as much of its reply as fits to `from_host`, and returns the full
+/// length of the reply, as described in [ecall::SOFTWARE].
+#[no_mangle]
+pub unsafe extern "C" fn sys_io(
+    name: *const u8,
+    name_len: usize,
+    to_host: *const u8,
+    to_host_len: usize,
+    from_host: *mut u8,
+    from_host_len: usize,
+) -> u64 {
+    #[cfg(target_os = "zkvm")]
+    {
+        let result: u64;
+        asm!(
+            "ecall",
+            in("a7") ecall::SOFTWARE,
+            inout("a0") name => result,
+            in("a1") name_len,
+            in("a2") to_host,
+            in("a3") to_host_len,
+            in("a4") from_host,
+            in("a5") from_host_len,
+        );
+        result
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Enters the profiling region `id`, named by the `len` bytes at `name`.
+#[no_mangle]
+pub unsafe extern "C" fn sys_region_start(name: *const u8, len: usize, id: u64) {
+    #[cfg(target_os = "zkvm")]
+    {
+        asm!(
+            "ecall",
+            in("a7") ecall::REGION_START,
+            in("a0") name,
+            in("a1") len,
+            in("a2") id,
+        );
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Leaves the profiling region `id`.
+#[no_mangle]
+pub unsafe extern "C" fn sys_region_end(id: u64) {
+    #[cfg(target_os = "zkvm")]
+    {
+        asm!(
+            "ecall",
+            in("a7") ecall::REGION_END,
+            in("a0") id,
+        );
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Assumes the claim that the guest with the image ID at `image_id` halted
+/// with the journal whose digest is at `journal_digest`, as described in
+/// [ecall::VERIFY].
+#[no_mangle]
+pub unsafe extern "C" fn sys_verify(
+    image_id: *const [u32; DIGEST_WORDS],
+    journal_digest: *const [u8; DIGEST_BYTES],
+) {
+    #[cfg(target_os = "zkvm")]
+    {
+        asm!(
+            "ecall",
+            in("a7") ecall::VERIFY,
+            in("a0") image_id,
+            in("a1") journal_digest,
+        );
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Hashes the `count` field elements at `elements` with Poseidon on the host,
+/// writing the hash to `out`, as described in [ecall::POSEIDON], and returns
+/// 0, or u64::MAX if an element is not canonical.
+#[no_mangle]
+pub unsafe extern "C" fn sys_poseidon(out: *mut u8, elements: *const u8, count: usize) -> u64 {
+    #[cfg(target_os = "zkvm")]
+    {
+        let result: u64;
+        asm!(
+            "ecall",
+            in("a7") ecall::POSEIDON,
+            inout("a0") out => result,
+            in("a1") elements,
+            in("a2") count,
+        );
+        result
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
+
+/// Computes `x` `op` `y` modulo `modulus` on the host, writing it to
+/// `result`. All of them are little-endian integers of `width` bits, 256 or
+/// 384, as described in [bigint]. Returns 0, or u64::MAX if `op` or `width`
+/// is unknown.
+#[no_mangle]
+pub unsafe extern "C" fn sys_bigint(
+    op: u64,
+    result: *mut u8,
+    x: *const u8,
+    y: *const u8,
+    modulus: *const u8,
+    width: usize,
+) -> u64 {
+    #[cfg(target_os = "zkvm")]
+    {
+        let ret: u64;
+        asm!(
+            "ecall",
+            in("a7") ecall::BIGINT,
+            inout("a0") op => ret,
+            in("a1") result,
+            in("a2") x,
+            in("a3") y,
+            in("a4") modulus,
+            in("a5") width,
+        );
+        ret
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sys_panic(msg_ptr: *const u8, len: usize) -> ! {
-    syscall_2(nr::SYS_PANIC, null_mut(), 0, msg_ptr as u32, len as u32);
+    sys_panic_at(msg_ptr, len, null_mut(), 0, 0)
+}
+
+/// Reports a panic with the `len` bytes at `msg_ptr` as its message, raised at
+/// `line` of the `file_len` bytes long file name at `file_ptr`.
+#[no_mangle]
+pub unsafe extern "C" fn sys_panic_at(
+    msg_ptr: *const u8,
+    len: usize,
+    file_ptr: *const u8,
+    file_len: usize,
+    line: u32,
+) -> ! {
+    #[cfg(target_os = "zkvm")]
+    {
+        asm!(
+            "ecall",
+            in("a7") ecall::PANIC,
+            in("a0") msg_ptr,
+            in("a1") len,
+            in("a2") file_ptr,
+            in("a3") file_len,
+            in("a4") line,
+        );
+    }
     unreachable!()
 }
 
+/// Logs the `len` bytes at `msg_ptr` on the host at `level`, one of
+/// [log_level].
 #[no_mangle]
-pub unsafe extern "C" fn sys_log(msg_ptr: *const u8, len: usize) {
-    syscall_2(nr::SYS_LOG, null_mut(), 0, msg_ptr as u32, len as u32);
+pub unsafe extern "C" fn sys_log(level: u64, msg_ptr: *const u8, len: usize) {
+    #[cfg(target_os = "zkvm")]
+    {
+        asm!(
+            "ecall",
+            in("a7") ecall::LOG,
+            in("a0") level,
+            in("a1") msg_ptr,
+            in("a2") len,
+        );
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sys_cycle_count() -> usize {
-    let Return(a0, _) = syscall_0(nr::SYS_CYCLE_COUNT, null_mut(), 0);
-    a0 as usize
+    #[cfg(target_os = "zkvm")]
+    {
+        let cycle: usize;
+        asm!(
+            "ecall",
+            in("a7") ecall::CYCLE_COUNT,
+            lateout("a0") cycle,
+        );
+        cycle
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
 }
 
 /// Reads the given number of bytes into the given buffer, posix-style.  Returns
@@ -346,78 +720,23 @@ pub unsafe extern "C" fn sys_cycle_count() -> usize {
 /// Users should prefer a higher-level abstraction.
 #[no_mangle]
 pub unsafe extern "C" fn sys_read(fd: u32, recv_buf: *mut u8, nrequested: usize) -> usize {
-    // The SYS_READ system call can do a given number of word-aligned reads
-    // efficiently. The semantics of the system call are:
-    //
-    //   (nread, word) = syscall_2(nr::SYS_READ, outbuf,
-    //                             num_words_in_outbuf, fd, nbytes);
-    //
-    // This reads exactly nbytes from the file descriptor, and fills the words
-    // in outbuf, followed by up to 4 bytes returned in "word", and fills
-    // the rest with NULs.  It returns the number of bytes read.
-    //
-    // sys_read exposes this as a byte-aligned read by:
-    //   * Uses sys_read_avail to determine how many bytes actually need to be read
-    //     to avoid having to null-pad the whole buffer if we're not reading very
-    //     much
-    //   * Copies any unaligned bytes at the start or end of the region.
-
-    // Fills 0-3 bytes from a u32 into memory, returning the pointer afterwards.
-    unsafe fn fill_from_word(mut ptr: *mut u8, mut word: u32, nfill: usize) -> *mut u8 {
-        debug_assert!(nfill < 4, "nfill={nfill}");
-        for _ in 0..nfill {
-            *ptr = (word & 0xFF) as u8;
-            word = word >> 8;
-            ptr = ptr.add(1);
-        }
-        ptr
-    }
-
-    // Find out how many bytes to actually read, given how many we requested.
-    let Return(navail, _) = syscall_1(nr::SYS_READ_AVAIL, null_mut(), 0, fd);
-    let nread = min(nrequested, navail as usize);
-
-    // Determine how many bytes at the beginning of the buffer we have
-    // to read in order to become word-aligned.
-    let ptr_offset = (recv_buf as usize) & (WORD_SIZE - 1);
-    let unaligned_at_start = if ptr_offset == 0 {
-        0
-    } else {
-        min(nread, WORD_SIZE - ptr_offset)
-    };
-
-    // Read unaligned bytes into "firstword".
-    let Return(nread_first, firstword) =
-        syscall_2(nr::SYS_READ, null_mut(), 0, fd, unaligned_at_start as u32);
-    debug_assert_eq!(nread_first as usize, unaligned_at_start);
-
-    // Align up to a word boundry to do the main copy.
-    let main_ptr = fill_from_word(recv_buf, firstword, unaligned_at_start);
-    if nread == unaligned_at_start {
-        // We only read part of a word, and don't have to read any full words.
-        return nread;
+    // The host writes the bytes to guest memory itself, so unlike on RV32
+    // there is no need to align the buffer to words.
+    #[cfg(target_os = "zkvm")]
+    {
+        let nread: isize;
+        asm!(
+            "ecall",
+            in("a7") ecall::READ,
+            inout("a0") fd as usize => nread,
+            in("a1") recv_buf,
+            in("a2") nrequested,
+        );
+        assert!(nread >= 0, "sys_read: fd {fd} failed with error {}", -nread);
+        nread as usize
     }
-    // Copy in all of the word-aligned data
-    let main_requested = nread - unaligned_at_start;
-    let main_words = main_requested / WORD_SIZE;
-    let Return(nread_main, lastword) = syscall_2(
-        nr::SYS_READ,
-        main_ptr as *mut u32,
-        main_words,
-        fd,
-        main_requested as u32,
-    );
-    debug_assert_eq!(nread_main as usize, main_requested);
-
-    // Copy in individual bytes after the word-aligned section.
-    let unaligned_at_end = (main_requested as usize) % WORD_SIZE;
-    fill_from_word(
-        main_ptr.add(main_words * WORD_SIZE),
-        lastword,
-        unaligned_at_end,
-    );
-
-    nread
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
 }
 
 /// Reads up to the given number of words into the buffer [recv_buf,
@@ -431,38 +750,52 @@ pub unsafe extern "C" fn sys_read(fd: u32, recv_buf: *mut u8, nrequested: usize)
 /// * If not all data is available, sys_read_words will block on the
 /// input stream instead of returning a short read.
 ///
-/// * recv_buf must be word-aligned.
-///
 /// * All of the buffer is overwritten, even in the case of EOF
 /// mid-way through.
 ///
 /// # Safety
 ///
-/// `recv_buf' must be a word-aligned pointer and point to a region of
-/// `nwords' size.
+/// `recv_buf' must point to a region of `nwords' size.
 pub unsafe extern "C" fn sys_read_words(fd: u32, recv_buf: *mut u32, nwords: usize) -> usize {
+    let recv_buf = recv_buf.cast::<u8>();
     let nbytes_requested = nwords * WORD_SIZE;
-    let Return(nread, _) = syscall_2(
-        nr::SYS_READ,
-        recv_buf,
-        nwords,
-        fd,
-        (nwords * WORD_SIZE) as u32,
-    );
-    assert!(nread as usize <= nbytes_requested);
-    nread as usize
+    let mut nread = 0;
+    while nread < nbytes_requested {
+        let nread_now = sys_read(fd, recv_buf.add(nread), nbytes_requested - nread);
+        if nread_now == 0 {
+            break;
+        }
+        nread += nread_now;
+    }
+    recv_buf.add(nread).write_bytes(0, nbytes_requested - nread);
+    nread
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sys_write(fd: u32, write_buf: *const u8, nbytes: usize) {
-    syscall_3(
-        nr::SYS_WRITE,
-        null_mut(),
-        0,
-        fd,
-        write_buf as u32,
-        nbytes as u32,
-    );
+    #[cfg(target_os = "zkvm")]
+    {
+        // The host writes at most a bounded number of bytes per call.
+        let mut written = 0;
+        while written < nbytes {
+            let nwritten: isize;
+            asm!(
+                "ecall",
+                in("a7") ecall::WRITE,
+                inout("a0") fd as usize => nwritten,
+                in("a1") write_buf.add(written),
+                in("a2") nbytes - written,
+            );
+            assert!(
+                nwritten > 0,
+                "sys_write: fd {fd} failed with error {}",
+                -nwritten
+            );
+            written += nwritten as usize;
+        }
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
 }
 
 /// Retrieves the value of an environment variable, and stores as much
@@ -479,30 +812,43 @@ pub unsafe extern "C" fn sys_getenv(
     varname: *const u8,
     varname_len: usize,
 ) -> usize {
-    let Return(a0, _) = syscall_2(
-        nr::SYS_GETENV,
-        out_words,
-        out_nwords,
-        varname as u32,
-        varname_len as u32,
-    );
-    if a0 == u32::MAX {
-        usize::MAX
-    } else {
-        a0 as usize
+    #[cfg(target_os = "zkvm")]
+    {
+        let len: usize;
+        asm!(
+            "ecall",
+            in("a7") ecall::GETENV,
+            inout("a0") varname => len,
+            in("a1") varname_len,
+            in("a2") out_words,
+            in("a3") out_nwords * WORD_SIZE,
+        );
+        len
     }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
 }
 
 // Number of words remaining in the heap that haven't yet been allocated.
 static mut HEAP_WORDS_REMAINING: usize = crate::memory::HEAP.len_words();
 
+// The next free address and the end of the mapped part of the heap the host
+// published at HEAP_INFO, which is used once the platform heap runs out. Both
+// are 0 until then.
+static mut HOST_HEAP_NEXT: usize = 0;
+static mut HOST_HEAP_MAPPED: usize = 0;
+
+// The least number of bytes of the host heap mapped at once.
+const HOST_HEAP_CHUNK: usize = 1 << 20;
+
 #[no_mangle]
 pub unsafe extern "C" fn sys_alloc_words(nwords: usize) -> *mut u32 {
     // SAFETY: Single threaded, so nothing else can touch this while we're working.
     let heap_words_remaining: &mut usize = unsafe { &mut HEAP_WORDS_REMAINING };
-    let new_words_remaining = heap_words_remaining
-        .checked_sub(nwords)
-        .expect("Out of memory!");
+    let new_words_remaining = match heap_words_remaining.checked_sub(nwords) {
+        Some(new_words_remaining) => new_words_remaining,
+        None => return unsafe { alloc_host_heap_words(nwords) },
+    };
     // SAFETY: We've already checked to make sure we haven't
     // overflowed the heap, so the pointer arithmetic here should not
     // cause any undefined behavior.
@@ -510,3 +856,57 @@ pub unsafe extern "C" fn sys_alloc_words(nwords: usize) -> *mut u32 {
     *heap_words_remaining = new_words_remaining;
     ptr
 }
+
+/// Allocate from the heap the host published at HEAP_INFO, which lies above
+/// main memory, mapping it with mmap a chunk at a time as it is used.
+unsafe fn alloc_host_heap_words(nwords: usize) -> *mut u32 {
+    // SAFETY: Single threaded, and HEAP_INFO is written by the host before the
+    // guest starts.
+    let (next, mapped) = unsafe {
+        (
+            &mut *core::ptr::addr_of_mut!(HOST_HEAP_NEXT),
+            &mut *core::ptr::addr_of_mut!(HOST_HEAP_MAPPED),
+        )
+    };
+    let info = crate::memory::HEAP_INFO.start() as *const u64;
+    let (start, size) = unsafe { (*info as usize, *info.add(1) as usize) };
+    if *next == 0 {
+        *next = start;
+        *mapped = start;
+    }
+    let end = nwords
+        .checked_mul(WORD_SIZE)
+        .and_then(|len| next.checked_add(len))
+        .filter(|end| *end <= start + size)
+        .expect("Out of memory!");
+    if end > *mapped {
+        let len = core::cmp::min(
+            core::cmp::max(end - *mapped, HOST_HEAP_CHUNK),
+            start + size - *mapped,
+        );
+        if unsafe { sys_mmap(*mapped, len) } != *mapped {
+            panic!("Out of memory!");
+        }
+        *mapped += len;
+    }
+    let ptr = *next as *mut u32;
+    *next = end;
+    ptr
+}
+
+/// Map `len` bytes of fresh memory at `addr`, returning the address mapped.
+unsafe fn sys_mmap(addr: usize, len: usize) -> usize {
+    #[cfg(target_os = "zkvm")]
+    {
+        let base: usize;
+        asm!(
+            "ecall",
+            in("a7") ecall::MMAP,
+            inout("a0") addr => base,
+            in("a1") len,
+        );
+        base
+    }
+    #[cfg(not(target_os = "zkvm"))]
+    unimplemented!()
+}
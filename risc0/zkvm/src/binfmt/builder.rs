@@ -0,0 +1,442 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module defines the [MemoryImageBuilder].
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::{bail, ensure, Result};
+use risc0_zkvm_platform::{
+    memory::{ARGS, HEAP_INFO, MEM_SIZE, STACK_INITIAL_ADDRESS},
+    DOUBLE_WORD_SIZE, PAGE_SIZE,
+};
+use rrs_lib::{MemAccessSize, Memory};
+
+use super::{
+    elf::{Program, ProgramSegment},
+    hash::{PageHasher, Sha256PageHasher},
+    image::{MemoryImage, PageTableInfo},
+    paged::{MemoryBackend, PagedMemory},
+    region::HeapRegion,
+    store::PageStore,
+};
+
+/// Contents placed into guest memory, in the order they were added.
+#[derive(Clone)]
+enum Chunk {
+    Words(BTreeMap<u64, u32>),
+    Bytes(u64, Vec<u8>),
+}
+
+/// A builder pattern used to construct a [MemoryImage].
+///
+/// Unlike [MemoryImage::new], this does not require a [Program]: arbitrary
+/// byte ranges can be placed into guest memory, e.g. to resume from the state
+/// left behind by a previous session. Later writes take precedence over
+/// earlier ones where they overlap. The page table is computed once, by
+/// [MemoryImageBuilder::build].
+#[derive(Clone)]
+pub struct MemoryImageBuilder {
+    mem_size: u64,
+    hasher: Arc<dyn PageHasher>,
+    backend: MemoryBackend,
+    chunks: Vec<Chunk>,
+    segments: Vec<ProgramSegment>,
+    memory_data: Option<Vec<u8>>,
+    heap: Option<HeapRegion>,
+    args: Vec<String>,
+    page_store: Option<Arc<PageStore>>,
+    #[cfg(feature = "parallel")]
+    hash_threads: Option<usize>,
+}
+
+impl Default for MemoryImageBuilder {
+    fn default() -> Self {
+        Self {
+            mem_size: MEM_SIZE as u64,
+            hasher: Arc::new(Sha256PageHasher),
+            backend: MemoryBackend::Heap,
+            chunks: Vec::new(),
+            segments: Vec::new(),
+            memory_data: None,
+            heap: None,
+            args: Vec::new(),
+            page_store: None,
+            #[cfg(feature = "parallel")]
+            hash_threads: None,
+        }
+    }
+}
+
+impl MemoryImageBuilder {
+    /// Construct a builder for an empty image with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the size of guest memory in bytes.
+    ///
+    /// The platform places the stack, the saved registers and the program
+    /// arguments at fixed addresses within the first [MEM_SIZE] bytes, so
+    /// guest memory may not be any smaller.
+    pub fn mem_size(&mut self, mem_size: u64) -> &mut Self {
+        self.mem_size = mem_size;
+        self
+    }
+
+    /// Set the hash function used to build the page table.
+    pub fn hasher(&mut self, hasher: Arc<dyn PageHasher>) -> &mut Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Set where the main guest memory is kept.
+    pub fn backend(&mut self, backend: MemoryBackend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Load the memory image and segments of `program`.
+    pub fn program(&mut self, program: &Program) -> &mut Self {
+        self.chunks.push(Chunk::Words(program.image.clone()));
+        self.segments.extend(program.segments.iter().cloned());
+        self
+    }
+
+    /// Place `data` into guest memory starting at `addr`.
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) -> &mut Self {
+        self.chunks.push(Chunk::Bytes(addr, data.to_vec()));
+        self
+    }
+
+    /// Map `data` as a separate, uncommitted region for the guest to read.
+    pub fn memory_data(&mut self, data: Vec<u8>) -> &mut Self {
+        self.memory_data = Some(data);
+        self
+    }
+
+    /// Serve the guest heap from `size` bytes starting at `start`.
+    ///
+    /// The heap must lie outside of main guest memory. Defaults to
+    /// [HeapRegion::default_for] the memory size.
+    pub fn heap(&mut self, start: u64, size: u64) -> &mut Self {
+        self.heap = Some(HeapRegion { start, size });
+        self
+    }
+
+    /// Pass `args` to the guest as its argc and argv.
+    ///
+    /// The strings are placed in [ARGS], and argc and the argv pointers at
+    /// [STACK_INITIAL_ADDRESS], where the Linux ABI has them, so that a
+    /// program's startup code finds them as it would on Linux.
+    pub fn args(&mut self, args: &[String]) -> &mut Self {
+        self.args = args.to_vec();
+        self
+    }
+
+    /// Share identical pages, and their digests, with other images built
+    /// with the same `store`.
+    ///
+    /// The store must use the same hash function as this builder.
+    pub fn page_store(&mut self, store: Arc<PageStore>) -> &mut Self {
+        self.page_store = Some(store);
+        self
+    }
+
+    /// Hash pages on a dedicated pool of `num_threads` threads, both while
+    /// building and for later page table updates.
+    ///
+    /// By default the global rayon pool is used.
+    #[cfg(feature = "parallel")]
+    pub fn hash_threads(&mut self, num_threads: usize) -> &mut Self {
+        self.hash_threads = Some(num_threads);
+        self
+    }
+
+    /// Finalize this builder, hashing every page to construct the page table.
+    pub fn build(&self) -> Result<MemoryImage> {
+        let mem_size = self.mem_size;
+        if mem_size == 0 || mem_size % PAGE_SIZE as u64 != 0 {
+            bail!("guest memory size 0x{mem_size:x} is not a multiple of the page size");
+        }
+        ensure!(
+            mem_size >= MEM_SIZE as u64,
+            "guest memory size 0x{mem_size:x} is smaller than the platform layout, which needs 0x{MEM_SIZE:x}"
+        );
+        let info = PageTableInfo::for_mem_size(mem_size);
+        let mut memory = PagedMemory::with_backend(mem_size, &self.backend)?;
+        for chunk in self.chunks.iter() {
+            match chunk {
+                Chunk::Words(words) => {
+                    for (addr, data) in words.iter() {
+                        if info.is_table_page(info.get_page_index(*addr))
+                            || !memory.write_mem(*addr, MemAccessSize::Word, u64::from(*data))
+                        {
+                            bail!("program does not fit in guest memory at 0x{addr:x}");
+                        }
+                    }
+                }
+                Chunk::Bytes(addr, data) => {
+                    let end = addr + data.len() as u64;
+                    let overlaps_table = !data.is_empty()
+                        && info.page_table_addr < end
+                        && *addr < info.page_table_addr + info.page_table_size();
+                    if overlaps_table || !memory.store_bytes(*addr, data) {
+                        bail!(
+                            "range 0x{addr:x}..0x{end:x} does not fit in guest memory outside the page table"
+                        );
+                    }
+                }
+            }
+        }
+
+        // Publish the heap layout to the guest.
+        let heap = self
+            .heap
+            .unwrap_or_else(|| HeapRegion::default_for(mem_size));
+        if heap.start < mem_size || heap.start.checked_add(heap.size).is_none() {
+            bail!("heap must lie above guest memory: {heap:x?}");
+        }
+        if self.memory_data.is_some() && heap.end() > HeapRegion::MEMORY_DATA_ADDR {
+            bail!("heap overlaps memory data: {heap:x?}");
+        }
+        let mut heap_info = heap.start.to_le_bytes().to_vec();
+        heap_info.extend_from_slice(&heap.size.to_le_bytes());
+        if !memory.store_bytes(HEAP_INFO.start() as u64, &heap_info) {
+            bail!("guest memory is too small to hold the heap layout");
+        }
+
+        if !self.args.is_empty() {
+            write_args(&mut memory, &self.args)?;
+        }
+
+        let mut image = MemoryImage::from_memory(memory, info, self.hasher.clone());
+        image.set_segments(self.segments.clone());
+        #[cfg(feature = "parallel")]
+        if let Some(num_threads) = self.hash_threads {
+            image.set_hash_threads(num_threads)?;
+        }
+        if let Some(store) = self.page_store.as_ref() {
+            image.set_page_store(store.clone())?;
+        }
+
+        if let Some(memory_data) = self.memory_data.as_ref() {
+            let size = (memory_data.len() as u64 + 7) / 8 * 8;
+            let mut memory = PagedMemory::new(size);
+            memory.store_bytes(0, memory_data);
+            image.add_region(
+                "memory_data",
+                HeapRegion::MEMORY_DATA_ADDR,
+                size,
+                Box::new(memory),
+            )?;
+        }
+
+        image.hash_pages();
+        Ok(image)
+    }
+}
+
+/// The double words from the initial stack pointer to the end of memory,
+/// which hold argc, argv and the empty environment and auxiliary vectors
+const ARG_VECTOR_LEN: usize = (MEM_SIZE - STACK_INITIAL_ADDRESS) / DOUBLE_WORD_SIZE;
+
+/// The most program arguments that fit in the argument vector, besides argc,
+/// the null ending argv and the three ending the other vectors
+pub const MAX_ARGS: usize = ARG_VECTOR_LEN - 5;
+
+/// Returns the bytes of `args` to place in [ARGS], and those of the vector to
+/// place at [STACK_INITIAL_ADDRESS], as described in
+/// [MemoryImageBuilder::args].
+pub(crate) fn arg_layout(args: &[String]) -> Result<(Vec<u8>, Vec<u8>)> {
+    ensure!(
+        args.len() <= MAX_ARGS,
+        "{} program arguments exceed the limit of {MAX_ARGS}",
+        args.len()
+    );
+    let mut strings = Vec::new();
+    let mut vector = vec![args.len() as u64];
+    for arg in args {
+        vector.push(ARGS.start() as u64 + strings.len() as u64);
+        strings.extend_from_slice(arg.as_bytes());
+        strings.push(0);
+    }
+    // The null ending argv, the null ending the empty environment, and the
+    // AT_NULL entry ending the auxiliary vector.
+    vector.extend([0, 0, 0, 0]);
+    ensure!(
+        strings.len() <= ARGS.len_bytes(),
+        "program arguments of {} bytes do not fit in {}",
+        strings.len(),
+        ARGS.len_bytes()
+    );
+    let vector = vector.iter().flat_map(|word| word.to_le_bytes()).collect();
+    Ok((strings, vector))
+}
+
+/// Write `args` to `memory`, as described in [MemoryImageBuilder::args].
+fn write_args(memory: &mut PagedMemory, args: &[String]) -> Result<()> {
+    let (strings, vector) = arg_layout(args)?;
+    ensure!(
+        memory.store_bytes(ARGS.start() as u64, &strings)
+            && memory.store_bytes(STACK_INITIAL_ADDRESS as u64, &vector),
+        "guest memory is too small to hold {} program arguments",
+        args.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm_platform::memory::{HEAP_INFO, MEM_SIZE};
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use super::{HeapRegion, MemoryImageBuilder, MAX_ARGS};
+    use crate::PAGE_SIZE;
+
+    #[test]
+    fn raw_regions() {
+        let mut builder = MemoryImageBuilder::new();
+        builder
+            .write_bytes(0x2000, &[1; 2 * PAGE_SIZE])
+            .write_bytes(0x2000 + PAGE_SIZE as u64, &[2, 2, 2, 2]);
+        let mut image = builder.build().unwrap();
+        assert_eq!(
+            image.memory_space.read_mem(0x2000, MemAccessSize::Word),
+            Some(0x01010101)
+        );
+        assert_eq!(
+            image
+                .memory_space
+                .read_mem(0x2000 + PAGE_SIZE as u64, MemAccessSize::DoubleWord),
+            Some(0x01010101_02020202)
+        );
+
+        // The root only depends on the contents of memory.
+        let root = image.get_root();
+        let mut other = MemoryImageBuilder::new();
+        other
+            .write_bytes(0x2000 + PAGE_SIZE as u64, &[2, 2, 2, 2])
+            .write_bytes(0x2000, &[1; PAGE_SIZE])
+            .write_bytes(0x2004 + PAGE_SIZE as u64, &[1; PAGE_SIZE - 4]);
+        assert_eq!(other.build().unwrap().get_root(), root);
+
+        let table = image.info.page_table_addr;
+        assert!(MemoryImageBuilder::new()
+            .write_bytes(table, &[1])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn heap_layout() {
+        let mut image = MemoryImageBuilder::new()
+            .heap(0x4000_0000, 0x100_0000)
+            .build()
+            .unwrap();
+        let heap = image.heap();
+        assert_eq!((heap.start, heap.size), (0x4000_0000, 0x100_0000));
+        assert_eq!(
+            image
+                .memory_space
+                .read_mem(HEAP_INFO.start() as u64, MemAccessSize::DoubleWord),
+            Some(0x4000_0000)
+        );
+
+        let image = MemoryImageBuilder::new().build().unwrap();
+        assert_eq!(image.heap(), HeapRegion::default_for(MEM_SIZE as u64));
+        assert_eq!(HeapRegion::default_for(u64::MAX).size, 0);
+
+        assert!(MemoryImageBuilder::new()
+            .heap(0x1000, 0x1000)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn mem_size() {
+        // Guest memory must hold the fixed platform layout, up to the stack.
+        for mem_size in [0, PAGE_SIZE as u64 + 1, MEM_SIZE as u64 / 2] {
+            assert!(MemoryImageBuilder::new()
+                .mem_size(mem_size)
+                .build()
+                .is_err());
+        }
+        let image = MemoryImageBuilder::new()
+            .mem_size(2 * MEM_SIZE as u64)
+            .build()
+            .unwrap();
+        assert_eq!(image.heap(), HeapRegion::default_for(2 * MEM_SIZE as u64));
+    }
+
+    #[test]
+    fn args() {
+        use risc0_zkvm_platform::memory::{ARGS, STACK_INITIAL_ADDRESS};
+
+        let args = ["prog".to_string(), "-v".to_string()];
+        let mut image = MemoryImageBuilder::new().args(&args).build().unwrap();
+        let mut load = |addr: u64| {
+            image
+                .memory_space
+                .read_mem(addr, MemAccessSize::DoubleWord)
+                .unwrap()
+        };
+        let sp = STACK_INITIAL_ADDRESS as u64;
+        assert_eq!(load(sp), 2);
+        assert_eq!(load(sp + 8), ARGS.start() as u64);
+        assert_eq!(load(sp + 16), ARGS.start() as u64 + 5);
+        assert_eq!(load(sp + 24), 0);
+        assert_eq!(
+            load(ARGS.start() as u64),
+            u64::from_le_bytes(*b"prog\0-v\0")
+        );
+
+        let too_long = ["x".repeat(ARGS.len_bytes())];
+        assert!(MemoryImageBuilder::new().args(&too_long).build().is_err());
+
+        // The vector fills the memory above the initial stack pointer.
+        let most = vec![String::new(); MAX_ARGS];
+        let mut image = MemoryImageBuilder::new().args(&most).build().unwrap();
+        let end = STACK_INITIAL_ADDRESS as u64 + (MAX_ARGS as u64 + 5) * 8;
+        assert_eq!(end, MEM_SIZE as u64);
+        assert_eq!(
+            image
+                .memory_space
+                .read_mem(STACK_INITIAL_ADDRESS as u64, MemAccessSize::DoubleWord),
+            Some(MAX_ARGS as u64)
+        );
+        let too_many = vec![String::new(); MAX_ARGS + 1];
+        let Err(err) = MemoryImageBuilder::new().args(&too_many).build() else {
+            panic!("{} arguments fit", too_many.len());
+        };
+        assert!(err.to_string().contains("exceed the limit"), "{err}");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn hash_threads() {
+        let mut builder = MemoryImageBuilder::new();
+        builder.write_bytes(0x4000, &[7; 3 * PAGE_SIZE]);
+        let mut serial = builder.clone().hash_threads(1).build().unwrap();
+        let mut parallel = builder.hash_threads(4).build().unwrap();
+        assert_eq!(serial.get_root(), parallel.get_root());
+
+        for image in [&mut serial, &mut parallel] {
+            image.write_u64(0x8000, 1).unwrap();
+            image.update_page_table();
+        }
+        assert_eq!(serial.get_root(), parallel.get_root());
+        assert!(parallel.verify_integrity().is_ok());
+    }
+}
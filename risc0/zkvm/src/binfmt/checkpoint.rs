@@ -0,0 +1,79 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use super::{
+    image::{MemoryImage, PageTableInfo},
+    paged::PagedMemory,
+};
+
+/// A saved state of a [MemoryImage], produced by [MemoryImage::snapshot].
+///
+/// This captures the main guest memory, including the page table, so restoring
+/// it requires neither reloading the ELF nor rehashing any pages. Pages are
+/// shared with the image until either side writes to them.
+#[derive(Clone)]
+pub struct Checkpoint {
+    memory: PagedMemory,
+    info: PageTableInfo,
+    dirty_pages: BTreeSet<u64>,
+}
+
+impl MemoryImage {
+    /// Capture the current state of guest memory.
+    ///
+    /// Regions mapped at runtime outside of the main guest memory are not
+    /// captured.
+    pub fn snapshot(&self) -> Checkpoint {
+        Checkpoint {
+            memory: self.main_memory().clone(),
+            info: self.info.clone(),
+            dirty_pages: self.dirty_pages().clone(),
+        }
+    }
+
+    /// Roll guest memory back to a state captured by
+    /// [MemoryImage::snapshot].
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        *self.main_memory_mut() = checkpoint.memory;
+        self.info = checkpoint.info;
+        self.set_dirty_pages(checkpoint.dirty_pages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::MemAccessSize;
+
+    use crate::testutil::{image, RET};
+
+    #[test]
+    fn snapshot_restore() {
+        let mut image = image(&[RET], &[]);
+        image.write_mem(0x2000, MemAccessSize::Byte, 1);
+        let checkpoint = image.snapshot();
+        image.update_page_table();
+        let root = image.get_root();
+
+        image.write_mem(0x3000, MemAccessSize::DoubleWord, u64::MAX);
+        image.update_page_table();
+        assert_ne!(image.get_root(), root);
+
+        image.restore(checkpoint);
+        assert_eq!(image.dirty_pages().len(), 1);
+        image.update_page_table();
+        assert_eq!(image.get_root(), root);
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Batched page hashing on a CUDA device.
+
+use std::sync::Mutex;
+
+use cust::{
+    context::{Context, CurrentContext},
+    error::CudaResult,
+    launch,
+    memory::{CopyDestination, DeviceBuffer},
+    module::Module,
+    stream::{Stream, StreamFlags},
+};
+
+use super::{
+    digest::{sha_compress_raw, Digest, DIGEST_WORDS},
+    hash::PageHasher,
+};
+use crate::PAGE_SIZE;
+
+const KERNELS_FATBIN: &[u8] = include_bytes!(env!("ZKVM_CUDA_KERNELS"));
+
+/// Batches smaller than this are hashed on the CPU, where the cost of the
+/// transfer to the device would dominate.
+const MIN_BATCH_PAGES: usize = 64;
+
+const THREADS_PER_BLOCK: u32 = 256;
+
+const PAGE_WORDS: usize = PAGE_SIZE / 4;
+
+struct CudaDevice {
+    context: Context,
+    module: Module,
+    stream: Stream,
+}
+
+// SAFETY: the device is only used while holding the [Mutex] in
+// [CudaPageHasher], and binds its context to the calling thread first.
+unsafe impl Send for CudaDevice {}
+
+impl CudaDevice {
+    fn new() -> CudaResult<Self> {
+        let context = cust::quick_init()?;
+        let module = Module::from_fatbin(KERNELS_FATBIN, &[])?;
+        let stream = Stream::new(StreamFlags::NON_BLOCKING, None)?;
+        Ok(Self {
+            context,
+            module,
+            stream,
+        })
+    }
+
+    fn hash_pages(&self, pages: &[&[u8]]) -> CudaResult<Vec<Digest>> {
+        CurrentContext::set_current(&self.context)?;
+        let words: Vec<u32> = pages
+            .iter()
+            .flat_map(|page| page.chunks_exact(4))
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        let count = pages.len() as u32;
+        let mut host = vec![0u32; pages.len() * DIGEST_WORDS];
+        let input = DeviceBuffer::from_slice(&words)?;
+        let output = DeviceBuffer::from_slice(&host)?;
+        let sha_pages = self.module.get_function("sha_pages")?;
+        let blocks = (count + THREADS_PER_BLOCK - 1) / THREADS_PER_BLOCK;
+        let stream = &self.stream;
+        unsafe {
+            launch!(sha_pages<<<blocks, THREADS_PER_BLOCK, 0, stream>>>(
+                output.as_device_ptr(),
+                input.as_device_ptr(),
+                count,
+                PAGE_WORDS as u32
+            ))?;
+        }
+        stream.synchronize()?;
+
+        output.copy_to(&mut host)?;
+        // The kernel stores each state word in big-endian byte order.
+        Ok(host
+            .chunks_exact(DIGEST_WORDS)
+            .map(|words| Digest::new(core::array::from_fn(|i| words[i].swap_bytes())))
+            .collect())
+    }
+}
+
+/// Hashes pages with the raw SHA-256 compression function, batching large
+/// updates onto a CUDA device.
+///
+/// The digests are identical to those of [super::hash::Sha256PageHasher], so
+/// images built with either hasher have the same root and are provable. If no
+/// device can be initialized, or a batch fails on the device, pages are hashed
+/// on the CPU instead.
+pub struct CudaPageHasher {
+    device: Option<Mutex<CudaDevice>>,
+}
+
+impl CudaPageHasher {
+    /// Initialize the first CUDA device, falling back to the CPU if none is
+    /// available.
+    pub fn new() -> Self {
+        let device = match CudaDevice::new() {
+            Ok(device) => Some(Mutex::new(device)),
+            Err(err) => {
+                tracing::warn!("CUDA page hashing unavailable, using the CPU: {err}");
+                None
+            }
+        };
+        Self { device }
+    }
+
+    /// Returns true if batches are hashed on a CUDA device.
+    pub fn is_available(&self) -> bool {
+        self.device.is_some()
+    }
+}
+
+impl Default for CudaPageHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PageHasher for CudaPageHasher {
+    fn name(&self) -> &'static str {
+        "sha-256"
+    }
+
+    fn hash_page(&self, data: &[u8]) -> Digest {
+        sha_compress_raw(data)
+    }
+
+    fn hash_batch(&self, pages: &[&[u8]]) -> Option<Vec<Digest>> {
+        if pages.len() < MIN_BATCH_PAGES {
+            return None;
+        }
+        let device = self.device.as_ref()?.lock().unwrap();
+        match device.hash_pages(pages) {
+            Ok(digests) => Some(digests),
+            Err(err) => {
+                tracing::warn!("CUDA page hashing failed, using the CPU: {err}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use risc0_zkvm_platform::PAGE_SIZE;
+
+use super::{
+    image::MemoryImage,
+    paged::{Page, PagedMemory},
+};
+
+const ZERO_PAGE: Page = [0; PAGE_SIZE];
+
+/// A page whose contents differ between two [MemoryImage]s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageDelta {
+    /// Index of the page
+    pub page_idx: u64,
+
+    /// Contents of the page in the image `diff` was called on
+    pub before: Box<Page>,
+
+    /// Contents of the page in the image passed to `diff`
+    pub after: Box<Page>,
+}
+
+impl MemoryImage {
+    /// Report the pages of guest memory which differ between `self` and
+    /// `other`, in ascending page order.
+    ///
+    /// Pages holding the page table are skipped, since their contents follow
+    /// from the data pages. Pages that were never written compare as zero.
+    pub fn diff(&self, other: &MemoryImage) -> Vec<PageDelta> {
+        let lhs = self.main_memory();
+        let rhs = other.main_memory();
+        let page_idxs: BTreeSet<u64> = lhs
+            .pages()
+            .chain(rhs.pages())
+            .map(|(page_idx, _)| page_idx)
+            .filter(|page_idx| {
+                !self.info.is_table_page(*page_idx) && !other.info.is_table_page(*page_idx)
+            })
+            .collect();
+        page_idxs
+            .into_iter()
+            .filter_map(|page_idx| {
+                let before = page_or_zero(lhs, page_idx);
+                let after = page_or_zero(rhs, page_idx);
+                (before != after).then(|| PageDelta {
+                    page_idx,
+                    before: Box::new(*before),
+                    after: Box::new(*after),
+                })
+            })
+            .collect()
+    }
+}
+
+fn page_or_zero(memory: &PagedMemory, page_idx: u64) -> &Page {
+    memory.page(page_idx).unwrap_or(&ZERO_PAGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::MemAccessSize;
+
+    use crate::testutil::{image, RET};
+
+    #[test]
+    fn diff_pages() {
+        let pre = image(&[RET], &[]);
+        let mut post = image(&[RET], &[]);
+        assert!(pre.diff(&post).is_empty());
+
+        post.write_mem(0x1000, MemAccessSize::Word, 0);
+        post.write_mem(0x4008, MemAccessSize::Byte, 0xff);
+        post.write_mem(0x5000, MemAccessSize::Byte, 0);
+        post.update_page_table();
+
+        let deltas = pre.diff(&post);
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].page_idx, 4);
+        assert_eq!(deltas[0].before[0], 0x67);
+        assert!(deltas[0].after.iter().all(|byte| *byte == 0));
+        assert_eq!(deltas[1].page_idx, 16);
+        assert_eq!(deltas[1].after[8], 0xff);
+    }
+}
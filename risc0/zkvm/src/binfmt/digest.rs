@@ -0,0 +1,111 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt::{Debug, Display, Formatter};
+
+use bytemuck::{Pod, Zeroable};
+pub use risc0_zkvm_platform::syscall::{DIGEST_BYTES, DIGEST_WORDS};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "binfmt")]
+use sha2::digest::generic_array::GenericArray;
+
+/// The number of bytes in a single SHA-256 block.
+#[cfg(feature = "binfmt")]
+pub const BLOCK_BYTES: usize = DIGEST_BYTES * 2;
+
+/// The initial state of SHA-256, as defined by FIPS 180-4.
+#[cfg(feature = "binfmt")]
+const SHA256_INIT: [u32; DIGEST_WORDS] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// A SHA-256 digest, stored as state words
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Pod,
+    Zeroable,
+    Serialize,
+    Deserialize,
+)]
+#[repr(transparent)]
+pub struct Digest([u32; DIGEST_WORDS]);
+
+impl Digest {
+    /// A digest with all words set to zero.
+    pub const ZERO: Self = Self([0; DIGEST_WORDS]);
+
+    /// Construct a digest from its state words.
+    pub const fn new(words: [u32; DIGEST_WORDS]) -> Self {
+        Self(words)
+    }
+
+    /// Returns the state words of this digest.
+    pub fn as_words(&self) -> &[u32] {
+        &self.0
+    }
+
+    /// Returns the bytes of this digest as they are laid out in guest memory.
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::cast_slice(&self.0)
+    }
+
+    /// Construct a digest from bytes laid out as in guest memory.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != DIGEST_BYTES {
+            return None;
+        }
+        Some(bytemuck::pod_read_unaligned(bytes))
+    }
+}
+
+impl Debug for Digest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Digest({})", hex::encode(self.as_bytes()))
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&hex::encode(self.as_bytes()))
+    }
+}
+
+/// Run the SHA-256 compression function over `bytes`, starting from the
+/// standard initial state.
+///
+/// No padding or length suffix is applied, so `bytes` must be a multiple of
+/// [BLOCK_BYTES] long. This is how pages and page table entries are committed.
+#[cfg(feature = "binfmt")]
+pub fn sha_compress_raw(bytes: &[u8]) -> Digest {
+    assert_eq!(bytes.len() % BLOCK_BYTES, 0, "unaligned sha input");
+    let mut state = SHA256_INIT;
+    for block in bytes.chunks_exact(BLOCK_BYTES) {
+        sha2::compress256(&mut state, &[GenericArray::clone_from_slice(block)]);
+    }
+    Digest(state)
+}
+
+/// Returns the standard SHA-256 digest of `bytes`, padded as usual.
+pub fn sha256(bytes: &[u8]) -> Digest {
+    use sha2::Digest as _;
+
+    Digest::from_bytes(&sha2::Sha256::digest(bytes)).unwrap()
+}
@@ -16,6 +16,106 @@ use alloc::collections::BTreeMap;
 
 use anyhow::{anyhow, bail, Context, Result};
 use elf::{endian::LittleEndian, file::Class, ElfBytes};
+use risc0_zkvm_platform::memory::MEM_SIZE;
+use serde::{Deserialize, Serialize};
+
+/// The default address position-independent executables are loaded at.
+pub const DEFAULT_PIE_BASE: u64 = 0x10000;
+
+/// Options controlling how an ELF file is loaded into a [Program]
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    /// The size of guest memory; every segment must be loaded below this
+    /// address.
+    pub max_mem: u64,
+
+    /// The address a position-independent (`ET_DYN`) executable is loaded at.
+    ///
+    /// Ignored for `ET_EXEC` binaries, which are loaded at their link address.
+    pub base_addr: u64,
+
+    /// Whether the ELF is a 32-bit RV32 one, rather than a 64-bit one.
+    pub rv32: bool,
+}
+
+impl LoadOptions {
+    /// Options for a guest memory of `max_mem` bytes.
+    pub fn new(max_mem: u64) -> Self {
+        Self {
+            max_mem,
+            base_addr: DEFAULT_PIE_BASE,
+            rv32: false,
+        }
+    }
+
+    /// Load position-independent executables at `base_addr`.
+    pub fn with_base_addr(mut self, base_addr: u64) -> Self {
+        self.base_addr = base_addr;
+        self
+    }
+
+    /// Load a 32-bit RV32 ELF if `rv32` is set, rather than a 64-bit one.
+    pub fn with_rv32(mut self, rv32: bool) -> Self {
+        self.rv32 = rv32;
+        self
+    }
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self::new(MEM_SIZE as u64)
+    }
+}
+
+/// Access permissions of a loadable segment, from its `p_flags`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentFlags {
+    /// The segment may be read
+    pub read: bool,
+
+    /// The segment may be written
+    pub write: bool,
+
+    /// The segment may be executed
+    pub execute: bool,
+}
+
+impl SegmentFlags {
+    /// Decode the `p_flags` field of a program header.
+    pub fn from_p_flags(p_flags: u32) -> Self {
+        Self {
+            read: p_flags & elf::abi::PF_R != 0,
+            write: p_flags & elf::abi::PF_W != 0,
+            execute: p_flags & elf::abi::PF_X != 0,
+        }
+    }
+}
+
+/// A `PT_LOAD` segment of a program
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramSegment {
+    /// The address the segment is loaded at
+    pub vaddr: u64,
+
+    /// The size of the segment in memory, including any zero-filled tail
+    pub mem_size: u64,
+
+    /// The number of bytes of the segment initialized from the file
+    pub file_size: u64,
+
+    /// The alignment of the segment, or 0 if unconstrained
+    pub align: u64,
+
+    /// Access permissions of the segment
+    pub flags: SegmentFlags,
+}
+
+impl ProgramSegment {
+    /// Returns whether `addr` lies within this segment.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.vaddr && addr - self.vaddr < self.mem_size
+    }
+}
 
 /// A RISC Zero program
 pub struct Program {
@@ -24,23 +124,37 @@ pub struct Program {
 
     /// The initial memory image
     pub image: BTreeMap<u64, u32>,
+
+    /// The loadable segments of the program
+    pub segments: Vec<ProgramSegment>,
 }
 
 impl Program {
     /// Initialize a RISC Zero Program from an appropriate ELF file
-    pub fn load_elf(input: &[u8], max_mem: u64) -> Result<Program> {
+    ///
+    /// Both static (`ET_EXEC`) and position-independent (`ET_DYN`) executables
+    /// are supported. The latter are loaded at [LoadOptions::base_addr], and
+    /// their dynamic relocations are applied to the image.
+    pub fn load_elf(input: &[u8], options: &LoadOptions) -> Result<Program> {
+        let max_mem = options.max_mem;
         let mut image: BTreeMap<u64, u32> = BTreeMap::new();
         let elf = ElfBytes::<LittleEndian>::minimal_parse(input)?;
-        if elf.ehdr.class != Class::ELF64 {
-            bail!("Not a 64-bit ELF");
+        match (elf.ehdr.class, options.rv32) {
+            (Class::ELF64, false) | (Class::ELF32, true) => {}
+            (_, false) => bail!("Not a 64-bit ELF"),
+            (_, true) => bail!("Not a 32-bit ELF"),
         }
         if elf.ehdr.e_machine != elf::abi::EM_RISCV {
             bail!("Invalid machine type, must be RISC-V");
         }
-        if elf.ehdr.e_type != elf::abi::ET_EXEC {
-            bail!("Invalid ELF type, must be executable");
-        }
+        // The offset added to every link-time address.
+        let bias = match elf.ehdr.e_type {
+            elf::abi::ET_EXEC => 0,
+            elf::abi::ET_DYN => options.base_addr,
+            _ => bail!("Invalid ELF type, must be executable"),
+        };
         let entry: u64 = elf.ehdr.e_entry.try_into()?;
+        let entry = entry.checked_add(bias).context("Invalid entrypoint")?;
         if entry >= max_mem || entry % 4 != 0 {
             bail!("Invalid entrypoint");
         }
@@ -48,6 +162,7 @@ impl Program {
         if segments.len() > 256 {
             bail!("Too many program headers");
         }
+        let mut loaded = Vec::new();
         for segment in segments.iter().filter(|x| x.p_type == elf::abi::PT_LOAD) {
             let file_size: u64 = segment.p_filesz.try_into()?;
             if file_size >= max_mem {
@@ -57,33 +172,75 @@ impl Program {
             if mem_size >= max_mem {
                 bail!("Invalid segment mem_size");
             }
+            if file_size > mem_size {
+                bail!("Segment file_size exceeds mem_size");
+            }
             let vaddr: u64 = segment.p_vaddr.try_into()?;
             let offset: u64 = segment.p_offset.try_into()?;
-            for i in (0..mem_size).step_by(4) {
-                let addr = vaddr.checked_add(i).context("Invalid segment vaddr")?;
-                if i >= file_size {
-                    // Past the file size, all zeros.
-                    image.insert(addr, 0);
-                } else {
-                    let mut word = 0;
-                    // Don't read past the end of the file.
-                    let len = std::cmp::min(file_size - i, 4);
-                    for j in 0..len {
-                        let offset = (offset + i + j) as usize;
-                        let byte = input.get(offset).context("Invalid segment offset")?;
-                        word |= (*byte as u32) << (j * 8);
+            let align: u64 = segment.p_align.try_into()?;
+            if align > 1 {
+                if !align.is_power_of_two() {
+                    bail!("Invalid segment alignment");
+                }
+                if vaddr % align != offset % align {
+                    bail!("Segment vaddr and offset are not congruent modulo alignment");
+                }
+                if bias % align != 0 {
+                    bail!("Base address is not aligned to segment alignment");
+                }
+            }
+            let vaddr = vaddr.checked_add(bias).context("Invalid segment vaddr")?;
+            let end = vaddr
+                .checked_add(mem_size)
+                .filter(|end| *end <= max_mem)
+                .context("Invalid segment vaddr")?;
+            // Fill every word the segment touches, one byte at a time so that
+            // unaligned segment boundaries don't clobber neighbouring data.
+            // Bytes past the file size are zero (BSS).
+            for word_addr in (vaddr & !3..end).step_by(4) {
+                let word = image.entry(word_addr).or_insert(0);
+                for j in 0..4 {
+                    let addr = word_addr + j;
+                    if addr < vaddr || addr >= end {
+                        continue;
                     }
-                    image.insert(addr, word);
+                    let i = addr - vaddr;
+                    let byte = if i < file_size {
+                        let offset = (offset + i) as usize;
+                        *input.get(offset).context("Invalid segment offset")?
+                    } else {
+                        0
+                    };
+                    *word = *word & !(0xff << (j * 8)) | (byte as u32) << (j * 8);
                 }
             }
+            loaded.push(ProgramSegment {
+                vaddr,
+                mem_size,
+                file_size,
+                align,
+                flags: SegmentFlags::from_p_flags(segment.p_flags),
+            });
+        }
+        // Relocations carry their values in the addend, so they are needed at
+        // any base, including 0.
+        if elf.ehdr.e_type == elf::abi::ET_DYN {
+            relocate(&elf, bias, &loaded, &mut image)?;
         }
         // patch below symbols to `ret` assembly
         // refer https://github.com/ethereum-optimism/cannon/blob/32c76db43dc4b5fb25f49ba8fbdb84fed8e5615a/mipsevm/patch.go#L66
-        let (symtab, strtab) = elf
-            .symbol_table()
-            .expect("Failed to read symbol table")
-            .expect("Failed to find strtab table");
-        symtab.iter().for_each(|entry| {
+        let symbols = elf.symbol_table().context("Failed to read symbol table")?;
+        let (symtab, strtab) = match symbols {
+            Some(symbols) => symbols,
+            None => {
+                return Ok(Program {
+                    entry,
+                    image,
+                    segments: loaded,
+                })
+            }
+        };
+        for entry in symtab.iter() {
             let symbol_name = strtab.get(entry.st_name as usize).unwrap();
             match symbol_name {
                 "runtime.gcenable"
@@ -113,19 +270,327 @@ impl Program {
                 // | "runtime.osinit"
                 // | "runtime.schedinit"
                 => {
-                    println!(
+                    let addr = entry
+                        .st_value
+                        .checked_add(bias)
+                        .context("Invalid symbol value")?;
+                    tracing::debug!(
                         "symbol_name: {:?}, st_value {:08x}, image.get(key): {:08x}",
                         symbol_name,
-                        entry.st_value,
-                        image.get(&entry.st_value).unwrap(),
+                        addr,
+                        image.get(&addr).unwrap(),
                     );
-                    image.insert(entry.st_value, 0x00008067); // ret, immediate return
-                    ()
+                    image.insert(addr, 0x00008067); // ret, immediate return
                 }
                 _ => (),
             }
-        });
+        }
         // common.symtab.iter().map(|f| f)
-        Ok(Program { entry, image })
+        Ok(Program {
+            entry,
+            image,
+            segments: loaded,
+        })
+    }
+}
+
+/// Apply the dynamic relocations of a position-independent executable loaded
+/// `bias` bytes above its link address.
+fn relocate(
+    elf: &ElfBytes<LittleEndian>,
+    bias: u64,
+    segments: &[ProgramSegment],
+    image: &mut BTreeMap<u64, u32>,
+) -> Result<()> {
+    let shdrs = match elf.section_headers() {
+        Some(shdrs) => shdrs,
+        None => return Ok(()),
+    };
+    let dynsyms = elf
+        .dynamic_symbol_table()
+        .context("Failed to read dynamic symbol table")?;
+    // Relocated addresses are as wide as those of the ELF class.
+    let (symbolic, width) = match elf.ehdr.class {
+        Class::ELF32 => (elf::abi::R_RISCV_32, 4),
+        Class::ELF64 => (elf::abi::R_RISCV_64, 8),
+    };
+    for shdr in shdrs.iter().filter(|x| x.sh_type == elf::abi::SHT_RELA) {
+        for rela in elf.section_data_as_relas(&shdr)? {
+            let value = match rela.r_type {
+                elf::abi::R_RISCV_NONE => continue,
+                elf::abi::R_RISCV_RELATIVE => bias.wrapping_add(rela.r_addend as u64),
+                r_type if r_type == symbolic => {
+                    let (symtab, _) = dynsyms.as_ref().context("Missing dynamic symbol table")?;
+                    let symbol = symtab.get(rela.r_sym as usize)?;
+                    if symbol.is_undefined() {
+                        bail!("Undefined symbol in relocation");
+                    }
+                    symbol
+                        .st_value
+                        .checked_add(bias)
+                        .context("Invalid symbol value")?
+                        .wrapping_add(rela.r_addend as u64)
+                }
+                r_type => bail!("Unsupported relocation type: {r_type}"),
+            };
+            let addr = rela
+                .r_offset
+                .checked_add(bias)
+                .context("Invalid relocation offset")?;
+            let last = addr
+                .checked_add(width - 1)
+                .context("Invalid relocation offset")?;
+            if !segments
+                .iter()
+                .any(|segment| segment.contains(addr) && segment.contains(last))
+            {
+                bail!("Relocation outside of loaded segments");
+            }
+            for (i, byte) in value
+                .to_le_bytes()
+                .into_iter()
+                .take(width as usize)
+                .enumerate()
+            {
+                let addr = addr + i as u64;
+                let shift = (addr % 4) * 8;
+                let word = image.entry(addr & !3).or_insert(0);
+                *word = *word & !(0xff << shift) | (byte as u32) << shift;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadOptions, Program, SegmentFlags};
+
+    const PHDR_OFFSET: usize = 64;
+    const PHDR_SIZE: usize = 56;
+
+    struct Load {
+        vaddr: u64,
+        offset: u64,
+        data: Vec<u8>,
+        mem_size: u64,
+        align: u64,
+        flags: u32,
+    }
+
+    const SHDR_SIZE: usize = 64;
+    const RELA_SIZE: usize = 24;
+
+    /// Build a minimal RV64 executable with the given `PT_LOAD` segments and
+    /// no section headers.
+    fn build_elf(entry: u64, loads: &[Load]) -> Vec<u8> {
+        build_elf_with_type(elf::abi::ET_EXEC, entry, loads)
+    }
+
+    fn build_elf_with_type(e_type: u16, entry: u64, loads: &[Load]) -> Vec<u8> {
+        let mut elf = vec![0; PHDR_OFFSET + PHDR_SIZE * loads.len()];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2; // ELFCLASS64
+        elf[5] = 1; // ELFDATA2LSB
+        elf[6] = 1; // EV_CURRENT
+        elf[16..18].copy_from_slice(&e_type.to_le_bytes());
+        elf[18..20].copy_from_slice(&elf::abi::EM_RISCV.to_le_bytes());
+        elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+        elf[24..32].copy_from_slice(&entry.to_le_bytes());
+        elf[32..40].copy_from_slice(&(PHDR_OFFSET as u64).to_le_bytes());
+        elf[52..54].copy_from_slice(&(PHDR_OFFSET as u16).to_le_bytes());
+        elf[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        elf[56..58].copy_from_slice(&(loads.len() as u16).to_le_bytes());
+        for (i, load) in loads.iter().enumerate() {
+            let file_size = load.data.len() as u64;
+            let mut phdr = Vec::with_capacity(PHDR_SIZE);
+            phdr.extend_from_slice(&elf::abi::PT_LOAD.to_le_bytes());
+            phdr.extend_from_slice(&load.flags.to_le_bytes());
+            for field in [
+                load.offset,
+                load.vaddr,
+                load.vaddr,
+                file_size,
+                load.mem_size,
+                load.align,
+            ] {
+                phdr.extend_from_slice(&field.to_le_bytes());
+            }
+            let start = PHDR_OFFSET + PHDR_SIZE * i;
+            elf[start..start + PHDR_SIZE].copy_from_slice(&phdr);
+            let offset = load.offset as usize;
+            if elf.len() < offset + load.data.len() {
+                elf.resize(offset + load.data.len(), 0);
+            }
+            elf[offset..offset + load.data.len()].copy_from_slice(&load.data);
+        }
+        elf
+    }
+
+    /// Append a section header table with a single `SHT_RELA` section holding
+    /// `relas` as `(r_offset, r_type, r_addend)` triples.
+    fn add_relas(elf: &mut Vec<u8>, relas: &[(u64, u32, i64)]) {
+        let rela_offset = elf.len() as u64;
+        for (r_offset, r_type, r_addend) in relas {
+            elf.extend_from_slice(&r_offset.to_le_bytes());
+            elf.extend_from_slice(&(*r_type as u64).to_le_bytes());
+            elf.extend_from_slice(&r_addend.to_le_bytes());
+        }
+        let shoff = elf.len() as u64;
+        // The null section header.
+        elf.extend_from_slice(&[0; SHDR_SIZE]);
+        let mut shdr = Vec::with_capacity(SHDR_SIZE);
+        shdr.extend_from_slice(&0u32.to_le_bytes()); // sh_name
+        shdr.extend_from_slice(&elf::abi::SHT_RELA.to_le_bytes());
+        for field in [
+            0, // sh_flags
+            0, // sh_addr
+            rela_offset,
+            (relas.len() * RELA_SIZE) as u64,
+        ] {
+            shdr.extend_from_slice(&field.to_le_bytes());
+        }
+        shdr.extend_from_slice(&0u32.to_le_bytes()); // sh_link
+        shdr.extend_from_slice(&0u32.to_le_bytes()); // sh_info
+        shdr.extend_from_slice(&8u64.to_le_bytes()); // sh_addralign
+        shdr.extend_from_slice(&(RELA_SIZE as u64).to_le_bytes()); // sh_entsize
+        elf.extend_from_slice(&shdr);
+        elf[40..48].copy_from_slice(&shoff.to_le_bytes());
+        elf[58..60].copy_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+        elf[60..62].copy_from_slice(&2u16.to_le_bytes());
+    }
+
+    #[test]
+    fn bss_and_flags() {
+        let text = Load {
+            vaddr: 0x1000,
+            offset: 0x1000,
+            data: 0x00008067u32.to_le_bytes().to_vec(),
+            mem_size: 4,
+            align: 0x1000,
+            flags: elf::abi::PF_R | elf::abi::PF_X,
+        };
+        // An unaligned data segment whose BSS shares a word with its data.
+        let data = Load {
+            vaddr: 0x2002,
+            offset: 0x2002,
+            data: vec![0xaa, 0xbb, 0xcc],
+            mem_size: 10,
+            align: 0x1000,
+            flags: elf::abi::PF_R | elf::abi::PF_W,
+        };
+        let input = build_elf(0x1000, &[text, data]);
+        let program = Program::load_elf(&input, &LoadOptions::new(1 << 28)).unwrap();
+
+        assert_eq!(program.image[&0x1000], 0x00008067);
+        assert_eq!(program.image[&0x2000], 0xbbaa_0000);
+        assert_eq!(program.image[&0x2004], 0x0000_00cc);
+        assert_eq!(program.image[&0x2008], 0);
+        assert!(!program.image.contains_key(&0x200c));
+
+        assert_eq!(program.segments.len(), 2);
+        let flags = program.segments[1].flags;
+        assert_eq!(
+            flags,
+            SegmentFlags {
+                read: true,
+                write: true,
+                execute: false,
+            }
+        );
+        assert_eq!(program.segments[1].file_size, 3);
+        assert!(program.segments[0].flags.execute);
+    }
+
+    #[test]
+    fn rejects_misaligned_segment() {
+        let load = Load {
+            vaddr: 0x1000,
+            offset: 0x1004,
+            data: vec![0; 4],
+            mem_size: 4,
+            align: 0x1000,
+            flags: elf::abi::PF_R,
+        };
+        assert!(
+            Program::load_elf(&build_elf(0x1000, &[load]), &LoadOptions::new(1 << 28)).is_err()
+        );
+    }
+
+    #[test]
+    fn pie_relocations() {
+        // A pointer to the start of the segment, to be fixed up at load time.
+        let mut data = vec![0; 16];
+        data[..4].copy_from_slice(&0x00008067u32.to_le_bytes());
+        let load = Load {
+            vaddr: 0,
+            offset: 0x1000,
+            data,
+            mem_size: 16,
+            align: 0x1000,
+            flags: elf::abi::PF_R | elf::abi::PF_W | elf::abi::PF_X,
+        };
+        let mut input = build_elf_with_type(elf::abi::ET_DYN, 0, &[load]);
+        add_relas(&mut input, &[(8, elf::abi::R_RISCV_RELATIVE, 4)]);
+
+        let options = LoadOptions::new(1 << 28).with_base_addr(0x40000);
+        let program = Program::load_elf(&input, &options).unwrap();
+        assert_eq!(program.entry, 0x40000);
+        assert_eq!(program.segments[0].vaddr, 0x40000);
+        assert_eq!(program.image[&0x40000], 0x00008067);
+        assert_eq!(program.image[&0x40008], 0x40004);
+        assert_eq!(program.image[&0x4000c], 0);
+
+        // Relocations are applied at a base of 0 too.
+        let options = LoadOptions::new(1 << 28).with_base_addr(0);
+        let program = Program::load_elf(&input, &options).unwrap();
+        assert_eq!(program.image[&0x8], 0x4);
+
+        // The base must respect the segment alignment.
+        let options = LoadOptions::new(1 << 28).with_base_addr(0x40800);
+        assert!(Program::load_elf(&input, &options).is_err());
+    }
+
+    #[test]
+    fn rv32() {
+        // An ELF32 header, followed by a single program header.
+        let mut input = vec![0; 0x1004];
+        input[..4].copy_from_slice(b"\x7fELF");
+        input[4] = 1; // ELFCLASS32
+        input[5] = 1; // ELFDATA2LSB
+        input[6] = 1; // EV_CURRENT
+        input[16..18].copy_from_slice(&elf::abi::ET_EXEC.to_le_bytes());
+        input[18..20].copy_from_slice(&elf::abi::EM_RISCV.to_le_bytes());
+        input[20..24].copy_from_slice(&1u32.to_le_bytes());
+        input[24..28].copy_from_slice(&0x1000u32.to_le_bytes());
+        input[28..32].copy_from_slice(&52u32.to_le_bytes());
+        input[40..42].copy_from_slice(&52u16.to_le_bytes());
+        input[42..44].copy_from_slice(&32u16.to_le_bytes());
+        input[44..46].copy_from_slice(&1u16.to_le_bytes());
+        let phdr = [
+            elf::abi::PT_LOAD,
+            0x1000,
+            0x1000,
+            0x1000,
+            4,
+            4,
+            elf::abi::PF_R | elf::abi::PF_X,
+            0x1000,
+        ];
+        for (i, field) in phdr.iter().enumerate() {
+            input[52 + i * 4..56 + i * 4].copy_from_slice(&field.to_le_bytes());
+        }
+        input[0x1000..].copy_from_slice(&0x00008067u32.to_le_bytes());
+
+        let options = LoadOptions::new(1 << 28);
+        assert!(Program::load_elf(&input, &options).is_err());
+        let program = Program::load_elf(&input, &options.with_rv32(true)).unwrap();
+        assert_eq!(program.entry, 0x1000);
+        assert_eq!(program.image[&0x1000], 0x00008067);
+        assert!(program.segments[0].flags.execute);
+
+        let input = build_elf(0x1000, &[]);
+        let options = LoadOptions::new(1 << 28).with_rv32(true);
+        assert!(Program::load_elf(&input, &options).is_err());
     }
 }
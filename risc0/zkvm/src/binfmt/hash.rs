@@ -0,0 +1,211 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hash functions used to build the page table Merkle tree.
+
+use std::sync::Arc;
+
+use super::digest::{sha_compress_raw, Digest};
+
+/// A hash function used to commit to pages of a [crate::MemoryImage].
+///
+/// The same function is used for data pages and for the pages of the page
+/// table itself, so it determines the image root.
+pub trait PageHasher: Send + Sync {
+    /// A stable name for this hash function, used to identify it in saved
+    /// images.
+    fn name(&self) -> &'static str;
+
+    /// Compute the digest of `data`.
+    ///
+    /// `data` is either a full page, or the populated prefix of the root page;
+    /// in both cases its length is a multiple of 64 bytes.
+    fn hash_page(&self, data: &[u8]) -> Digest;
+
+    /// Compute the digests of many full pages in one call.
+    ///
+    /// Returns `None` when this hasher has no batched implementation, or it is
+    /// unavailable; each page is then hashed with [PageHasher::hash_page].
+    fn hash_batch(&self, _pages: &[&[u8]]) -> Option<Vec<Digest>> {
+        None
+    }
+}
+
+/// Hashes pages with the raw SHA-256 compression function.
+///
+/// This is the default, and matches the page hashing of the RISC Zero circuit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256PageHasher;
+
+impl PageHasher for Sha256PageHasher {
+    fn name(&self) -> &'static str {
+        "sha-256"
+    }
+
+    fn hash_page(&self, data: &[u8]) -> Digest {
+        sha_compress_raw(data)
+    }
+}
+
+/// Look up one of the built-in hash functions by its [PageHasher::name].
+pub fn page_hasher_by_name(name: &str) -> Option<Arc<dyn PageHasher>> {
+    match name {
+        "sha-256" => Some(Arc::new(Sha256PageHasher)),
+        #[cfg(feature = "blake3")]
+        "blake3" => Some(Arc::new(Blake3PageHasher)),
+        #[cfg(feature = "cuda")]
+        "sha-256-cuda" => Some(Arc::new(CudaPageHasher::new())),
+        #[cfg(feature = "poseidon")]
+        "poseidon-pasta" => Some(Arc::new(poseidon::PoseidonPageHasher::default())),
+        _ => None,
+    }
+}
+
+/// Hashes pages with BLAKE3.
+///
+/// This is much faster than SHA-256 on the host, but the circuit cannot verify
+/// it: images hashed this way can be checked for integrity, but not proven.
+/// Use it for development and for host-only execution.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3PageHasher;
+
+#[cfg(feature = "blake3")]
+impl PageHasher for Blake3PageHasher {
+    fn name(&self) -> &'static str {
+        "blake3"
+    }
+
+    fn hash_page(&self, data: &[u8]) -> Digest {
+        Digest::from_bytes(blake3::hash(data).as_bytes()).unwrap()
+    }
+}
+
+#[cfg(feature = "poseidon")]
+pub use self::poseidon::PoseidonPageHasher;
+#[cfg(feature = "cuda")]
+pub use super::cuda::CudaPageHasher;
+
+#[cfg(feature = "poseidon")]
+mod poseidon {
+    use generic_array::typenum::U2;
+    use neptune::{poseidon::PoseidonConstants, Poseidon};
+    use pasta_curves::{group::ff::PrimeField, Fp};
+
+    use super::PageHasher;
+    use crate::binfmt::digest::Digest;
+
+    /// The number of bytes packed into each field element; one less than the
+    /// size of the field so every chunk is canonical.
+    const CHUNK_BYTES: usize = 31;
+
+    /// Hashes pages with Poseidon over the Pallas base field.
+    ///
+    /// Each page is packed into 31-byte field elements which are absorbed one
+    /// at a time with a 2-to-1 Poseidon instance. This is far cheaper than
+    /// SHA-256 to verify inside a folding circuit over the Pasta cycle.
+    pub struct PoseidonPageHasher {
+        constants: PoseidonConstants<Fp, U2>,
+    }
+
+    impl Default for PoseidonPageHasher {
+        fn default() -> Self {
+            Self {
+                constants: PoseidonConstants::new(),
+            }
+        }
+    }
+
+    impl PageHasher for PoseidonPageHasher {
+        fn name(&self) -> &'static str {
+            "poseidon-pasta"
+        }
+
+        fn hash_page(&self, data: &[u8]) -> Digest {
+            let acc = data.chunks(CHUNK_BYTES).fold(Fp::zero(), |acc, chunk| {
+                let mut repr = [0; 32];
+                repr[..chunk.len()].copy_from_slice(chunk);
+                let elem = Fp::from_repr(repr).unwrap();
+                Poseidon::new_with_preimage(&[acc, elem], &self.constants).hash()
+            });
+            Digest::from_bytes(&acc.to_repr()).unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::page_hasher_by_name;
+
+    #[test]
+    fn lookup_by_name() {
+        let sha = page_hasher_by_name("sha-256").unwrap();
+        assert_eq!(sha.name(), "sha-256");
+        assert!(page_hasher_by_name("md5").is_none());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3_image() {
+        use std::sync::Arc;
+
+        use super::Blake3PageHasher;
+        use crate::{
+            testutil::{program, RET},
+            MemoryImage, PAGE_SIZE,
+        };
+
+        let program = program(&[RET]);
+        let mut sha = MemoryImage::new(&program, PAGE_SIZE as u64, None);
+        let mut blake3 =
+            MemoryImage::with_hasher(&program, PAGE_SIZE as u64, None, Arc::new(Blake3PageHasher));
+        assert_ne!(sha.get_root(), blake3.get_root());
+        assert!(blake3.verify_integrity().is_ok());
+        assert_eq!(page_hasher_by_name("blake3").unwrap().name(), "blake3");
+    }
+
+    #[cfg(feature = "cuda")]
+    #[test]
+    fn cuda_matches_sha() {
+        use super::{CudaPageHasher, PageHasher, Sha256PageHasher};
+        use crate::PAGE_SIZE;
+
+        let pages: Vec<Vec<u8>> = (0..256u32)
+            .map(|i| (0..PAGE_SIZE as u32).map(|j| (i ^ j) as u8).collect())
+            .collect();
+        let pages: Vec<&[u8]> = pages.iter().map(|page| page.as_slice()).collect();
+        let cuda = CudaPageHasher::new();
+        let expected: Vec<_> = pages
+            .iter()
+            .map(|page| Sha256PageHasher.hash_page(page))
+            .collect();
+        match cuda.hash_batch(&pages) {
+            Some(digests) => assert_eq!(digests, expected),
+            None => assert!(!cuda.is_available()),
+        }
+    }
+
+    #[cfg(feature = "poseidon")]
+    #[test]
+    fn poseidon_differs_from_sha() {
+        use crate::PAGE_SIZE;
+
+        let sha = page_hasher_by_name("sha-256").unwrap();
+        let poseidon = page_hasher_by_name("poseidon-pasta").unwrap();
+        let mut page = [0; PAGE_SIZE];
+        page[7] = 1;
+        assert_ne!(sha.hash_page(&page), poseidon.hash_page(&page));
+        assert_eq!(poseidon.hash_page(&page), poseidon.hash_page(&page));
+    }
+}
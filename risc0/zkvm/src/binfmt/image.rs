@@ -11,13 +11,31 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use risc0_zkvm_platform::memory::{MEM_SIZE, PAGE_TABLE};
-use rrs_lib::{
-    memories::{MemorySpace, VecMemory},
-    MemAccessSize, Memory,
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem::take,
+    sync::Arc,
 };
 
-use crate::binfmt::elf::Program;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use risc0_zkvm_platform::{
+    memory::{HEAP_INFO, MEM_SIZE, PAGE_TABLE},
+    PAGE_SIZE,
+};
+use rrs_lib::{memories::MemorySpace, MemAccessSize, Memory};
+
+use crate::binfmt::{
+    builder::MemoryImageBuilder,
+    digest::{Digest, DIGEST_BYTES},
+    elf::{Program, ProgramSegment, SegmentFlags},
+    hash::{PageHasher, Sha256PageHasher},
+    paged::{MemoryBackend, PagedMemory},
+    region::{HeapRegion, RegionInfo},
+    store::PageStore,
+};
+
+/// Index of the main guest memory within the [MemorySpace].
+const MAIN_MEMORY: usize = 0;
 
 /// Compute `ceil(a / b)` via truncated integer division.
 const fn div_ceil(a: u64, b: u64) -> u64 {
@@ -29,6 +47,180 @@ const fn round_up(a: u64, b: u64) -> u64 {
     div_ceil(a, b) * b
 }
 
+/// A single layer of the page table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageTableLayer {
+    /// Address of the first entry of this layer
+    pub addr: u64,
+    /// Number of entries in this layer
+    pub num_entries: u64,
+}
+
+impl PageTableLayer {
+    /// Returns the number of bytes occupied by the entries of this layer.
+    pub fn size(&self) -> u64 {
+        self.num_entries * DIGEST_BYTES as u64
+    }
+
+    /// Returns the range of page indices holding this layer.
+    pub fn page_indices(&self) -> core::ops::Range<u64> {
+        let first = self.addr / PAGE_SIZE as u64;
+        first..first + div_ceil(self.size(), PAGE_SIZE as u64)
+    }
+}
+
+/// Layout of the page table Merkle tree within guest memory
+///
+/// Every page of guest memory, except the pages holding the table itself, has
+/// a digest entry in layer 0. Each further layer holds one entry for every page
+/// of the layer below it, until a layer fits within a single page: that page is
+/// the root page, and its digest is the image root.
+#[derive(Clone, Debug)]
+pub struct PageTableInfo {
+    /// Address of the first page table entry
+    pub page_table_addr: u64,
+    /// Number of bytes reserved for the page table, including the root page
+    page_table_size: u64,
+    /// Address of the root page
+    pub root_addr: u64,
+    /// Page index of the root page
+    pub root_idx: u64,
+    /// Number of entries held by the root page
+    pub num_root_entries: u64,
+    /// The layers of the table, from the data page digests up to the root
+    layers: Vec<PageTableLayer>,
+}
+
+impl PageTableInfo {
+    /// Compute the page table layout for `mem_size` bytes of memory with the
+    /// table located at `page_table_addr`.
+    pub fn new(page_table_addr: u64, mem_size: u64) -> Self {
+        match Self::try_new(page_table_addr, mem_size) {
+            Ok(info) => info,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Like [PageTableInfo::new], but returns an error rather than panicking
+    /// if the table is unaligned or does not fit in memory, for layouts read
+    /// from outside the program.
+    pub fn try_new(page_table_addr: u64, mem_size: u64) -> Result<Self> {
+        ensure!(
+            page_table_addr % PAGE_SIZE as u64 == 0,
+            "unaligned page table"
+        );
+        ensure!(
+            mem_size != 0 && mem_size % PAGE_SIZE as u64 == 0,
+            "guest memory size 0x{mem_size:x} is not a multiple of the page size"
+        );
+        let mut layers = Vec::new();
+        let mut offset = 0;
+        let mut num_entries = mem_size / PAGE_SIZE as u64;
+        loop {
+            let addr = page_table_addr
+                .checked_add(offset)
+                .context("page table does not fit in memory")?;
+            layers.push(PageTableLayer { addr, num_entries });
+            let layer_size = num_entries * DIGEST_BYTES as u64;
+            if layer_size <= PAGE_SIZE as u64 {
+                break;
+            }
+            offset += round_up(layer_size, PAGE_SIZE as u64);
+            num_entries = div_ceil(layer_size, PAGE_SIZE as u64);
+        }
+        let root_addr = page_table_addr + offset;
+        let page_table_size = offset + PAGE_SIZE as u64;
+        ensure!(
+            page_table_addr
+                .checked_add(page_table_size)
+                .map_or(false, |end| end <= mem_size),
+            "page table does not fit in memory"
+        );
+        Ok(Self {
+            page_table_addr,
+            page_table_size,
+            root_addr,
+            root_idx: root_addr / PAGE_SIZE as u64,
+            num_root_entries: num_entries,
+            layers,
+        })
+    }
+
+    /// Compute the page table layout for a guest memory of `mem_size` bytes.
+    ///
+    /// For the platform memory size the table is placed at [PAGE_TABLE], as
+    /// the guest expects. For any other size it occupies the top of memory, so
+    /// that the guest's code, data and stack layout is unaffected.
+    pub fn for_mem_size(mem_size: u64) -> Self {
+        if mem_size == MEM_SIZE as u64 {
+            return Self::new(PAGE_TABLE.start() as u64, mem_size);
+        }
+        let page_table_size = Self::new(0, mem_size).page_table_size;
+        Self::new(mem_size - page_table_size, mem_size)
+    }
+
+    /// Returns the index of the page containing `addr`.
+    pub fn get_page_index(&self, addr: u64) -> u64 {
+        addr / PAGE_SIZE as u64
+    }
+
+    /// Returns the address of the first byte of the page at `page_idx`.
+    pub fn get_page_addr(&self, page_idx: u64) -> u64 {
+        page_idx * PAGE_SIZE as u64
+    }
+
+    /// Returns whether the page at `page_idx` holds part of the page table.
+    pub fn is_table_page(&self, page_idx: u64) -> bool {
+        let first = self.page_table_addr / PAGE_SIZE as u64;
+        let last = self.root_idx;
+        page_idx >= first && page_idx <= last
+    }
+
+    /// Returns the number of bytes reserved for the page table, including the
+    /// root page.
+    pub fn page_table_size(&self) -> u64 {
+        self.page_table_size
+    }
+
+    /// Returns the layers of the table, starting with the layer committing to
+    /// the data pages and ending with the root layer.
+    pub fn layers(&self) -> &[PageTableLayer] {
+        &self.layers
+    }
+
+    /// Returns the number of layers in the table.
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Returns the address of the entry in `layer` which commits to the page at
+    /// `page_idx`.
+    ///
+    /// For layer 0 this is any page of guest memory; for higher layers it must
+    /// be a page of the layer directly below.
+    pub fn get_entry_addr(&self, layer: usize, page_idx: u64) -> u64 {
+        let rel_idx = if layer == 0 {
+            page_idx
+        } else {
+            page_idx - self.layers[layer - 1].addr / PAGE_SIZE as u64
+        };
+        self.layers[layer].addr + rel_idx * DIGEST_BYTES as u64
+    }
+
+    /// Returns the address of the entry committing to `page_idx` at every
+    /// layer, i.e. the path from the page up to the root page.
+    pub fn get_entry_path(&self, page_idx: u64) -> Vec<u64> {
+        let mut page_idx = page_idx;
+        (0..self.num_layers())
+            .map(|layer| {
+                let entry_addr = self.get_entry_addr(layer, page_idx);
+                page_idx = self.get_page_index(entry_addr);
+                entry_addr
+            })
+            .collect()
+    }
+}
+
 /// An image of a zkVM guest's memory
 ///
 /// This is an image of the full memory state of the zkVM, including the data,
@@ -39,7 +231,39 @@ pub struct MemoryImage {
     // pub buf: Vec<u8>,
 
     /// memorySpace to support memory segment across different region
+    ///
+    /// The main guest memory is backed by a [PagedMemory], so pages are only
+    /// allocated once the guest (or the ELF loader) writes to them.
     pub memory_space: MemorySpace,
+
+    /// Metadata about the structure of the page table
+    pub info: PageTableInfo,
+
+    /// The hash function used to build the page table
+    hasher: Arc<dyn PageHasher>,
+
+    /// Pages written since the page table was last updated
+    dirty_pages: BTreeSet<u64>,
+
+    /// The regions mapped into `memory_space`, starting with main memory
+    regions: Vec<RegionInfo>,
+
+    /// The loadable segments of the program this image was built from
+    segments: Vec<ProgramSegment>,
+
+    /// The access permissions of every page covered by `segments`
+    page_permissions: BTreeMap<u64, SegmentFlags>,
+
+    /// The digest of the root page, if it has not changed since it was last
+    /// computed
+    cached_root: Option<Digest>,
+
+    /// The thread pool pages are hashed on, or the global pool if unset
+    #[cfg(feature = "parallel")]
+    hash_pool: Option<Arc<rayon::ThreadPool>>,
+
+    /// Pages shared with other images, whose digests need not be recomputed
+    page_store: Option<Arc<PageStore>>,
 }
 
 impl MemoryImage {
@@ -49,54 +273,794 @@ impl MemoryImage {
     /// execution not yet begun), and with the page table Merkle tree
     /// constructed.
     pub fn new(program: &Program, page_size: u64, memory_data: Option<Vec<u8>>) -> Self {
-        // let mut buf = vec![0_u8; MEM_SIZE];
+        Self::with_hasher(program, page_size, memory_data, Arc::new(Sha256PageHasher))
+    }
+
+    /// Construct the initial memory image for `program`, building the page
+    /// table with `hasher` instead of SHA-256.
+    pub fn with_hasher(
+        program: &Program,
+        page_size: u64,
+        memory_data: Option<Vec<u8>>,
+        hasher: Arc<dyn PageHasher>,
+    ) -> Self {
+        assert_eq!(page_size, PAGE_SIZE as u64, "unsupported page size");
+        Self::builder_for(program, memory_data)
+            .hasher(hasher)
+            .build()
+            .unwrap()
+    }
+
+    /// Construct the initial memory image for `program`, keeping the main
+    /// guest memory in `backend`.
+    ///
+    /// Use [MemoryBackend::Mmap] (with the `mmap` feature) for guests whose
+    /// memory would not comfortably fit in host RAM.
+    pub fn with_backend(
+        program: &Program,
+        page_size: u64,
+        memory_data: Option<Vec<u8>>,
+        hasher: Arc<dyn PageHasher>,
+        backend: &MemoryBackend,
+    ) -> Result<Self> {
+        assert_eq!(page_size, PAGE_SIZE as u64, "unsupported page size");
+        Self::builder_for(program, memory_data)
+            .hasher(hasher)
+            .backend(backend.clone())
+            .build()
+    }
+
+    /// Construct the initial memory image for `program` with `mem_size` bytes
+    /// of guest memory instead of the platform default [MEM_SIZE].
+    ///
+    /// `mem_size` must be a multiple of the page size, and large enough to hold
+    /// the program along with its page table.
+    pub fn with_mem_size(
+        program: &Program,
+        page_size: u64,
+        memory_data: Option<Vec<u8>>,
+        mem_size: u64,
+    ) -> Result<Self> {
+        assert_eq!(page_size, PAGE_SIZE as u64, "unsupported page size");
+        Self::builder_for(program, memory_data)
+            .mem_size(mem_size)
+            .build()
+    }
+
+    /// Construct a [MemoryImageBuilder].
+    pub fn builder() -> MemoryImageBuilder {
+        MemoryImageBuilder::new()
+    }
+
+    fn builder_for(program: &Program, memory_data: Option<Vec<u8>>) -> MemoryImageBuilder {
+        let mut builder = MemoryImageBuilder::new();
+        builder.program(program);
+        if let Some(memory_data) = memory_data {
+            builder.memory_data(memory_data);
+        }
+        builder
+    }
 
+    /// Construct an image around an already populated main memory.
+    ///
+    /// The page table held by `memory` is taken as-is and not rehashed.
+    pub(crate) fn from_memory(
+        memory: PagedMemory,
+        info: PageTableInfo,
+        hasher: Arc<dyn PageHasher>,
+    ) -> Self {
+        let main = RegionInfo {
+            name: "main".to_string(),
+            base: 0,
+            size: memory.size(),
+            committed: true,
+        };
         let mut memory_space = MemorySpace::new();
         let _ = memory_space
-            .add_memory(
-                0,
-                MEM_SIZE as u64,
-                Box::new(VecMemory::new(vec![0_u64; MEM_SIZE / 8])),
-            )
+            .add_memory(main.base, main.size, Box::new(memory))
             .unwrap();
-        // Load the ELF into the memory image.
-        let program_region = memory_space.get_memory_mut::<VecMemory>(0).unwrap();
-        for (addr, data) in program.image.iter() {
-            program_region.write_mem(*addr, MemAccessSize::Word, u64::from(*data));
-        }
+        let mut image = Self {
+            memory_space,
+            info,
+            hasher,
+            dirty_pages: BTreeSet::new(),
+            regions: vec![main],
+            segments: Vec::new(),
+            page_permissions: BTreeMap::new(),
+            cached_root: None,
+            #[cfg(feature = "parallel")]
+            hash_pool: None,
+            page_store: None,
+        };
         // add memory region `0xd0000deadbeef` as playground
-        let _ = memory_space
-            .add_memory(
+        image
+            .add_region(
+                "playground",
                 0xd0000deadbee0,
                 MEM_SIZE as u64,
-                Box::new(VecMemory::new(vec![0_u64; MEM_SIZE / 8])),
+                Box::new(PagedMemory::new(MEM_SIZE as u64)),
             )
             .unwrap();
+        image
+    }
 
-        // Load the ELF into the memory image.
-        let _ = memory_data.map(|memory_data| {
-            println!("first length {:?}", memory_data[..4].to_vec());
-            let memory_data = memory_data
-                .chunks(8)
-                .map(|chunk| {
-                    let mut chunk = chunk.to_vec();
-                    if chunk.len() < 8 {
-                        chunk.resize(8, 0)
-                    }
-                    u64::from_le_bytes(chunk.try_into().unwrap())
-                })
-                .collect::<Vec<u64>>();
-            // memory data space
-            let _ = memory_space
-                .add_memory(
-                    0x100000000000,
-                    memory_data.len() as u64 * 8 as u64,
-                    Box::new(VecMemory::new(memory_data)),
-                )
-                .unwrap();
+    /// Map an additional region of `size` bytes at `base`, backed by `memory`.
+    ///
+    /// The region is not committed to by the page table, so writes to it do
+    /// not affect the image root. This can be used for device MMIO windows
+    /// (see [MmioMemory](super::region::MmioMemory)), read-only data (see
+    /// [ReadOnlyMemory](super::region::ReadOnlyMemory)), or buffers shared
+    /// with the host.
+    pub fn add_region(
+        &mut self,
+        name: &str,
+        base: u64,
+        size: u64,
+        memory: Box<dyn Memory>,
+    ) -> Result<()> {
+        self.memory_space
+            .add_memory(base, size, memory)
+            .map_err(|err| anyhow!("failed to map region {name} at 0x{base:x}: {err:?}"))?;
+        self.regions.push(RegionInfo {
+            name: name.to_string(),
+            base,
+            size,
+            committed: false,
         });
+        Ok(())
+    }
+
+    /// Unmap the region starting at `base`.
+    ///
+    /// The main guest memory cannot be unmapped.
+    pub fn remove_region(&mut self, base: u64) -> Result<RegionInfo> {
+        let idx = self
+            .regions
+            .iter()
+            .position(|region| region.base == base)
+            .ok_or_else(|| anyhow!("no region mapped at 0x{base:x}"))?;
+        if self.regions[idx].committed {
+            bail!("cannot unmap committed region {}", self.regions[idx].name);
+        }
+        self.memory_space
+            .remove_memory_by_base(base)
+            .map_err(|err| anyhow!("failed to unmap region at 0x{base:x}: {err:?}"))?;
+        Ok(self.regions.remove(idx))
+    }
+
+    /// Returns a copy of this image which shares all pages with the original.
+    ///
+    /// Pages are only duplicated once either image writes to them, so this is
+    /// cheap regardless of how much memory is in use. This allows several
+    /// executors to run from the same starting state, e.g. to re-execute a
+    /// segment while generating its witness.
+    ///
+    /// Regions backed by a [PagedMemory] are carried over in the same way.
+    /// Other regions, such as MMIO windows, are bound to their host callbacks
+    /// and are not present in the copy; map them again with
+    /// [MemoryImage::add_region] if needed.
+    pub fn cow_clone(&self) -> Self {
+        let mut image = Self {
+            memory_space: MemorySpace::new(),
+            info: self.info.clone(),
+            hasher: self.hasher.clone(),
+            dirty_pages: self.dirty_pages.clone(),
+            regions: Vec::new(),
+            segments: self.segments.clone(),
+            page_permissions: self.page_permissions.clone(),
+            cached_root: self.cached_root,
+            #[cfg(feature = "parallel")]
+            hash_pool: self.hash_pool.clone(),
+            page_store: self.page_store.clone(),
+        };
+        for (idx, region) in self.regions.iter().enumerate() {
+            if let Some(memory) = self.memory_space.get_memory_ref::<PagedMemory>(idx) {
+                let _ = image
+                    .memory_space
+                    .add_memory(region.base, region.size, Box::new(memory.clone()))
+                    .unwrap();
+                image.regions.push(region.clone());
+            }
+        }
+        image
+    }
+
+    /// Returns the regions registered with this image, starting with the
+    /// main guest memory.
+    pub fn regions(&self) -> &[RegionInfo] {
+        &self.regions
+    }
+
+    /// Returns the loadable segments of the program this image was built
+    /// from.
+    ///
+    /// This is empty for images that were not built from a [Program], e.g.
+    /// ones loaded from disk.
+    pub fn segments(&self) -> &[ProgramSegment] {
+        &self.segments
+    }
+
+    /// Returns the access permissions of the segment containing `addr`, if
+    /// any.
+    pub fn segment_flags(&self, addr: u64) -> Option<SegmentFlags> {
+        self.segments
+            .iter()
+            .find(|segment| segment.contains(addr))
+            .map(|segment| segment.flags)
+    }
+
+    /// Returns the access permissions of the page containing `addr`.
+    ///
+    /// A page covered by loadable segments takes the union of their flags.
+    /// Every other page, e.g. the stack, heap and system memory, may be read
+    /// and written but not executed. Images without segments place no
+    /// restrictions on any page.
+    pub fn page_permissions(&self, addr: u64) -> SegmentFlags {
+        if self.segments.is_empty() {
+            return SegmentFlags {
+                read: true,
+                write: true,
+                execute: true,
+            };
+        }
+        let page_idx = self.info.get_page_index(addr);
+        self.page_permissions
+            .get(&page_idx)
+            .copied()
+            .unwrap_or(SegmentFlags {
+                read: true,
+                write: true,
+                execute: false,
+            })
+    }
+
+    /// Returns the range of addresses the guest heap is allocated from.
+    ///
+    /// This is read back from [HEAP_INFO], where the guest finds it too. Images
+    /// which predate it fall back to [HeapRegion::default_for] their memory
+    /// size.
+    pub fn heap(&self) -> HeapRegion {
+        let load = |addr: u64| {
+            let mut bytes = [0; 8];
+            if let Some(page) = self.main_memory().page(self.info.get_page_index(addr)) {
+                let offset = (addr % PAGE_SIZE as u64) as usize;
+                bytes.copy_from_slice(&page[offset..offset + 8]);
+            }
+            u64::from_le_bytes(bytes)
+        };
+        let start = load(HEAP_INFO.start() as u64);
+        let size = load(HEAP_INFO.start() as u64 + 8);
+        if start == 0 && size == 0 {
+            return HeapRegion::default_for(self.main_memory().size());
+        }
+        HeapRegion { start, size }
+    }
+
+    /// Returns whether `addr` lies in memory committed to by the page table.
+    pub fn is_committed(&self, addr: u64) -> bool {
+        self.regions
+            .iter()
+            .any(|region| region.committed && region.contains(addr))
+    }
+
+    /// Returns the main guest memory.
+    pub(crate) fn main_memory(&self) -> &PagedMemory {
+        self.memory_space
+            .get_memory_ref::<PagedMemory>(MAIN_MEMORY)
+            .unwrap()
+    }
+
+    /// Returns the main guest memory for modification.
+    ///
+    /// Changes made through this are not tracked as dirty pages, but the cached
+    /// root is discarded since the page table may change.
+    pub(crate) fn main_memory_mut(&mut self) -> &mut PagedMemory {
+        self.cached_root = None;
+        self.memory_space
+            .get_memory_mut::<PagedMemory>(MAIN_MEMORY)
+            .unwrap()
+    }
+
+    /// Write `data` to guest memory, tracking the modified page.
+    ///
+    /// Returns false if `addr` is not mapped.
+    pub fn write_mem(&mut self, addr: u64, size: MemAccessSize, data: u64) -> bool {
+        if self.is_committed(addr) {
+            self.mark_dirty(addr, size);
+            if self.info.is_table_page(self.info.get_page_index(addr)) {
+                self.cached_root = None;
+            }
+        }
+        self.memory_space.write_mem(addr, size, data)
+    }
+
+    /// Read the word at `addr`, which must be 4-byte aligned.
+    pub fn read_u32(&mut self, addr: u64) -> Result<u32> {
+        ensure!(addr % 4 == 0, "unaligned word read at 0x{addr:x}");
+        self.read(addr, MemAccessSize::Word).map(|word| word as u32)
+    }
+
+    /// Write the word at `addr`, which must be 4-byte aligned.
+    pub fn write_u32(&mut self, addr: u64, value: u32) -> Result<()> {
+        ensure!(addr % 4 == 0, "unaligned word write at 0x{addr:x}");
+        self.write(addr, MemAccessSize::Word, value as u64)
+    }
+
+    /// Read the double word at `addr`, which must be 8-byte aligned.
+    pub fn read_u64(&mut self, addr: u64) -> Result<u64> {
+        ensure!(addr % 8 == 0, "unaligned double word read at 0x{addr:x}");
+        self.read(addr, MemAccessSize::DoubleWord)
+    }
+
+    /// Write the double word at `addr`, which must be 8-byte aligned.
+    pub fn write_u64(&mut self, addr: u64, value: u64) -> Result<()> {
+        ensure!(addr % 8 == 0, "unaligned double word write at 0x{addr:x}");
+        self.write(addr, MemAccessSize::DoubleWord, value)
+    }
+
+    /// Read `len` bytes starting at `addr`.
+    ///
+    /// The range may be unaligned, but must be mapped in its entirety.
+    pub fn read_region(&mut self, addr: u64, len: u64) -> Result<Vec<u8>> {
+        let end = addr
+            .checked_add(len)
+            .ok_or_else(|| anyhow!("region at 0x{addr:x} overflows"))?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        let mut addr = addr;
+        while addr < end {
+            if addr % 8 == 0 && end - addr >= 8 {
+                let dword = self.read(addr, MemAccessSize::DoubleWord)?;
+                bytes.extend_from_slice(&dword.to_le_bytes());
+                addr += 8;
+            } else {
+                bytes.push(self.read(addr, MemAccessSize::Byte)? as u8);
+                addr += 1;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Copy `data` into guest memory starting at `addr`, tracking the modified
+    /// pages.
+    ///
+    /// The range may be unaligned. On error, a prefix of `data` may already
+    /// have been written.
+    pub fn write_region(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        addr.checked_add(data.len() as u64)
+            .ok_or_else(|| anyhow!("region at 0x{addr:x} overflows"))?;
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            if addr % 8 == 0 && data.len() >= 8 {
+                let dword = u64::from_le_bytes(data[..8].try_into().unwrap());
+                self.write(addr, MemAccessSize::DoubleWord, dword)?;
+                addr += 8;
+                data = &data[8..];
+            } else {
+                self.write(addr, MemAccessSize::Byte, data[0] as u64)?;
+                addr += 1;
+                data = &data[1..];
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, addr: u64, size: MemAccessSize) -> Result<u64> {
+        self.memory_space
+            .read_mem(addr, size)
+            .ok_or_else(|| anyhow!("read of unmapped address 0x{addr:x}"))
+    }
+
+    fn write(&mut self, addr: u64, size: MemAccessSize, value: u64) -> Result<()> {
+        ensure!(
+            self.write_mem(addr, size, value),
+            "write to unmapped or read-only address 0x{addr:x}"
+        );
+        Ok(())
+    }
+
+    /// Mark the page(s) covered by an access at `addr` as modified.
+    pub fn mark_dirty(&mut self, addr: u64, size: MemAccessSize) {
+        let len = match size {
+            MemAccessSize::Byte => 1,
+            MemAccessSize::HalfWord => 2,
+            MemAccessSize::Word => 4,
+            MemAccessSize::DoubleWord => 8,
+        };
+        let first = self.info.get_page_index(addr);
+        let last = self.info.get_page_index(addr + len - 1);
+        self.dirty_pages.extend(first..=last);
+    }
+
+    /// Returns the pages modified since the page table was last updated.
+    pub fn dirty_pages(&self) -> &BTreeSet<u64> {
+        &self.dirty_pages
+    }
+
+    pub(crate) fn set_dirty_pages(&mut self, dirty_pages: BTreeSet<u64>) {
+        self.dirty_pages = dirty_pages;
+    }
+
+    pub(crate) fn set_segments(&mut self, segments: Vec<ProgramSegment>) {
+        self.page_permissions.clear();
+        for segment in segments.iter().filter(|segment| segment.mem_size > 0) {
+            let first = self.info.get_page_index(segment.vaddr);
+            let last = self
+                .info
+                .get_page_index(segment.vaddr + segment.mem_size - 1);
+            for page_idx in first..=last {
+                let flags = self.page_permissions.entry(page_idx).or_default();
+                flags.read |= segment.flags.read;
+                flags.write |= segment.flags.write;
+                flags.execute |= segment.flags.execute;
+            }
+        }
+        self.segments = segments;
+    }
+
+    /// Compute and store the hashes of every page, rebuilding the full page
+    /// table.
+    pub fn hash_pages(&mut self) {
+        let num_pages = self.info.get_page_index(self.main_memory().size());
+        self.dirty_pages.clear();
+        self.rehash((0..num_pages).collect(), true);
+    }
+
+    /// Rehash the pages modified since the last update, along with the page
+    /// table entries above them.
+    pub fn update_page_table(&mut self) {
+        let dirty = take(&mut self.dirty_pages);
+        self.rehash(dirty, false);
+    }
+
+    /// Rehash the data pages in `dirty`, then every table page whose entries
+    /// changed as a result. With `full`, every table page is rehashed instead,
+    /// including those which only commit to other table pages.
+    fn rehash(&mut self, dirty: BTreeSet<u64>, full: bool) {
+        let zero_page = self.hasher.hash_page(&[0; PAGE_SIZE]);
+        let mut dirty: BTreeSet<u64> = dirty
+            .into_iter()
+            .filter(|page_idx| !self.info.is_table_page(*page_idx))
+            .collect();
+        for layer in 0..self.info.num_layers() {
+            if full && layer > 0 {
+                dirty = self.info.layers()[layer - 1].page_indices().collect();
+            }
+            let page_idxs: Vec<u64> = dirty.into_iter().collect();
+            let digests = self.hash_many(&page_idxs, zero_page);
+            let mut parents = BTreeSet::new();
+            for (page_idx, digest) in page_idxs.into_iter().zip(digests) {
+                let entry_addr = self.info.get_entry_addr(layer, page_idx);
+                self.store_digest(entry_addr, &digest);
+                parents.insert(self.info.get_page_index(entry_addr));
+            }
+            dirty = parents;
+        }
+    }
+
+    /// Returns the root digest of the page table.
+    ///
+    /// The digest is cached until the page table is next modified. Pending
+    /// page writes are not reflected until [MemoryImage::update_page_table]
+    /// is called.
+    pub fn get_root(&mut self) -> Digest {
+        if let Some(root) = self.cached_root {
+            return root;
+        }
+        let root_page = self.load_root_page();
+        let root = self.hasher.hash_page(&root_page);
+        self.cached_root = Some(root);
+        root
+    }
+
+    /// Load the populated prefix of the root page, padded to a whole number of
+    /// hash blocks.
+    pub(crate) fn load_root_page(&mut self) -> Vec<u8> {
+        let len = round_up(
+            self.info.num_root_entries * DIGEST_BYTES as u64,
+            (DIGEST_BYTES * 2) as u64,
+        );
+        self.load_bytes(self.info.root_addr, len)
+    }
+
+    /// Returns the hash function used to build the page table.
+    pub fn hasher(&self) -> &Arc<dyn PageHasher> {
+        &self.hasher
+    }
+
+    /// Hash pages on a dedicated pool of `num_threads` threads instead of
+    /// the global rayon pool.
+    #[cfg(feature = "parallel")]
+    pub fn set_hash_threads(&mut self, num_threads: usize) -> Result<()> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()?;
+        self.hash_pool = Some(Arc::new(pool));
+        Ok(())
+    }
+
+    /// Share the pages of main memory through `store`, reusing the stored
+    /// digest of any page found there when the page table is next rehashed.
+    pub fn set_page_store(&mut self, store: Arc<PageStore>) -> Result<()> {
+        ensure!(
+            store.hasher().name() == self.hasher.name(),
+            "page store uses {}, but the image uses {}",
+            store.hasher().name(),
+            self.hasher.name()
+        );
+        self.main_memory_mut().intern_pages(&store);
+        self.page_store = Some(store);
+        Ok(())
+    }
+
+    /// Returns the page store shared with other images, if any.
+    pub fn page_store(&self) -> Option<&Arc<PageStore>> {
+        self.page_store.as_ref()
+    }
+
+    /// Hash the pages of main memory at `page_idxs`, with the hasher's batched
+    /// implementation if it has one, or in parallel when the `parallel`
+    /// feature is enabled.
+    fn hash_many(&self, page_idxs: &[u64], zero_page: Digest) -> Vec<Digest> {
+        let memory = self.main_memory();
+        let hasher = self.hasher.as_ref();
+        let pages: Vec<&[u8]> = page_idxs
+            .iter()
+            .filter_map(|page_idx| memory.page(*page_idx))
+            .map(|page| page.as_slice())
+            .collect();
+        if let Some(digests) = hasher.hash_batch(&pages) {
+            let mut digests = digests.into_iter();
+            return page_idxs
+                .iter()
+                .map(|page_idx| match memory.page(*page_idx) {
+                    Some(_) => digests.next().unwrap(),
+                    None => zero_page,
+                })
+                .collect();
+        }
+        let store = self.page_store.as_deref();
+        let hash = |page_idx: &u64| {
+            memory.page(*page_idx).map_or(zero_page, |page| {
+                store
+                    .and_then(|store| store.digest(page))
+                    .unwrap_or_else(|| hasher.hash_page(page))
+            })
+        };
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            let run = || page_idxs.par_iter().map(hash).collect();
+            match self.hash_pool.as_ref() {
+                Some(pool) => pool.install(run),
+                None => run(),
+            }
+        }
+        #[cfg(not(feature = "parallel"))]
+        page_idxs.iter().map(hash).collect()
+    }
+
+    pub(crate) fn load_bytes(&mut self, addr: u64, len: u64) -> Vec<u8> {
+        (addr..addr + len)
+            .step_by(8)
+            .flat_map(|addr| {
+                self.memory_space
+                    .read_mem(addr, MemAccessSize::DoubleWord)
+                    .unwrap()
+                    .to_le_bytes()
+            })
+            .collect()
+    }
+
+    fn store_digest(&mut self, addr: u64, digest: &Digest) {
+        self.cached_root = None;
+        for (i, chunk) in digest.as_bytes().chunks_exact(8).enumerate() {
+            let dword = u64::from_le_bytes(chunk.try_into().unwrap());
+            self.memory_space
+                .write_mem(addr + i as u64 * 8, MemAccessSize::DoubleWord, dword);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use risc0_zkvm_platform::{memory::MEM_SIZE, PAGE_SIZE};
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use super::MemoryImage;
+    use crate::{
+        testutil::{image, RET},
+        PagedMemory, Program, ProgramSegment, SegmentFlags,
+    };
+
+    #[test]
+    fn page_table_layout() {
+        let image = image(&[RET], &[]);
+        let info = &image.info;
+        assert_eq!(info.num_root_entries, 8);
+        assert!(info.is_table_page(info.root_idx));
+        assert!(!info.is_table_page(info.get_page_index(0x1000)));
+        assert!(info.root_addr + PAGE_SIZE as u64 <= MEM_SIZE as u64);
+
+        let layers = info.layers();
+        assert_eq!(layers.len(), 4);
+        assert_eq!(layers[0].num_entries, MEM_SIZE as u64 / PAGE_SIZE as u64);
+        assert_eq!(layers[3].addr, info.root_addr);
+        assert_eq!(
+            info.page_table_addr + info.page_table_size(),
+            info.root_addr + PAGE_SIZE as u64
+        );
+
+        let path = info.get_entry_path(info.get_page_index(0x1000));
+        assert_eq!(path.len(), layers.len());
+        for (layer, entry_addr) in layers.iter().zip(path) {
+            assert!(layer
+                .page_indices()
+                .contains(&info.get_page_index(entry_addr)));
+        }
+    }
+
+    #[test]
+    fn incremental_update() {
+        let mut image = image(&[RET], &[]);
+        let root = image.get_root();
+        assert!(image.dirty_pages().is_empty());
+
+        assert!(image.write_mem(0x2000, MemAccessSize::DoubleWord, 42));
+        assert_eq!(image.dirty_pages().len(), 1);
+        image.update_page_table();
+        let updated = image.get_root();
+        assert_ne!(root, updated);
+
+        image.hash_pages();
+        assert_eq!(image.get_root(), updated);
+    }
+
+    #[test]
+    fn cow_clone() {
+        let mut image = image(&[RET], &[]);
+        image
+            .add_region(
+                "mmap",
+                0x1000_0000_0000,
+                PAGE_SIZE as u64,
+                Box::new(PagedMemory::new(PAGE_SIZE as u64)),
+            )
+            .unwrap();
+        let root = image.get_root();
+        let shared = image.main_memory().num_resident_pages();
+
+        let mut clone = image.cow_clone();
+        assert_eq!(clone.regions(), image.regions());
+        assert_eq!(clone.get_root(), root);
+        assert_eq!(clone.main_memory().num_shared_pages(), shared);
+
+        assert!(clone.write_mem(0x1000, MemAccessSize::Word, 0x13));
+        clone.update_page_table();
+        assert_ne!(clone.get_root(), root);
+        assert_eq!(image.get_root(), root);
+        assert_eq!(
+            image.memory_space.read_mem(0x1000, MemAccessSize::Word),
+            Some(0x00008067)
+        );
+
+        assert!(clone.remove_region(0x1000_0000_0000).is_ok());
+        assert!(clone.remove_region(0).is_err());
+        assert_eq!(image.regions().len(), 3);
+    }
+
+    #[test]
+    fn large_memory() {
+        let program = Program {
+            entry: 0x1000,
+            image: BTreeMap::from([(0x1000, 0x00008067), (0x1800_0000, 1)]),
+            segments: Vec::new(),
+        };
+        let mem_size = 512 << 20;
+        let mut image =
+            MemoryImage::with_mem_size(&program, PAGE_SIZE as u64, None, mem_size).unwrap();
+        let info = &image.info;
+        assert_eq!(info.page_table_addr + info.page_table_size(), mem_size);
+        assert_eq!(info.layers()[0].num_entries, mem_size / PAGE_SIZE as u64);
+        assert!(!image.is_committed(mem_size));
+
+        let root = image.get_root();
+        assert!(image.write_mem(0x1800_0000, MemAccessSize::Word, 2));
+        image.update_page_table();
+        assert_ne!(image.get_root(), root);
+
+        assert!(
+            MemoryImage::with_mem_size(&program, PAGE_SIZE as u64, None, MEM_SIZE as u64).is_err()
+        );
+    }
+
+    #[test]
+    fn cached_root() {
+        let mut image = image(&[RET], &[]);
+        let checkpoint = image.snapshot();
+        let root = image.get_root();
+        assert_eq!(image.cached_root, Some(root));
+        assert_eq!(image.get_root(), root);
+
+        // Data writes only affect the root once the table is updated.
+        assert!(image.write_mem(0x3000, MemAccessSize::Word, 1));
+        assert_eq!(image.get_root(), root);
+        image.update_page_table();
+        assert_eq!(image.cached_root, None);
+        let updated = image.get_root();
+        assert_ne!(updated, root);
+
+        // Writing the root page directly invalidates the cache.
+        let root_addr = image.info.root_addr;
+        assert!(image.write_mem(root_addr, MemAccessSize::Word, 0));
+        assert_ne!(image.get_root(), updated);
+
+        image.restore(checkpoint);
+        assert_eq!(image.get_root(), root);
+    }
+
+    #[test]
+    fn word_helpers() {
+        let mut image = image(&[RET], &[]);
+        let root = image.get_root();
+        image.write_u64(0x4000, 0x0102030405060708).unwrap();
+        assert_eq!(image.read_u32(0x4004).unwrap(), 0x01020304);
+        assert!(image.read_u64(0x4004).is_err());
+        assert!(image.write_u32(0x4002, 0).is_err());
+
+        let data: Vec<u8> = (0..20).collect();
+        image.write_region(0x5003, &data).unwrap();
+        assert_eq!(image.read_region(0x5003, 20).unwrap(), data);
+        assert_eq!(image.read_region(0x5000, 4).unwrap(), [0, 0, 0, 0]);
+        assert_eq!(image.dirty_pages().len(), 2);
+
+        image.update_page_table();
+        assert_ne!(image.get_root(), root);
+        assert!(image.read_region(MEM_SIZE as u64 - 4, 8).is_err());
+    }
+
+    #[test]
+    fn page_permissions() {
+        let segment = |vaddr, mem_size, write, execute| ProgramSegment {
+            vaddr,
+            mem_size,
+            file_size: mem_size,
+            align: PAGE_SIZE as u64,
+            flags: SegmentFlags {
+                read: true,
+                write,
+                execute,
+            },
+        };
+        let program = Program {
+            entry: 0x1000,
+            image: BTreeMap::from([(0x1000, 0x00008067)]),
+            segments: vec![
+                segment(0x1000, 0x1000, false, true),
+                segment(0x2000, 0x800, false, false),
+                // Shares its first page with the read-only segment.
+                segment(0x2400, 0x1000, true, false),
+            ],
+        };
+        let image = MemoryImage::new(&program, PAGE_SIZE as u64, None);
+        let text = image.page_permissions(0x1ffc);
+        assert!(text.execute && !text.write);
+        assert!(!image.page_permissions(0x2000).write);
+        assert!(image.page_permissions(0x2400).write);
+        let stack = image.page_permissions(0x0800_0000);
+        assert!(stack.write && !stack.execute);
 
-        // Compute the page table hashes except for the very last root hash.
-        Self { memory_space }
+        let unrestricted = MemoryImage::new(
+            &Program {
+                segments: Vec::new(),
+                ..program
+            },
+            PAGE_SIZE as u64,
+            None,
+        );
+        assert!(unrestricted.page_permissions(0x2000).write);
     }
 }
@@ -0,0 +1,159 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use risc0_zkvm_platform::PAGE_SIZE;
+
+use super::{
+    digest::{Digest, DIGEST_BYTES},
+    image::MemoryImage,
+};
+
+/// A page table entry which does not match the page it commits to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryMismatch {
+    /// The layer holding the entry
+    pub layer: usize,
+
+    /// Index of the page the entry commits to
+    pub page_idx: u64,
+
+    /// Address of the entry
+    pub entry_addr: u64,
+
+    /// The digest held by the entry
+    pub stored: Digest,
+
+    /// The digest of the page's current contents
+    pub computed: Digest,
+}
+
+/// The result of [MemoryImage::verify_integrity].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The number of page table entries checked
+    pub entries_checked: u64,
+
+    /// The entries which do not match their page, in the order they were
+    /// checked: layer by layer, in ascending page order.
+    pub mismatches: Vec<EntryMismatch>,
+}
+
+impl IntegrityReport {
+    /// Returns whether every entry matched its page.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl MemoryImage {
+    /// Check every entry of the page table against the page it commits to.
+    ///
+    /// This walks all of guest memory: each data page against its entry in
+    /// layer 0, then each page of the table against its entry in the layer
+    /// above, up to the root page. Pages modified since the last
+    /// [MemoryImage::update_page_table] are reported as mismatches.
+    pub fn verify_integrity(&self) -> IntegrityReport {
+        let info = &self.info;
+        let memory = self.main_memory();
+        let zero_page = self.hasher().hash_page(&[0; PAGE_SIZE]);
+        let mut report = IntegrityReport::default();
+
+        let mut check = |layer: usize, page_idx: u64| {
+            let entry_addr = info.get_entry_addr(layer, page_idx);
+            let offset = (entry_addr % PAGE_SIZE as u64) as usize;
+            let stored = memory
+                .page(info.get_page_index(entry_addr))
+                .map_or(Digest::ZERO, |page| {
+                    Digest::from_bytes(&page[offset..offset + DIGEST_BYTES]).unwrap()
+                });
+            let computed = memory
+                .page(page_idx)
+                .map_or(zero_page, |page| self.hasher().hash_page(page));
+            report.entries_checked += 1;
+            if stored != computed {
+                report.mismatches.push(EntryMismatch {
+                    layer,
+                    page_idx,
+                    entry_addr,
+                    stored,
+                    computed,
+                });
+            }
+        };
+
+        let num_pages = info.get_page_index(memory.size());
+        for page_idx in (0..num_pages).filter(|idx| !info.is_table_page(*idx)) {
+            check(0, page_idx);
+        }
+        let layers = info.layers();
+        for layer in 1..layers.len() {
+            for page_idx in layers[layer - 1].page_indices() {
+                check(layer, page_idx);
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use crate::{
+        testutil::{image, RET},
+        PAGE_SIZE,
+    };
+
+    #[test]
+    fn detects_tampering() {
+        let mut image = image(&[RET], &[]);
+        let report = image.verify_integrity();
+        assert!(report.is_ok());
+        assert_eq!(
+            report.entries_checked,
+            image.info.layers()[1..]
+                .iter()
+                .map(|layer| layer.num_entries)
+                .sum::<u64>()
+                + image.info.layers()[0].num_entries
+                - image.info.page_table_size() / PAGE_SIZE as u64
+        );
+
+        // Modify a page behind the image's back.
+        image
+            .main_memory_mut()
+            .write_mem(0x1000, MemAccessSize::Word, 0);
+        let report = image.verify_integrity();
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!((mismatch.layer, mismatch.page_idx), (0, 4));
+
+        // A corrupted layer 1 entry no longer matches the layer 0 page below
+        // it, and its own page no longer matches its entry in layer 2.
+        image.hash_pages();
+        let entry_addr = image
+            .info
+            .get_entry_addr(1, image.info.layers()[0].page_indices().start);
+        image
+            .main_memory_mut()
+            .write_mem(entry_addr, MemAccessSize::Byte, 0xff);
+        let layers: Vec<usize> = image
+            .verify_integrity()
+            .mismatches
+            .iter()
+            .map(|mismatch| mismatch.layer)
+            .collect();
+        assert_eq!(layers, [1, 2]);
+    }
+}
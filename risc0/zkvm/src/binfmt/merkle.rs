@@ -0,0 +1,243 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exported form of the page table Merkle tree.
+//!
+//! The export is self-describing, so verifiers and circuit generators can
+//! consume the commitment without knowing the [PageTableInfo] layout. All
+//! integers are little-endian.
+//!
+//! | field             | size                     |
+//! |-------------------|--------------------------|
+//! | magic (`R0MT`)    | 4                        |
+//! | version           | 4                        |
+//! | page size         | 4                        |
+//! | digest size       | 4                        |
+//! | mem_size          | 8                        |
+//! | hasher name len   | 1                        |
+//! | hasher name       | hasher name len          |
+//! | level count       | 4                        |
+//! | levels            | see below                |
+//! | root digest       | digest size              |
+//!
+//! Each level is an entry count (8 bytes) followed by that many digests.
+//! Level 0 holds one entry per page of guest memory, in page order; entries for
+//! the pages that hold the table itself are zero. Entry `i` of every further
+//! level is the digest of entries `i * n .. (i + 1) * n` of the level below,
+//! where `n` is page size / digest size and missing entries are zero. The last
+//! level is the root page: the root digest is the hash of its entries, padded
+//! with zero entries to a multiple of two.
+
+use std::io::Read;
+
+use anyhow::{ensure, Result};
+use risc0_zkvm_platform::PAGE_SIZE;
+
+use super::{
+    digest::{Digest, DIGEST_BYTES},
+    image::MemoryImage,
+    persist::{read_name, read_u32, read_u64, write_name},
+};
+
+const MAGIC: &[u8; 4] = b"R0MT";
+const VERSION: u32 = 1;
+
+/// The page table Merkle tree of a [MemoryImage], level by level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleTree {
+    /// Size in bytes of the pages committed to by each entry
+    pub page_size: u32,
+
+    /// Size in bytes of the guest memory
+    pub mem_size: u64,
+
+    /// Name of the [PageHasher](super::hash::PageHasher) used to build the tree
+    pub hasher: String,
+
+    /// Entries of each level, from the data page digests up to the root page
+    pub levels: Vec<Vec<Digest>>,
+
+    /// Digest of the root page
+    pub root: Digest,
+}
+
+impl MerkleTree {
+    /// Serialize this tree in the versioned layout described in the module
+    /// documentation.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.page_size.to_le_bytes());
+        out.extend_from_slice(&(DIGEST_BYTES as u32).to_le_bytes());
+        out.extend_from_slice(&self.mem_size.to_le_bytes());
+        write_name(&mut out, &self.hasher)?;
+        out.extend_from_slice(&(self.levels.len() as u32).to_le_bytes());
+        for level in &self.levels {
+            out.extend_from_slice(&(level.len() as u64).to_le_bytes());
+            for entry in level {
+                out.extend_from_slice(entry.as_bytes());
+            }
+        }
+        out.extend_from_slice(self.root.as_bytes());
+        Ok(out)
+    }
+
+    /// Deserialize a tree written by [MerkleTree::to_bytes].
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let reader = &mut bytes;
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a merkle tree export");
+        let version = read_u32(reader)?;
+        ensure!(
+            version == VERSION,
+            "unsupported merkle tree version: {version}"
+        );
+        let page_size = read_u32(reader)?;
+        let digest_size = read_u32(reader)?;
+        ensure!(
+            digest_size as usize == DIGEST_BYTES,
+            "unsupported digest size: {digest_size}"
+        );
+        let mem_size = read_u64(reader)?;
+        let hasher = read_name(reader)?;
+
+        let num_levels = read_u32(reader)?;
+        let mut levels = Vec::new();
+        for _ in 0..num_levels {
+            let num_entries = read_u64(reader)?;
+            ensure!(
+                num_entries <= (reader.len() / DIGEST_BYTES) as u64,
+                "truncated merkle tree"
+            );
+            let level = (0..num_entries)
+                .map(|_| read_digest(reader))
+                .collect::<Result<_>>()?;
+            levels.push(level);
+        }
+        let root = read_digest(reader)?;
+        ensure!(reader.is_empty(), "trailing bytes after merkle tree");
+        Ok(Self {
+            page_size,
+            mem_size,
+            hasher,
+            levels,
+            root,
+        })
+    }
+}
+
+impl MemoryImage {
+    /// Returns the page table Merkle tree of this image.
+    ///
+    /// Pending page table updates are applied first, so the tree matches the
+    /// current root.
+    pub fn merkle_tree(&mut self) -> MerkleTree {
+        self.update_page_table();
+        let root = self.get_root();
+        let info = self.info.clone();
+        let memory = self.main_memory();
+        let levels = info
+            .layers()
+            .iter()
+            .map(|layer| {
+                let mut bytes: Vec<u8> = layer
+                    .page_indices()
+                    .flat_map(|page_idx| memory.page(page_idx).copied().unwrap_or([0; PAGE_SIZE]))
+                    .collect();
+                let offset = (layer.addr % PAGE_SIZE as u64) as usize;
+                bytes.drain(..offset);
+                bytes.truncate(layer.size() as usize);
+                bytes
+                    .chunks_exact(DIGEST_BYTES)
+                    .map(|entry| Digest::from_bytes(entry).unwrap())
+                    .collect()
+            })
+            .collect();
+        MerkleTree {
+            page_size: PAGE_SIZE as u32,
+            mem_size: memory.size(),
+            hasher: self.hasher().name().to_string(),
+            levels,
+            root,
+        }
+    }
+
+    /// Export the page table Merkle tree in the versioned binary layout of
+    /// [MerkleTree::to_bytes].
+    pub fn export_merkle_tree(&mut self) -> Result<Vec<u8>> {
+        self.merkle_tree().to_bytes()
+    }
+}
+
+fn read_digest(reader: &mut &[u8]) -> Result<Digest> {
+    let mut buf = [0; DIGEST_BYTES];
+    reader.read_exact(&mut buf)?;
+    Ok(Digest::from_bytes(&buf).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::MemAccessSize;
+
+    use super::MerkleTree;
+    use crate::{
+        binfmt::digest::DIGEST_BYTES,
+        testutil::{image, RET},
+        Digest, PAGE_SIZE,
+    };
+
+    #[test]
+    fn export_and_check() {
+        let mut image = image(&[RET], &[]);
+        image.write_mem(0x8000, MemAccessSize::Word, 7);
+
+        let bytes = image.export_merkle_tree().unwrap();
+        let tree = MerkleTree::from_bytes(&bytes).unwrap();
+        assert_eq!(tree, image.merkle_tree());
+        assert_eq!(tree.root, image.get_root());
+        assert_eq!(tree.levels.len(), image.info.num_layers());
+
+        let hasher = image.hasher().clone();
+        let page = image.load_bytes(0x8000, PAGE_SIZE as u64);
+        assert_eq!(tree.levels[0][0x8000 / PAGE_SIZE], hasher.hash_page(&page));
+
+        // Every level commits to the one below it, and the root to the last.
+        let hash_entries = |entries: &[Digest]| {
+            let mut bytes: Vec<u8> = entries
+                .iter()
+                .flat_map(|entry| entry.as_bytes().iter().copied())
+                .collect();
+            bytes.resize(bytes.len() + bytes.len() % (2 * DIGEST_BYTES), 0);
+            hasher.hash_page(&bytes)
+        };
+        let per_page = PAGE_SIZE / DIGEST_BYTES;
+        for pair in tree.levels.windows(2) {
+            for (i, entry) in pair[1].iter().enumerate() {
+                let mut page = pair[0][i * per_page..].to_vec();
+                page.resize(per_page, Digest::ZERO);
+                assert_eq!(*entry, hash_entries(&page));
+            }
+        }
+        assert_eq!(tree.root, hash_entries(tree.levels.last().unwrap()));
+
+        assert!(MerkleTree::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // The length of the hasher name must fit in a byte.
+        let mut tree = tree;
+        tree.hasher = "x".repeat(256);
+        assert!(tree.to_bytes().is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result};
+use memmap2::MmapMut;
+use risc0_zkvm_platform::PAGE_SIZE;
+
+use super::paged::Page;
+
+/// Page storage in a memory-mapped file.
+///
+/// The file is created sparse, so only pages that are actually flushed to it
+/// occupy disk space, and the OS is free to evict them from host RAM.
+pub(crate) struct MmapBacking {
+    path: PathBuf,
+    map: MmapMut,
+}
+
+/// The number of copies of backing files made by this process, to name them
+static NUM_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+impl MmapBacking {
+    /// Create (or truncate) the file at `path` to hold `size` bytes.
+    pub fn create(path: &Path, size: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        file.set_len(size)?;
+        // Safety: the file was just created by us and is only accessed through
+        // this mapping.
+        let map = unsafe { MmapMut::map_mut(&file) }.context("failed to map backing file")?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            map,
+        })
+    }
+
+    /// Create a new file next to this one holding the pages at `page_indices`.
+    ///
+    /// The new file is removed as soon as it is mapped, where the platform
+    /// allows, so that it does not outlive the mapping.
+    pub fn copy(&self, page_indices: impl Iterator<Item = u64>) -> Result<Self> {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(
+            ".{}.{}",
+            std::process::id(),
+            NUM_COPIES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut copy = Self::create(Path::new(&path), self.map.len() as u64)?;
+        let _ = std::fs::remove_file(&path);
+        for page_idx in page_indices {
+            copy.store_page(page_idx, self.page(page_idx));
+        }
+        Ok(copy)
+    }
+
+    pub fn page(&self, page_idx: u64) -> &Page {
+        let start = page_idx as usize * PAGE_SIZE;
+        self.map[start..start + PAGE_SIZE].try_into().unwrap()
+    }
+
+    pub fn store_page(&mut self, page_idx: u64, data: &Page) {
+        let start = page_idx as usize * PAGE_SIZE;
+        self.map[start..start + PAGE_SIZE].copy_from_slice(data);
+    }
+}
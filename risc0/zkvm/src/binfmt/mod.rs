@@ -13,6 +13,37 @@
 // limitations under the License.
 
 //! Manages formatted binaries used by the RISC Zero zkVM
+//!
+//! Only `digest` builds without the `binfmt` feature, for verifiers.
 
+#[cfg(feature = "binfmt")]
+pub(crate) mod builder;
+#[cfg(feature = "binfmt")]
+pub(crate) mod checkpoint;
+#[cfg(feature = "cuda")]
+pub(crate) mod cuda;
+#[cfg(feature = "binfmt")]
+pub(crate) mod diff;
+pub(crate) mod digest;
+#[cfg(feature = "binfmt")]
 pub(crate) mod elf;
+#[cfg(feature = "binfmt")]
+pub mod hash;
+#[cfg(feature = "binfmt")]
 pub(crate) mod image;
+#[cfg(feature = "binfmt")]
+pub(crate) mod integrity;
+#[cfg(feature = "binfmt")]
+pub(crate) mod merkle;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "binfmt")]
+pub(crate) mod paged;
+#[cfg(feature = "binfmt")]
+pub(crate) mod persist;
+#[cfg(feature = "binfmt")]
+pub mod proof;
+#[cfg(feature = "binfmt")]
+pub mod region;
+#[cfg(feature = "binfmt")]
+pub(crate) mod store;
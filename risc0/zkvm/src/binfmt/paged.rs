@@ -0,0 +1,449 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::{collections::BTreeMap, sync::Arc};
+#[cfg(feature = "mmap")]
+use std::path::PathBuf;
+
+use anyhow::Result;
+use risc0_zkvm_platform::PAGE_SIZE;
+use rrs_lib::{MemAccessSize, Memory};
+
+#[cfg(feature = "mmap")]
+use super::mmap::MmapBacking;
+use super::store::PageStore;
+
+/// The contents of a single page.
+pub type Page = [u8; PAGE_SIZE];
+
+/// Where the pages of a [PagedMemory] are kept.
+#[derive(Clone, Debug, Default)]
+pub enum MemoryBackend {
+    /// All pages are held in host RAM.
+    #[default]
+    Heap,
+
+    /// Pages are spilled to a memory-mapped file at `path`.
+    ///
+    /// Written pages are kept in RAM until more than `max_resident_pages` of
+    /// them accumulate, and are then flushed to the file together.
+    #[cfg(feature = "mmap")]
+    Mmap {
+        /// The file backing the memory; it is created or truncated.
+        path: PathBuf,
+
+        /// The number of pages held in RAM before they are flushed.
+        max_resident_pages: usize,
+    },
+}
+
+/// A sparse memory backend that only materializes pages on first write
+///
+/// Reads from pages that have never been written return zero. Writing zero to
+/// a page that has not been materialized yet is a no-op, so a guest that
+/// clears memory it never uses does not pay for it either.
+///
+/// Pages are reference counted: cloning a [PagedMemory] shares every page with
+/// the original, and a page is only copied once one of the clones writes to
+/// it.
+#[derive(Clone)]
+pub struct PagedMemory {
+    size: u64,
+    pages: BTreeMap<u64, Slot>,
+    num_resident: usize,
+    #[cfg(feature = "mmap")]
+    spill: Option<Spill>,
+}
+
+/// Where a materialized page is kept.
+#[derive(Clone)]
+enum Slot {
+    /// In host RAM
+    Resident(Arc<Page>),
+
+    /// In the backing file of the [Spill]
+    #[cfg(feature = "mmap")]
+    Flushed,
+}
+
+impl Slot {
+    /// Returns the page, if it is in host RAM.
+    fn resident(&self) -> Option<&Arc<Page>> {
+        match self {
+            Slot::Resident(page) => Some(page),
+            #[cfg(feature = "mmap")]
+            Slot::Flushed => None,
+        }
+    }
+}
+
+/// The file that pages are flushed to, shared by clones of a [PagedMemory]
+/// until one of them flushes.
+#[cfg(feature = "mmap")]
+#[derive(Clone)]
+struct Spill {
+    backing: Arc<MmapBacking>,
+    max_resident_pages: usize,
+}
+
+impl PagedMemory {
+    /// Construct an empty memory of `size` bytes.
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            pages: BTreeMap::new(),
+            num_resident: 0,
+            #[cfg(feature = "mmap")]
+            spill: None,
+        }
+    }
+
+    /// Construct an empty memory of `size` bytes, keeping its pages in
+    /// `backend`.
+    pub fn with_backend(size: u64, backend: &MemoryBackend) -> Result<Self> {
+        match backend {
+            MemoryBackend::Heap => Ok(Self::new(size)),
+            #[cfg(feature = "mmap")]
+            MemoryBackend::Mmap {
+                path,
+                max_resident_pages,
+            } => Ok(Self {
+                spill: Some(Spill {
+                    backing: Arc::new(MmapBacking::create(path, size)?),
+                    max_resident_pages: *max_resident_pages,
+                }),
+                ..Self::new(size)
+            }),
+        }
+    }
+
+    /// The size of this memory in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The number of pages that have been materialized in host RAM.
+    pub fn num_resident_pages(&self) -> usize {
+        self.num_resident
+    }
+
+    /// Returns the page at `page_idx`, if it has been materialized.
+    pub fn page(&self, page_idx: u64) -> Option<&Page> {
+        self.pages
+            .get(&page_idx)
+            .map(|slot| self.slot_page(page_idx, slot))
+    }
+
+    /// Iterate over all materialized pages in ascending index order.
+    pub fn pages(&self) -> impl Iterator<Item = (u64, &Page)> {
+        self.pages
+            .iter()
+            .map(|(idx, slot)| (*idx, self.slot_page(*idx, slot)))
+    }
+
+    /// Overwrite the page at `page_idx` with `data`.
+    ///
+    /// Returns false if the page lies outside of this memory.
+    pub fn store_page(&mut self, page_idx: u64, data: &Page) -> bool {
+        if page_idx >= self.size / PAGE_SIZE as u64 {
+            return false;
+        }
+        self.insert_resident(page_idx, Arc::new(*data));
+        self.maybe_flush();
+        true
+    }
+
+    /// Copy `data` into memory starting at `addr`.
+    ///
+    /// Returns false, without writing anything, if the range does not fit.
+    pub fn store_bytes(&mut self, addr: u64, data: &[u8]) -> bool {
+        if !self.in_bounds(addr, data.len() as u64) {
+            return false;
+        }
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let (page_idx, offset) = split_addr(addr);
+            let len = std::cmp::min(PAGE_SIZE - offset, data.len());
+            let (chunk, rest) = data.split_at(len);
+            let current = self.page(page_idx).map(|page| &page[offset..offset + len]);
+            let unchanged = match current {
+                Some(current) => current == chunk,
+                None => chunk.iter().all(|byte| *byte == 0),
+            };
+            if !unchanged {
+                self.page_mut(page_idx)[offset..offset + len].copy_from_slice(chunk);
+            }
+            addr += len as u64;
+            data = rest;
+        }
+        self.maybe_flush();
+        true
+    }
+
+    /// Returns the number of materialized pages that are shared with a clone
+    /// of this memory.
+    pub fn num_shared_pages(&self) -> usize {
+        self.resident_pages()
+            .filter(|page| Arc::strong_count(page) > 1)
+            .count()
+    }
+
+    /// Replace every page held in RAM with its shared copy from `store`.
+    pub fn intern_pages(&mut self, store: &PageStore) {
+        for slot in self.pages.values_mut() {
+            match slot {
+                Slot::Resident(page) => *page = store.intern(page).0,
+                #[cfg(feature = "mmap")]
+                Slot::Flushed => {}
+            }
+        }
+    }
+
+    /// Write all pages held in RAM out to the backing file, if any.
+    ///
+    /// This is done automatically once too many pages are resident. If the
+    /// backing file is shared with a clone of this memory, which may still
+    /// read from it, this memory first moves to a private copy of the file,
+    /// holding the pages it has flushed so far. If the copy cannot be made,
+    /// the pages stay in RAM.
+    pub fn flush(&mut self) {
+        #[cfg(feature = "mmap")]
+        if let Some(spill) = &mut self.spill {
+            if Arc::get_mut(&mut spill.backing).is_none() {
+                let flushed = self
+                    .pages
+                    .iter()
+                    .filter(|(_, slot)| matches!(slot, Slot::Flushed))
+                    .map(|(idx, _)| *idx);
+                match spill.backing.copy(flushed) {
+                    Ok(backing) => spill.backing = Arc::new(backing),
+                    Err(err) => {
+                        tracing::warn!("not flushing pages: {err:#}");
+                        return;
+                    }
+                }
+            }
+            let backing = Arc::get_mut(&mut spill.backing).unwrap();
+            for (idx, slot) in self.pages.iter_mut() {
+                if let Slot::Resident(page) = slot {
+                    backing.store_page(*idx, page);
+                    *slot = Slot::Flushed;
+                }
+            }
+            self.num_resident = 0;
+        }
+    }
+
+    fn maybe_flush(&mut self) {
+        #[cfg(feature = "mmap")]
+        if let Some(spill) = &self.spill {
+            if self.num_resident > spill.max_resident_pages {
+                self.flush();
+            }
+        }
+    }
+
+    /// Returns the contents of the page at `page_idx`, kept in `slot`.
+    fn slot_page<'a>(&'a self, _page_idx: u64, slot: &'a Slot) -> &'a Page {
+        match slot {
+            Slot::Resident(page) => page,
+            #[cfg(feature = "mmap")]
+            Slot::Flushed => self.spill.as_ref().unwrap().backing.page(_page_idx),
+        }
+    }
+
+    fn resident_pages(&self) -> impl Iterator<Item = &Arc<Page>> {
+        self.pages.values().filter_map(Slot::resident)
+    }
+
+    fn insert_resident(&mut self, page_idx: u64, page: Arc<Page>) {
+        if !matches!(
+            self.pages.insert(page_idx, Slot::Resident(page)),
+            Some(Slot::Resident(_))
+        ) {
+            self.num_resident += 1;
+        }
+    }
+
+    /// Returns the page at `page_idx` for writing, materializing it in RAM
+    /// and unsharing it as needed.
+    fn page_mut(&mut self, page_idx: u64) -> &mut Page {
+        if !matches!(self.pages.get(&page_idx), Some(Slot::Resident(_))) {
+            let page = self.page(page_idx).copied().unwrap_or([0; PAGE_SIZE]);
+            self.insert_resident(page_idx, Arc::new(page));
+        }
+        match self.pages.get_mut(&page_idx) {
+            Some(Slot::Resident(page)) => Arc::make_mut(page),
+            _ => unreachable!(),
+        }
+    }
+
+    fn load_u8(&self, addr: u64) -> u8 {
+        let (page_idx, offset) = split_addr(addr);
+        self.page(page_idx).map_or(0, |page| page[offset])
+    }
+
+    fn store_u8(&mut self, addr: u64, data: u8) {
+        if self.load_u8(addr) != data {
+            let (page_idx, offset) = split_addr(addr);
+            self.page_mut(page_idx)[offset] = data;
+        }
+    }
+
+    fn in_bounds(&self, addr: u64, len: u64) -> bool {
+        addr.checked_add(len).map_or(false, |end| end <= self.size)
+    }
+}
+
+impl Memory for PagedMemory {
+    fn read_mem(&mut self, addr: u64, size: MemAccessSize) -> Option<u64> {
+        let len = access_len(size);
+        if !self.in_bounds(addr, len) {
+            return None;
+        }
+        Some((0..len).fold(0, |acc, i| acc | (self.load_u8(addr + i) as u64) << (i * 8)))
+    }
+
+    fn write_mem(&mut self, addr: u64, size: MemAccessSize, store_data: u64) -> bool {
+        let len = access_len(size);
+        if !self.in_bounds(addr, len) {
+            return false;
+        }
+        for i in 0..len {
+            self.store_u8(addr + i, (store_data >> (i * 8)) as u8);
+        }
+        self.maybe_flush();
+        true
+    }
+}
+
+fn split_addr(addr: u64) -> (u64, usize) {
+    (addr / PAGE_SIZE as u64, (addr % PAGE_SIZE as u64) as usize)
+}
+
+pub(crate) fn access_len(size: MemAccessSize) -> u64 {
+    match size {
+        MemAccessSize::Byte => 1,
+        MemAccessSize::HalfWord => 2,
+        MemAccessSize::Word => 4,
+        MemAccessSize::DoubleWord => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use super::PagedMemory;
+    use crate::PAGE_SIZE;
+
+    #[test]
+    fn lazy_pages() {
+        let mut mem = PagedMemory::new(1 << 28);
+        assert_eq!(mem.read_mem(0x1000, MemAccessSize::DoubleWord), Some(0));
+        assert!(mem.write_mem(0x1000, MemAccessSize::Word, 0));
+        assert_eq!(mem.num_resident_pages(), 0);
+
+        assert!(mem.write_mem(0x1004, MemAccessSize::Word, 0xdeadbeef));
+        assert_eq!(mem.num_resident_pages(), 1);
+        assert_eq!(
+            mem.read_mem(0x1000, MemAccessSize::DoubleWord),
+            Some(0xdeadbeef_00000000)
+        );
+        assert_eq!(mem.read_mem(0x1005, MemAccessSize::Byte), Some(0xbe));
+    }
+
+    #[test]
+    fn cross_page_and_bounds() {
+        let mut mem = PagedMemory::new(4 * PAGE_SIZE as u64);
+        let addr = PAGE_SIZE as u64 - 2;
+        assert!(mem.write_mem(addr, MemAccessSize::Word, 0x11223344));
+        assert_eq!(mem.num_resident_pages(), 2);
+        assert_eq!(mem.read_mem(addr, MemAccessSize::Word), Some(0x11223344));
+
+        let end = 4 * PAGE_SIZE as u64;
+        assert_eq!(mem.read_mem(end - 4, MemAccessSize::DoubleWord), None);
+        assert!(!mem.write_mem(end, MemAccessSize::Byte, 1));
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let mut mem = PagedMemory::new(1 << 20);
+        assert!(mem.store_bytes(PAGE_SIZE as u64 - 2, &[1, 2, 3, 4]));
+        assert_eq!(mem.num_resident_pages(), 2);
+
+        let mut clone = mem.clone();
+        assert_eq!(clone.num_shared_pages(), 2);
+        assert!(clone.write_mem(0, MemAccessSize::Byte, 0));
+        assert_eq!(clone.num_shared_pages(), 2);
+
+        assert!(clone.write_mem(PAGE_SIZE as u64, MemAccessSize::Byte, 9));
+        assert_eq!(clone.num_shared_pages(), 1);
+        assert_eq!(mem.read_mem(PAGE_SIZE as u64, MemAccessSize::Byte), Some(3));
+        assert_eq!(
+            clone.read_mem(PAGE_SIZE as u64, MemAccessSize::Byte),
+            Some(9)
+        );
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_backend() {
+        use super::MemoryBackend;
+
+        let path = std::env::temp_dir().join(format!("paged-{}.mem", std::process::id()));
+        let backend = MemoryBackend::Mmap {
+            path: path.clone(),
+            max_resident_pages: 2,
+        };
+        let mut mem = PagedMemory::with_backend(1 << 28, &backend).unwrap();
+        for i in 0..3 {
+            let addr = i * PAGE_SIZE as u64;
+            assert!(mem.write_mem(addr, MemAccessSize::Word, i + 1));
+        }
+        // The third page pushed everything out to the file.
+        assert_eq!(mem.num_resident_pages(), 0);
+        assert_eq!(mem.pages().count(), 3);
+        assert_eq!(mem.read_mem(PAGE_SIZE as u64, MemAccessSize::Word), Some(2));
+
+        assert!(mem.write_mem(4, MemAccessSize::Word, 7));
+        assert_eq!(mem.num_resident_pages(), 1);
+        assert_eq!(
+            mem.read_mem(0, MemAccessSize::DoubleWord),
+            Some(7 << 32 | 1)
+        );
+
+        // A clone sharing the file flushes to a copy of it, leaving the pages
+        // the original reads from the file alone.
+        let mut clone = mem.clone();
+        for i in 0..3 {
+            let addr = i * PAGE_SIZE as u64;
+            assert!(clone.write_mem(addr, MemAccessSize::Word, i + 10));
+        }
+        assert_eq!(clone.num_resident_pages(), 0);
+        assert_eq!(mem.read_mem(PAGE_SIZE as u64, MemAccessSize::Word), Some(2));
+        assert_eq!(
+            clone.read_mem(PAGE_SIZE as u64, MemAccessSize::Word),
+            Some(11)
+        );
+        mem.flush();
+        assert_eq!(mem.num_resident_pages(), 0);
+        assert_eq!(mem.read_mem(0, MemAccessSize::Word), Some(1));
+        assert_eq!(clone.read_mem(0, MemAccessSize::Word), Some(10));
+        drop(clone);
+        drop(mem);
+        std::fs::remove_file(path).unwrap();
+    }
+}
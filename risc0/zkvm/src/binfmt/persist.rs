@@ -0,0 +1,235 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-disk format for [MemoryImage].
+//!
+//! A saved image consists of an uncompressed header followed by a zstd stream
+//! of the non-zero pages of main memory. All integers are little-endian.
+//!
+//! | field             | size                 |
+//! |-------------------|----------------------|
+//! | magic (`R0IM`)    | 4                    |
+//! | version           | 4                    |
+//! | mem_size          | 8                    |
+//! | page_table_addr   | 8                    |
+//! | root digest       | 32                   |
+//! | hasher name len   | 1                    |
+//! | hasher name       | hasher name len      |
+//! | page count        | 8                    |
+//! | pages (zstd)      | count * (8 + 1024)   |
+//!
+//! Each page is stored as its page index followed by its contents. Pages that
+//! are entirely zero are elided. The page table itself lives in main memory, so
+//! it is saved along with the rest of the pages. When loading, every page is
+//! rehashed against the table and the table against the root digest. The
+//! hasher name identifies one of the built-in
+//! [PageHasher](super::hash::PageHasher)s, which is used again for the loaded
+//! image.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use risc0_zkvm_platform::PAGE_SIZE;
+
+use super::{
+    digest::{Digest, DIGEST_BYTES},
+    hash::page_hasher_by_name,
+    image::{MemoryImage, PageTableInfo},
+    paged::PagedMemory,
+};
+
+const MAGIC: &[u8; 4] = b"R0IM";
+const VERSION: u32 = 2;
+const COMPRESSION_LEVEL: i32 = 3;
+
+impl MemoryImage {
+    /// Save this image to the file at `path`.
+    ///
+    /// Any pending page table updates are applied first, so the saved root
+    /// matches the saved memory. Only the main guest memory is saved; regions
+    /// mapped at runtime (e.g. via mmap) are not part of the image.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.save_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load an image previously written by [MemoryImage::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        Self::load_from(BufReader::new(file))
+    }
+
+    /// Serialize this image to `writer`.
+    pub fn save_to(&mut self, mut writer: impl Write) -> Result<()> {
+        self.update_page_table();
+        let root = self.get_root();
+        let memory = self.main_memory();
+        let pages: Vec<_> = memory
+            .pages()
+            .filter(|(_, page)| page.iter().any(|byte| *byte != 0))
+            .collect();
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&memory.size().to_le_bytes())?;
+        writer.write_all(&self.info.page_table_addr.to_le_bytes())?;
+        writer.write_all(root.as_bytes())?;
+        write_name(&mut writer, self.hasher().name())?;
+        writer.write_all(&(pages.len() as u64).to_le_bytes())?;
+
+        let mut encoder = zstd::stream::Encoder::new(writer, COMPRESSION_LEVEL)?;
+        for (page_idx, page) in pages {
+            encoder.write_all(&page_idx.to_le_bytes())?;
+            encoder.write_all(page)?;
+        }
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Deserialize an image from `reader`, verifying its root digest and every
+    /// page against the page table.
+    pub fn load_from(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a memory image");
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            bail!("unsupported memory image version: {version}");
+        }
+        let mem_size = read_u64(&mut reader)?;
+        let page_table_addr = read_u64(&mut reader)?;
+        let mut root = [0; DIGEST_BYTES];
+        reader.read_exact(&mut root)?;
+        let root = Digest::from_bytes(&root).unwrap();
+        let hasher_name = read_name(&mut reader)?;
+        let hasher = page_hasher_by_name(&hasher_name)
+            .with_context(|| format!("unknown page hasher: {hasher_name}"))?;
+        let num_pages = read_u64(&mut reader)?;
+        let info = PageTableInfo::try_new(page_table_addr, mem_size)
+            .context("invalid memory image header")?;
+
+        let mut memory = PagedMemory::new(mem_size);
+        let mut decoder = zstd::stream::Decoder::new(reader)?;
+        let mut page = [0; PAGE_SIZE];
+        for _ in 0..num_pages {
+            let page_idx = read_u64(&mut decoder)?;
+            decoder.read_exact(&mut page)?;
+            ensure!(memory.store_page(page_idx, &page), "page out of range");
+        }
+
+        let mut image = MemoryImage::from_memory(memory, info, hasher);
+        ensure!(
+            image.get_root() == root,
+            "memory image root mismatch, the image is corrupt"
+        );
+        let report = image.verify_integrity();
+        if let Some(mismatch) = report.mismatches.first() {
+            bail!(
+                "page 0x{:x} does not match its page table entry, the image is corrupt",
+                mismatch.page_idx
+            );
+        }
+        Ok(image)
+    }
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Write `name` preceded by its length in a byte.
+pub(crate) fn write_name(writer: &mut impl Write, name: &str) -> Result<()> {
+    let len = u8::try_from(name.len())
+        .with_context(|| format!("name is longer than 255 bytes: {name}"))?;
+    writer.write_all(&[len])?;
+    writer.write_all(name.as_bytes())?;
+    Ok(())
+}
+
+/// Read a name written by [write_name].
+pub(crate) fn read_name(reader: &mut impl Read) -> Result<String> {
+    let mut len = [0; 1];
+    reader.read_exact(&mut len)?;
+    let mut name = vec![0; len[0] as usize];
+    reader.read_exact(&mut name)?;
+    Ok(String::from_utf8(name)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use crate::{
+        testutil::{image, RET},
+        MemoryImage,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut image = image(&[RET, 0x12345678], &[]);
+        image.write_mem(0x40_0000, MemAccessSize::DoubleWord, u64::MAX);
+
+        let mut buf = Vec::new();
+        image.save_to(&mut buf).unwrap();
+        let mut loaded = MemoryImage::load_from(buf.as_slice()).unwrap();
+        assert_eq!(loaded.get_root(), image.get_root());
+        assert_eq!(
+            loaded
+                .memory_space
+                .read_mem(0x1004, MemAccessSize::Word)
+                .unwrap(),
+            0x12345678
+        );
+
+        // Corrupt the saved root digest.
+        let mut corrupt = buf.clone();
+        corrupt[24] ^= 1;
+        assert!(MemoryImage::load_from(corrupt.as_slice()).is_err());
+
+        // Header fields which describe no valid page table are errors rather
+        // than panics.
+        for (offset, value) in [(8, 0), (8, u64::MAX), (16, 0x123), (16, u64::MAX)] {
+            let mut corrupt = buf.clone();
+            corrupt[offset..offset + 8].copy_from_slice(&u64::to_le_bytes(value));
+            assert!(MemoryImage::load_from(corrupt.as_slice()).is_err());
+        }
+
+        // Modify a data page behind the page table's back, so that the saved
+        // root still matches the saved table.
+        image
+            .main_memory_mut()
+            .write_mem(0x1004, MemAccessSize::Word, 0);
+        let mut corrupt = Vec::new();
+        image.save_to(&mut corrupt).unwrap();
+        let err = MemoryImage::load_from(corrupt.as_slice()).err().unwrap();
+        assert!(err.to_string().contains("page 0x4 "), "{err}");
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merkle inclusion proofs for pages of a [MemoryImage].
+
+use anyhow::{bail, ensure, Context, Result};
+use risc0_zkvm_platform::PAGE_SIZE;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    digest::{Digest, DIGEST_BYTES},
+    hash::{page_hasher_by_name, PageHasher},
+    image::MemoryImage,
+};
+
+/// The number of page table entries held by a page
+const ENTRIES_PER_PAGE: usize = PAGE_SIZE / DIGEST_BYTES;
+
+/// One step of a [PageProof]: the contents of a page table page, and the
+/// position within it of the entry for the page below.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProofLevel {
+    /// Position of the committed entry within `entries`
+    pub index: usize,
+
+    /// Every entry of the page table page, which are hashed together to give
+    /// the entry one level up
+    pub entries: Vec<Digest>,
+}
+
+/// A proof that a page with a given digest is part of an image root.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PageProof {
+    /// Index of the proven page
+    pub page_idx: u64,
+
+    /// Digest of the proven page
+    pub digest: Digest,
+
+    /// Name of the [PageHasher] used to build the page table
+    pub hasher: String,
+
+    /// Page table pages from layer 0 up to, and including, the root page
+    pub levels: Vec<ProofLevel>,
+}
+
+impl PageProof {
+    /// Verify this proof against `root`, using the built-in hash function it
+    /// names.
+    pub fn verify(&self, root: &Digest) -> Result<()> {
+        let hasher = page_hasher_by_name(&self.hasher)
+            .with_context(|| format!("unknown page hasher: {}", self.hasher))?;
+        self.verify_with(root, hasher.as_ref())
+    }
+
+    /// Verify this proof against `root` using `hasher`.
+    ///
+    /// The position of the entry at each level is not taken from the proof
+    /// but worked out from [PageProof::page_idx], as the page table lays it
+    /// out, so that the proof commits to that page and no other.
+    pub fn verify_with(&self, root: &Digest, hasher: &dyn PageHasher) -> Result<()> {
+        let mut digest = self.digest;
+        let mut rel_idx = self.page_idx;
+        for (layer, level) in self.levels.iter().enumerate() {
+            let is_root = layer + 1 == self.levels.len();
+            let index = if is_root {
+                ensure!(
+                    level.entries.len() <= ENTRIES_PER_PAGE && level.entries.len() % 2 == 0,
+                    "page proof has {} root entries",
+                    level.entries.len()
+                );
+                rel_idx
+            } else {
+                ensure!(
+                    level.entries.len() == ENTRIES_PER_PAGE,
+                    "page proof has {} entries at layer {layer}",
+                    level.entries.len()
+                );
+                rel_idx % ENTRIES_PER_PAGE as u64
+            };
+            rel_idx /= ENTRIES_PER_PAGE as u64;
+            ensure!(
+                level.index as u64 == index,
+                "page proof is not for page 0x{:x}",
+                self.page_idx
+            );
+            ensure!(
+                level.entries.get(level.index) == Some(&digest),
+                "page proof mismatch at layer {layer}"
+            );
+            let bytes: Vec<u8> = level
+                .entries
+                .iter()
+                .flat_map(|entry| entry.as_bytes().iter().copied())
+                .collect();
+            digest = hasher.hash_page(&bytes);
+        }
+        ensure!(digest == *root, "page proof does not match root");
+        Ok(())
+    }
+}
+
+impl MemoryImage {
+    /// Produce a proof that the page containing `addr` is committed to by the
+    /// image root.
+    ///
+    /// Pending page table updates are applied first.
+    pub fn prove_page(&mut self, addr: u64) -> Result<PageProof> {
+        self.update_page_table();
+        let info = self.info.clone();
+        let page_idx = info.get_page_index(addr);
+        if page_idx >= info.get_page_index(self.main_memory().size()) {
+            bail!("address 0x{addr:x} is outside of guest memory");
+        }
+        if info.is_table_page(page_idx) {
+            bail!("address 0x{addr:x} is part of the page table");
+        }
+
+        let mut levels = Vec::with_capacity(info.num_layers());
+        for entry_addr in info.get_entry_path(page_idx) {
+            let table_idx = info.get_page_index(entry_addr);
+            let bytes = if table_idx == info.root_idx {
+                self.load_root_page()
+            } else {
+                self.load_bytes(info.get_page_addr(table_idx), PAGE_SIZE as u64)
+            };
+            levels.push(ProofLevel {
+                index: ((entry_addr - info.get_page_addr(table_idx)) / DIGEST_BYTES as u64)
+                    as usize,
+                entries: bytes
+                    .chunks_exact(DIGEST_BYTES)
+                    .map(|entry| Digest::from_bytes(entry).unwrap())
+                    .collect(),
+            });
+        }
+
+        Ok(PageProof {
+            page_idx,
+            digest: levels[0].entries[levels[0].index],
+            hasher: self.hasher().name().to_string(),
+            levels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rrs_lib::MemAccessSize;
+
+    use crate::{
+        testutil::{image, RET},
+        Digest, PAGE_SIZE,
+    };
+
+    #[test]
+    fn prove_and_verify() {
+        let mut image = image(&[RET], &[]);
+        image.write_mem(0x8000, MemAccessSize::Word, 7);
+        let proof = image.prove_page(0x8004).unwrap();
+        let root = image.get_root();
+        proof.verify(&root).unwrap();
+
+        let page = image.load_bytes(0x8000, PAGE_SIZE as u64);
+        assert_eq!(proof.digest, image.hasher().hash_page(&page));
+
+        let mut bad = proof.clone();
+        bad.levels[1].entries[0] = Digest::ZERO;
+        assert!(bad.verify(&root).is_err());
+
+        let page_table = image.info.page_table_addr;
+        assert!(image.prove_page(page_table).is_err());
+
+        // A valid proof for one page does not prove another, whether the
+        // indices in the proof are left alone or moved to match.
+        let mut other = proof.clone();
+        other.page_idx = proof.page_idx + 1;
+        assert!(other.verify(&root).is_err());
+        other.levels[0].index += 1;
+        assert!(other.verify(&root).is_err());
+        let mut other = proof.clone();
+        other.page_idx = proof.page_idx + PAGE_SIZE as u64;
+        assert!(other.verify(&root).is_err());
+
+        // Nor do levels of the wrong size.
+        let mut short = proof.clone();
+        short.levels[0].entries.pop();
+        assert!(short.verify(&root).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Additional regions of the guest address space.
+//!
+//! Besides the main guest memory, which is committed to by the page table, a
+//! [MemoryImage](crate::MemoryImage) may map further regions with their own
+//! backends. These regions are not part of the image root.
+
+use rrs_lib::{MemAccessSize, Memory};
+
+/// Describes a region mapped into the guest address space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionInfo {
+    /// A name for the region, used in diagnostics
+    pub name: String,
+
+    /// The first address of the region
+    pub base: u64,
+
+    /// The size of the region in bytes
+    pub size: u64,
+
+    /// Whether the region is committed to by the page table
+    pub committed: bool,
+}
+
+impl RegionInfo {
+    /// Returns whether `addr` lies within this region.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+}
+
+/// The range of addresses the guest heap is allocated from.
+///
+/// Anonymous `mmap` requests are served from this range, which lies outside of
+/// the main guest memory. The guest allocator moves on to it, through `mmap`,
+/// once the platform heap in main memory is used up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapRegion {
+    /// The first address of the heap
+    pub start: u64,
+
+    /// The size of the heap in bytes
+    pub size: u64,
+}
+
+impl HeapRegion {
+    /// The address the `memory_data` region is mapped at, which bounds the
+    /// default heap.
+    pub(crate) const MEMORY_DATA_ADDR: u64 = 0x100000000000;
+
+    /// The default heap for a guest memory of `mem_size` bytes: everything
+    /// from the end of main memory up to the `memory_data` region, which is
+    /// empty if main memory reaches past it.
+    pub fn default_for(mem_size: u64) -> Self {
+        Self {
+            start: mem_size,
+            size: Self::MEMORY_DATA_ADDR.saturating_sub(mem_size),
+        }
+    }
+
+    /// Returns the address just past the end of the heap.
+    pub fn end(&self) -> u64 {
+        self.start + self.size
+    }
+}
+
+/// A memory backend which rejects all writes, e.g. for a ROM.
+pub struct ReadOnlyMemory<M: Memory> {
+    inner: M,
+}
+
+impl<M: Memory> ReadOnlyMemory<M> {
+    /// Wrap `inner`, which holds the contents of the region.
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<M: Memory> Memory for ReadOnlyMemory<M> {
+    fn read_mem(&mut self, addr: u64, size: MemAccessSize) -> Option<u64> {
+        self.inner.read_mem(addr, size)
+    }
+
+    fn write_mem(&mut self, _addr: u64, _size: MemAccessSize, _store_data: u64) -> bool {
+        false
+    }
+}
+
+/// Handler for reads of a [MmioMemory] window.
+pub type MmioRead = Box<dyn FnMut(u64, MemAccessSize) -> Option<u64>>;
+
+/// Handler for writes to a [MmioMemory] window.
+pub type MmioWrite = Box<dyn FnMut(u64, MemAccessSize, u64) -> bool>;
+
+/// A memory-mapped device window backed by host callbacks.
+///
+/// Addresses passed to the callbacks are offsets from the start of the window.
+pub struct MmioMemory {
+    read: MmioRead,
+    write: MmioWrite,
+}
+
+impl MmioMemory {
+    /// Construct a window from its read and write handlers.
+    pub fn new(read: MmioRead, write: MmioWrite) -> Self {
+        Self { read, write }
+    }
+}
+
+impl Memory for MmioMemory {
+    fn read_mem(&mut self, addr: u64, size: MemAccessSize) -> Option<u64> {
+        (self.read)(addr, size)
+    }
+
+    fn write_mem(&mut self, addr: u64, size: MemAccessSize, store_data: u64) -> bool {
+        (self.write)(addr, size, store_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use super::{MmioMemory, ReadOnlyMemory};
+    use crate::{
+        binfmt::paged::PagedMemory,
+        testutil::{image, RET},
+        PAGE_SIZE,
+    };
+
+    const ROM_BASE: u64 = 0x2000_0000_0000;
+    const MMIO_BASE: u64 = 0x3000_0000_0000;
+
+    #[test]
+    fn read_only_region() {
+        let mut image = image(&[RET], &[]);
+        let mut rom = PagedMemory::new(PAGE_SIZE as u64);
+        assert!(rom.write_mem(0, MemAccessSize::Word, 0xcafe));
+        image
+            .add_region(
+                "rom",
+                ROM_BASE,
+                PAGE_SIZE as u64,
+                Box::new(ReadOnlyMemory::new(rom)),
+            )
+            .unwrap();
+
+        let root = image.get_root();
+        assert_eq!(
+            image.memory_space.read_mem(ROM_BASE, MemAccessSize::Word),
+            Some(0xcafe)
+        );
+        assert!(!image.write_mem(ROM_BASE, MemAccessSize::Word, 1));
+        assert!(image.dirty_pages().is_empty());
+        image.update_page_table();
+        assert_eq!(image.get_root(), root);
+
+        // Overlapping an existing region is rejected.
+        let overlap = PagedMemory::new(PAGE_SIZE as u64);
+        assert!(image
+            .add_region("overlap", ROM_BASE, PAGE_SIZE as u64, Box::new(overlap))
+            .is_err());
+    }
+
+    #[test]
+    fn mmio_callbacks() {
+        let mut image = image(&[RET], &[]);
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let log = writes.clone();
+        let mmio = MmioMemory::new(
+            Box::new(|offset, _| Some(offset + 1)),
+            Box::new(move |offset, _, data| {
+                log.borrow_mut().push((offset, data));
+                true
+            }),
+        );
+        image
+            .add_region("mmio", MMIO_BASE, 0x100, Box::new(mmio))
+            .unwrap();
+        assert_eq!(image.regions().len(), 3);
+        assert!(!image.is_committed(MMIO_BASE));
+        assert!(image.is_committed(0x1000));
+
+        assert_eq!(
+            image
+                .memory_space
+                .read_mem(MMIO_BASE + 8, MemAccessSize::Word),
+            Some(9)
+        );
+        assert!(image.write_mem(MMIO_BASE + 4, MemAccessSize::Word, 7));
+        assert_eq!(*writes.borrow(), vec![(4, 7)]);
+        assert!(image.dirty_pages().is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A content-addressed store of pages shared between [MemoryImage]s.
+//!
+//! [MemoryImage]: super::image::MemoryImage
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::{digest::Digest, hash::PageHasher, paged::Page};
+
+/// A content-addressed cache of pages and their digests.
+///
+/// Images built with the same store (see
+/// [MemoryImageBuilder::page_store](super::builder::MemoryImageBuilder::page_store))
+/// share a single copy of every identical page, and each distinct page is
+/// hashed only once. Pages written after an image is built are copied on
+/// write as usual, and are not added to the store.
+pub struct PageStore {
+    hasher: Arc<dyn PageHasher>,
+    pages: RwLock<HashMap<Arc<Page>, Digest>>,
+}
+
+impl PageStore {
+    /// Construct an empty store whose digests are computed with `hasher`.
+    pub fn new(hasher: Arc<dyn PageHasher>) -> Self {
+        Self {
+            hasher,
+            pages: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the hash function used for the stored digests.
+    pub fn hasher(&self) -> &Arc<dyn PageHasher> {
+        &self.hasher
+    }
+
+    /// Returns the shared copy of `page` and its digest, adding it to the
+    /// store if no identical page is present yet.
+    pub fn intern(&self, page: &Arc<Page>) -> (Arc<Page>, Digest) {
+        if let Some((page, digest)) = self.pages.read().unwrap().get_key_value(page.as_ref()) {
+            return (page.clone(), *digest);
+        }
+        let digest = self.hasher.hash_page(page.as_ref());
+        let mut pages = self.pages.write().unwrap();
+        if let Some((page, digest)) = pages.get_key_value(page.as_ref()) {
+            return (page.clone(), *digest);
+        }
+        pages.insert(page.clone(), digest);
+        (page.clone(), digest)
+    }
+
+    /// Returns the digest of `page` if an identical page is in the store.
+    pub fn digest(&self, page: &Page) -> Option<Digest> {
+        self.pages.read().unwrap().get(page).copied()
+    }
+
+    /// Returns the number of distinct pages in the store.
+    pub fn len(&self) -> usize {
+        self.pages.read().unwrap().len()
+    }
+
+    /// Returns true if the store holds no pages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every page that is no longer used by any image.
+    pub fn prune(&self) {
+        self.pages
+            .write()
+            .unwrap()
+            .retain(|page, _| Arc::strong_count(page) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::PageStore;
+    use crate::{
+        binfmt::{
+            digest::{sha_compress_raw, Digest},
+            hash::PageHasher,
+        },
+        MemoryImageBuilder, PAGE_SIZE,
+    };
+
+    #[derive(Default)]
+    struct CountingHasher(AtomicUsize);
+
+    impl PageHasher for CountingHasher {
+        fn name(&self) -> &'static str {
+            "sha-256"
+        }
+
+        fn hash_page(&self, data: &[u8]) -> Digest {
+            if data.iter().all(|byte| *byte == 1) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            sha_compress_raw(data)
+        }
+    }
+
+    #[test]
+    fn shared_pages() {
+        let hasher = Arc::new(CountingHasher::default());
+        let store = Arc::new(PageStore::new(hasher.clone()));
+        let build = || {
+            MemoryImageBuilder::new()
+                .hasher(hasher.clone())
+                .page_store(store.clone())
+                .write_bytes(0x1000, &[1; PAGE_SIZE])
+                .write_bytes(0x2000, &[1; PAGE_SIZE])
+                .build()
+                .unwrap()
+        };
+
+        let mut first = build();
+        assert_eq!(hasher.0.load(Ordering::Relaxed), 1);
+        let mut second = build();
+        assert_eq!(first.get_root(), second.get_root());
+        // Identical pages are only ever hashed once.
+        assert_eq!(hasher.0.load(Ordering::Relaxed), 1);
+        // Both images share one copy of the repeated page.
+        assert!(first.main_memory().num_shared_pages() >= 2);
+
+        let unshared = MemoryImageBuilder::new()
+            .write_bytes(0x1000, &[1; PAGE_SIZE])
+            .write_bytes(0x2000, &[1; PAGE_SIZE])
+            .build()
+            .unwrap()
+            .get_root();
+        assert_eq!(first.get_root(), unshared);
+
+        drop(first);
+        drop(second);
+        store.prune();
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The canonical CBOR encoding of receipts and journals, for exchanging them
+//! across languages and versions.
+//!
+//! Everything is encoded with the deterministic encoding of RFC 8949, section
+//! 4.2.1: integers in their shortest form, and arrays and byte strings of
+//! definite length. The schema has no maps, so there are no keys to order.
+//! Decoding rejects anything that does not re-encode to the same bytes, so a
+//! value has exactly one encoding.
+
+use anyhow::{anyhow, bail, Context, Result};
+use ciborium::Value;
+
+use crate::{
+    claim::{ExitCode, Journal, SegmentIo},
+    receipt::{check_version, Receipt, Seal},
+};
+
+impl Journal {
+    /// Returns the journal in canonical CBOR: a byte string of its bytes.
+    pub fn to_cbor(&self) -> Vec<u8> {
+        encode(&Value::Bytes(self.bytes.clone()))
+    }
+
+    /// Reads a journal encoded as by [Journal::to_cbor].
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        let journal = into_bytes(decode(bytes)?).context("decoding journal")?;
+        Ok(Self::new(journal))
+    }
+}
+
+impl Receipt {
+    /// Returns the receipt in canonical CBOR, as an array of:
+    ///
+    /// 1. the format version, as [Receipt::version];
+    /// 2. the public IO of each segment, an array of arrays of the words of
+    ///    [SegmentIo::to_words](crate::SegmentIo::to_words);
+    /// 3. the journal, a byte string;
+    /// 4. the exit code, an array of the words of
+    ///    [ExitCode::to_words](crate::ExitCode::to_words);
+    /// 5. the kind of seal, 0 for [Seal::Fake].
+    ///
+    /// The version comes first, so that a reader can tell a receipt it does
+    /// not support from a malformed one.
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let words = |words: &[u32]| Value::Array(words.iter().map(|word| (*word).into()).collect());
+        let seal = match self.seal {
+            Seal::Fake => 0,
+        };
+        encode(&Value::Array(vec![
+            self.version.into(),
+            Value::Array(
+                self.segments
+                    .iter()
+                    .map(|io| words(&io.to_words()))
+                    .collect(),
+            ),
+            Value::Bytes(self.journal.bytes.clone()),
+            words(&self.exit_code.to_words()),
+            seal.into(),
+        ]))
+    }
+
+    /// Reads a receipt encoded as by [Receipt::to_cbor]. Fails on receipts
+    /// of a format version that this release does not support.
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        let mut fields = into_array(decode(bytes)?)?;
+        if fields.is_empty() {
+            bail!("Receipt has no format version");
+        }
+        let version = into_integer(fields.remove(0))?;
+        check_version(version)?;
+        let [segments, journal, exit_code, seal] = take_fields(fields)?;
+        let segments = into_array(segments)?
+            .into_iter()
+            .enumerate()
+            .map(|(idx, io)| {
+                SegmentIo::from_words(&into_words(io)?)
+                    .with_context(|| format!("decoding segment {idx}"))
+            })
+            .collect::<Result<_>>()?;
+        let exit_code = into_words(exit_code)?
+            .try_into()
+            .map_err(|words| anyhow!("Exit code has words {words:?}"))?;
+        let seal = match into_integer(seal)? {
+            0 => Seal::Fake,
+            kind => bail!("Unknown kind of seal: {kind}"),
+        };
+        Ok(Self {
+            version,
+            segments,
+            journal: Journal::new(into_bytes(journal)?),
+            exit_code: ExitCode::from_words(exit_code)?,
+            seal,
+        })
+    }
+}
+
+fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).expect("writing to a Vec cannot fail");
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Result<Value> {
+    let value: Value = ciborium::from_reader(bytes).context("decoding CBOR")?;
+    if encode(&value) != bytes {
+        bail!("CBOR is not in canonical form");
+    }
+    Ok(value)
+}
+
+fn take_fields<const N: usize>(fields: Vec<Value>) -> Result<[Value; N]> {
+    fields
+        .try_into()
+        .map_err(|fields: Vec<Value>| anyhow!("Expected {N} fields, not {}", fields.len()))
+}
+
+fn into_array(value: Value) -> Result<Vec<Value>> {
+    value
+        .into_array()
+        .map_err(|value| anyhow!("Expected an array, not {value:?}"))
+}
+
+fn into_bytes(value: Value) -> Result<Vec<u8>> {
+    value
+        .into_bytes()
+        .map_err(|value| anyhow!("Expected a byte string, not {value:?}"))
+}
+
+fn into_integer(value: Value) -> Result<u32> {
+    let integer = value
+        .into_integer()
+        .map_err(|value| anyhow!("Expected an integer, not {value:?}"))?;
+    u32::try_from(integer).map_err(|_| anyhow!("Integer {} is out of range", i128::from(integer)))
+}
+
+fn into_words(value: Value) -> Result<Vec<u32>> {
+    into_array(value)?.into_iter().map(into_integer).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        binfmt::digest::sha256,
+        claim::{chain_journal_digest, ExitCode, GuestFault, Journal, SegmentIo},
+        receipt::{Receipt, Seal, RECEIPT_VERSION},
+        Digest,
+    };
+
+    fn receipt(exit_code: ExitCode) -> Receipt {
+        let journal = b"public".to_vec();
+        let io = SegmentIo {
+            pre_image_id: sha256(b"pre"),
+            pre_pc: 0x1_0000_1000,
+            pre_journal_len: 0,
+            pre_journal_digest: Digest::ZERO,
+            post_image_id: sha256(b"post"),
+            post_pc: 0x1100,
+            journal_len: journal.len() as u64,
+            journal_digest: chain_journal_digest(&Digest::ZERO, &journal),
+            exit_code,
+            assumptions_digest: Digest::ZERO,
+        };
+        Receipt {
+            version: RECEIPT_VERSION,
+            segments: vec![io; 2],
+            journal: Journal::new(journal),
+            exit_code,
+            seal: Seal::Fake,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for exit_code in [
+            ExitCode::Halted(0),
+            ExitCode::Fault(GuestFault::Load(0xdead_beef_0000)),
+        ] {
+            let receipt = receipt(exit_code);
+            let cbor = receipt.to_cbor();
+            assert_eq!(Receipt::from_cbor(&cbor).unwrap(), receipt);
+            // The encoding is deterministic.
+            assert_eq!(Receipt::from_cbor(&cbor).unwrap().to_cbor(), cbor);
+        }
+
+        let journal = Journal::new(b"public".to_vec());
+        let cbor = journal.to_cbor();
+        assert_eq!(cbor, b"\x46public");
+        assert_eq!(Journal::from_cbor(&cbor).unwrap(), journal);
+    }
+
+    #[test]
+    fn rejects() {
+        // An integer that is not in its shortest form.
+        assert!(Journal::from_cbor(b"\x58\x06public")
+            .unwrap_err()
+            .to_string()
+            .contains("canonical"));
+        assert!(Journal::from_cbor(b"\x46publi").is_err());
+        assert!(Journal::from_cbor(b"\x01").is_err());
+
+        let mut cbor = receipt(ExitCode::Halted(0)).to_cbor();
+        // The seal is the last byte.
+        *cbor.last_mut().unwrap() = 0x07;
+        assert!(Receipt::from_cbor(&cbor)
+            .unwrap_err()
+            .to_string()
+            .contains("seal"));
+        assert!(Receipt::from_cbor(&cbor[1..]).is_err());
+
+        // The version is the first field, after the array header.
+        let mut newer = receipt(ExitCode::Halted(0));
+        newer.version = RECEIPT_VERSION + 1;
+        let cbor = newer.to_cbor();
+        assert_eq!(cbor[1], RECEIPT_VERSION as u8 + 1);
+        assert!(Receipt::from_cbor(&cbor)
+            .unwrap_err()
+            .to_string()
+            .contains("format version"));
+    }
+}
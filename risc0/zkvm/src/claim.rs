@@ -0,0 +1,537 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The public claims of an execution, which are what a verifier checks.
+//!
+//! These build with the `verify` feature alone, without the executor and its
+//! dependencies.
+
+use core::fmt;
+
+use anyhow::{bail, Context, Result};
+use risc0_zkvm_platform::WORD_SIZE;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    binfmt::digest::{sha256, DIGEST_WORDS},
+    serde::{to_words, Deserializer},
+    Digest,
+};
+
+/// The data publicly committed by a guest, with `env::commit`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    /// The committed bytes, in the order they were committed
+    pub bytes: Vec<u8>,
+}
+
+impl Journal {
+    /// Construct a journal from its bytes.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    /// Returns the digest of the journal, chained over its bytes from
+    /// [Digest::ZERO] as by [chain_journal_digest]. This is the journal
+    /// digest the last segment of the session binds.
+    pub fn digest(&self) -> Digest {
+        chain_journal_digest(&Digest::ZERO, &self.bytes)
+    }
+
+    /// Decode the journal as a single value of type `T`, as the guest
+    /// committed it with `env::commit`.
+    ///
+    /// Fails if the journal holds more than the value. Use
+    /// [Journal::cursor] to decode a journal committed in several parts.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T> {
+        let mut cursor = self.cursor();
+        let value = cursor.decode()?;
+        if !cursor.is_empty() {
+            bail!(
+                "Journal has {} bytes left after decoding",
+                cursor.remaining_bytes()
+            );
+        }
+        Ok(value)
+    }
+
+    /// Returns a cursor decoding the values of the journal in the order
+    /// they were committed.
+    pub fn cursor(&self) -> JournalCursor {
+        // The journal is a sequence of words, which need not be aligned in
+        // `bytes`.
+        JournalCursor {
+            words: to_words(&self.bytes),
+            pos: 0,
+            len: self.bytes.len(),
+        }
+    }
+}
+
+/// Decodes the values committed to a [Journal] one at a time
+pub struct JournalCursor {
+    words: Vec<u32>,
+    pos: usize,
+    len: usize,
+}
+
+impl JournalCursor {
+    /// Decode the next value, of type `T`, moving past it.
+    ///
+    /// On failure, the cursor stays where it was.
+    pub fn decode<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let mut rest = &self.words[self.pos..];
+        let value = T::deserialize(&mut Deserializer::new(&mut rest))
+            .with_context(|| format!("decoding journal at byte {}", self.pos * WORD_SIZE))?;
+        self.pos = self.words.len() - rest.len();
+        Ok(value)
+    }
+
+    /// Returns the number of bytes not yet decoded.
+    pub fn remaining_bytes(&self) -> usize {
+        self.len.saturating_sub(self.pos * WORD_SIZE)
+    }
+
+    /// Returns true if every value has been decoded.
+    pub fn is_empty(&self) -> bool {
+        self.remaining_bytes() == 0
+    }
+}
+
+/// Indicates how the execution of a segment or session has terminated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitCode {
+    /// This indicates that the segment reached its cycle limit. Execution
+    /// continues in the next segment, starting from the post-state of this
+    /// one.
+    SystemSplit,
+
+    /// This indicates that the session limit has been reached. The instruction
+    /// at the PC has not been executed, so execution can be resumed from the
+    /// post-state.
+    SessionLimit,
+
+    /// This indicates normal termination of a program with an interior exit
+    /// code returned from the guest.
+    Halted(u32),
+
+    /// This indicates that execution was paused by the host, e.g. at a
+    /// watchpoint. Running the executor again resumes from the next
+    /// instruction.
+    Paused,
+
+    /// This indicates that the guest accessed memory in a way that its page
+    /// permissions forbid. The faulting instruction has no effect.
+    Fault(GuestFault),
+}
+
+/// A guest memory access that could not be performed, ending execution
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GuestFault {
+    /// A store to an address on a page that is not writable
+    Store(u64),
+
+    /// An instruction fetch from an address on a page that is not executable
+    Fetch(u64),
+
+    /// A load from an address that is not aligned to the size of the access
+    MisalignedLoad(u64),
+
+    /// A store to an address that is not aligned to the size of the access
+    MisalignedStore(u64),
+
+    /// An instruction fetch from an address that is not 4-byte aligned
+    MisalignedFetch(u64),
+
+    /// A load or store to the guard page below the stack, at the given
+    /// address, from a stack that has grown past its bottom
+    StackOverflow(u64),
+
+    /// A load from an address that is not mapped, by the guest or by the host
+    /// on its behalf
+    Load(u64),
+
+    /// An instruction at the given address that the guest may not execute,
+    /// such as a write to a read-only CSR
+    IllegalInstruction(u64),
+}
+
+impl fmt::Display for GuestFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Store(addr) => write!(f, "store to non-writable address 0x{addr:x}"),
+            Self::Fetch(addr) => write!(f, "fetch from non-executable address 0x{addr:x}"),
+            Self::MisalignedLoad(addr) => write!(f, "misaligned load from 0x{addr:x}"),
+            Self::MisalignedStore(addr) => write!(f, "misaligned store to 0x{addr:x}"),
+            Self::MisalignedFetch(addr) => write!(f, "misaligned fetch from 0x{addr:x}"),
+            Self::StackOverflow(addr) => write!(f, "guest stack overflow accessing 0x{addr:x}"),
+            Self::Load(addr) => write!(f, "load from unmapped address 0x{addr:x}"),
+            Self::IllegalInstruction(pc) => write!(f, "illegal instruction at 0x{pc:x}"),
+        }
+    }
+}
+
+impl std::error::Error for GuestFault {}
+
+impl ExitCode {
+    /// The number of words in the layout of [ExitCode::to_words].
+    pub const WORDS: usize = 4;
+
+    /// Returns the exit code as words: the kind of exit, then the guest's exit
+    /// code for a halt, or the kind of fault and its address, low first.
+    pub fn to_words(&self) -> [u32; Self::WORDS] {
+        let (kind, code, addr) = match *self {
+            ExitCode::SystemSplit => (0, 0, 0),
+            ExitCode::SessionLimit => (1, 0, 0),
+            ExitCode::Halted(code) => (2, code, 0),
+            ExitCode::Paused => (3, 0, 0),
+            ExitCode::Fault(fault) => {
+                let (kind, addr) = match fault {
+                    GuestFault::Store(addr) => (0, addr),
+                    GuestFault::Fetch(addr) => (1, addr),
+                    GuestFault::MisalignedLoad(addr) => (2, addr),
+                    GuestFault::MisalignedStore(addr) => (3, addr),
+                    GuestFault::MisalignedFetch(addr) => (4, addr),
+                    GuestFault::StackOverflow(addr) => (5, addr),
+                    GuestFault::Load(addr) => (6, addr),
+                    GuestFault::IllegalInstruction(addr) => (7, addr),
+                };
+                (4, kind, addr)
+            }
+        };
+        [kind, code, addr as u32, (addr >> 32) as u32]
+    }
+
+    /// Reads an exit code laid out as by [ExitCode::to_words].
+    pub fn from_words(words: [u32; Self::WORDS]) -> Result<Self> {
+        let [kind, code, ..] = words;
+        let addr = words[2] as u64 | (words[3] as u64) << 32;
+        let fault = |fault: fn(u64) -> GuestFault| Ok(ExitCode::Fault(fault(addr)));
+        match (kind, code) {
+            (0, _) => Ok(ExitCode::SystemSplit),
+            (1, _) => Ok(ExitCode::SessionLimit),
+            (2, code) => Ok(ExitCode::Halted(code)),
+            (3, _) => Ok(ExitCode::Paused),
+            (4, 0) => fault(GuestFault::Store),
+            (4, 1) => fault(GuestFault::Fetch),
+            (4, 2) => fault(GuestFault::MisalignedLoad),
+            (4, 3) => fault(GuestFault::MisalignedStore),
+            (4, 4) => fault(GuestFault::MisalignedFetch),
+            (4, 5) => fault(GuestFault::StackOverflow),
+            (4, 6) => fault(GuestFault::Load),
+            (4, 7) => fault(GuestFault::IllegalInstruction),
+            _ => bail!("Unknown exit code: {words:?}"),
+        }
+    }
+}
+
+/// Returns the journal digest of a segment that committed `bytes`, from the
+/// digest `prev` of the segments before it: `prev` extended with each byte in
+/// turn, as the SHA-256 digest of the digest so far followed by the byte.
+///
+/// The journal digest of the first segment starts from [Digest::ZERO]. Each
+/// segment thus hashes only what it committed, and as the chain takes one
+/// byte at a time, its end does not depend on where the session was split
+/// into segments: it is [Journal::digest], which a guest can compute from the
+/// journal alone.
+pub fn chain_journal_digest(prev: &Digest, bytes: &[u8]) -> Digest {
+    bytes.iter().fold(*prev, |digest, byte| {
+        sha256(&[digest.as_bytes(), &[*byte]].concat())
+    })
+}
+
+/// A claim that a guest halted with exit code 0, having committed a journal
+///
+/// A guest assumes such a claim with `env::verify`, which composes its
+/// session with that of the other guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assumption {
+    /// The image ID of the guest, the page table root of its initial memory
+    /// image
+    pub image_id: Digest,
+
+    /// The digest of the journal of the guest, as by [Journal::digest],
+    /// which the last of its segments binds
+    pub journal_digest: Digest,
+}
+
+/// Returns the digest of the assumptions a segment made, in order: the
+/// SHA-256 digest of the image ID and journal digest of each, or
+/// [Digest::ZERO] if it made none.
+pub fn assumptions_digest(assumptions: &[Assumption]) -> Digest {
+    if assumptions.is_empty() {
+        return Digest::ZERO;
+    }
+    let bytes: Vec<u8> = assumptions
+        .iter()
+        .flat_map(|assumption| {
+            [assumption.image_id, assumption.journal_digest]
+                .into_iter()
+                .flat_map(|digest| digest.as_bytes().to_vec())
+        })
+        .collect();
+    sha256(&bytes)
+}
+
+/// The public inputs and outputs of a segment, which its proof binds
+///
+/// A verifier checks these, and nothing else, about what was executed: the
+/// memory image, PC and journal the segment starts from, those it ends with,
+/// how it exited, and the claims it assumed. Consecutive segments chain, each
+/// starting from the image, PC and journal the previous one ended with, and
+/// extending its journal digest with [chain_journal_digest].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentIo {
+    /// The page table root of the memory image the segment starts from
+    pub pre_image_id: Digest,
+    /// The PC the segment starts at
+    pub pre_pc: u64,
+    /// The length of the journal at the start of the segment
+    pub pre_journal_len: u64,
+    /// The journal digest at the start of the segment: that of the previous
+    /// segment, or [Digest::ZERO] for the first
+    pub pre_journal_digest: Digest,
+    /// The page table root of the memory image the segment ends with
+    pub post_image_id: Digest,
+    /// The PC the segment ends at
+    pub post_pc: u64,
+    /// The length of the journal at the end of the segment
+    pub journal_len: u64,
+    /// The journal digest at the end of the segment, chained as by
+    /// [chain_journal_digest]
+    pub journal_digest: Digest,
+    /// How the segment exited
+    pub exit_code: ExitCode,
+    /// The digest of the claims the segment assumed, as by
+    /// [assumptions_digest]
+    pub assumptions_digest: Digest,
+}
+
+impl SegmentIo {
+    /// The number of words in the layout of [SegmentIo::to_words].
+    pub const WORDS: usize = 5 * DIGEST_WORDS + 8 + ExitCode::WORDS;
+
+    /// Returns the public IO as words, in the order the verifier reads them:
+    /// the pre image ID, PC, journal length and journal digest, then the post
+    /// ones, the exit code as by [ExitCode::to_words] and the assumptions
+    /// digest. PCs and lengths take two words, low first.
+    pub fn to_words(&self) -> Vec<u32> {
+        let halves = |value: u64| [value as u32, (value >> 32) as u32];
+        let mut words = Vec::with_capacity(Self::WORDS);
+        words.extend_from_slice(self.pre_image_id.as_words());
+        words.extend(halves(self.pre_pc));
+        words.extend(halves(self.pre_journal_len));
+        words.extend_from_slice(self.pre_journal_digest.as_words());
+        words.extend_from_slice(self.post_image_id.as_words());
+        words.extend(halves(self.post_pc));
+        words.extend(halves(self.journal_len));
+        words.extend_from_slice(self.journal_digest.as_words());
+        words.extend(self.exit_code.to_words());
+        words.extend_from_slice(self.assumptions_digest.as_words());
+        words
+    }
+
+    /// Reads public IO laid out as by [SegmentIo::to_words].
+    pub fn from_words(words: &[u32]) -> Result<Self> {
+        if words.len() != Self::WORDS {
+            bail!("Segment IO has {} words, not {}", words.len(), Self::WORDS);
+        }
+        let mut at = 0;
+        let mut next = |len: usize| {
+            at += len;
+            &words[at - len..at]
+        };
+        let digest = |words: &[u32]| Digest::new(words.try_into().unwrap());
+        let double_word = |words: &[u32]| words[0] as u64 | (words[1] as u64) << 32;
+        Ok(Self {
+            pre_image_id: digest(next(DIGEST_WORDS)),
+            pre_pc: double_word(next(2)),
+            pre_journal_len: double_word(next(2)),
+            pre_journal_digest: digest(next(DIGEST_WORDS)),
+            post_image_id: digest(next(DIGEST_WORDS)),
+            post_pc: double_word(next(2)),
+            journal_len: double_word(next(2)),
+            journal_digest: digest(next(DIGEST_WORDS)),
+            exit_code: ExitCode::from_words(next(ExitCode::WORDS).try_into().unwrap())?,
+            assumptions_digest: digest(next(DIGEST_WORDS)),
+        })
+    }
+}
+
+/// Check that the public IO of the segments of a session chains, from the
+/// first segment to the last: that each starts from the memory image, PC and
+/// journal the previous one ended with, having exited with
+/// [ExitCode::SystemSplit], and that the last ended with `exit_code`. Each
+/// segment must bind the part of `journal` it committed, and together they
+/// must commit all of it. A session without segments proves nothing, and
+/// fails.
+pub fn verify_segments(ios: &[SegmentIo], journal: &Journal, exit_code: ExitCode) -> Result<()> {
+    let (Some(first), Some(last)) = (ios.first(), ios.last()) else {
+        bail!("Session has no segments");
+    };
+    if (first.pre_journal_len, first.pre_journal_digest) != (0, Digest::ZERO) {
+        bail!("Segment 0 does not start from an empty journal");
+    }
+    for (idx, pair) in ios.windows(2).enumerate() {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if prev.exit_code != ExitCode::SystemSplit {
+            bail!(
+                "Segment {idx} ended with {:?}, yet another follows",
+                prev.exit_code
+            );
+        }
+        if (prev.post_image_id, prev.post_pc) != (next.pre_image_id, next.pre_pc) {
+            bail!(
+                "Segment {} does not start where segment {idx} ended",
+                idx + 1
+            );
+        }
+        if (prev.journal_len, prev.journal_digest)
+            != (next.pre_journal_len, next.pre_journal_digest)
+        {
+            bail!(
+                "Segment {} does not start from the journal segment {idx} ended with",
+                idx + 1
+            );
+        }
+    }
+    if last.exit_code != exit_code {
+        bail!(
+            "Last segment ended with {:?}, not {:?}",
+            last.exit_code,
+            exit_code
+        );
+    }
+    let bytes = &journal.bytes;
+    for (idx, io) in ios.iter().enumerate() {
+        let (start, end) = (io.pre_journal_len as usize, io.journal_len as usize);
+        let Some(committed) = bytes.get(start..end) else {
+            bail!(
+                "Segment {idx} commits the journal at {start}..{end}, outside 0..{}",
+                bytes.len()
+            );
+        };
+        if chain_journal_digest(&io.pre_journal_digest, committed) != io.journal_digest {
+            bail!("Segment {idx} does not bind the journal it committed");
+        }
+    }
+    if last.journal_len as usize != bytes.len() {
+        bail!(
+            "Journal has {} bytes no segment committed",
+            bytes.len() - last.journal_len as usize
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chain_journal_digest, verify_segments, ExitCode, GuestFault, Journal, SegmentIo};
+    use crate::{binfmt::digest::sha256, Digest};
+
+    // Two segments that split after committing `first`, then halt after
+    // committing `second`.
+    fn segments(first: &[u8], second: &[u8]) -> [SegmentIo; 2] {
+        let image_ids = [1, 2, 3].map(|n: u32| sha256(&n.to_le_bytes()));
+        let split_digest = chain_journal_digest(&Digest::ZERO, first);
+        let split = SegmentIo {
+            pre_image_id: image_ids[0],
+            pre_pc: 0x1000,
+            pre_journal_len: 0,
+            pre_journal_digest: Digest::ZERO,
+            post_image_id: image_ids[1],
+            post_pc: 0x1100,
+            journal_len: first.len() as u64,
+            journal_digest: split_digest,
+            exit_code: ExitCode::SystemSplit,
+            assumptions_digest: Digest::ZERO,
+        };
+        let halt = SegmentIo {
+            pre_image_id: image_ids[1],
+            pre_pc: 0x1100,
+            pre_journal_len: first.len() as u64,
+            pre_journal_digest: split_digest,
+            post_image_id: image_ids[2],
+            post_pc: 0x1200,
+            journal_len: (first.len() + second.len()) as u64,
+            journal_digest: chain_journal_digest(&split_digest, second),
+            exit_code: ExitCode::Halted(0),
+            assumptions_digest: Digest::ZERO,
+        };
+        [split, halt]
+    }
+
+    #[test]
+    fn words_round_trip() {
+        for exit_code in [
+            ExitCode::SystemSplit,
+            ExitCode::SessionLimit,
+            ExitCode::Halted(7),
+            ExitCode::Paused,
+            ExitCode::Fault(GuestFault::StackOverflow(0x1234_5678_9abc)),
+            ExitCode::Fault(GuestFault::IllegalInstruction(0x1000)),
+        ] {
+            assert_eq!(
+                ExitCode::from_words(exit_code.to_words()).unwrap(),
+                exit_code
+            );
+        }
+        assert!(ExitCode::from_words([4, 8, 0, 0]).is_err());
+
+        for io in segments(b"public", b"") {
+            let words = io.to_words();
+            assert_eq!(words.len(), SegmentIo::WORDS);
+            assert_eq!(SegmentIo::from_words(&words).unwrap(), io);
+            assert!(SegmentIo::from_words(&words[1..]).is_err());
+        }
+    }
+
+    #[test]
+    fn segment_chain() {
+        let journal = Journal::new(b"publicjournal".to_vec());
+        let ios = segments(b"public", b"journal");
+        verify_segments(&ios, &journal, ExitCode::Halted(0)).unwrap();
+        assert!(verify_segments(&[], &journal, ExitCode::Halted(0)).is_err());
+
+        // The session must end as the last segment did.
+        assert!(verify_segments(&ios, &journal, ExitCode::Paused).is_err());
+        // Every byte of the journal must be committed, as it was.
+        let extended = Journal::new(b"publicjournal!".to_vec());
+        assert!(verify_segments(&ios, &extended, ExitCode::Halted(0)).is_err());
+        let altered = Journal::new(b"publicjournax".to_vec());
+        assert!(verify_segments(&ios, &altered, ExitCode::Halted(0)).is_err());
+
+        let mut broken = ios;
+        broken[1].pre_pc += 4;
+        assert!(verify_segments(&broken, &journal, ExitCode::Halted(0)).is_err());
+        let mut broken = ios;
+        broken[0].exit_code = ExitCode::Paused;
+        assert!(verify_segments(&broken, &journal, ExitCode::Halted(0)).is_err());
+        let mut broken = ios;
+        broken[0].pre_journal_digest = sha256(b"earlier");
+        broken[0].journal_digest = chain_journal_digest(&broken[0].pre_journal_digest, b"public");
+        assert!(verify_segments(&broken, &journal, ExitCode::Halted(0)).is_err());
+    }
+
+    #[test]
+    fn journal_cursor() {
+        let words = crate::serde::to_vec(&(7u32, 9u8)).unwrap();
+        let journal = Journal::new(bytemuck::cast_slice(&words).to_vec());
+        assert_eq!(journal.decode::<(u32, u8)>().unwrap(), (7, 9));
+        let mut cursor = journal.cursor();
+        assert_eq!(cursor.decode::<u32>().unwrap(), 7);
+        assert!(!cursor.is_empty());
+        assert!(journal.decode::<u32>().is_err());
+    }
+}
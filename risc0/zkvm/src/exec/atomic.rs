@@ -0,0 +1,242 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The RV64A atomic memory operation extension.
+//!
+//! The guest runs on a single hart, so every memory operation is atomic
+//! already: an AMO is simply a load followed by a store, and the acquire and
+//! release bits have nothing to order. A store conditional succeeds if the
+//! last load reserved was from the same address, and no store conditional has
+//! been made since.
+//!
+//! Unlike plain loads and stores, misaligned atomics are never split into
+//! bytes, even if misaligned accesses are emulated: they always fault.
+
+use anyhow::{bail, Result};
+use rrs_lib::{MemAccessSize, Memory};
+
+use super::monitor::MemoryMonitor;
+use crate::{binfmt::paged::access_len, GuestFault};
+
+const FUNCT5_LR: u32 = 0b00010;
+const FUNCT5_SC: u32 = 0b00011;
+
+/// Execute the A instruction `insn`, which accesses `addr` with the source
+/// operand `src`, and return the value of its destination register.
+///
+/// `reservation` holds the address reserved by the last load reserved.
+pub(crate) fn execute(
+    monitor: &mut MemoryMonitor,
+    reservation: &mut Option<u64>,
+    insn: u32,
+    addr: u64,
+    src: u64,
+) -> Result<u64> {
+    let funct3 = (insn >> 12) & 0x7;
+    let funct5 = insn >> 27;
+    let size = match funct3 {
+        0b010 => MemAccessSize::Word,
+        0b011 => MemAccessSize::DoubleWord,
+        _ => bail!("Illegal instruction: {insn:#010x}"),
+    };
+    let is_word = funct3 == 0b010;
+
+    if addr % access_len(size) != 0 {
+        monitor.raise_fault(match funct5 {
+            FUNCT5_LR => GuestFault::MisalignedLoad(addr),
+            _ => GuestFault::MisalignedStore(addr),
+        });
+        return Ok(0);
+    }
+
+    if funct5 == FUNCT5_SC {
+        if reservation.take() != Some(addr) {
+            return Ok(1);
+        }
+        monitor.write_mem(addr, size, src);
+        return Ok(0);
+    }
+
+    let loaded = monitor.read_mem(addr, size).unwrap();
+    // Word operations work on the sign-extended low 32 bits of both operands.
+    let (loaded, src) = match is_word {
+        true => (loaded as u32 as i32 as u64, src as u32 as i32 as u64),
+        false => (loaded, src),
+    };
+    if funct5 == FUNCT5_LR {
+        *reservation = Some(addr);
+        return Ok(loaded);
+    }
+
+    let stored = match funct5 {
+        // AMOADD
+        0b00000 => loaded.wrapping_add(src),
+        // AMOSWAP
+        0b00001 => src,
+        // AMOXOR
+        0b00100 => loaded ^ src,
+        // AMOOR
+        0b01000 => loaded | src,
+        // AMOAND
+        0b01100 => loaded & src,
+        // AMOMIN
+        0b10000 => (loaded as i64).min(src as i64) as u64,
+        // AMOMAX
+        0b10100 => (loaded as i64).max(src as i64) as u64,
+        // AMOMINU
+        0b11000 if is_word => (loaded as u32).min(src as u32) as u64,
+        0b11000 => loaded.min(src),
+        // AMOMAXU
+        0b11100 if is_word => (loaded as u32).max(src as u32) as u64,
+        0b11100 => loaded.max(src),
+        _ => bail!("Illegal instruction: {insn:#010x}"),
+    };
+    monitor.write_mem(addr, size, stored);
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::execute;
+    use crate::{
+        exec::{monitor::MemoryMonitor, OpCodeResult},
+        testutil::{image, RET},
+        GuestFault,
+    };
+
+    const ADDR: u64 = 0x4000;
+
+    // Encode the A instruction `funct5` of width `funct3`.
+    fn insn(funct5: u32, funct3: u32) -> u32 {
+        funct5 << 27 | 12 << 20 | 11 << 15 | funct3 << 12 | 10 << 7 | 0b0101111
+    }
+
+    fn monitor() -> MemoryMonitor {
+        let mut image = image(&[RET], &[]);
+        image.write_u64(ADDR, 0xffff_ffff_8000_0005).unwrap();
+        MemoryMonitor::new(image)
+    }
+
+    fn commit(monitor: &mut MemoryMonitor) {
+        monitor.save_op(OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+    }
+
+    #[test]
+    fn amo() {
+        let mut monitor = monitor();
+        let mut reservation = None;
+        let mut amo = |funct5, funct3, src| {
+            let rd = execute(
+                &mut monitor,
+                &mut reservation,
+                insn(funct5, funct3),
+                ADDR,
+                src,
+            );
+            commit(&mut monitor);
+            (rd.unwrap(), monitor.load_u64(ADDR).unwrap())
+        };
+
+        // amoadd.w only changes the low word, and sign-extends what it loaded.
+        assert_eq!(
+            amo(0b00000, 0b010, 3),
+            (0xffff_ffff_8000_0005, 0xffff_ffff_8000_0008)
+        );
+        // amominu.w compares the words unsigned, amomin.d the double words
+        // signed.
+        assert_eq!(amo(0b11000, 0b010, 1).1, 0xffff_ffff_0000_0001);
+        assert_eq!(amo(0b10000, 0b011, 7).1, 0xffff_ffff_0000_0001);
+        assert_eq!(amo(0b11100, 0b011, 7).1, 0xffff_ffff_0000_0001);
+        assert_eq!(amo(0b00001, 0b011, 9), (0xffff_ffff_0000_0001, 9));
+        assert_eq!(amo(0b01000, 0b011, 6), (9, 15));
+        assert_eq!(amo(0b01100, 0b011, 5), (15, 5));
+        assert_eq!(amo(0b00100, 0b011, 1), (5, 4));
+        assert_eq!(amo(0b10100, 0b010, u64::MAX), (4, 4));
+    }
+
+    #[test]
+    fn load_reserved() {
+        let mut monitor = monitor();
+        let mut reservation = None;
+        let lr_d = insn(0b00010, 0b011);
+        let sc_d = insn(0b00011, 0b011);
+
+        // A store conditional without a reservation fails.
+        assert_eq!(
+            execute(&mut monitor, &mut reservation, sc_d, ADDR, 1).unwrap(),
+            1
+        );
+        commit(&mut monitor);
+        assert_eq!(monitor.load_u64(ADDR).unwrap(), 0xffff_ffff_8000_0005);
+
+        let loaded = execute(&mut monitor, &mut reservation, lr_d, ADDR, 0).unwrap();
+        assert_eq!(loaded, 0xffff_ffff_8000_0005);
+        assert_eq!(
+            execute(&mut monitor, &mut reservation, sc_d, ADDR, 1).unwrap(),
+            0
+        );
+        commit(&mut monitor);
+        assert_eq!(monitor.load_u64(ADDR).unwrap(), 1);
+
+        // The reservation is spent, and only covers the address loaded.
+        assert_eq!(
+            execute(&mut monitor, &mut reservation, sc_d, ADDR, 2).unwrap(),
+            1
+        );
+        execute(&mut monitor, &mut reservation, lr_d, ADDR, 0).unwrap();
+        assert_eq!(
+            execute(&mut monitor, &mut reservation, sc_d, ADDR + 8, 2).unwrap(),
+            1
+        );
+        commit(&mut monitor);
+        assert_eq!(monitor.load_u64(ADDR).unwrap(), 1);
+        assert_eq!(monitor.load_u64(ADDR + 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn misaligned() {
+        let mut monitor = monitor();
+        monitor.emulate_misaligned = true;
+        let mut reservation = None;
+
+        execute(
+            &mut monitor,
+            &mut reservation,
+            insn(0b00010, 0b010),
+            ADDR + 2,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            monitor.take_fault(),
+            Some(GuestFault::MisalignedLoad(ADDR + 2))
+        );
+        assert_eq!(reservation, None);
+        execute(
+            &mut monitor,
+            &mut reservation,
+            insn(0b00000, 0b011),
+            ADDR + 4,
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            monitor.take_fault(),
+            Some(GuestFault::MisalignedStore(ADDR + 4))
+        );
+        commit(&mut monitor);
+        assert_eq!(monitor.load_u64(ADDR).unwrap(), 0xffff_ffff_8000_0005);
+    }
+}
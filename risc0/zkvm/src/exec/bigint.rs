@@ -0,0 +1,175 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The big integer accelerator.
+//!
+//! The `bigint` host call multiplies or adds two 256 or 384 bit integers
+//! modulo a third, which is the core of the field arithmetic of elliptic
+//! curves such as secp256k1, P-256 and BLS12-381. Integers are little-endian,
+//! and a zero modulus stands for 2 to the width, so that the result is
+//! truncated.
+
+use anyhow::{bail, Result};
+use risc0_zkvm_platform::syscall::bigint::{OP_ADD, OP_MUL};
+
+use super::to_double_words;
+
+/// The cycles charged for an operation.
+pub(crate) const BIGINT_CYCLES: usize = 10;
+
+/// Returns the length in bytes of the integers of `width` bits.
+pub(crate) fn byte_len(width: u64) -> Result<u64> {
+    match width {
+        256 | 384 => Ok(width / 8),
+        _ => bail!("Unsupported bigint width: {width}"),
+    }
+}
+
+/// Returns `x` times `y`, which has as many limbs as both together.
+fn mul(x: &[u64], y: &[u64]) -> Vec<u64> {
+    let mut product = vec![0; x.len() + y.len()];
+    for (i, &x) in x.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in y.iter().enumerate() {
+            let sum = x as u128 * y as u128 + product[i + j] as u128 + carry;
+            product[i + j] = sum as u64;
+            carry = sum >> 64;
+        }
+        product[i + y.len()] = carry as u64;
+    }
+    product
+}
+
+/// Returns `x` plus `y`, which has one more limb than `x`.
+fn add(x: &[u64], y: &[u64]) -> Vec<u64> {
+    let mut sum = Vec::with_capacity(x.len() + 1);
+    let mut carry = false;
+    for (&x, &y) in x.iter().zip(y) {
+        let (limb, carry1) = x.overflowing_add(y);
+        let (limb, carry2) = limb.overflowing_add(carry as u64);
+        sum.push(limb);
+        carry = carry1 || carry2;
+    }
+    sum.push(carry as u64);
+    sum
+}
+
+/// Returns whether `x` is at least `y`, which have as many limbs.
+fn at_least(x: &[u64], y: &[u64]) -> bool {
+    x.iter().rev().cmp(y.iter().rev()).is_ge()
+}
+
+/// Subtract `y` from `x`, which have as many limbs, in place.
+fn sub_assign(x: &mut [u64], y: &[u64]) {
+    let mut borrow = false;
+    for (x, &y) in x.iter_mut().zip(y) {
+        let (limb, borrow1) = x.overflowing_sub(y);
+        let (limb, borrow2) = limb.overflowing_sub(borrow as u64);
+        *x = limb;
+        borrow = borrow1 || borrow2;
+    }
+}
+
+/// Returns `value` modulo `modulus`, with as many limbs as `modulus`.
+fn reduce(value: &[u64], modulus: &[u64]) -> Vec<u64> {
+    // Shift the value into the remainder a bit at a time, subtracting the
+    // modulus whenever the remainder reaches it. The remainder has a spare
+    // limb, as shifting may carry out of its top limb.
+    let mut modulus = modulus.to_vec();
+    modulus.push(0);
+    let mut rem = vec![0; modulus.len()];
+    for bit in (0..value.len() * 64).rev() {
+        let mut carry = (value[bit / 64] >> (bit % 64)) & 1;
+        for limb in rem.iter_mut() {
+            let top = *limb >> 63;
+            *limb = *limb << 1 | carry;
+            carry = top;
+        }
+        if at_least(&rem, &modulus) {
+            sub_assign(&mut rem, &modulus);
+        }
+    }
+    rem.pop();
+    rem
+}
+
+/// Returns `x` `op` `y` modulo `modulus`, all in little-endian bytes of the
+/// same length.
+pub(crate) fn compute(op: u64, x: &[u8], y: &[u8], modulus: &[u8]) -> Result<Vec<u8>> {
+    let (x, y, modulus) = (
+        to_double_words(x),
+        to_double_words(y),
+        to_double_words(modulus),
+    );
+    let value = match op {
+        OP_MUL => mul(&x, &y),
+        OP_ADD => add(&x, &y),
+        _ => bail!("Unknown bigint operation: {op}"),
+    };
+    let result = match modulus.iter().all(|&limb| limb == 0) {
+        true => value[..modulus.len()].to_vec(),
+        false => reduce(&value, &modulus),
+    };
+    Ok(result.iter().flat_map(|limb| limb.to_le_bytes()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm_platform::syscall::bigint::{OP_ADD, OP_MUL};
+
+    use super::compute;
+
+    fn bytes(limbs: &[u64]) -> Vec<u8> {
+        limbs.iter().flat_map(|limb| limb.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn modular_arithmetic() {
+        // The field prime of secp256k1, 2^256 - 2^32 - 977
+        let p = bytes(&[0xfffffffefffffc2f, u64::MAX, u64::MAX, u64::MAX]);
+        let minus_one = bytes(&[0xfffffffefffffc2e, u64::MAX, u64::MAX, u64::MAX]);
+        let one = bytes(&[1, 0, 0, 0]);
+
+        assert_eq!(compute(OP_MUL, &minus_one, &minus_one, &p).unwrap(), one);
+        assert_eq!(compute(OP_ADD, &minus_one, &one, &p).unwrap(), [0; 32]);
+        let half = bytes(&[0, 0, 0, 1 << 63]);
+        let expected = bytes(&[0x400001e84003a334, 0, 0, 1 << 62]);
+        assert_eq!(compute(OP_MUL, &half, &half, &p).unwrap(), expected);
+        assert!(compute(2, &one, &one, &p).is_err());
+    }
+
+    #[test]
+    fn widths() {
+        // The field prime of BLS12-381
+        let p = [
+            0xb9feffffffffaaab,
+            0x1eabfffeb153ffff,
+            0x6730d2a0f6b0f624,
+            0x64774b84f38512bf,
+            0x4b1ba7b6434bacd7,
+            0x1a0111ea397fe69a,
+        ];
+        let mut minus_one = p;
+        minus_one[0] -= 1;
+        let (p, minus_one) = (bytes(&p), bytes(&minus_one));
+        let one = bytes(&[1, 0, 0, 0, 0, 0]);
+        assert_eq!(compute(OP_MUL, &minus_one, &minus_one, &p).unwrap(), one);
+
+        // A zero modulus truncates.
+        let max = bytes(&[u64::MAX; 6]);
+        let mut sum = max.clone();
+        sum[0] = 0xfe;
+        assert_eq!(compute(OP_ADD, &max, &max, &[0; 48]).unwrap(), sum);
+    }
+}
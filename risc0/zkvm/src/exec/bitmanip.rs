@@ -0,0 +1,136 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The Zba, Zbb and Zbs bit manipulation extensions.
+//!
+//! The instructions are decoded into a [BitOp] with the rest of the
+//! instruction set, in [crate::opcode].
+
+use crate::opcode::BitOp;
+
+impl BitOp {
+    /// Compute the result of the instruction `insn` for the operands `rs1`
+    /// and `rs2`.
+    pub(crate) fn execute(self, insn: u32, rs1: u64, rs2: u64) -> u64 {
+        use BitOp::*;
+
+        let shamt = (insn >> 20) & 0x3f;
+        let uw = rs1 as u32 as u64;
+        match self {
+            AddUw => uw.wrapping_add(rs2),
+            Sh1add => (rs1 << 1).wrapping_add(rs2),
+            Sh1addUw => (uw << 1).wrapping_add(rs2),
+            Sh2add => (rs1 << 2).wrapping_add(rs2),
+            Sh2addUw => (uw << 2).wrapping_add(rs2),
+            Sh3add => (rs1 << 3).wrapping_add(rs2),
+            Sh3addUw => (uw << 3).wrapping_add(rs2),
+            SlliUw => uw << shamt,
+            Andn => rs1 & !rs2,
+            Orn => rs1 | !rs2,
+            Xnor => !(rs1 ^ rs2),
+            Clz => rs1.leading_zeros() as u64,
+            Clzw => (rs1 as u32).leading_zeros() as u64,
+            Ctz => rs1.trailing_zeros() as u64,
+            Ctzw => (rs1 as u32).trailing_zeros() as u64,
+            Cpop => rs1.count_ones() as u64,
+            Cpopw => (rs1 as u32).count_ones() as u64,
+            Max => (rs1 as i64).max(rs2 as i64) as u64,
+            Maxu => rs1.max(rs2),
+            Min => (rs1 as i64).min(rs2 as i64) as u64,
+            Minu => rs1.min(rs2),
+            SextB => rs1 as i8 as u64,
+            SextH => rs1 as i16 as u64,
+            ZextH => rs1 as u16 as u64,
+            Rol => rs1.rotate_left(rs2 as u32 & 0x3f),
+            Rolw => (rs1 as u32).rotate_left(rs2 as u32 & 0x1f) as i32 as u64,
+            Ror => rs1.rotate_right(rs2 as u32 & 0x3f),
+            Rori => rs1.rotate_right(shamt),
+            Roriw => (rs1 as u32).rotate_right(shamt & 0x1f) as i32 as u64,
+            Rorw => (rs1 as u32).rotate_right(rs2 as u32 & 0x1f) as i32 as u64,
+            OrcB => u64::from_le_bytes(rs1.to_le_bytes().map(|b| if b == 0 { 0 } else { 0xff })),
+            Rev8 => rs1.swap_bytes(),
+            Bclr => rs1 & !(1 << (rs2 & 0x3f)),
+            Bclri => rs1 & !(1 << shamt),
+            Bext => (rs1 >> (rs2 & 0x3f)) & 1,
+            Bexti => (rs1 >> shamt) & 1,
+            Binv => rs1 ^ 1 << (rs2 & 0x3f),
+            Binvi => rs1 ^ 1 << shamt,
+            Bset => rs1 | 1 << (rs2 & 0x3f),
+            Bseti => rs1 | 1 << shamt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opcode::BitOp;
+
+    fn exec(insn: u32, rs1: u64, rs2: u64) -> (&'static str, u64) {
+        let op = BitOp::decode(insn).unwrap();
+        (op.mnemonic(), op.execute(insn, rs1, rs2))
+    }
+
+    #[test]
+    fn decode() {
+        // slli a0, a0, 3, srai a0, a0, 3 and add a0, a0, a1
+        for insn in [0x00351513, 0x40355513, 0x00b50533] {
+            assert_eq!(BitOp::decode(insn), None);
+        }
+        assert_eq!(BitOp::decode(0x60051513).unwrap().extension(), "Zbb");
+        assert_eq!(BitOp::decode(0x20b52533).unwrap().extension(), "Zba");
+        assert_eq!(BitOp::decode(0x48b51533).unwrap().extension(), "Zbs");
+    }
+
+    #[test]
+    fn execute() {
+        // sh1add a0, a0, a1
+        assert_eq!(exec(0x20b52533, 3, 10), ("SH1ADD", 16));
+        // add.uw a0, a0, a1
+        assert_eq!(exec(0x08b5053b, u64::MAX, 1), ("ADD.UW", 1 << 32));
+        // slli.uw a0, a0, 4
+        assert_eq!(
+            exec(0x0845151b, 0xffff_ffff_8000_0001, 0),
+            ("SLLI.UW", 0x8_0000_0010)
+        );
+        // andn a0, a0, a1
+        assert_eq!(exec(0x40b57533, 0b1100, 0b1010), ("ANDN", 0b0100));
+        // clz a0, a0, ctz a0, a0 and cpopw a0, a0
+        assert_eq!(exec(0x60051513, 1 << 40, 0), ("CLZ", 23));
+        assert_eq!(exec(0x60151513, 0, 0), ("CTZ", 64));
+        assert_eq!(exec(0x6025151b, u64::MAX, 0), ("CPOPW", 32));
+        // max a0, a0, a1 and minu a0, a0, a1
+        assert_eq!(exec(0x0ab56533, -1i64 as u64, 1), ("MAX", 1));
+        assert_eq!(exec(0x0ab55533, -1i64 as u64, 1), ("MINU", 1));
+        // sext.b a0, a0 and zext.h a0, a0
+        assert_eq!(exec(0x60451513, 0x80, 0), ("SEXT.B", -128i64 as u64));
+        assert_eq!(exec(0x0805453b, u64::MAX, 0), ("ZEXT.H", 0xffff));
+        // rori a0, a0, 4 and rolw a0, a0, a1
+        assert_eq!(exec(0x60455513, 0x1234, 0), ("RORI", 0x4000_0000_0000_0123));
+        assert_eq!(exec(0x60b5153b, 0x8000_0001, 1), ("ROLW", 3));
+        // orc.b a0, a0 and rev8 a0, a0
+        assert_eq!(
+            exec(0x28755513, 0x0100_0000_0000_3000, 0),
+            ("ORC.B", 0xff00_0000_0000_ff00)
+        );
+        assert_eq!(
+            exec(0x6b855513, 0x0102_0304_0506_0708, 0),
+            ("REV8", 0x0807_0605_0403_0201)
+        );
+        // bclr a0, a0, a1, bexti a0, a0, 63, binvi a0, a0, 1 and bset a0, a0, a1
+        assert_eq!(exec(0x48b51533, 0b111, 1), ("BCLR", 0b101));
+        assert_eq!(exec(0x4bf55513, 1 << 63, 0), ("BEXTI", 1));
+        assert_eq!(exec(0x68151513, 0b10, 0), ("BINVI", 0));
+        assert_eq!(exec(0x28b51533, 0, 65), ("BSET", 0b10));
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The BLS12-381 pairing accelerator.
+//!
+//! Points are in the uncompressed encoding of the zcash serialization, and
+//! scalars in 32 little-endian bytes, reduced modulo the group order.
+
+use anyhow::{bail, Result};
+use blstrs::{Bls12, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Scalar};
+use group::{Curve, Group};
+use pairing::{MillerLoopResult, MultiMillerLoop};
+
+use super::pairing::PairingCurve;
+
+/// The BLS12-381 curve, as served by the `bls12_381` host call
+pub(crate) struct Bls12_381;
+
+fn g1(bytes: &[u8]) -> Result<G1Affine> {
+    match Option::from(G1Affine::from_uncompressed(bytes.try_into()?)) {
+        Some(point) => Ok(point),
+        None => bail!("Invalid G1 point"),
+    }
+}
+
+fn g2(bytes: &[u8]) -> Result<G2Affine> {
+    match Option::from(G2Affine::from_uncompressed(bytes.try_into()?)) {
+        Some(point) => Ok(point),
+        None => bail!("Invalid G2 point"),
+    }
+}
+
+fn scalar(bytes: &[u8]) -> Result<Scalar> {
+    match Option::from(Scalar::from_bytes_le(bytes.try_into()?)) {
+        Some(scalar) => Ok(scalar),
+        None => bail!("Scalar is not reduced"),
+    }
+}
+
+impl PairingCurve for Bls12_381 {
+    const G1_LEN: u64 = 96;
+    const G2_LEN: u64 = 192;
+    const SCALAR_LEN: u64 = 32;
+
+    fn g1_add(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>> {
+        let sum = G1Projective::from(g1(lhs)?) + g1(rhs)?;
+        Ok(sum.to_affine().to_uncompressed().to_vec())
+    }
+
+    fn g1_mul(point: &[u8], scalar: &[u8]) -> Result<Vec<u8>> {
+        let product = G1Projective::from(g1(point)?) * self::scalar(scalar)?;
+        Ok(product.to_affine().to_uncompressed().to_vec())
+    }
+
+    fn g2_add(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>> {
+        let sum = G2Projective::from(g2(lhs)?) + g2(rhs)?;
+        Ok(sum.to_affine().to_uncompressed().to_vec())
+    }
+
+    fn g2_mul(point: &[u8], scalar: &[u8]) -> Result<Vec<u8>> {
+        let product = G2Projective::from(g2(point)?) * self::scalar(scalar)?;
+        Ok(product.to_affine().to_uncompressed().to_vec())
+    }
+
+    fn pairing_check(pairs: &[u8]) -> Result<bool> {
+        let pairs = pairs
+            .chunks((Self::G1_LEN + Self::G2_LEN) as usize)
+            .map(|pair| {
+                let (p, q) = pair.split_at(Self::G1_LEN as usize);
+                Ok((g1(p)?, G2Prepared::from(g2(q)?)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let terms: Vec<_> = pairs.iter().map(|(p, q)| (p, q)).collect();
+        let result = Bls12::multi_miller_loop(&terms).final_exponentiation();
+        Ok(bool::from(result.is_identity()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blstrs::{G1Affine, G2Affine, Scalar};
+    use group::prime::PrimeCurveAffine;
+
+    use super::{Bls12_381, PairingCurve};
+
+    fn g1_generator() -> [u8; 96] {
+        G1Affine::generator().to_uncompressed()
+    }
+
+    fn scalar(value: u64) -> [u8; 32] {
+        Scalar::from(value).to_bytes_le()
+    }
+
+    #[test]
+    fn g1_arithmetic() {
+        let g = g1_generator();
+        let double = Bls12_381::g1_add(&g, &g).unwrap();
+        assert_eq!(double, Bls12_381::g1_mul(&g, &scalar(2)).unwrap());
+        assert!(Bls12_381::g1_add(&g, &[0; 96]).is_err());
+        assert!(Bls12_381::g1_mul(&g, &[0xff; 32]).is_err());
+    }
+
+    #[test]
+    fn bls_signature() {
+        // A BLS signature over G2: sig = sk * H, pk = sk * G1, and
+        // e(pk, H) * e(-G1, sig) is the identity.
+        let sk = scalar(0x5eed);
+        let h = G2Affine::generator().to_uncompressed();
+        let pk = Bls12_381::g1_mul(&g1_generator(), &sk).unwrap();
+        let sig = Bls12_381::g2_mul(&h, &sk).unwrap();
+        let neg_g1 = (-G1Affine::generator()).to_uncompressed();
+
+        let pairs = |pk: &[u8], sig: &[u8]| [pk, &h, &neg_g1, sig].concat();
+        assert!(Bls12_381::pairing_check(&pairs(&pk, &sig)).unwrap());
+        let wrong = Bls12_381::g2_mul(&h, &scalar(2)).unwrap();
+        assert!(!Bls12_381::pairing_check(&pairs(&pk, &wrong)).unwrap());
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The BN254 pairing accelerator.
+//!
+//! Points and scalars are encoded as by the Ethereum precompiles, so that
+//! SNARKs verified on Ethereum can be verified in the guest unchanged:
+//! coordinates are 32 big-endian bytes, with the imaginary part of each G2
+//! coordinate first, and the point at infinity is all zeros. Scalars are 32
+//! big-endian bytes, reduced modulo the group order.
+
+use anyhow::{anyhow, Result};
+use substrate_bn::{pairing_batch, AffineG1, AffineG2, Fq, Fq2, Fr, Group, Gt, G1, G2};
+
+use super::pairing::PairingCurve;
+
+/// The BN254 curve, as served by the `bn254` host call
+pub(crate) struct Bn254;
+
+fn fq(bytes: &[u8]) -> Result<Fq> {
+    Fq::from_slice(bytes).map_err(|_| anyhow!("Coordinate is not reduced"))
+}
+
+fn fq_bytes(value: Fq) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes).unwrap();
+    bytes
+}
+
+fn g1(bytes: &[u8]) -> Result<G1> {
+    let (x, y) = (fq(&bytes[..32])?, fq(&bytes[32..])?);
+    if x.is_zero() && y.is_zero() {
+        return Ok(G1::zero());
+    }
+    let point = AffineG1::new(x, y).map_err(|_| anyhow!("Invalid G1 point"))?;
+    Ok(point.into())
+}
+
+fn g1_bytes(point: G1) -> Vec<u8> {
+    match AffineG1::from_jacobian(point) {
+        Some(point) => [fq_bytes(point.x()), fq_bytes(point.y())].concat(),
+        None => vec![0; Bn254::G1_LEN as usize],
+    }
+}
+
+fn fq2(bytes: &[u8]) -> Result<Fq2> {
+    Ok(Fq2::new(fq(&bytes[32..])?, fq(&bytes[..32])?))
+}
+
+fn fq2_bytes(value: Fq2) -> [u8; 64] {
+    let mut bytes = [0; 64];
+    bytes[..32].copy_from_slice(&fq_bytes(value.imaginary()));
+    bytes[32..].copy_from_slice(&fq_bytes(value.real()));
+    bytes
+}
+
+fn g2(bytes: &[u8]) -> Result<G2> {
+    let (x, y) = (fq2(&bytes[..64])?, fq2(&bytes[64..])?);
+    if x.is_zero() && y.is_zero() {
+        return Ok(G2::zero());
+    }
+    let point = AffineG2::new(x, y).map_err(|_| anyhow!("Invalid G2 point"))?;
+    Ok(point.into())
+}
+
+fn g2_bytes(point: G2) -> Vec<u8> {
+    match AffineG2::from_jacobian(point) {
+        Some(point) => [fq2_bytes(point.x()), fq2_bytes(point.y())].concat(),
+        None => vec![0; Bn254::G2_LEN as usize],
+    }
+}
+
+fn scalar(bytes: &[u8]) -> Result<Fr> {
+    Fr::from_slice(bytes).map_err(|_| anyhow!("Invalid scalar"))
+}
+
+impl PairingCurve for Bn254 {
+    const G1_LEN: u64 = 64;
+    const G2_LEN: u64 = 128;
+    const SCALAR_LEN: u64 = 32;
+
+    fn g1_add(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>> {
+        Ok(g1_bytes(g1(lhs)? + g1(rhs)?))
+    }
+
+    fn g1_mul(point: &[u8], scalar: &[u8]) -> Result<Vec<u8>> {
+        Ok(g1_bytes(g1(point)? * self::scalar(scalar)?))
+    }
+
+    fn g2_add(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>> {
+        Ok(g2_bytes(g2(lhs)? + g2(rhs)?))
+    }
+
+    fn g2_mul(point: &[u8], scalar: &[u8]) -> Result<Vec<u8>> {
+        Ok(g2_bytes(g2(point)? * self::scalar(scalar)?))
+    }
+
+    fn pairing_check(pairs: &[u8]) -> Result<bool> {
+        let pairs = pairs
+            .chunks((Self::G1_LEN + Self::G2_LEN) as usize)
+            .map(|pair| {
+                let (p, q) = pair.split_at(Self::G1_LEN as usize);
+                Ok((g1(p)?, g2(q)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(pairing_batch(&pairs) == Gt::one())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use substrate_bn::{Group, G1, G2};
+
+    use super::{g1_bytes, g2_bytes, Bn254, PairingCurve};
+
+    fn scalar(value: u64) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes[24..].copy_from_slice(&value.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn g1_arithmetic() {
+        // The generator is (1, 2).
+        let mut g = [0; 64];
+        g[31] = 1;
+        g[63] = 2;
+        assert_eq!(g1_bytes(G1::one()), g);
+        let double = Bn254::g1_add(&g, &g).unwrap();
+        assert_eq!(double, Bn254::g1_mul(&g, &scalar(2)).unwrap());
+        // The point at infinity is the identity.
+        assert_eq!(Bn254::g1_add(&g, &[0; 64]).unwrap(), g);
+        assert!(Bn254::g1_add(&g, &[1; 64]).is_err());
+    }
+
+    #[test]
+    fn groth16_shape() {
+        // e(a * G1, b * G2) * e(-(a * b) * G1, G2) is the identity, as in the
+        // final check of a Groth16 verifier.
+        let (a, b) = (scalar(0x5eed), scalar(7));
+        let g1 = g1_bytes(G1::one());
+        let g2 = g2_bytes(G2::one());
+        let p = Bn254::g1_mul(&g1, &a).unwrap();
+        let q = Bn254::g2_mul(&g2, &b).unwrap();
+        let ab = Bn254::g1_mul(&p, &b).unwrap();
+        let neg_ab = g1_bytes(-super::g1(&ab).unwrap());
+
+        let pairs = |r: &[u8]| [&p, &q, r, &g2].concat();
+        assert!(Bn254::pairing_check(&pairs(&neg_ab)).unwrap());
+        assert!(!Bn254::pairing_check(&pairs(&ab)).unwrap());
+    }
+}
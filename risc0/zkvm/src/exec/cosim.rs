@@ -0,0 +1,514 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Differential execution against a reference RISC-V simulator.
+//!
+//! The guest is run by a reference simulator, such as spike or QEMU, and the
+//! trace it writes is then replayed against the [Executor] one instruction at
+//! a time, comparing the PC, the registers written and the memory stored to.
+//! The first difference is reported as a [Divergence].
+//!
+//! Two trace formats are understood:
+//!
+//! * spike's commit log, written with `spike -l --log-commits`, which lists the
+//!   registers and memory each instruction writes.
+//! * QEMU's CPU state dumps, written with `qemu-riscv64 -one-insn-per-tb -d
+//!   cpu`, which list every register before each instruction. The registers an
+//!   instruction writes are those that differ in the next dump, and stores are
+//!   not checked.
+//!
+//! The reference must run the same image from the same initial state, e.g.
+//! with the same stack pointer, for the traces to agree. Host calls are
+//! emulated differently by every simulator, so only the PC of an `ecall` is
+//! compared.
+
+use std::{fmt, io::BufRead};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::Executor;
+use crate::ExitCode;
+
+const ECALL: u32 = 0x00000073;
+
+/// An instruction executed by a reference simulator
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReferenceStep {
+    /// The address of the instruction
+    pub pc: u64,
+
+    /// The instruction, if the trace records it
+    pub insn: Option<u32>,
+
+    /// The registers written by the instruction, by index, with their new
+    /// values
+    pub registers: Vec<(usize, u64)>,
+
+    /// The memory stored to by the instruction, by address, with the bytes
+    /// stored
+    pub memory: Vec<(u64, Vec<u8>)>,
+}
+
+/// The instructions executed by a reference simulator, in order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReferenceTrace {
+    /// The executed instructions
+    pub steps: Vec<ReferenceStep>,
+}
+
+impl ReferenceTrace {
+    /// Parse a spike commit log.
+    ///
+    /// Lines other than instruction commits, e.g. disassembly, are skipped.
+    pub fn from_spike(reader: impl BufRead) -> Result<Self> {
+        let mut steps = Vec::new();
+        for (line_idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let step = parse_spike_commit(&line)
+                .with_context(|| format!("line {}: {line}", line_idx + 1))?;
+            steps.extend(step);
+        }
+        Ok(Self { steps })
+    }
+
+    /// Parse a sequence of QEMU CPU state dumps.
+    pub fn from_qemu(reader: impl BufRead) -> Result<Self> {
+        let mut states: Vec<(u64, [u64; 32])> = Vec::new();
+        for (line_idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let context = || format!("line {}: {line}", line_idx + 1);
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                let Some(value) = tokens.next() else {
+                    break;
+                };
+                if token == "pc" {
+                    let pc = parse_hex(value).with_context(context)?;
+                    states.push((pc, [0; 32]));
+                } else if let Some(reg) = parse_reg(token.split('/').next().unwrap()) {
+                    let Some((_, registers)) = states.last_mut() else {
+                        bail!("registers before the first pc at {}", context());
+                    };
+                    registers[reg] = parse_hex(value).with_context(context)?;
+                }
+            }
+        }
+        let steps = states
+            .iter()
+            .enumerate()
+            .map(|(idx, (pc, before))| {
+                let registers = match states.get(idx + 1) {
+                    Some((_, after)) => (1..32)
+                        .filter(|reg| after[*reg] != before[*reg])
+                        .map(|reg| (reg, after[reg]))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                ReferenceStep {
+                    pc: *pc,
+                    insn: None,
+                    registers,
+                    memory: Vec::new(),
+                }
+            })
+            .collect();
+        Ok(Self { steps })
+    }
+}
+
+/// Parse a line of a spike commit log, of the form
+/// `core 0: <priv> <pc> (<insn>) [<reg> <value> | mem <addr> [<value>]]...`.
+fn parse_spike_commit(line: &str) -> Result<Option<ReferenceStep>> {
+    let tokens: Vec<_> = line.split_whitespace().collect();
+    // Disassembly lines have no privilege level before the PC.
+    let is_commit = tokens.len() >= 5
+        && tokens[0] == "core"
+        && tokens[1].ends_with(':')
+        && tokens[2].len() == 1
+        && tokens[2].chars().all(|c| c.is_ascii_digit());
+    if !is_commit {
+        return Ok(None);
+    }
+    let pc = parse_hex(tokens[3])?;
+    let insn = tokens[4]
+        .strip_prefix('(')
+        .and_then(|insn| insn.strip_suffix(')'))
+        .ok_or_else(|| anyhow!("malformed instruction {}", tokens[4]))?;
+    let mut step = ReferenceStep {
+        pc,
+        insn: Some(parse_hex(insn)? as u32),
+        ..Default::default()
+    };
+    let mut idx = 5;
+    while idx < tokens.len() {
+        let token = tokens[idx];
+        let value = tokens
+            .get(idx + 1)
+            .ok_or_else(|| anyhow!("missing value for {token}"))?;
+        idx += 2;
+        if token == "mem" {
+            // A load lists only the address, a store the value too, with two
+            // hex digits per byte stored.
+            let addr = parse_hex(value)?;
+            if let Some(data) = tokens.get(idx).filter(|data| data.starts_with("0x")) {
+                let len = (data.len() - 2) / 2;
+                let bytes = parse_hex(data)?.to_le_bytes()[..len.min(8)].to_vec();
+                step.memory.push((addr, bytes));
+                idx += 1;
+            }
+        } else if let Some(reg) = parse_reg(token) {
+            if reg != 0 {
+                step.registers.push((reg, parse_hex(value)?));
+            }
+        }
+    }
+    Ok(Some(step))
+}
+
+/// Parse an integer register name of the form `x<n>`.
+fn parse_reg(token: &str) -> Option<usize> {
+    let reg: usize = token.strip_prefix('x')?.parse().ok()?;
+    (reg < 32).then_some(reg)
+}
+
+fn parse_hex(token: &str) -> Result<u64> {
+    let digits = token.strip_prefix("0x").unwrap_or(token);
+    u64::from_str_radix(digits, 16).with_context(|| format!("invalid hex value {token}"))
+}
+
+/// The first difference between the [Executor] and a [ReferenceTrace]
+///
+/// Steps are counted from the first one in the trace at the PC the executor
+/// started from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Divergence {
+    /// The executor reached a different instruction
+    Pc {
+        /// The index of the step
+        step: usize,
+        /// The PC of the reference
+        expected: u64,
+        /// The PC of the executor
+        actual: u64,
+    },
+
+    /// The executor found a different instruction at the PC
+    Instruction {
+        /// The index of the step
+        step: usize,
+        /// The address of the instruction
+        pc: u64,
+        /// The instruction run by the reference
+        expected: u32,
+        /// The instruction in guest memory
+        actual: u32,
+    },
+
+    /// The instruction left a different value in a register
+    Register {
+        /// The index of the step
+        step: usize,
+        /// The address of the instruction
+        pc: u64,
+        /// The index of the register
+        reg: usize,
+        /// The value written by the reference
+        expected: u64,
+        /// The value in the executor
+        actual: u64,
+    },
+
+    /// The instruction left different bytes in memory
+    Memory {
+        /// The index of the step
+        step: usize,
+        /// The address of the instruction
+        pc: u64,
+        /// The address stored to
+        addr: u64,
+        /// The bytes stored by the reference
+        expected: Vec<u8>,
+        /// The bytes in guest memory
+        actual: Vec<u8>,
+    },
+
+    /// The executor stopped without executing the instruction
+    Stopped {
+        /// The index of the step
+        step: usize,
+        /// The address of the instruction
+        pc: u64,
+        /// Why the executor stopped
+        exit_code: ExitCode,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pc {
+                step,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "step {step}: expected pc 0x{expected:08x}, found 0x{actual:08x}"
+            ),
+            Self::Instruction {
+                step,
+                pc,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "step {step} at pc 0x{pc:08x}: expected instruction 0x{expected:08x}, found 0x{actual:08x}"
+            ),
+            Self::Register {
+                step,
+                pc,
+                reg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "step {step} at pc 0x{pc:08x}: expected x{reg} = 0x{expected:x}, found 0x{actual:x}"
+            ),
+            Self::Memory {
+                step,
+                pc,
+                addr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "step {step} at pc 0x{pc:08x}: expected {expected:02x?} at 0x{addr:x}, found {actual:02x?}"
+            ),
+            Self::Stopped {
+                step,
+                pc,
+                exit_code,
+            } => write!(f, "step {step} at pc 0x{pc:08x}: stopped with {exit_code:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+impl<'a> Executor<'a> {
+    /// Execute the guest in lockstep with `reference`, returning the first
+    /// [Divergence] from it, if any.
+    ///
+    /// Execution starts at the first step of the trace at the current PC, and
+    /// stops at the end of the trace, or once the guest halts, pauses or
+    /// reaches the session limit. It can be continued with [Executor::run].
+    pub fn diff_against(&mut self, reference: &ReferenceTrace) -> Result<Option<Divergence>> {
+        let start = reference
+            .steps
+            .iter()
+            .position(|step| step.pc == self.pc)
+            .ok_or_else(|| anyhow!("reference never reaches pc 0x{:08x}", self.pc))?;
+        for (step, expected) in reference.steps[start..].iter().enumerate() {
+            let pc = self.pc;
+            if pc != expected.pc {
+                return Ok(Some(Divergence::Pc {
+                    step,
+                    expected: expected.pc,
+                    actual: pc,
+                }));
+            }
+            let insn = self.monitor.image.read_u32(pc)?;
+            if let Some(expected) = expected.insn.filter(|expected| *expected != insn) {
+                return Ok(Some(Divergence::Instruction {
+                    step,
+                    pc,
+                    expected,
+                    actual: insn,
+                }));
+            }
+
+            let insn_counter = self.insn_counter;
+            let exit_code = loop {
+                let exit_code = self.step()?;
+                // A split leaves the instruction pending.
+                if exit_code != Some(ExitCode::SystemSplit) || self.insn_counter != insn_counter {
+                    break exit_code;
+                }
+            };
+            if self.insn_counter == insn_counter {
+                let exit_code = exit_code.unwrap();
+                if exit_code == ExitCode::SessionLimit {
+                    return Ok(None);
+                }
+                return Ok(Some(Divergence::Stopped {
+                    step,
+                    pc,
+                    exit_code,
+                }));
+            }
+
+            if insn != ECALL {
+                for (reg, expected) in expected.registers.iter().copied() {
+                    let actual = self.monitor.register(reg);
+                    if actual != expected {
+                        return Ok(Some(Divergence::Register {
+                            step,
+                            pc,
+                            reg,
+                            expected,
+                            actual,
+                        }));
+                    }
+                }
+            }
+            for (addr, expected) in expected.memory.iter() {
+                let actual = self
+                    .monitor
+                    .image
+                    .read_region(*addr, expected.len() as u64)?;
+                if actual != *expected {
+                    return Ok(Some(Divergence::Memory {
+                        step,
+                        pc,
+                        addr: *addr,
+                        expected: expected.clone(),
+                        actual,
+                    }));
+                }
+            }
+            if exit_code.is_some() {
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm_platform::syscall::{ecall, reg_abi::REG_A7};
+
+    use super::{Divergence, ReferenceStep, ReferenceTrace};
+    use crate::{
+        testutil::{image, ECALL, ENTRY},
+        Executor, ExecutorEnv,
+    };
+
+    #[test]
+    fn spike() {
+        let log = "\
+core   0: 0x0000000000001000 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x0000000000001000 (0x00000297) x5  0x0000000000001000
+core   0: 3 0x0000000000001004 (0x0182b283) x5  0x0000000000000018 mem 0x0000000000001018
+core   0: 3 0x0000000000001008 (0x00b29023) mem 0x0000000000002000 0xbeef
+core   0: 3 0x000000000000100c (0x30529073) c773_mtvec 0x0000000000001000
+";
+        let trace = ReferenceTrace::from_spike(log.as_bytes()).unwrap();
+        assert_eq!(
+            trace.steps,
+            [
+                ReferenceStep {
+                    pc: 0x1000,
+                    insn: Some(0x00000297),
+                    registers: vec![(5, 0x1000)],
+                    memory: vec![],
+                },
+                ReferenceStep {
+                    pc: 0x1004,
+                    insn: Some(0x0182b283),
+                    registers: vec![(5, 0x18)],
+                    memory: vec![],
+                },
+                ReferenceStep {
+                    pc: 0x1008,
+                    insn: Some(0x00b29023),
+                    registers: vec![],
+                    memory: vec![(0x2000, vec![0xef, 0xbe])],
+                },
+                ReferenceStep {
+                    pc: 0x100c,
+                    insn: Some(0x30529073),
+                    registers: vec![],
+                    memory: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn qemu() {
+        let log = "\
+ pc       0000000000001000
+ x0/zero  0000000000000000 x1/ra    0000000000000000 x2/sp    0000000000008000
+ pc       0000000000001004
+ x0/zero  0000000000000000 x1/ra    0000000000001004 x2/sp    0000000000008000
+";
+        let trace = ReferenceTrace::from_qemu(log.as_bytes()).unwrap();
+        assert_eq!(trace.steps.len(), 2);
+        assert_eq!(trace.steps[0].pc, 0x1000);
+        assert_eq!(trace.steps[0].registers, [(1, 0x1004)]);
+        assert!(trace.steps[1].registers.is_empty());
+    }
+
+    #[test]
+    fn diff_against() {
+        // A run of `ecall`s, each a gettid.
+        let new_executor = || {
+            let image = image(&[ECALL; 4], &[(REG_A7, ecall::GETTID)]);
+            Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap()
+        };
+        let step = |pc| ReferenceStep {
+            pc,
+            insn: Some(ECALL),
+            // The result of a host call is not compared.
+            registers: vec![(10, 42)],
+            memory: vec![],
+        };
+
+        let reference = ReferenceTrace {
+            steps: vec![step(0x0), step(0x1000), step(0x1004)],
+        };
+        let mut exec = new_executor();
+        assert_eq!(exec.diff_against(&reference).unwrap(), None);
+        assert_eq!(exec.pc, 0x1008);
+
+        let reference = ReferenceTrace {
+            steps: vec![step(0x1000), step(0x1008)],
+        };
+        let mut exec = new_executor();
+        assert_eq!(
+            exec.diff_against(&reference).unwrap(),
+            Some(Divergence::Pc {
+                step: 1,
+                expected: 0x1008,
+                actual: 0x1004,
+            })
+        );
+
+        let mut reference = ReferenceTrace {
+            steps: vec![step(0x1000)],
+        };
+        reference.steps[0].memory = vec![(0x1000, vec![0x13])];
+        let mut exec = new_executor();
+        assert_eq!(
+            exec.diff_against(&reference).unwrap(),
+            Some(Divergence::Memory {
+                step: 0,
+                pc: 0x1000,
+                addr: 0x1000,
+                expected: vec![0x13],
+                actual: vec![0x73],
+            })
+        );
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `cycle`, `time` and `instret` counter CSRs.
+//!
+//! The counters are read-only, and an instruction that writes one is illegal.
+//! `cycle` counts the cycles of the session so far as the session limit does,
+//! including paging and the room reserved to finish a SHA compression, and
+//! `instret` the instructions retired. There is no wall clock in the guest, so
+//! `time` counts the cycles of the instructions executed instead: unlike
+//! `cycle`, it leaves out paging, and so does not depend on how the session is
+//! split into segments.
+
+use crate::opcode::OPCODE_SYSTEM;
+
+const CSR_CYCLE: u32 = 0xc00;
+const CSR_TIME: u32 = 0xc01;
+const CSR_INSTRET: u32 = 0xc02;
+
+/// The values of the counters at an instruction.
+pub(crate) struct Counters {
+    pub cycle: u64,
+    pub time: u64,
+    pub instret: u64,
+}
+
+/// Returns whether `insn` accesses one of the counter CSRs.
+pub(crate) fn is_counter(insn: u32) -> bool {
+    let funct3 = (insn >> 12) & 0x7;
+    insn & 0x7f == OPCODE_SYSTEM
+        && funct3 & 0b11 != 0
+        && (CSR_CYCLE..=CSR_INSTRET).contains(&(insn >> 20))
+}
+
+/// Returns the value of the counter read by `insn`, or `None` if it writes
+/// the counter, which makes it an illegal instruction.
+pub(crate) fn read(insn: u32, counters: &Counters) -> Option<u64> {
+    let funct3 = (insn >> 12) & 0x7;
+    let rs1 = (insn >> 15) & 0x1f;
+    // CSRRW always writes, and CSRRS and CSRRC do unless rs1 is x0 or their
+    // immediate is 0.
+    if funct3 & 0b11 == 0b01 || rs1 != 0 {
+        return None;
+    }
+    match insn >> 20 {
+        CSR_CYCLE => Some(counters.cycle),
+        CSR_TIME => Some(counters.time),
+        CSR_INSTRET => Some(counters.instret),
+        _ => None,
+    }
+}
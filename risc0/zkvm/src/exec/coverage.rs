@@ -0,0 +1,215 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Code coverage of the guest.
+//!
+//! This records the basic blocks the guest executes, and how many times each
+//! was entered. A block ends at any branch, jump or `ecall`, or wherever
+//! control does not fall through to the next instruction.
+//!
+//! Coverage can be exported as an lcov tracefile, mapped to source lines
+//! through the DWARF info of the guest ELF, for `genhtml` or any other lcov
+//! consumer.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::Path,
+};
+
+use addr2line::{object::read::File, Context};
+use anyhow::{Context as _, Result};
+use risc0_zkvm_platform::WORD_SIZE;
+
+use crate::opcode::{OPCODE_BRANCH, OPCODE_JAL, OPCODE_JALR, OPCODE_SYSTEM};
+
+/// A run of instructions executed one after another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The address of the first instruction
+    pub start: u64,
+
+    /// The address just past the last instruction
+    pub end: u64,
+
+    /// The number of times the block was entered
+    pub hits: u64,
+}
+
+/// Collects the basic blocks executed by the guest
+#[derive(Default)]
+pub struct Coverage {
+    // Blocks by start address
+    blocks: HashMap<u64, BasicBlock>,
+
+    // The start and end of the block being executed, if any
+    current: Option<(u64, u64)>,
+}
+
+impl Coverage {
+    /// Return an empty coverage collector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the execution of `insn` at `pc`, which continues at `next_pc`.
+    pub fn record(&mut self, pc: u64, insn: u32, next_pc: u64) {
+        let (start, _) = *self.current.get_or_insert_with(|| {
+            let block = self.blocks.entry(pc).or_insert(BasicBlock {
+                start: pc,
+                end: pc,
+                hits: 0,
+            });
+            block.hits += 1;
+            (pc, pc)
+        });
+        let end = pc + WORD_SIZE as u64;
+        self.current = Some((start, end));
+
+        let opcode = insn & 0x7f;
+        let is_jump = matches!(
+            opcode,
+            OPCODE_BRANCH | OPCODE_JAL | OPCODE_JALR | OPCODE_SYSTEM
+        );
+        if is_jump || next_pc != end {
+            self.close_block();
+        }
+    }
+
+    fn close_block(&mut self) {
+        if let Some((start, end)) = self.current.take() {
+            let block = self.blocks.get_mut(&start).unwrap();
+            block.end = block.end.max(end);
+        }
+    }
+
+    /// Returns the blocks executed so far, ordered by address.
+    pub fn blocks(&self) -> Vec<BasicBlock> {
+        let mut blocks: Vec<BasicBlock> = self.blocks.values().copied().collect();
+        if let Some((start, end)) = self.current {
+            let block = blocks
+                .iter_mut()
+                .find(|block| block.start == start)
+                .unwrap();
+            block.end = block.end.max(end);
+        }
+        blocks.sort_by_key(|block| block.start);
+        blocks
+    }
+
+    /// Returns the number of times each executed instruction ran, by address.
+    pub fn instruction_hits(&self) -> BTreeMap<u64, u64> {
+        let mut hits = BTreeMap::new();
+        for block in self.blocks() {
+            for pc in (block.start..block.end).step_by(WORD_SIZE) {
+                *hits.entry(pc).or_default() += block.hits;
+            }
+        }
+        hits
+    }
+
+    /// Write coverage in lcov format to the file at `path`, resolving source
+    /// lines through the DWARF info of `elf`.
+    pub fn write_lcov(&self, elf: &[u8], path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut out = Vec::new();
+        self.to_lcov(elf, &mut out)?;
+        fs::write(path, out).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Write coverage in lcov format to `writer`.
+    ///
+    /// Every line with code is listed, with the number of times its most
+    /// executed instruction ran.
+    pub fn to_lcov(&self, elf: &[u8], mut writer: impl Write) -> Result<()> {
+        let file = File::parse(elf)?;
+        let ctx = Context::new(&file)?;
+        let hits = self.instruction_hits();
+        let mut files: BTreeMap<String, BTreeMap<u32, u64>> = BTreeMap::new();
+        for (addr, size, location) in ctx.find_location_range(0, u64::MAX)? {
+            let (Some(file), Some(line)) = (location.file, location.line) else {
+                continue;
+            };
+            if line == 0 {
+                continue;
+            }
+            let count = hits
+                .range(addr..addr.saturating_add(size))
+                .map(|(_, count)| *count)
+                .max()
+                .unwrap_or(0);
+            let entry = files
+                .entry(file.to_string())
+                .or_default()
+                .entry(line)
+                .or_default();
+            *entry = (*entry).max(count);
+        }
+
+        for (file, lines) in files {
+            writeln!(writer, "TN:")?;
+            writeln!(writer, "SF:{file}")?;
+            for (line, count) in lines.iter() {
+                writeln!(writer, "DA:{line},{count}")?;
+            }
+            writeln!(writer, "LF:{}", lines.len())?;
+            let hit = lines.values().filter(|count| **count > 0).count();
+            writeln!(writer, "LH:{hit}")?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BasicBlock, Coverage};
+
+    const ADDI: u32 = 0x00000013;
+    const BNEZ: u32 = 0xfe051ee3;
+    const ECALL: u32 = 0x00000073;
+
+    #[test]
+    fn blocks() {
+        let mut coverage = Coverage::new();
+        // A loop over a two-instruction body, which the first block falls
+        // into, branching back twice.
+        coverage.record(0x1000, ADDI, 0x1004);
+        for _ in 0..2 {
+            coverage.record(0x1004, ADDI, 0x1008);
+            coverage.record(0x1008, BNEZ, 0x1004);
+        }
+        // Falling out of the loop.
+        coverage.record(0x1004, ADDI, 0x1008);
+        coverage.record(0x1008, BNEZ, 0x100c);
+        coverage.record(0x100c, ECALL, 0x1010);
+        coverage.record(0x1010, ADDI, 0x1014);
+
+        let block = |start, end, hits| BasicBlock { start, end, hits };
+        assert_eq!(
+            coverage.blocks(),
+            [
+                block(0x1000, 0x100c, 1),
+                block(0x1004, 0x100c, 2),
+                block(0x100c, 0x1010, 1),
+                block(0x1010, 0x1014, 1),
+            ]
+        );
+        let hits = coverage.instruction_hits();
+        assert_eq!(hits[&0x1000], 1);
+        assert_eq!(hits[&0x1008], 3);
+        assert_eq!(hits.get(&0x1014), None);
+    }
+}
@@ -14,23 +14,38 @@
 
 //! This module defines the [ExecutorEnv] and [ExecutorEnvBuilder].
 
+#[cfg(feature = "profiler")]
+use std::path::PathBuf;
 use std::{
     cell::RefCell,
-    collections::HashMap,
-    io::{BufRead, BufReader, Cursor, Read, Write},
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Cursor, Read, Seek, Write},
     rc::Rc,
+    sync::Arc,
 };
 
+use anyhow::Result;
 use bytemuck::Pod;
 use risc0_zkvm_platform::{
     fileno,
+    memory::MEM_SIZE,
     syscall::{
         nr::{SYS_GETENV, SYS_READ, SYS_READ_AVAIL, SYS_WRITE},
         SyscallName,
     },
 };
 
-use super::io::PosixIo;
+use super::{
+    files::FileTable,
+    float::FloatSupport,
+    hooks::ExecutorHooks,
+    io::PosixIo,
+    registry::{CustomInsnHandler, CustomOpcode, EcallHandler, EcallRegistry, IoCallbacks},
+};
+use crate::{
+    binfmt::{builder::arg_layout, hash::PageHasher},
+    claim::Assumption,
+};
 
 // use super::io::{slice_io_from_fn, syscalls, PosixIo, SliceIo, Syscall,
 // SyscallTable};
@@ -39,9 +54,6 @@ use super::io::PosixIo;
 /// to try and fit with 8GB of RAM.
 const DEFAULT_SEGMENT_LIMIT_PO2: usize = 20; // 1M cycles
 
-/// The default session limit specified in cycles.
-const DEFAULT_SESSION_LIMIT: usize = 64 * 1024 * 1024; // 64M cycles
-
 /// A builder pattern used to construct an [ExecutorEnv].
 #[derive(Clone)]
 pub struct ExecutorEnvBuilder<'a> {
@@ -51,12 +63,35 @@ pub struct ExecutorEnvBuilder<'a> {
 /// The [super::Executor] is configured from this object.
 #[derive(Clone)]
 pub struct ExecutorEnv<'a> {
-    env_vars: HashMap<String, String>,
+    pub(crate) env_vars: HashMap<String, String>,
     pub(crate) segment_limit_po2: usize,
-    session_limit: usize,
+    session_limit: Option<usize>,
+    pub(crate) mem_size: u64,
+    pub(crate) heap: Option<(u64, u64)>,
+    pub(crate) page_hasher: Option<Arc<dyn PageHasher>>,
+    pub(crate) emulate_misaligned: bool,
+    pub(crate) detect_uninit: bool,
+    pub(crate) write_xor_execute: bool,
+    pub(crate) float_support: FloatSupport,
+    pub(crate) rv32: bool,
+    pub(crate) random_seed: Option<u64>,
+    pub(crate) guest_name: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) assumptions: Vec<Assumption>,
     // syscalls: SyscallTable<'a>,
+    pub(crate) ecalls: EcallRegistry<'a>,
+    pub(crate) io_callbacks: IoCallbacks<'a>,
+    pub(crate) custom_insns: BTreeMap<CustomOpcode, Rc<RefCell<dyn CustomInsnHandler + 'a>>>,
     pub(crate) io: Rc<RefCell<PosixIo<'a>>>,
+    pub(crate) files: Rc<RefCell<FileTable<'a>>>,
+    pub(crate) hooks: Vec<Rc<RefCell<dyn ExecutorHooks + 'a>>>,
     input: Vec<u8>,
+    #[cfg(feature = "profiler")]
+    pub(crate) profile_path: Option<PathBuf>,
+    #[cfg(feature = "profiler")]
+    pub(crate) profile_sample_period: u64,
+    #[cfg(feature = "profiler")]
+    pub(crate) coverage_path: Option<PathBuf>,
 }
 
 impl<'a> ExecutorEnv<'a> {
@@ -69,7 +104,7 @@ impl<'a> ExecutorEnv<'a> {
         1 << self.segment_limit_po2
     }
 
-    pub(crate) fn get_session_limit(&self) -> usize {
+    pub(crate) fn get_session_limit(&self) -> Option<usize> {
         self.session_limit
     }
 
@@ -80,7 +115,8 @@ impl<'a> ExecutorEnv<'a> {
 
 impl<'a> Default for ExecutorEnv<'a> {
     fn default() -> Self {
-        Self::builder().build()
+        // There are no program arguments to reject.
+        Self::builder().build().unwrap()
     }
 }
 
@@ -90,35 +126,71 @@ impl<'a> Default for ExecutorEnvBuilder<'a> {
             inner: ExecutorEnv {
                 env_vars: Default::default(),
                 segment_limit_po2: DEFAULT_SEGMENT_LIMIT_PO2,
-                session_limit: DEFAULT_SESSION_LIMIT,
+                session_limit: None,
+                mem_size: MEM_SIZE as u64,
+                heap: None,
+                page_hasher: None,
+                emulate_misaligned: false,
+                detect_uninit: false,
+                write_xor_execute: false,
+                float_support: FloatSupport::default(),
+                rv32: false,
+                random_seed: None,
+                guest_name: "guest".to_string(),
+                args: Vec::new(),
+                assumptions: Vec::new(),
                 // syscalls: Default::default(),
+                ecalls: Default::default(),
+                io_callbacks: Default::default(),
+                custom_insns: BTreeMap::new(),
                 io: Default::default(),
+                files: Default::default(),
+                hooks: Vec::new(),
                 input: Default::default(),
+                #[cfg(feature = "profiler")]
+                profile_path: None,
+                #[cfg(feature = "profiler")]
+                profile_sample_period: 1,
+                #[cfg(feature = "profiler")]
+                coverage_path: None,
             },
         }
     }
 }
 
+/// Check that the host does not map `fd` over the journal, which the executor
+/// maps to capture what the guest commits.
+fn check_fd(fd: u64) {
+    assert!(
+        fd != fileno::JOURNAL as u64,
+        "file descriptor {fd} is reserved for the journal"
+    );
+}
+
 impl<'a> ExecutorEnvBuilder<'a> {
     /// Finalize this builder to construct an [ExecutorEnv].
-    pub fn build(&mut self) -> ExecutorEnv<'a> {
+    ///
+    /// Fails if the program arguments do not fit in guest memory, as
+    /// described in [ExecutorEnvBuilder::args].
+    pub fn build(&mut self) -> Result<ExecutorEnv<'a>> {
+        arg_layout(&self.inner.args)?;
         let mut result = self.clone();
         // let getenv = syscalls::Getenv(self.inner.env_vars.clone());
-        if !self.inner.input.is_empty() {
-            // let reader = Cursor::new(self.inner.input.clone());
-            // result
-            //     .inner
-            //     .io
-            //     .borrow_mut()
-            //     .with_read_fd(fileno::STDIN, reader);
+        // Each environment gets its own table, so that what the executor maps
+        // or the guest closes does not leak into the next one built.
+        let mut files = self.inner.files.borrow().clone();
+        let stdin = fileno::STDIN as u64;
+        if !self.inner.input.is_empty() && !files.contains(stdin) {
+            files.insert_input(stdin, Cursor::new(self.inner.input.clone()));
         }
+        result.inner.files = Rc::new(RefCell::new(files));
         // let io = result.inner.io.clone();
         // result
         //     .syscall(SYS_GETENV, getenv)
         //     .syscall(SYS_READ, io.clone())
         //     .syscall(SYS_READ_AVAIL, io.clone())
         //     .syscall(SYS_WRITE, io);
-        result.inner.clone()
+        Ok(result.inner.clone())
     }
 
     /// Set a segment limit, specified in powers of 2 cycles.
@@ -128,8 +200,142 @@ impl<'a> ExecutorEnvBuilder<'a> {
     }
 
     /// Set a session limit, specified in number of cycles.
+    ///
+    /// Once a session has used `limit` cycles, including paging, execution
+    /// stops with [ExitCode::SessionLimit](crate::ExitCode::SessionLimit)
+    /// before the next instruction. By default there is no limit.
     pub fn session_limit(&mut self, limit: usize) -> &mut Self {
-        self.inner.session_limit = limit;
+        self.inner.session_limit = Some(limit);
+        self
+    }
+
+    /// Set the size of guest memory in bytes.
+    ///
+    /// Defaults to the platform [MEM_SIZE], which is also the smallest size
+    /// accepted. Larger memories allow RV64 guests to address multiple
+    /// gigabytes, at the cost of a deeper page table.
+    pub fn mem_size(&mut self, mem_size: u64) -> &mut Self {
+        self.inner.mem_size = mem_size;
+        self
+    }
+
+    /// Serve the guest heap from `size` bytes starting at `start`.
+    ///
+    /// By default the heap spans from the end of guest memory up to the
+    /// memory data region.
+    pub fn heap(&mut self, start: u64, size: u64) -> &mut Self {
+        self.inner.heap = Some((start, size));
+        self
+    }
+
+    /// Set the hash function used to commit to guest memory.
+    ///
+    /// Defaults to SHA-256, which the RISC Zero circuit verifies. Poseidon is
+    /// cheaper to verify in a folding circuit over the Pasta cycle, and
+    /// images hashed with BLAKE3 can be checked for integrity but not proven.
+    pub fn page_hasher(&mut self, hasher: Arc<dyn PageHasher>) -> &mut Self {
+        self.inner.page_hasher = Some(hasher);
+        self
+    }
+
+    /// Emulate misaligned guest loads and stores in software instead of
+    /// trapping them.
+    ///
+    /// By default a misaligned access ends execution with
+    /// [ExitCode::Fault](crate::ExitCode::Fault). Misaligned atomics fault
+    /// either way.
+    pub fn emulate_misaligned(&mut self, emulate: bool) -> &mut Self {
+        self.inner.emulate_misaligned = emulate;
+        self
+    }
+
+    /// Never let the guest write to a page it may execute.
+    ///
+    /// Segment permissions are always enforced, but a page shared by a code
+    /// and a data segment, or covered by one that is both writable and
+    /// executable, may otherwise be written. With this set, such pages are
+    /// read-only, so a guest that modifies its own code, which cannot be
+    /// proven, stops with [ExitCode::Fault](crate::ExitCode::Fault).
+    pub fn write_xor_execute(&mut self, enforce: bool) -> &mut Self {
+        self.inner.write_xor_execute = enforce;
+        self
+    }
+
+    /// Run an RV32 guest, loaded from a 32-bit ELF.
+    ///
+    /// Its registers are 32 bits wide, and laid out as words in SYSTEM
+    /// memory. Instructions that only exist in RV64 are illegal, and so is bit
+    /// manipulation. Host calls are made as for RV64 guests.
+    pub fn rv32(&mut self, rv32: bool) -> &mut Self {
+        self.inner.rv32 = rv32;
+        self
+    }
+
+    /// Draw the guest's random bytes from a generator seeded with `seed`,
+    /// rather than with the host's entropy, so that runs are reproducible.
+    pub fn random_seed(&mut self, seed: u64) -> &mut Self {
+        self.inner.random_seed = Some(seed);
+        self
+    }
+
+    /// Name the guest, which the events of the messages it logs carry in
+    /// their `guest` field.
+    ///
+    /// Defaults to `guest`.
+    pub fn guest_name(&mut self, name: &str) -> &mut Self {
+        self.inner.guest_name = name.to_string();
+        self
+    }
+
+    /// Choose which floating-point instructions the guest may use.
+    ///
+    /// By default all of F and D are emulated. With
+    /// [FloatSupport::Provable], an instruction outside of the subset that
+    /// can be proven ends execution with an error instead.
+    pub fn float_support(&mut self, support: FloatSupport) -> &mut Self {
+        self.inner.float_support = support;
+        self
+    }
+
+    /// Track which bytes of guest memory have been initialized, and report
+    /// guest loads from those that have not.
+    ///
+    /// This is a debugging aid which slows execution down. The loads are
+    /// logged and collected in
+    /// [Executor::uninit_loads](super::Executor::uninit_loads).
+    pub fn detect_uninit(&mut self, detect: bool) -> &mut Self {
+        self.inner.detect_uninit = detect;
+        self
+    }
+
+    /// Profile the guest, writing the cycles spent in each call stack to
+    /// `path` when execution stops.
+    ///
+    /// The output is in the collapsed-stack format read by flamegraph tools.
+    /// Symbols are resolved when the executor is built from an ELF.
+    #[cfg(feature = "profiler")]
+    pub fn profile(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.inner.profile_path = Some(path.into());
+        self
+    }
+
+    /// When profiling, only count every `period`th cycle.
+    ///
+    /// Defaults to 1, which counts every cycle.
+    #[cfg(feature = "profiler")]
+    pub fn profile_sample_period(&mut self, period: u64) -> &mut Self {
+        self.inner.profile_sample_period = period;
+        self
+    }
+
+    /// Record the basic blocks the guest executes, writing them to `path` as
+    /// an lcov tracefile when execution stops.
+    ///
+    /// Source lines are resolved through the DWARF info of the guest, so the
+    /// executor must be built from an ELF.
+    #[cfg(feature = "profiler")]
+    pub fn coverage(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.inner.coverage_path = Some(path.into());
         self
     }
 
@@ -139,6 +345,20 @@ impl<'a> ExecutorEnvBuilder<'a> {
         self
     }
 
+    /// Pass `args` to the guest as its argc and argv, starting with the
+    /// program name.
+    ///
+    /// They are laid out in guest memory by
+    /// [Executor::new](super::Executor::new), as described in
+    /// [MemoryImageBuilder::args](crate::MemoryImageBuilder::args).
+    /// [ExecutorEnvBuilder::build] fails if there are more than
+    /// [MAX_ARGS](crate::MAX_ARGS) arguments, or they do not fit in
+    /// [ARGS](risc0_zkvm_platform::memory::ARGS).
+    pub fn args<S: AsRef<str>>(&mut self, args: &[S]) -> &mut Self {
+        self.inner.args = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+        self
+    }
+
     /// Add an environment variable to the guest environment.
     pub fn env_var(&mut self, name: &str, val: &str) -> &mut Self {
         self.inner
@@ -161,15 +381,80 @@ impl<'a> ExecutorEnvBuilder<'a> {
     //     self
     // }
 
-    /// Add a posix-style standard input.
+    /// Let the guest assume `assumption` with `env::verify`.
+    ///
+    /// This is usually the [Session::assumption](crate::Session::assumption)
+    /// of the guest whose output this one consumes.
+    pub fn add_assumption(&mut self, assumption: Assumption) -> &mut Self {
+        self.inner.assumptions.push(assumption);
+        self
+    }
+
+    /// Handle host call `number`, named `name`, with `handler`.
+    ///
+    /// This takes precedence over the executor's own handling of `number`,
+    /// and over any handler added for it before.
+    pub fn ecall(&mut self, number: u64, name: &str, handler: impl EcallHandler + 'a) -> &mut Self {
+        self.inner.ecalls.register(number, name, handler);
+        self
+    }
+
+    /// Execute the instructions of the custom opcode `opcode` with `handler`.
+    ///
+    /// Without a handler, a custom instruction ends execution with an error,
+    /// unless a [SyscallTrace](crate::SyscallTrace) being replayed serves
+    /// its results.
+    pub fn custom_insn(
+        &mut self,
+        opcode: CustomOpcode,
+        handler: impl CustomInsnHandler + 'a,
+    ) -> &mut Self {
+        self.inner
+            .custom_insns
+            .insert(opcode, Rc::new(RefCell::new(handler)));
+        self
+    }
+
+    /// Add `hooks` to be called back as the guest runs.
+    ///
+    /// Hooks are called in the order they were added.
+    pub fn hooks(&mut self, hooks: impl ExecutorHooks + 'a) -> &mut Self {
+        self.inner.hooks.push(Rc::new(RefCell::new(hooks)));
+        self
+    }
+
+    /// Serve the guest's reads from standard input with `reader`.
     pub fn stdin(&mut self, reader: impl Read + 'a) -> &mut Self {
-        self.read_fd(fileno::STDIN, BufReader::new(reader))
+        self.inner
+            .files
+            .borrow_mut()
+            .insert_input(fileno::STDIN as u64, reader);
+        self
     }
 
-    // Add a posix-style standard output.
-    // pub fn stdout(&mut self, writer: impl Write + 'a) -> &mut Self {
-    //     self.write_fd(fileno::STDOUT, writer)
-    // }
+    /// Pass what the guest writes to standard output to `writer` as it is
+    /// written.
+    ///
+    /// By default it goes to the host's standard output.
+    pub fn stdout(&mut self, writer: impl Write + 'a) -> &mut Self {
+        self.inner
+            .files
+            .borrow_mut()
+            .insert_output(fileno::STDOUT as u64, writer);
+        self
+    }
+
+    /// Pass what the guest writes to standard error to `writer` as it is
+    /// written.
+    ///
+    /// By default it goes to the host's standard error.
+    pub fn stderr(&mut self, writer: impl Write + 'a) -> &mut Self {
+        self.inner
+            .files
+            .borrow_mut()
+            .insert_output(fileno::STDERR as u64, writer);
+        self
+    }
 
     /// Add a posix-style file descriptor for reading.
     pub fn read_fd(&mut self, fd: u32, reader: impl BufRead + 'a) -> &mut Self {
@@ -177,6 +462,38 @@ impl<'a> ExecutorEnvBuilder<'a> {
         self
     }
 
+    /// Let the guest read `file` through file descriptor `fd`.
+    ///
+    /// `file` may be a host [File](std::fs::File) or a buffer in a
+    /// [Cursor]. The guest reads it with the `read` host call and moves
+    /// within it with `lseek`, but cannot write to it.
+    ///
+    /// File descriptors 0 to 2 are the standard streams, and 3 is the
+    /// journal, which the executor maps itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd` is the journal's.
+    pub fn file(&mut self, fd: u64, file: impl Read + Seek + 'a) -> &mut Self {
+        check_fd(fd);
+        self.inner.files.borrow_mut().insert(fd, file);
+        self
+    }
+
+    /// Let the guest read and write `file` through file descriptor `fd`.
+    ///
+    /// Writing to a `Cursor<&mut Vec<u8>>` leaves what the guest wrote in the
+    /// vector once the executor is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd` is the journal's.
+    pub fn writable_file(&mut self, fd: u64, file: impl Read + Write + Seek + 'a) -> &mut Self {
+        check_fd(fd);
+        self.inner.files.borrow_mut().insert_writable(fd, file);
+        self
+    }
+
     // Add a posix-style file descriptor for writing.
     // pub fn write_fd(&mut self, fd: u64, writer: impl Write + 'a) -> &mut Self {
     //     self.inner.io.borrow_mut().with_write_fd(fd, writer);
@@ -191,15 +508,17 @@ impl<'a> ExecutorEnvBuilder<'a> {
     //     self
     // }
 
-    // Add a handler for a syscall which inputs and outputs a slice
-    // of plain old data. The guest can call these callbacks by
-    // invoking `risc0_zkvm::guest::env::send_recv_slice`.
-    // pub fn io_callback(
-    //     &mut self,
-    //     syscall: SyscallName,
-    //     f: impl Fn(&[u8]) -> Vec<u8> + 'a,
-    // ) -> &mut Self {
-    //     self.slice_io(syscall, slice_io_from_fn(f));
-    //     self
-    // }
+    /// Answer the guest's calls of the host callback `name` with `callback`.
+    ///
+    /// The guest sends bytes to the callback and receives its reply with
+    /// `risc0_zkvm::guest::env::send_recv`. Both are recorded along with the
+    /// other host calls, so that the execution can be replayed.
+    pub fn io_callback(
+        &mut self,
+        name: &str,
+        callback: impl FnMut(&[u8]) -> Vec<u8> + 'a,
+    ) -> &mut Self {
+        self.inner.io_callbacks.register(name, callback);
+        self
+    }
 }
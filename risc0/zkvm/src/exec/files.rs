@@ -0,0 +1,262 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The files the host makes available to the guest by file descriptor.
+//!
+//! The host maps a guest file descriptor to an in-memory buffer or a host file
+//! with [ExecutorEnvBuilder::file](super::ExecutorEnvBuilder::file), or
+//! [ExecutorEnvBuilder::writable_file](super::ExecutorEnvBuilder::writable_file)
+//! to let the guest write to it. The guest accesses its files with the
+//! `read`, `write` and `lseek` host calls, which fail as they do on Linux,
+//! returning the negated error number.
+//!
+//! The standard streams may be mapped the same way, to a reader or writer
+//! that is not seekable, so that the host sees the guest's output as it is
+//! written.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+/// I/O error
+const EIO: i32 = 5;
+/// Bad file descriptor
+const EBADF: i32 = 9;
+/// Invalid argument
+const EINVAL: i32 = 22;
+/// Illegal seek
+const ESPIPE: i32 = 29;
+
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+const SEEK_END: u64 = 2;
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+trait ReadWriteSeek: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> ReadWriteSeek for T {}
+
+#[derive(Clone)]
+enum GuestFile<'a> {
+    ReadOnly(Rc<RefCell<dyn ReadSeek + 'a>>),
+    ReadWrite(Rc<RefCell<dyn ReadWriteSeek + 'a>>),
+    Input(Rc<RefCell<dyn Read + 'a>>),
+    Output(Rc<RefCell<dyn Write + 'a>>),
+}
+
+/// The files of a session, by guest file descriptor.
+///
+/// A clone maps the same files, but opens and closes them independently.
+#[derive(Clone, Default)]
+pub(crate) struct FileTable<'a> {
+    files: BTreeMap<u64, GuestFile<'a>>,
+}
+
+fn bad_fd() -> io::Error {
+    io::Error::from_raw_os_error(EBADF)
+}
+
+/// Returns the value of a0 for a host call that failed with `err`: the
+/// negated error number.
+pub(crate) fn error_return(err: &io::Error) -> u64 {
+    -(err.raw_os_error().unwrap_or(EIO) as i64) as u64
+}
+
+impl<'a> FileTable<'a> {
+    /// Map `fd` to the read-only `file`, replacing any file mapped to it.
+    pub(crate) fn insert(&mut self, fd: u64, file: impl Read + Seek + 'a) {
+        self.files
+            .insert(fd, GuestFile::ReadOnly(Rc::new(RefCell::new(file))));
+    }
+
+    /// Map `fd` to `file`, which the guest may also write, replacing any file
+    /// mapped to it.
+    pub(crate) fn insert_writable(&mut self, fd: u64, file: impl Read + Write + Seek + 'a) {
+        self.files
+            .insert(fd, GuestFile::ReadWrite(Rc::new(RefCell::new(file))));
+    }
+
+    /// Map `fd` to the stream `reader`, which the guest can only read.
+    pub(crate) fn insert_input(&mut self, fd: u64, reader: impl Read + 'a) {
+        self.files
+            .insert(fd, GuestFile::Input(Rc::new(RefCell::new(reader))));
+    }
+
+    /// Map `fd` to the stream `writer`, which the guest can only write.
+    pub(crate) fn insert_output(&mut self, fd: u64, writer: impl Write + 'a) {
+        self.files
+            .insert(fd, GuestFile::Output(Rc::new(RefCell::new(writer))));
+    }
+
+    /// Returns whether a file is mapped to `fd`.
+    pub(crate) fn contains(&self, fd: u64) -> bool {
+        self.files.contains_key(&fd)
+    }
+
+    /// Unmap `fd`.
+    pub(crate) fn close(&mut self, fd: u64) -> io::Result<()> {
+        self.files.remove(&fd).map(|_| ()).ok_or_else(bad_fd)
+    }
+
+    /// Read up to `len` bytes from `fd` at its current offset. Fewer are
+    /// returned at the end of the file.
+    pub(crate) fn read(&mut self, fd: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self.files.get(&fd).ok_or_else(bad_fd)? {
+            GuestFile::ReadOnly(file) => {
+                (&mut *file.borrow_mut()).take(len).read_to_end(&mut buf)?
+            }
+            GuestFile::ReadWrite(file) => {
+                (&mut *file.borrow_mut()).take(len).read_to_end(&mut buf)?
+            }
+            GuestFile::Input(reader) => (&mut *reader.borrow_mut())
+                .take(len)
+                .read_to_end(&mut buf)?,
+            GuestFile::Output(_) => return Err(bad_fd()),
+        };
+        Ok(buf)
+    }
+
+    /// Write `data` to `fd` at its current offset. Streams are flushed, so
+    /// that the host sees the data right away.
+    pub(crate) fn write(&mut self, fd: u64, data: &[u8]) -> io::Result<()> {
+        match self.files.get(&fd).ok_or_else(bad_fd)? {
+            GuestFile::ReadOnly(_) | GuestFile::Input(_) => Err(bad_fd()),
+            GuestFile::ReadWrite(file) => file.borrow_mut().write_all(data),
+            GuestFile::Output(writer) => {
+                let mut writer = writer.borrow_mut();
+                writer.write_all(data)?;
+                writer.flush()
+            }
+        }
+    }
+
+    /// Move the offset of `fd` to `offset` relative to `whence`, one of
+    /// SEEK_SET, SEEK_CUR and SEEK_END, and return the new offset.
+    pub(crate) fn seek(&mut self, fd: u64, offset: i64, whence: u64) -> io::Result<u64> {
+        let pos = match whence {
+            SEEK_SET => SeekFrom::Start(
+                u64::try_from(offset).map_err(|_| io::Error::from_raw_os_error(EINVAL))?,
+            ),
+            SEEK_CUR => SeekFrom::Current(offset),
+            SEEK_END => SeekFrom::End(offset),
+            _ => return Err(io::Error::from_raw_os_error(EINVAL)),
+        };
+        match self.files.get(&fd).ok_or_else(bad_fd)? {
+            GuestFile::ReadOnly(file) => file.borrow_mut().seek(pos),
+            GuestFile::ReadWrite(file) => file.borrow_mut().seek(pos),
+            GuestFile::Input(_) | GuestFile::Output(_) => Err(io::Error::from_raw_os_error(ESPIPE)),
+        }
+    }
+
+    /// Returns the mapped file descriptors, with the offsets of those that
+    /// are seekable.
+    pub(crate) fn offsets(&mut self) -> io::Result<BTreeMap<u64, Option<u64>>> {
+        self.files
+            .iter()
+            .map(|(fd, file)| {
+                let offset = match file {
+                    GuestFile::ReadOnly(file) => Some(file.borrow_mut().stream_position()?),
+                    GuestFile::ReadWrite(file) => Some(file.borrow_mut().stream_position()?),
+                    GuestFile::Input(_) | GuestFile::Output(_) => None,
+                };
+                Ok((*fd, offset))
+            })
+            .collect()
+    }
+
+    /// Bring the files back to the `offsets` returned by [FileTable::offsets]:
+    /// unmap the file descriptors missing from them, and seek the others.
+    pub(crate) fn restore_offsets(
+        &mut self,
+        offsets: &BTreeMap<u64, Option<u64>>,
+    ) -> io::Result<()> {
+        self.files.retain(|fd, _| offsets.contains_key(fd));
+        for (fd, offset) in offsets {
+            match offset {
+                Some(offset) => {
+                    self.seek(*fd, *offset as i64, SEEK_SET)?;
+                }
+                None if !self.contains(*fd) => return Err(bad_fd()),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{FileTable, EBADF, EINVAL, ESPIPE, SEEK_CUR, SEEK_END, SEEK_SET};
+
+    #[test]
+    fn read_write_seek() {
+        let mut output = Vec::new();
+        {
+            let mut files = FileTable::default();
+            files.insert(3, Cursor::new(b"hello world"));
+            files.insert_writable(4, Cursor::new(&mut output));
+
+            assert_eq!(files.read(3, 5).unwrap(), b"hello");
+            assert_eq!(files.seek(3, -3, SEEK_END).unwrap(), 8);
+            assert_eq!(files.read(3, 16).unwrap(), b"rld");
+            assert!(files.read(3, 16).unwrap().is_empty());
+
+            // Files are read-only unless mapped as writable.
+            let err = files.write(3, b"x").unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(EBADF));
+            files.write(4, b"abc").unwrap();
+            files.seek(4, 1, SEEK_SET).unwrap();
+            files.write(4, b"x").unwrap();
+
+            let err = files.seek(3, -1, SEEK_SET).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(EINVAL));
+            files.close(3).unwrap();
+            assert!(!files.contains(3));
+            assert!(files.read(3, 1).is_err());
+        }
+        assert_eq!(output, b"axc");
+    }
+
+    #[test]
+    fn streams() {
+        let mut output = Vec::new();
+        {
+            let mut files = FileTable::default();
+            files.insert_input(0, &b"input"[..]);
+            files.insert_output(1, &mut output);
+
+            assert_eq!(files.read(0, 2).unwrap(), b"in");
+            files.write(1, b"out").unwrap();
+            // Streams go one way, and cannot seek.
+            assert_eq!(
+                files.write(0, b"x").unwrap_err().raw_os_error(),
+                Some(EBADF)
+            );
+            assert_eq!(files.read(1, 1).unwrap_err().raw_os_error(), Some(EBADF));
+            let err = files.seek(0, 0, SEEK_CUR).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(ESPIPE));
+            assert_eq!(files.read(0, 16).unwrap(), b"put");
+        }
+        assert_eq!(output, b"out");
+    }
+}
@@ -0,0 +1,1058 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The RV64F and RV64D floating-point extensions.
+//!
+//! The 32 floating-point registers and `fcsr` are kept by the executor rather
+//! than in guest memory. Single-precision values are NaN-boxed in the 64-bit
+//! registers: their upper 32 bits are all ones, and an operand that is not
+//! boxed this way reads as the canonical NaN.
+//!
+//! Arithmetic is done by the host, rounding to nearest even. The other
+//! rounding modes are derived from the error of that result, which is exact
+//! except for fused multiply-adds and results that underflow. A NaN result is
+//! always the canonical NaN, and exceptions accrue in `fflags`.
+//!
+//! Only part of F and D can be proven: loads, stores, moves, sign injection,
+//! comparisons and classification, whose results are a function of the bits
+//! of their operands and need no rounding. With [FloatSupport::Provable], any
+//! other F or D instruction is an error.
+
+use std::{
+    array,
+    cmp::Ordering,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use anyhow::{bail, ensure, Result};
+use risc0_zkvm_platform::{memory::FLOAT_REGISTERS, DOUBLE_WORD_SIZE};
+use rrs_lib::{MemAccessSize, Memory};
+
+use super::monitor::MemoryMonitor;
+use crate::{opcode::OPCODE_SYSTEM, MemoryImage};
+
+const OPCODE_LOAD_FP: u32 = 0b0000111;
+const OPCODE_STORE_FP: u32 = 0b0100111;
+const OPCODE_MADD: u32 = 0b1000011;
+const OPCODE_MSUB: u32 = 0b1000111;
+const OPCODE_NMSUB: u32 = 0b1001011;
+const OPCODE_NMADD: u32 = 0b1001111;
+const OPCODE_OP_FP: u32 = 0b1010011;
+
+const FUNCT5_ADD: u32 = 0b00000;
+const FUNCT5_SUB: u32 = 0b00001;
+const FUNCT5_MUL: u32 = 0b00010;
+const FUNCT5_DIV: u32 = 0b00011;
+const FUNCT5_SQRT: u32 = 0b01011;
+const FUNCT5_SGNJ: u32 = 0b00100;
+const FUNCT5_MIN_MAX: u32 = 0b00101;
+const FUNCT5_CVT_FMT: u32 = 0b01000;
+const FUNCT5_COMPARE: u32 = 0b10100;
+const FUNCT5_CVT_TO_INT: u32 = 0b11000;
+const FUNCT5_CVT_FROM_INT: u32 = 0b11010;
+const FUNCT5_MV_TO_INT: u32 = 0b11100;
+const FUNCT5_MV_FROM_INT: u32 = 0b11110;
+
+const CSR_FFLAGS: u32 = 0x001;
+const CSR_FRM: u32 = 0x002;
+const CSR_FCSR: u32 = 0x003;
+
+// Exception flags, as laid out in fflags
+const FLAG_NX: u32 = 1 << 0;
+const FLAG_UF: u32 = 1 << 1;
+const FLAG_OF: u32 = 1 << 2;
+const FLAG_DZ: u32 = 1 << 3;
+const FLAG_NV: u32 = 1 << 4;
+
+/// Which floating-point instructions the guest may use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatSupport {
+    /// All of F and D, emulated by the host. A session that uses instructions
+    /// outside of the provable subset can be executed, but not proven.
+    #[default]
+    Emulated,
+
+    /// Only the subset of F and D that can be proven.
+    Provable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rounding {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+trait Float:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// The width of the encoding
+    const BITS: u32;
+
+    /// The width of the fraction
+    const FRACTION_BITS: u32;
+
+    const ZERO: Self;
+    const MAX: Self;
+    const INFINITY: Self;
+    const MIN_POSITIVE: Self;
+
+    fn from_bits(bits: u64) -> Self;
+    fn to_bits(self) -> u64;
+    fn mul_add(self, a: Self, b: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn round_int(self, rm: Rounding) -> Self;
+
+    /// Convert an integral value, saturating.
+    fn to_i128(self) -> i128;
+
+    /// Convert `value`, rounding to nearest even.
+    fn from_i128(value: i128) -> Self;
+
+    fn canonical_nan() -> Self {
+        let exponent = (1 << (Self::BITS - 1)) - (1 << Self::FRACTION_BITS);
+        Self::from_bits(exponent | 1 << (Self::FRACTION_BITS - 1))
+    }
+
+    fn is_nan(self) -> bool {
+        self.partial_cmp(&self).is_none()
+    }
+
+    fn is_signaling(self) -> bool {
+        self.is_nan() && (self.to_bits() & 1 << (Self::FRACTION_BITS - 1)) == 0
+    }
+
+    fn is_infinite(self) -> bool {
+        self == Self::INFINITY || self == -Self::INFINITY
+    }
+
+    fn is_zero(self) -> bool {
+        self == Self::ZERO
+    }
+
+    fn is_negative(self) -> bool {
+        self.to_bits() >> (Self::BITS - 1) != 0
+    }
+
+    fn abs(self) -> Self {
+        Self::from_bits(self.to_bits() & !(1 << (Self::BITS - 1)))
+    }
+}
+
+macro_rules! impl_float {
+    ($ty:ident, $bits:ident) => {
+        impl Float for $ty {
+            const BITS: u32 = $bits::BITS;
+            const FRACTION_BITS: u32 = $ty::MANTISSA_DIGITS - 1;
+            const ZERO: Self = 0.0;
+            const MAX: Self = $ty::MAX;
+            const INFINITY: Self = $ty::INFINITY;
+            const MIN_POSITIVE: Self = $ty::MIN_POSITIVE;
+
+            fn from_bits(bits: u64) -> Self {
+                $ty::from_bits(bits as $bits)
+            }
+
+            fn to_bits(self) -> u64 {
+                $ty::to_bits(self) as u64
+            }
+
+            fn mul_add(self, a: Self, b: Self) -> Self {
+                $ty::mul_add(self, a, b)
+            }
+
+            fn sqrt(self) -> Self {
+                $ty::sqrt(self)
+            }
+
+            fn round_int(self, rm: Rounding) -> Self {
+                match rm {
+                    Rounding::NearestEven => {
+                        // round() breaks ties away from zero, so step back
+                        // towards zero when that lands on an odd integer.
+                        let rounded = self.round();
+                        if (self - self.trunc()).abs() == 0.5 && rounded % 2.0 != 0.0 {
+                            (rounded.abs() - 1.0).copysign(self)
+                        } else {
+                            rounded
+                        }
+                    }
+                    Rounding::TowardZero => self.trunc(),
+                    Rounding::Down => self.floor(),
+                    Rounding::Up => self.ceil(),
+                    Rounding::NearestMaxMagnitude => self.round(),
+                }
+            }
+
+            fn to_i128(self) -> i128 {
+                self as i128
+            }
+
+            fn from_i128(value: i128) -> Self {
+                value as $ty
+            }
+        }
+    };
+}
+
+impl_float!(f32, u32);
+impl_float!(f64, u64);
+
+fn next_up<F: Float>(x: F) -> F {
+    if x.is_nan() || x == F::INFINITY {
+        return x;
+    }
+    if x.is_zero() {
+        return F::from_bits(1);
+    }
+    match x.is_negative() {
+        true => F::from_bits(x.to_bits() - 1),
+        false => F::from_bits(x.to_bits() + 1),
+    }
+}
+
+fn next_down<F: Float>(x: F) -> F {
+    -next_up(-x)
+}
+
+fn sign<F: Float>(x: F) -> Ordering {
+    x.partial_cmp(&F::ZERO).unwrap_or(Ordering::Equal)
+}
+
+/// Returns whether `q + err` lies halfway between `q` and its neighbour.
+fn is_tie<F: Float>(q: F, err: F) -> bool {
+    let neighbour = match sign(err) {
+        Ordering::Equal => return false,
+        Ordering::Greater => next_up(q),
+        Ordering::Less => next_down(q),
+    };
+    (neighbour - q).abs() == err.abs() + err.abs()
+}
+
+/// Round the exact result of an operation in mode `rm`, given `q`, the exact
+/// result rounded to nearest even, the sign of the error `exact - q`, and
+/// whether the exact result is a tie between `q` and its neighbour.
+fn round<F: Float>(q: F, err: Ordering, tie: bool, rm: Rounding) -> F {
+    match (rm, err) {
+        (_, Ordering::Equal) | (Rounding::NearestEven, _) => q,
+        (Rounding::NearestMaxMagnitude, Ordering::Greater) if tie && !q.is_negative() => next_up(q),
+        (Rounding::NearestMaxMagnitude, Ordering::Less) if tie && q.is_negative() => next_down(q),
+        (Rounding::TowardZero, _) if q.is_zero() => q,
+        (Rounding::TowardZero, Ordering::Greater) if q.is_negative() => next_up(q),
+        (Rounding::TowardZero, Ordering::Less) if !q.is_negative() => next_down(q),
+        (Rounding::Down, Ordering::Less) => next_down(q),
+        (Rounding::Up, Ordering::Greater) => next_up(q),
+        _ => q,
+    }
+}
+
+/// The result of an operation that overflowed in mode `rm`.
+fn overflow<F: Float>(negative: bool, rm: Rounding) -> F {
+    let magnitude = match (rm, negative) {
+        (Rounding::TowardZero, _) | (Rounding::Down, false) | (Rounding::Up, true) => F::MAX,
+        _ => F::INFINITY,
+    };
+    match negative {
+        true => -magnitude,
+        false => magnitude,
+    }
+}
+
+fn classify<F: Float>(x: F) -> u64 {
+    let negative = x.is_negative();
+    let class = if x.is_nan() {
+        if x.is_signaling() {
+            8
+        } else {
+            9
+        }
+    } else if x.is_infinite() {
+        if negative {
+            0
+        } else {
+            7
+        }
+    } else if x.is_zero() {
+        if negative {
+            3
+        } else {
+            4
+        }
+    } else if x.abs() < F::MIN_POSITIVE {
+        if negative {
+            2
+        } else {
+            5
+        }
+    } else if negative {
+        1
+    } else {
+        6
+    };
+    1 << class
+}
+
+/// Returns whether `insn` is an F or D instruction, or accesses one of the
+/// floating-point CSRs.
+pub(crate) fn is_float(insn: u32) -> bool {
+    match insn & 0x7f {
+        OPCODE_LOAD_FP | OPCODE_STORE_FP | OPCODE_MADD | OPCODE_MSUB | OPCODE_NMSUB
+        | OPCODE_NMADD | OPCODE_OP_FP => true,
+        OPCODE_SYSTEM => {
+            let funct3 = (insn >> 12) & 0x7;
+            funct3 & 0b11 != 0 && (CSR_FFLAGS..=CSR_FCSR).contains(&(insn >> 20))
+        }
+        _ => false,
+    }
+}
+
+/// The registers a floating-point instruction reads and writes, from its
+/// encoding
+pub(crate) struct FloatOperands {
+    /// Whether it reads integer register rs1
+    pub(crate) int_rs1: bool,
+    /// The floating-point registers it reads, in order
+    pub(crate) reads: Vec<usize>,
+    /// The floating-point register it writes, if any, rather than an integer
+    /// one
+    pub(crate) write: Option<usize>,
+}
+
+/// Returns the registers the floating-point instruction `insn` reads and
+/// writes. The fcsr is not counted.
+pub(crate) fn operands(insn: u32) -> FloatOperands {
+    let rd = ((insn >> 7) & 0x1f) as usize;
+    let rs1 = ((insn >> 15) & 0x1f) as usize;
+    let rs2 = ((insn >> 20) & 0x1f) as usize;
+    let rs3 = (insn >> 27) as usize;
+    let (int_rs1, reads, write) = match insn & 0x7f {
+        // The immediate forms take a 5-bit value in place of rs1.
+        OPCODE_SYSTEM => ((insn >> 12) & 0b100 == 0, vec![], None),
+        OPCODE_LOAD_FP => (true, vec![], Some(rd)),
+        OPCODE_STORE_FP => (true, vec![rs2], None),
+        OPCODE_OP_FP => match insn >> 27 {
+            FUNCT5_SQRT | FUNCT5_CVT_FMT => (false, vec![rs1], Some(rd)),
+            FUNCT5_CVT_TO_INT | FUNCT5_MV_TO_INT => (false, vec![rs1], None),
+            FUNCT5_CVT_FROM_INT | FUNCT5_MV_FROM_INT => (true, vec![], Some(rd)),
+            FUNCT5_COMPARE => (false, vec![rs1, rs2], None),
+            _ => (false, vec![rs1, rs2], Some(rd)),
+        },
+        // The fused multiply-adds
+        _ => (false, vec![rs1, rs2, rs3], Some(rd)),
+    };
+    FloatOperands {
+        int_rs1,
+        reads,
+        write,
+    }
+}
+
+/// Returns whether the floating-point instruction `insn` can be proven.
+fn is_provable(insn: u32) -> bool {
+    match insn & 0x7f {
+        OPCODE_LOAD_FP | OPCODE_STORE_FP | OPCODE_SYSTEM => true,
+        OPCODE_OP_FP => matches!(
+            insn >> 27,
+            FUNCT5_SGNJ | FUNCT5_MIN_MAX | FUNCT5_COMPARE | FUNCT5_MV_TO_INT | FUNCT5_MV_FROM_INT
+        ),
+        _ => false,
+    }
+}
+
+/// Returns the address of floating-point register `idx` in SYSTEM memory, with
+/// fcsr as register 32.
+fn register_addr(idx: usize) -> u64 {
+    (FLOAT_REGISTERS.start() + idx * DOUBLE_WORD_SIZE) as u64
+}
+
+/// The floating-point registers and control and status register, which are
+/// authoritative over their copy in [FLOAT_REGISTERS] until written back by
+/// [Fpu::sync]
+#[derive(Clone, Default)]
+pub(crate) struct Fpu {
+    pub(crate) regs: [u64; 32],
+    pub(crate) fcsr: u32,
+}
+
+impl Fpu {
+    /// Load the registers from their copy in the SYSTEM memory of `image`.
+    pub(crate) fn load(image: &mut MemoryImage) -> Self {
+        Self {
+            regs: array::from_fn(|idx| image.read_u64(register_addr(idx)).unwrap_or_default()),
+            fcsr: image.read_u64(register_addr(32)).unwrap_or_default() as u32,
+        }
+    }
+
+    /// Write the registers back to SYSTEM memory, so that the image reflects
+    /// them. Unchanged registers are skipped, to leave their pages clean.
+    pub(crate) fn sync(&self, image: &mut MemoryImage) {
+        let values = self.regs.into_iter().chain([self.fcsr as u64]);
+        for (idx, value) in values.enumerate() {
+            let addr = register_addr(idx);
+            if image.read_u64(addr).ok() != Some(value) {
+                image.write_mem(addr, MemAccessSize::DoubleWord, value);
+            }
+        }
+    }
+
+    /// Execute the floating-point instruction `insn`, whose rs1 field names
+    /// the integer register holding `rs1`, and return the value of its integer
+    /// destination register, if it has one.
+    pub(crate) fn execute(
+        &mut self,
+        monitor: &mut MemoryMonitor,
+        support: FloatSupport,
+        insn: u32,
+        rs1: u64,
+    ) -> Result<Option<u64>> {
+        ensure!(
+            support == FloatSupport::Emulated || is_provable(insn),
+            "instruction {insn:#010x} is outside of the provable subset of F and D"
+        );
+        let opcode = insn & 0x7f;
+        let rd = ((insn >> 7) & 0x1f) as usize;
+        let funct3 = (insn >> 12) & 0x7;
+        let rs2 = ((insn >> 20) & 0x1f) as usize;
+        let fmt = (insn >> 25) & 0x3;
+        match opcode {
+            OPCODE_SYSTEM => return self.csr(insn, rs1).map(Some),
+            OPCODE_LOAD_FP => {
+                let addr = rs1.wrapping_add((insn as i32 >> 20) as u64);
+                self.regs[rd] = match funct3 {
+                    0b010 => monitor.read_mem(addr, MemAccessSize::Word).unwrap() | u64::MAX << 32,
+                    0b011 => monitor.read_mem(addr, MemAccessSize::DoubleWord).unwrap(),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+            }
+            OPCODE_STORE_FP => {
+                let offset = ((insn as i32 >> 20) & !0x1f) as u64 | rd as u64;
+                let addr = rs1.wrapping_add(offset);
+                match funct3 {
+                    0b010 => monitor.write_mem(addr, MemAccessSize::Word, self.regs[rs2]),
+                    0b011 => monitor.write_mem(addr, MemAccessSize::DoubleWord, self.regs[rs2]),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+            }
+            OPCODE_MADD | OPCODE_MSUB | OPCODE_NMSUB | OPCODE_NMADD => match fmt {
+                0 => self.fused::<f32>(insn)?,
+                1 => self.fused::<f64>(insn)?,
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            },
+            OPCODE_OP_FP => {
+                return match fmt {
+                    0 => self.op::<f32>(insn, rs1),
+                    1 => self.op::<f64>(insn, rs1),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                }
+            }
+            _ => bail!("Not an F or D instruction: {insn:#010x}"),
+        }
+        Ok(None)
+    }
+
+    /// Returns register `idx` as a value of type `F`, unboxing single
+    /// precision values.
+    fn read<F: Float>(&self, idx: usize) -> F {
+        let value = self.regs[idx];
+        if F::BITS == 32 && value >> 32 != u32::MAX as u64 {
+            return F::canonical_nan();
+        }
+        F::from_bits(value)
+    }
+
+    fn write<F: Float>(&mut self, idx: usize, value: F) {
+        self.regs[idx] = match F::BITS {
+            32 => value.to_bits() | u64::MAX << 32,
+            _ => value.to_bits(),
+        };
+    }
+
+    fn raise(&mut self, flags: u32) {
+        self.fcsr |= flags;
+    }
+
+    fn rounding(&self, rm: u32) -> Result<Rounding> {
+        // The dynamic rounding mode is held in frm.
+        let rm = match rm {
+            0b111 => (self.fcsr >> 5) & 0x7,
+            _ => rm,
+        };
+        Ok(match rm {
+            0 => Rounding::NearestEven,
+            1 => Rounding::TowardZero,
+            2 => Rounding::Down,
+            3 => Rounding::Up,
+            4 => Rounding::NearestMaxMagnitude,
+            _ => bail!("Invalid rounding mode: {rm}"),
+        })
+    }
+
+    fn csr(&mut self, insn: u32, rs1: u64) -> Result<u64> {
+        let funct3 = (insn >> 12) & 0x7;
+        // The immediate forms take a 5-bit value in place of rs1.
+        let src = match funct3 & 0b100 {
+            0 => rs1 as u32,
+            _ => (insn >> 15) & 0x1f,
+        };
+        let (shift, mask) = match insn >> 20 {
+            CSR_FFLAGS => (0, 0x1f),
+            CSR_FRM => (5, 0x7),
+            CSR_FCSR => (0, 0xff),
+            _ => bail!("Not a floating-point CSR: {insn:#010x}"),
+        };
+        let old = (self.fcsr >> shift) & mask;
+        let new = match funct3 & 0b11 {
+            0b01 => src,
+            0b10 => old | src,
+            _ => old & !src,
+        };
+        self.fcsr = self.fcsr & !(mask << shift) | (new & mask) << shift;
+        Ok(old as u64)
+    }
+
+    /// Finish an arithmetic operation on `inputs`, given `q`, its exact result
+    /// rounded to nearest even, the sign of its error and whether the exact
+    /// result is a tie, by rounding in mode `rm` and raising exceptions.
+    fn finish<F: Float>(
+        &mut self,
+        inputs: &[F],
+        q: F,
+        err: Ordering,
+        tie: bool,
+        rm: Rounding,
+    ) -> F {
+        if inputs.iter().any(|x| x.is_signaling()) {
+            self.raise(FLAG_NV);
+        }
+        if q.is_nan() {
+            if !inputs.iter().any(|x| x.is_nan()) {
+                self.raise(FLAG_NV);
+            }
+            return F::canonical_nan();
+        }
+        if q.is_infinite() && !inputs.iter().any(|x| x.is_infinite()) {
+            self.raise(FLAG_OF | FLAG_NX);
+            return overflow(q.is_negative(), rm);
+        }
+        let result = round(q, err, tie, rm);
+        if err != Ordering::Equal {
+            self.raise(FLAG_NX);
+            if result.is_infinite() {
+                self.raise(FLAG_OF);
+            }
+            if result.abs() < F::MIN_POSITIVE {
+                self.raise(FLAG_UF);
+            }
+        }
+        result
+    }
+
+    fn add<F: Float>(&mut self, a: F, b: F, rm: Rounding) -> F {
+        let q = a + b;
+        let b_virtual = q - a;
+        let err = (a - (q - b_virtual)) + (b - b_virtual);
+        let result = self.finish(&[a, b], q, sign(err), is_tie(q, err), rm);
+        // An exact sum of zero is -0 when rounding down, unless both operands
+        // are +0.
+        if result.is_zero() && rm == Rounding::Down && (a.is_negative() || b.is_negative()) {
+            return -F::ZERO;
+        }
+        result
+    }
+
+    fn mul<F: Float>(&mut self, a: F, b: F, rm: Rounding) -> F {
+        let q = a * b;
+        let err = a.mul_add(b, -q);
+        self.finish(&[a, b], q, sign(err), is_tie(q, err), rm)
+    }
+
+    fn div<F: Float>(&mut self, a: F, b: F, rm: Rounding) -> F {
+        let q = a / b;
+        if b.is_zero() && !a.is_zero() && !a.is_nan() && !a.is_infinite() {
+            self.raise(FLAG_DZ);
+            return q;
+        }
+        // A quotient is never a tie.
+        let remainder = (-q).mul_add(b, a);
+        let err = match b.is_negative() {
+            true => sign(remainder).reverse(),
+            false => sign(remainder),
+        };
+        self.finish(&[a, b], q, err, false, rm)
+    }
+
+    fn sqrt<F: Float>(&mut self, a: F, rm: Rounding) -> F {
+        let q = a.sqrt();
+        // Nor is a square root.
+        let remainder = (-q).mul_add(q, a);
+        self.finish(&[a], q, sign(remainder), false, rm)
+    }
+
+    fn fused<F: Float>(&mut self, insn: u32) -> Result<()> {
+        let rm = self.rounding((insn >> 12) & 0x7)?;
+        let a: F = self.read(((insn >> 15) & 0x1f) as usize);
+        let b: F = self.read(((insn >> 20) & 0x1f) as usize);
+        let c: F = self.read((insn >> 27) as usize);
+        let (a, c) = match insn & 0x7f {
+            OPCODE_MADD => (a, c),
+            OPCODE_MSUB => (a, -c),
+            OPCODE_NMSUB => (-a, c),
+            _ => (-a, -c),
+        };
+        if (a.is_zero() && b.is_infinite()) || (a.is_infinite() && b.is_zero()) {
+            self.raise(FLAG_NV);
+        }
+        let q = a.mul_add(b, c);
+        // The exact result is p + p_err + s + s_err, from the exact product and
+        // the exact sum of its rounding with c.
+        let p = a * b;
+        let p_err = a.mul_add(b, -p);
+        let s = p + c;
+        let c_virtual = s - p;
+        let s_err = (p - (s - c_virtual)) + (c - c_virtual);
+        let err = ((s - q) + s_err) + p_err;
+        let mut result = self.finish(&[a, b, c], q, sign(err), is_tie(q, err), rm);
+        let product_negative = a.is_negative() != b.is_negative();
+        if result.is_zero() && rm == Rounding::Down && (product_negative || c.is_negative()) {
+            result = -F::ZERO;
+        }
+        self.write(((insn >> 7) & 0x1f) as usize, result);
+        Ok(())
+    }
+
+    /// Round `x` to an integer in mode `rm`, saturating to the range from
+    /// `min` to `max`.
+    fn fcvt_to_int<F: Float>(&mut self, x: F, rm: Rounding, min: i128, max: i128) -> i128 {
+        if x.is_nan() {
+            self.raise(FLAG_NV);
+            return max;
+        }
+        let rounded = x.round_int(rm);
+        let value = rounded.to_i128();
+        if value < min || value > max {
+            self.raise(FLAG_NV);
+            return value.clamp(min, max);
+        }
+        if rounded != x {
+            self.raise(FLAG_NX);
+        }
+        value
+    }
+
+    fn fcvt_from_int<F: Float>(&mut self, value: i128, rm: Rounding) -> F {
+        let q = F::from_i128(value);
+        let diff = value - q.to_i128();
+        let neighbour = match diff.cmp(&0) {
+            Ordering::Greater => next_up(q),
+            _ => next_down(q),
+        };
+        let tie = diff != 0 && (neighbour.to_i128() - q.to_i128()).abs() == 2 * diff.abs();
+        self.finish(&[], q, diff.cmp(&0), tie, rm)
+    }
+
+    fn min_max<F: Float>(&mut self, a: F, b: F, max: bool) -> F {
+        if a.is_signaling() || b.is_signaling() {
+            self.raise(FLAG_NV);
+        }
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => F::canonical_nan(),
+            (true, false) => b,
+            (false, true) => a,
+            // -0 is less than +0.
+            _ if a.is_zero() && b.is_zero() => match a.is_negative() != max {
+                true => a,
+                false => b,
+            },
+            _ if (a < b) != max => a,
+            _ => b,
+        }
+    }
+
+    fn compare<F: Float>(&mut self, a: F, b: F, funct3: u32) -> Result<u64> {
+        if a.is_nan() || b.is_nan() {
+            // Only FEQ is a quiet comparison.
+            if funct3 != 0b010 || a.is_signaling() || b.is_signaling() {
+                self.raise(FLAG_NV);
+            }
+            return Ok(0);
+        }
+        Ok(match funct3 {
+            0b010 => a == b,
+            0b001 => a < b,
+            0b000 => a <= b,
+            _ => bail!("Illegal comparison: {funct3:#b}"),
+        } as u64)
+    }
+
+    /// Narrow the double `x` to single precision in mode `rm`.
+    fn narrow(&mut self, x: f64, rm: Rounding) -> f32 {
+        if x.is_nan() {
+            if Float::is_signaling(x) {
+                self.raise(FLAG_NV);
+            }
+            return f32::canonical_nan();
+        }
+        let q = x as f32;
+        if x.is_infinite() {
+            return q;
+        }
+        let err = x - q as f64;
+        let neighbour = match sign(err) {
+            Ordering::Greater => next_up(q),
+            _ => next_down(q),
+        };
+        let tie = err != 0.0 && (neighbour as f64 - q as f64).abs() == 2.0 * err.abs();
+        self.finish(&[], q, sign(err), tie, rm)
+    }
+
+    fn op<F: Float>(&mut self, insn: u32, rs1_value: u64) -> Result<Option<u64>> {
+        let rd = ((insn >> 7) & 0x1f) as usize;
+        let funct3 = (insn >> 12) & 0x7;
+        let rs1 = ((insn >> 15) & 0x1f) as usize;
+        let rs2 = ((insn >> 20) & 0x1f) as usize;
+        let a: F = self.read(rs1);
+        let b: F = self.read(rs2);
+        let result = match insn >> 27 {
+            FUNCT5_ADD => self.add(a, b, self.rounding(funct3)?),
+            FUNCT5_SUB => self.add(a, -b, self.rounding(funct3)?),
+            FUNCT5_MUL => self.mul(a, b, self.rounding(funct3)?),
+            FUNCT5_DIV => self.div(a, b, self.rounding(funct3)?),
+            FUNCT5_SQRT => self.sqrt(a, self.rounding(funct3)?),
+            FUNCT5_SGNJ => {
+                let sign = 1 << (F::BITS - 1);
+                let (a, b) = (a.to_bits(), b.to_bits());
+                let b = match funct3 {
+                    0b000 => b,
+                    0b001 => !b,
+                    0b010 => a ^ b,
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+                F::from_bits(a & !sign | b & sign)
+            }
+            FUNCT5_MIN_MAX => match funct3 {
+                0b000 => self.min_max(a, b, false),
+                0b001 => self.min_max(a, b, true),
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            },
+            FUNCT5_CVT_FMT => {
+                let rm = self.rounding(funct3)?;
+                match F::BITS {
+                    // FCVT.S.D
+                    32 => F::from_bits(Float::to_bits(self.narrow(self.read(rs1), rm))),
+                    // FCVT.D.S
+                    _ => {
+                        let x: f32 = self.read(rs1);
+                        if x.is_nan() {
+                            if Float::is_signaling(x) {
+                                self.raise(FLAG_NV);
+                            }
+                            F::canonical_nan()
+                        } else {
+                            F::from_bits((x as f64).to_bits())
+                        }
+                    }
+                }
+            }
+            FUNCT5_COMPARE => return self.compare(a, b, funct3).map(Some),
+            FUNCT5_CVT_TO_INT => {
+                let rm = self.rounding(funct3)?;
+                let (min, max) = match rs2 {
+                    0 => (i32::MIN as i128, i32::MAX as i128),
+                    1 => (0, u32::MAX as i128),
+                    2 => (i64::MIN as i128, i64::MAX as i128),
+                    3 => (0, u64::MAX as i128),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+                let value = self.fcvt_to_int(a, rm, min, max);
+                // Word results are sign-extended, even unsigned ones.
+                return Ok(Some(match rs2 {
+                    0 | 1 => value as i32 as u64,
+                    _ => value as u64,
+                }));
+            }
+            FUNCT5_CVT_FROM_INT => {
+                let rm = self.rounding(funct3)?;
+                let value = match rs2 {
+                    0 => rs1_value as i32 as i128,
+                    1 => rs1_value as u32 as i128,
+                    2 => rs1_value as i64 as i128,
+                    3 => rs1_value as i128,
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+                self.fcvt_from_int(value, rm)
+            }
+            FUNCT5_MV_TO_INT => match funct3 {
+                // FMV.X.W and FMV.X.D move the raw bits, sign-extended.
+                0b000 => {
+                    let shift = 64 - F::BITS;
+                    return Ok(Some(((self.regs[rs1] << shift) as i64 >> shift) as u64));
+                }
+                0b001 => return Ok(Some(classify(a))),
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            },
+            FUNCT5_MV_FROM_INT => F::from_bits(rs1_value),
+            _ => bail!("Illegal instruction: {insn:#010x}"),
+        };
+        self.write(rd, result);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm_platform::memory::FLOAT_REGISTERS;
+
+    use super::{Float, FloatSupport, Fpu, Rounding};
+    use crate::{
+        exec::monitor::MemoryMonitor,
+        testutil::{image, RET},
+    };
+
+    const RNE: u32 = 0b000;
+    const RTZ: u32 = 0b001;
+    const RDN: u32 = 0b010;
+    const RUP: u32 = 0b011;
+    const RMM: u32 = 0b100;
+    const DYN: u32 = 0b111;
+
+    const NV: u32 = 1 << 4;
+    const DZ: u32 = 1 << 3;
+    const NX: u32 = 1 << 0;
+
+    // Encode the OP-FP instruction `funct5` on doubles, with f1 = f2 op f3.
+    fn op_d(funct5: u32, funct3: u32) -> u32 {
+        funct5 << 27 | 1 << 25 | 3 << 20 | 2 << 15 | funct3 << 12 | 1 << 7 | 0b1010011
+    }
+
+    struct Harness {
+        fpu: Fpu,
+        monitor: MemoryMonitor,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let image = image(&[RET], &[]);
+            Self {
+                fpu: Fpu::default(),
+                monitor: MemoryMonitor::new(image),
+            }
+        }
+
+        fn exec(&mut self, insn: u32, rs1: u64) -> Option<u64> {
+            self.fpu
+                .execute(&mut self.monitor, FloatSupport::Emulated, insn, rs1)
+                .unwrap()
+        }
+
+        // Apply the double operation `funct5` to `a` and `b`, returning the
+        // result and the exceptions it raised.
+        fn double(&mut self, funct5: u32, funct3: u32, a: f64, b: f64) -> (f64, u32) {
+            self.fpu.fcsr &= !0x1f;
+            self.fpu.regs[2] = a.to_bits();
+            self.fpu.regs[3] = b.to_bits();
+            self.exec(op_d(funct5, funct3), 0);
+            (f64::from_bits(self.fpu.regs[1]), self.fpu.fcsr & 0x1f)
+        }
+    }
+
+    #[test]
+    fn ties_to_even() {
+        let round = |x: f64| x.round_int(Rounding::NearestEven);
+        assert_eq!(round(0.5).to_bits(), 0f64.to_bits());
+        assert_eq!(round(-0.5).to_bits(), (-0f64).to_bits());
+        assert_eq!(round(1.5), 2.0);
+        assert_eq!(round(2.5), 2.0);
+        assert_eq!(round(-2.5), -2.0);
+        assert_eq!(round(3.5), 4.0);
+        assert_eq!(round(2.6), 3.0);
+        assert_eq!(2.5f32.round_int(Rounding::NearestEven), 2.0);
+    }
+
+    #[test]
+    fn system_memory() {
+        let mut h = Harness::new();
+        h.fpu.regs[1] = 1.5f64.to_bits();
+        h.fpu.fcsr = 0xe1;
+        h.fpu.sync(&mut h.monitor.image);
+        let addr = FLOAT_REGISTERS.start() as u64;
+        assert_eq!(
+            h.monitor.image.read_u64(addr + 8).unwrap(),
+            1.5f64.to_bits()
+        );
+        assert_eq!(h.monitor.image.read_u32(addr + 32 * 8).unwrap(), 0xe1);
+
+        let fpu = Fpu::load(&mut h.monitor.image);
+        assert_eq!(fpu.regs, h.fpu.regs);
+        assert_eq!(fpu.fcsr, 0xe1);
+    }
+
+    #[test]
+    fn rounding() {
+        let mut h = Harness::new();
+        let tiny = 2f64.powi(-60);
+        let above_one = 1.0 + f64::EPSILON;
+        let below_one = 1.0 - f64::EPSILON / 2.0;
+
+        assert_eq!(h.double(0b00000, RNE, 1.0, tiny), (1.0, NX));
+        assert_eq!(h.double(0b00000, RUP, 1.0, tiny), (above_one, NX));
+        assert_eq!(h.double(0b00000, RDN, 1.0, tiny), (1.0, NX));
+        assert_eq!(h.double(0b00001, RDN, 1.0, tiny), (below_one, NX));
+        assert_eq!(h.double(0b00001, RTZ, -1.0, tiny), (-1.0, NX));
+        assert_eq!(h.double(0b00000, RDN, -1.0, -tiny), (-above_one, NX));
+        assert_eq!(h.double(0b00000, RNE, 1.0, 1.0), (2.0, 0));
+
+        // A tie rounds to even, or away from zero.
+        let half_ulp = f64::EPSILON / 2.0;
+        assert_eq!(h.double(0b00000, RNE, 1.0, half_ulp), (1.0, NX));
+        assert_eq!(h.double(0b00000, RMM, 1.0, half_ulp), (above_one, NX));
+        assert_eq!(h.double(0b00000, RMM, -1.0, -half_ulp), (-above_one, NX));
+
+        // Division and square roots are rounded from their remainders.
+        assert_eq!(h.double(0b00011, RNE, 1.0, 3.0).0, 1.0 / 3.0);
+        assert!(h.double(0b00011, RUP, 1.0, 3.0).0 > 1.0 / 3.0);
+        assert_eq!(h.double(0b00011, RDN, 1.0, 3.0).0, 1.0 / 3.0);
+        assert!(h.double(0b00011, RDN, 1.0, -3.0).0 < -1.0 / 3.0);
+        assert!(h.double(0b01011, RDN, 2.0, 0.0).0 < 2f64.sqrt());
+        assert_eq!(h.double(0b01011, RNE, 4.0, 0.0), (2.0, 0));
+
+        // The dynamic rounding mode is set through frm.
+        // csrwi frm, 3
+        h.exec(0x0021d073, 0);
+        assert_eq!(h.double(0b00000, DYN, 1.0, tiny), (above_one, NX));
+
+        // An exact zero sum is negative when rounding down.
+        assert!(h.double(0b00000, RDN, 1.0, -1.0).0.is_sign_negative());
+        assert!(h.double(0b00000, RNE, 1.0, -1.0).0.is_sign_positive());
+
+        // Overflow is rounded to infinity or the largest finite value.
+        assert_eq!(h.double(0b00010, RNE, f64::MAX, 2.0).0, f64::INFINITY);
+        assert_eq!(h.double(0b00010, RTZ, f64::MAX, 2.0).0, f64::MAX);
+        assert_eq!(h.double(0b00010, RUP, -f64::MAX, 2.0).0, -f64::MAX);
+        assert_eq!(h.double(0b00010, RDN, -f64::MAX, 2.0).0, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut h = Harness::new();
+        let canonical_nan = 0x7ff8_0000_0000_0000;
+
+        assert_eq!(h.double(0b00011, RNE, 1.0, 0.0), (f64::INFINITY, DZ));
+        let (nan, flags) = h.double(0b00011, RNE, 0.0, 0.0);
+        assert_eq!((nan.to_bits(), flags), (canonical_nan, NV));
+        let (nan, flags) = h.double(0b01011, RNE, -1.0, 0.0);
+        assert_eq!((nan.to_bits(), flags), (canonical_nan, NV));
+
+        // A quiet NaN propagates as the canonical NaN, quietly; a signaling
+        // one is invalid.
+        let quiet = f64::from_bits(0x7ff8_0000_0000_0001);
+        let signaling = f64::from_bits(0x7ff0_0000_0000_0001);
+        let (nan, flags) = h.double(0b00000, RNE, quiet, 1.0);
+        assert_eq!((nan.to_bits(), flags), (canonical_nan, 0));
+        assert_eq!(h.double(0b00000, RNE, signaling, 1.0).1, NV);
+
+        // FEQ is quiet, FLT is not.
+        assert_eq!(h.double(0b10100, 0b010, quiet, 1.0).1, 0);
+        assert_eq!(h.double(0b10100, 0b001, quiet, 1.0).1, NV);
+        assert_eq!(h.exec(op_d(0b10100, 0b001), 0), Some(0));
+
+        // FMIN and FMAX ignore a NaN, and order -0 below +0.
+        assert_eq!(h.double(0b00101, 0b000, quiet, 2.0).0, 2.0);
+        assert!(h.double(0b00101, 0b000, 0.0, -0.0).0.is_sign_negative());
+        assert!(h.double(0b00101, 0b001, -0.0, 0.0).0.is_sign_positive());
+    }
+
+    #[test]
+    fn nan_boxing() {
+        let mut h = Harness::new();
+        // fadd.s f1, f2, f3
+        let fadd_s = 0b0000000 << 25 | 3 << 20 | 2 << 15 | 1 << 7 | 0b1010011;
+        h.fpu.regs[2] = 1.5f32.to_bits() as u64 | 0xffff_ffff << 32;
+        h.fpu.regs[3] = 2.0f32.to_bits() as u64 | 0xffff_ffff << 32;
+        h.exec(fadd_s, 0);
+        assert_eq!(h.fpu.regs[1], 3.5f32.to_bits() as u64 | 0xffff_ffff << 32);
+
+        // An unboxed operand is the canonical NaN.
+        h.fpu.regs[3] = 2.0f32.to_bits() as u64;
+        h.exec(fadd_s, 0);
+        assert_eq!(h.fpu.regs[1], 0xffff_ffff_7fc0_0000);
+
+        // fmv.x.w a0, f1 sign-extends the raw bits; fmv.w.x f1, a0 boxes them.
+        assert_eq!(h.exec(0xe0008553, 0), Some(0x7fc0_0000));
+        h.exec(0xf00500d3, 0x1234_0000_3f80_0000);
+        assert_eq!(h.fpu.regs[1], 0xffff_ffff_3f80_0000);
+
+        // fcvt.d.s f1, f1
+        h.exec(0x420080d3, 0);
+        assert_eq!(f64::from_bits(h.fpu.regs[1]), 1.0);
+    }
+
+    #[test]
+    fn conversions() {
+        let mut h = Harness::new();
+        // fcvt.w.d a0, f2 and fcvt.wu.d a0, f2
+        let fcvt_w = |rm: u32| 0b1100001 << 25 | 2 << 15 | rm << 12 | 10 << 7 | 0b1010011;
+        let fcvt_wu = |rm: u32| fcvt_w(rm) | 1 << 20;
+        let mut to_int = |insn: u32, x: f64| {
+            h.fpu.fcsr = 0;
+            h.fpu.regs[2] = x.to_bits();
+            (h.exec(insn, 0).unwrap() as i64, h.fpu.fcsr)
+        };
+        assert_eq!(to_int(fcvt_w(RNE), 2.5), (2, NX));
+        assert_eq!(to_int(fcvt_w(RMM), 2.5), (3, NX));
+        assert_eq!(to_int(fcvt_w(RDN), -2.5), (-3, NX));
+        assert_eq!(to_int(fcvt_w(RNE), -7.0), (-7, 0));
+        assert_eq!(to_int(fcvt_w(RNE), f64::NAN), (i32::MAX as i64, NV));
+        assert_eq!(to_int(fcvt_w(RNE), 1e10), (i32::MAX as i64, NV));
+        assert_eq!(to_int(fcvt_wu(RNE), -1.0), (0, NV));
+        assert_eq!(to_int(fcvt_wu(RTZ), -0.5), (0, NX));
+        // The unsigned word result is sign-extended.
+        assert_eq!(
+            to_int(fcvt_wu(RNE), 4e9),
+            (4_000_000_000u32 as i32 as i64, 0)
+        );
+
+        // fcvt.d.lu f1, a0 rounds in the given mode.
+        let fcvt_d_lu =
+            |rm: u32| 0b1101001 << 25 | 3 << 20 | 10 << 15 | rm << 12 | 1 << 7 | 0b1010011;
+        h.exec(fcvt_d_lu(RUP), u64::MAX);
+        assert_eq!(f64::from_bits(h.fpu.regs[1]), 2f64.powi(64));
+        h.exec(fcvt_d_lu(RTZ), u64::MAX);
+        assert_eq!(f64::from_bits(h.fpu.regs[1]), 2f64.powi(64) - 2048.0);
+        h.exec(fcvt_d_lu(RNE), 3);
+        assert_eq!(f64::from_bits(h.fpu.regs[1]), 3.0);
+    }
+
+    #[test]
+    fn provable() {
+        let mut h = Harness::new();
+        let fadd_d = op_d(0b00000, RNE);
+        let fsgnjn_d = op_d(0b00100, 0b001);
+        h.fpu.regs[2] = 1f64.to_bits();
+        h.fpu.regs[3] = 1f64.to_bits();
+        let provable = FloatSupport::Provable;
+        assert!(h.fpu.execute(&mut h.monitor, provable, fadd_d, 0).is_err());
+        h.fpu
+            .execute(&mut h.monitor, provable, fsgnjn_d, 0)
+            .unwrap();
+        assert_eq!(f64::from_bits(h.fpu.regs[1]), -1.0);
+    }
+}
@@ -0,0 +1,527 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A stub for the GDB remote serial protocol.
+//!
+//! This lets a guest be debugged with a standard `riscv64` GDB (`target remote
+//! <addr>`) or LLDB (`gdb-remote <addr>`). The stub supports reading and
+//! writing registers and memory, software and hardware breakpoints,
+//! watchpoints, single-stepping and continuing. The guest cannot be
+//! interrupted while it runs.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{ErrorKind, Read, Write},
+    ops::Range,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{Executor, WatchKind};
+use crate::{ExitCode, GuestFault};
+
+/// Register names in GDB order; the pc follows them.
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const PC_REGISTER: usize = 32;
+
+const PACKET_SIZE: usize = 0x4000;
+
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGBUS: u8 = 7;
+const SIGSEGV: u8 = 11;
+
+/// How a debugging session ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GdbOutcome {
+    /// The debugger detached or disconnected; the guest may be resumed.
+    Detached,
+
+    /// The debugger killed the guest.
+    Killed,
+
+    /// The guest exited while being debugged.
+    Exited(ExitCode),
+}
+
+enum Reply {
+    Packet(String),
+    End(GdbOutcome),
+}
+
+/// Serves a single debugger connection for an [Executor].
+pub struct GdbStub<'e, 'a, S> {
+    exec: &'e mut Executor<'a>,
+    stream: S,
+    ack: bool,
+    exit: Option<ExitCode>,
+    watchpoints: HashMap<(u8, u64, u64), usize>,
+}
+
+impl<'e, 'a, S: Read + Write> GdbStub<'e, 'a, S> {
+    /// Construct a stub which debugs `exec` over `stream`.
+    pub fn new(exec: &'e mut Executor<'a>, stream: S) -> Self {
+        Self {
+            exec,
+            stream,
+            ack: true,
+            exit: None,
+            watchpoints: HashMap::new(),
+        }
+    }
+
+    /// Handle requests until the debugger detaches, kills the guest or
+    /// disconnects.
+    pub fn serve(mut self) -> Result<GdbOutcome> {
+        while let Some(packet) = self.read_packet()? {
+            tracing::debug!("gdb <- {packet}");
+            match self.handle(&packet) {
+                Reply::Packet(reply) => {
+                    self.write_packet(&reply)?;
+                    if packet == "QStartNoAckMode" {
+                        self.ack = false;
+                    }
+                }
+                Reply::End(outcome) => {
+                    if outcome == GdbOutcome::Detached {
+                        self.write_packet("OK")?;
+                    }
+                    return Ok(outcome);
+                }
+            }
+        }
+        Ok(match self.exit {
+            Some(exit_code) => GdbOutcome::Exited(exit_code),
+            None => GdbOutcome::Detached,
+        })
+    }
+
+    fn handle(&mut self, packet: &str) -> Reply {
+        let cmd = packet.get(..1).unwrap_or_default();
+        let args = packet.get(1..).unwrap_or_default();
+        let reply = match cmd {
+            "?" => Ok(self.stop_reply(None)),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.set_breakpoint(args, true),
+            "z" => self.set_breakpoint(args, false),
+            "s" => self.resume(Executor::step),
+            "c" => self.resume(|exec| exec.continue_until_break().map(Some)),
+            "H" | "T" => Ok("OK".into()),
+            "D" => return Reply::End(GdbOutcome::Detached),
+            "k" => return Reply::End(GdbOutcome::Killed),
+            "q" | "Q" => Ok(self.query(packet)),
+            _ => Ok(String::new()),
+        };
+        Reply::Packet(reply.unwrap_or_else(|err| {
+            tracing::debug!("gdb request {packet:?} failed: {err:#}");
+            "E01".into()
+        }))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(range) {
+                Ok(range) => {
+                    let xml = target_xml();
+                    let start = (range.start as usize).min(xml.len());
+                    let end = (range.end as usize).min(xml.len());
+                    let prefix = if end == xml.len() { 'l' } else { 'm' };
+                    format!("{prefix}{}", &xml[start..end])
+                }
+                Err(_) => "E01".into(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .into()
+    }
+
+    fn stop_reply(&self, watch: Option<(&str, u64)>) -> String {
+        match self.exit {
+            None | Some(ExitCode::Paused | ExitCode::SystemSplit) => match watch {
+                Some((kind, addr)) => format!("T{SIGTRAP:02x}{kind}:{addr:x};"),
+                None => format!("S{SIGTRAP:02x}"),
+            },
+            Some(ExitCode::Halted(code)) => format!("W{:02x}", code as u8),
+            Some(ExitCode::Fault(
+                GuestFault::MisalignedLoad(_)
+                | GuestFault::MisalignedStore(_)
+                | GuestFault::MisalignedFetch(_),
+            )) => format!("S{SIGBUS:02x}"),
+            Some(ExitCode::Fault(_)) => format!("S{SIGSEGV:02x}"),
+            Some(ExitCode::SessionLimit) => format!("S{SIGABRT:02x}"),
+        }
+    }
+
+    fn resume(
+        &mut self,
+        run: impl FnOnce(&mut Executor<'a>) -> Result<Option<ExitCode>>,
+    ) -> Result<String> {
+        if let Some(ExitCode::Halted(_)) = self.exit {
+            return Ok(self.stop_reply(None));
+        }
+        let exit_code = match run(self.exec) {
+            Ok(exit_code) => exit_code,
+            Err(err) => {
+                tracing::warn!("guest stopped at pc 0x{:08x}: {err:#}", self.exec.pc);
+                return Ok(format!("S{SIGABRT:02x}"));
+            }
+        };
+        self.exit = exit_code;
+        let watch = self
+            .exec
+            .watchpoint_hit()
+            .map(|hit| (if hit.write { "watch" } else { "rwatch" }, hit.addr));
+        Ok(self.stop_reply(watch))
+    }
+
+    fn register(&mut self, idx: usize) -> Result<u64> {
+        match idx {
+            0 => Ok(0),
+            PC_REGISTER => Ok(self.exec.pc),
+            1..=31 => Ok(self.exec.monitor.register(idx)),
+            _ => bail!("no register {idx}"),
+        }
+    }
+
+    fn set_register(&mut self, idx: usize, value: u64) -> Result<()> {
+        match idx {
+            0 => Ok(()),
+            PC_REGISTER => {
+                self.exec.pc = value;
+                Ok(())
+            }
+            1..=31 => {
+                self.exec.monitor.set_register(idx, value);
+                Ok(())
+            }
+            _ => bail!("no register {idx}"),
+        }
+    }
+
+    fn read_registers(&mut self) -> Result<String> {
+        let mut out = String::new();
+        for idx in 0..=PC_REGISTER {
+            out.push_str(&encode_hex(&self.register(idx)?.to_le_bytes()));
+        }
+        Ok(out)
+    }
+
+    fn write_registers(&mut self, args: &str) -> Result<String> {
+        let bytes = decode_hex(args)?;
+        for (idx, value) in bytes.chunks_exact(8).take(PC_REGISTER + 1).enumerate() {
+            self.set_register(idx, u64::from_le_bytes(value.try_into().unwrap()))?;
+        }
+        Ok("OK".into())
+    }
+
+    fn read_register(&mut self, args: &str) -> Result<String> {
+        let idx = parse_hex(args)? as usize;
+        Ok(encode_hex(&self.register(idx)?.to_le_bytes()))
+    }
+
+    fn write_register(&mut self, args: &str) -> Result<String> {
+        let (idx, value) = args.split_once('=').context("malformed register write")?;
+        let value: [u8; 8] = decode_hex(value)?
+            .try_into()
+            .map_err(|_| anyhow!("registers are 8 bytes"))?;
+        self.set_register(parse_hex(idx)? as usize, u64::from_le_bytes(value))?;
+        Ok("OK".into())
+    }
+
+    fn read_memory(&mut self, args: &str) -> Result<String> {
+        let range = parse_range(args)?;
+        let len = (range.end - range.start).min(PACKET_SIZE as u64 / 2);
+        let bytes = self.exec.monitor.image.read_region(range.start, len)?;
+        Ok(encode_hex(&bytes))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Result<String> {
+        let (range, data) = args.split_once(':').context("malformed memory write")?;
+        let range = parse_range(range)?;
+        let data = decode_hex(data)?;
+        if data.len() as u64 != range.end - range.start {
+            bail!("memory write length mismatch");
+        }
+        self.exec.monitor.image.write_region(range.start, &data)?;
+        Ok("OK".into())
+    }
+
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> Result<String> {
+        let (kind, range) = args.split_once(',').context("malformed breakpoint")?;
+        let range = parse_range(range.split(';').next().unwrap())?;
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.exec.add_breakpoint(range.start);
+                } else {
+                    self.exec.remove_breakpoint(range.start);
+                }
+                return Ok("OK".into());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Ok(String::new()),
+        };
+        let key = (kind.as_bytes()[0], range.start, range.end);
+        if insert {
+            let id = self.exec.add_watchpoint(range, watch);
+            if let Some(old) = self.watchpoints.insert(key, id) {
+                self.exec.remove_watchpoint(old);
+            }
+        } else if let Some(id) = self.watchpoints.remove(&key) {
+            self.exec.remove_watchpoint(id);
+        }
+        Ok("OK".into())
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            return match self.stream.read(&mut byte) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => Err(err.into()),
+            };
+        }
+    }
+
+    /// Read the next packet, returning None if the debugger disconnected.
+    fn read_packet(&mut self) -> Result<Option<String>> {
+        loop {
+            // Skip acknowledgements and interrupt requests.
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'$') => break,
+                    Some(_) => {}
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                let Some(byte) = self.read_byte()? else {
+                    return Ok(None);
+                };
+                *digit = byte;
+            }
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(checksum_of(&data));
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                self.stream.flush()?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8(data)?));
+            }
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> Result<()> {
+        tracing::debug!("gdb -> {data}");
+        let packet = format!("${data}#{:02x}", checksum_of(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            self.stream.flush()?;
+            if !self.ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><architecture>riscv:rv64</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+    for (idx, name) in REGISTER_NAMES.iter().enumerate() {
+        write!(
+            xml,
+            "<reg name=\"{name}\" bitsize=\"64\" type=\"int\" regnum=\"{idx}\"/>"
+        )
+        .unwrap();
+    }
+    write!(
+        xml,
+        "<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"{PC_REGISTER}\"/>\
+         </feature></target>"
+    )
+    .unwrap();
+    xml
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(text: &str) -> Result<u64> {
+    u64::from_str_radix(text, 16).with_context(|| format!("invalid hex number: {text:?}"))
+}
+
+/// Parse `addr,len` into the range it covers.
+fn parse_range(text: &str) -> Result<Range<u64>> {
+    let (addr, len) = text.split_once(',').context("malformed range")?;
+    let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+    Ok(addr..addr.checked_add(len).context("range overflows")?)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        bail!("odd length hex string");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| parse_hex(&text[i..i + 2]).map(|byte| byte as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use risc0_zkvm_platform::syscall::{ecall, reg_abi::REG_A7};
+
+    use super::{checksum_of, GdbOutcome, GdbStub};
+    use crate::{
+        testutil::{image, ECALL, ENTRY},
+        Executor, ExecutorEnv, ExitCode,
+    };
+
+    struct Session {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Session {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Session {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${data}#{:02x}", checksum_of(data.as_bytes()))
+    }
+
+    #[test]
+    fn debug_session() {
+        // A run of `ecall`s, each a gettid.
+        let image = image(&[ECALL; 8], &[(REG_A7, ecall::GETTID)]);
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+
+        // After the first reply, acknowledgements are turned off.
+        let requests = [
+            "QStartNoAckMode",
+            "?",
+            "m1000,4",
+            "Z0,1008,4",
+            // A watchpoint that runs past the end of the address space.
+            "Z2,fffffffffffffffc,8",
+            "c",
+            "p20",
+            "pa",
+            "s",
+            "p20",
+            // Halt: a7 = HALT, a0 = TERMINATE.
+            "P11=0000000000000000",
+            "Pa=0000000000000000",
+            "c",
+        ];
+        let mut input = packet(requests[0]) + "+";
+        for request in &requests[1..] {
+            input += &packet(request);
+        }
+        let mut session = Session {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        };
+        let outcome = GdbStub::new(&mut exec, &mut session).serve().unwrap();
+        assert_eq!(outcome, GdbOutcome::Exited(ExitCode::Halted(0)));
+
+        let output = String::from_utf8(session.output).unwrap();
+        let replies: Vec<&str> = output
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.split('#').next().unwrap())
+            .collect();
+        assert_eq!(
+            replies,
+            [
+                "OK",
+                "S05",
+                "73000000",
+                "OK",
+                "E01",
+                "S05",
+                "0810000000000000",
+                "e803000000000000",
+                "S05",
+                "0c10000000000000",
+                "OK",
+                "OK",
+                "W00",
+            ]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Callbacks for instrumenting execution.
+
+use std::time::Duration;
+
+use super::{MemAccess, StepActivity};
+use crate::Segment;
+
+/// How far a session has got, reported to
+/// [ExecutorHooks::on_progress] as each segment is finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of segments finished so far
+    pub segments: usize,
+
+    /// The cycles the finished segments took
+    pub cycles: usize,
+
+    /// The time since the [Executor](super::Executor) was constructed
+    pub elapsed: Duration,
+
+    /// The time the session will take at most to reach its cycle limit, at
+    /// the rate it has run so far. Without a session limit, there is no
+    /// telling how long the guest will run, and this is `None`.
+    pub remaining: Option<Duration>,
+}
+
+/// Callbacks made by the [Executor](super::Executor) as the guest runs.
+///
+/// Hooks are registered with
+/// [ExecutorEnvBuilder::hooks](super::ExecutorEnvBuilder::hooks),
+/// and allow profilers, gas meters, coverage tools and the like to observe
+/// execution without changing the executor loop. Every callback does nothing
+/// by default.
+pub trait ExecutorHooks {
+    /// Called when the instruction `insn` at `pc` retires, having taken
+    /// `cycles` cycles.
+    fn on_instruction(&mut self, _pc: u64, _insn: u32, _cycles: usize) {}
+
+    /// Called for every guest load and store, once the accessing instruction
+    /// retires.
+    fn on_memory_access(&mut self, _access: &MemAccess) {}
+
+    /// Called when an instruction retires, with everything it did. This is
+    /// what step witnesses are generated from, see
+    /// [WitnessCollector](super::WitnessCollector).
+    fn on_step(&mut self, _step: &StepActivity) {}
+
+    /// Called when the guest makes host call `number` from `pc`, with
+    /// arguments from registers a0 to a3, before it is handled.
+    fn on_syscall(&mut self, _pc: u64, _number: u64, _args: [u64; 4]) {}
+
+    /// Called when the `fence.i` at `pc` retires. Anything cached about the
+    /// guest's instructions, such as decoded instructions or basic blocks,
+    /// must be dropped here, as the guest may have rewritten its code.
+    fn on_fence_i(&mut self, _pc: u64) {}
+
+    /// Called when a segment is finished.
+    fn on_segment(&mut self, _segment: &Segment) {}
+
+    /// Called when a segment is finished, after
+    /// [on_segment](ExecutorHooks::on_segment), with the progress of the
+    /// session so far. UIs and job schedulers can report this for long
+    /// sessions.
+    fn on_progress(&mut self, _progress: &Progress) {}
+}
@@ -223,7 +223,7 @@ impl<'a> PosixIo<'a> {
             .get_mut(&fd)
             .expect(&format!("Bad read file descriptor {fd}"));
         let navail = reader.borrow_mut().fill_buf().unwrap().len() as u32;
-        log::debug!("navail: {navail}");
+        tracing::debug!("navail: {navail}");
         Ok((navail, 0))
     }
 
@@ -235,7 +235,7 @@ impl<'a> PosixIo<'a> {
         let fd = ctx.load_register(REG_A3);
         let nbytes = ctx.load_register(REG_A4) as usize;
 
-        log::debug!("sys_read, attempting to read {nbytes} bytes from fd {fd}");
+        tracing::debug!("sys_read, attempting to read {nbytes} bytes from fd {fd}");
 
         assert!(
             nbytes >= to_guest.len() * WORD_SIZE,
@@ -270,7 +270,7 @@ impl<'a> PosixIo<'a> {
             "Guest requested more data than was available"
         );
 
-        log::debug!(
+        tracing::debug!(
             "Main read got {nread_main} bytes out of requested {}",
             to_guest_u8.len()
         );
@@ -300,7 +300,7 @@ impl<'a> PosixIo<'a> {
             .get_mut(&fd)
             .expect(&format!("Bad write file descriptor {fd}"));
 
-        log::debug!("Writing {buf_len} bytes to file descriptor {fd}");
+        tracing::debug!("Writing {buf_len} bytes to file descriptor {fd}");
 
         writer
             .borrow_mut()
@@ -383,13 +383,14 @@ impl<'a> SyscallTable<'a> {
 pub(crate) mod syscalls {
     use std::{cmp::min, collections::HashMap, str::from_utf8};
 
-    use anyhow::{bail, Result};
+    use anyhow::Result;
     use risc0_zkvm_platform::{
         syscall::reg_abi::{REG_A3, REG_A4},
         WORD_SIZE,
     };
 
     use super::{Syscall, SyscallContext};
+    use crate::exec::GuestPanic;
 
     pub(crate) struct CycleCount;
     impl Syscall for CycleCount {
@@ -440,7 +441,7 @@ pub(crate) mod syscalls {
             let buf_len = ctx.load_register(REG_A4);
             let from_guest = ctx.load_region(buf_ptr, buf_len);
             let msg = from_utf8(&from_guest)?;
-            println!("R0VM[{}] {}", ctx.get_cycle(), msg);
+            tracing::info!(cycle = ctx.get_cycle(), "{msg}");
             Ok((0, 0))
         }
     }
@@ -456,8 +457,13 @@ pub(crate) mod syscalls {
             let buf_ptr = ctx.load_register(REG_A3);
             let buf_len = ctx.load_register(REG_A4);
             let from_guest = ctx.load_region(buf_ptr, buf_len);
-            let msg = from_utf8(&from_guest)?;
-            bail!("Guest panicked: {msg}");
+            Err(GuestPanic {
+                message: String::from_utf8_lossy(&from_guest).into_owned(),
+                file: None,
+                line: 0,
+                backtrace: Vec::new(),
+            }
+            .into())
         }
     }
 
@@ -469,7 +475,7 @@ pub(crate) mod syscalls {
             _ctx: &mut dyn SyscallContext,
             to_guest: &mut [u32],
         ) -> Result<(u32, u32)> {
-            log::debug!("SYS_RANDOM: {}", to_guest.len());
+            tracing::debug!("SYS_RANDOM: {}", to_guest.len());
             let mut rand_buf = vec![0u8; to_guest.len() * WORD_SIZE];
             getrandom::getrandom(rand_buf.as_mut_slice())?;
             bytemuck::cast_slice_mut(to_guest).clone_from_slice(rand_buf.as_slice());
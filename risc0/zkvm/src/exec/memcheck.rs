@@ -0,0 +1,241 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline memory checking of a [MemTrace].
+//!
+//! Rather than checking every access against the page table, a step circuit
+//! can check memory with the multiset argument of Blum et al. Memory is a set
+//! of (address, timestamp, value) tuples, one per byte. Each access reads the
+//! tuple of every byte it touches and writes it back with the value loaded or
+//! stored and a later timestamp. Memory is consistent exactly when the
+//! initial tuples and the writes make up the same multiset as the reads and
+//! the final tuples, which is checked by comparing fingerprints of both at
+//! random points.
+//!
+//! The trace holds the stores the host makes on behalf of the guest, such as
+//! the data a host call returns, along with those of the guest, so that the
+//! guest's later loads of that data are checked too.
+
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Result};
+use rand::{Rng, RngCore};
+
+use super::MemTrace;
+
+/// The Goldilocks prime, 2^64 - 2^32 + 1, over which fingerprints are taken.
+const P: u64 = 0xffff_ffff_0000_0001;
+
+fn mul(x: u64, y: u64) -> u64 {
+    (x as u128 * y as u128 % P as u128) as u64
+}
+
+/// A byte of memory, as the memory-checking argument sees it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemTuple {
+    /// The address of the byte
+    pub addr: u64,
+
+    /// The position of the access that last wrote the byte, counting from 1
+    /// at the start of the trace, or 0 before the first
+    pub timestamp: u64,
+
+    /// The value of the byte
+    pub value: u8,
+}
+
+impl MemTuple {
+    fn fingerprint(&self, alpha: u64, gamma: u64) -> u64 {
+        let alpha2 = mul(alpha, alpha);
+        let term = (self.addr % P) as u128
+            + mul(alpha, self.timestamp % P) as u128
+            + mul(alpha2, self.value as u64) as u128;
+        ((gamma as u128 + P as u128 - term % P as u128) % P as u128) as u64
+    }
+}
+
+fn fingerprint<'a>(tuples: impl Iterator<Item = &'a MemTuple>, alpha: u64, gamma: u64) -> u64 {
+    tuples.fold(1, |acc, tuple| mul(acc, tuple.fingerprint(alpha, gamma)))
+}
+
+/// The multisets of the memory-checking argument for a trace
+///
+/// Each access contributes one read and one write per byte, at the same
+/// index of [MemoryCheck::reads] and [MemoryCheck::writes].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryCheck {
+    /// The tuples of the bytes accessed, before the first access
+    pub init: Vec<MemTuple>,
+
+    /// The tuples read by each access
+    pub reads: Vec<MemTuple>,
+
+    /// The tuples written back by each access
+    pub writes: Vec<MemTuple>,
+
+    /// The tuples of the bytes accessed, after the last access
+    pub finals: Vec<MemTuple>,
+}
+
+impl MemoryCheck {
+    /// Build the multisets for `trace`, with the value of each byte before
+    /// its first access given by `initial`, such as a read of the memory
+    /// image the trace starts from.
+    ///
+    /// Loads read the value they loaded, so that the argument fails if it is
+    /// not the value last stored.
+    pub fn new(trace: &MemTrace, mut initial: impl FnMut(u64) -> u8) -> Self {
+        let mut check = Self::default();
+        let mut memory: BTreeMap<u64, MemTuple> = BTreeMap::new();
+        for (idx, access) in trace.iter().enumerate() {
+            let timestamp = idx as u64 + 1;
+            for i in 0..access.size as u64 {
+                let addr = access.addr.wrapping_add(i);
+                let byte = (access.value >> (i * 8)) as u8;
+                let prev = *memory.entry(addr).or_insert_with(|| {
+                    let tuple = MemTuple {
+                        addr,
+                        timestamp: 0,
+                        value: initial(addr),
+                    };
+                    check.init.push(tuple);
+                    tuple
+                });
+                let read = match access.write {
+                    true => prev,
+                    false => MemTuple {
+                        value: byte,
+                        ..prev
+                    },
+                };
+                let write = MemTuple {
+                    addr,
+                    timestamp,
+                    value: byte,
+                };
+                check.reads.push(read);
+                check.writes.push(write);
+                memory.insert(addr, write);
+            }
+        }
+        check.finals = memory.into_values().collect();
+        check
+    }
+
+    /// Check the argument at the challenges `alpha` and `gamma`: that each
+    /// access reads a tuple older than the one it writes, and that the
+    /// fingerprints of both sides agree.
+    pub fn verify_at(&self, alpha: u64, gamma: u64) -> Result<()> {
+        ensure!(
+            self.reads.len() == self.writes.len(),
+            "{} reads but {} writes",
+            self.reads.len(),
+            self.writes.len()
+        );
+        for (read, write) in self.reads.iter().zip(&self.writes) {
+            ensure!(
+                read.addr == write.addr && read.timestamp < write.timestamp,
+                "access at timestamp {} reads {read:?}",
+                write.timestamp
+            );
+        }
+        let lhs = fingerprint(self.init.iter().chain(&self.writes), alpha, gamma);
+        let rhs = fingerprint(self.reads.iter().chain(&self.finals), alpha, gamma);
+        ensure!(lhs == rhs, "memory is inconsistent");
+        Ok(())
+    }
+
+    /// Check the argument at challenges drawn from `rng`, which the prover
+    /// must not be able to predict.
+    pub fn verify(&self, rng: &mut impl RngCore) -> Result<()> {
+        self.verify_at(rng.gen_range(0..P), rng.gen_range(0..P))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+    use risc0_zkvm_platform::syscall::ecall;
+
+    use super::MemoryCheck;
+    use crate::{
+        testutil::{ecall, image, ECALL, ENTRY},
+        Executor, ExecutorEnv, MemAccess, MemTrace,
+    };
+
+    fn access(cycle: u64, addr: u64, size: u8, value: u64, write: bool) -> MemAccess {
+        MemAccess {
+            cycle,
+            addr,
+            size,
+            value,
+            write,
+        }
+    }
+
+    #[test]
+    fn consistency() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        let mut trace = MemTrace::new();
+        trace.push(&access(0, 0x1000, 4, 0xdeadbeef, true));
+        trace.push(&access(1, 0x1002, 2, 0xdead, false));
+        trace.push(&access(2, 0x1003, 1, 0x42, true));
+        trace.push(&access(3, 0x1000, 8, 0x1111_1111_42ad_beef, false));
+        let check = MemoryCheck::new(&trace, |addr| if addr < 0x1004 { 0 } else { 0x11 });
+        assert_eq!(check.init.len(), 8);
+        assert_eq!(check.reads.len(), 4 + 2 + 1 + 8);
+        check.verify(&mut rng).unwrap();
+
+        // A load of a value that was never stored is caught.
+        let mut trace = MemTrace::new();
+        trace.push(&access(0, 0x1000, 1, 0x12, true));
+        trace.push(&access(1, 0x1000, 1, 0x13, false));
+        let check = MemoryCheck::new(&trace, |_| 0);
+        assert!(check.verify(&mut rng).is_err());
+
+        // So is a read of a tuple from the future.
+        let mut trace = MemTrace::new();
+        trace.push(&access(0, 0x1000, 1, 0x12, true));
+        let mut check = MemoryCheck::new(&trace, |_| 0);
+        check.reads[0].timestamp = 1;
+        assert!(check.verify(&mut rng).is_err());
+    }
+
+    #[test]
+    fn host_writes() {
+        // ecall; fld f1, 0(a2)
+        let image = image(&[ECALL, 0x00063087], &[(12, 0x2000)]);
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        exec.record_memory_trace();
+        ecall(&mut exec, ecall::GETRANDOM, &[0x2000, 8]);
+        exec.step().unwrap();
+        let trace = exec.take_memory_trace().unwrap();
+        let accesses: Vec<MemAccess> = trace.iter().collect();
+        let [store, load] = &accesses[..] else {
+            panic!("expected two accesses, got {accesses:?}");
+        };
+        assert!(store.write && !load.write);
+        assert_eq!((store.addr, store.value), (load.addr, load.value));
+
+        // The guest loads what the host stored, which is only consistent with
+        // the host's store.
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        MemoryCheck::new(&trace, |_| 0).verify(&mut rng).unwrap();
+        let mut guest_only = MemTrace::new();
+        guest_only.push(load);
+        let check = MemoryCheck::new(&guest_only, |_| 0);
+        assert!(check.verify(&mut rng).is_err());
+    }
+}
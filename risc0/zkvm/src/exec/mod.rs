@@ -16,54 +16,313 @@
 //!
 //! The result of the execution phase is [Session], which contains one or more
 //! [Segment]s, each which contains an execution trace of the specified program.
+//!
+//! Execution is instrumented with [tracing]: each segment runs in a `segment`
+//! span, each instruction in an `instruction` span at trace level and each
+//! host call in a `syscall` span, and events are emitted under the path of the
+//! module they come from, so that they can be filtered per module.
 
+mod atomic;
+mod bigint;
+mod bitmanip;
+#[cfg(feature = "bls12_381")]
+mod bls12_381;
+#[cfg(feature = "bn254")]
+mod bn254;
+mod cosim;
+mod counter;
+#[cfg(feature = "profiler")]
+mod coverage;
 mod env;
+mod files;
+mod float;
+mod gdb;
+mod hooks;
 mod io;
+mod memcheck;
 mod monitor;
-use std::{array, cell::RefCell, fmt::Debug, io::Write, mem::take, rc::Rc, str};
+mod muldiv;
+#[cfg(any(feature = "bls12_381", feature = "bn254"))]
+mod pairing;
+mod panic;
+#[cfg(feature = "poseidon")]
+mod poseidon;
+#[cfg(feature = "profiler")]
+mod profiler;
+mod registry;
+mod replay;
+mod rv32;
+mod state;
+mod trace;
+mod uninit;
+mod witness;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Debug,
+    io::Write,
+    mem::take,
+    ops::Range,
+    rc::Rc,
+    str,
+    time::Instant,
+};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 use risc0_zkvm_platform::{
-    memory::{HEAP_INITIAL_ADDRESS, MEM_SIZE},
+    fileno,
+    memory::{ARGS, STACK_INITIAL_ADDRESS},
     syscall::{
-        ecall, halt,
-        reg_abi::{REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_A7},
+        ecall, halt, log_level,
+        reg_abi::{REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_A5, REG_A7},
+        DIGEST_BYTES, DIGEST_WORDS, MAX_SHA_COMPRESS_BLOCKS,
     },
-    PAGE_SIZE, WORD_SIZE,
+    DOUBLE_WORD_SIZE, WORD_SIZE,
 };
-use rrs_lib::{instruction_executor::InstructionExecutor, memories::VecMemory, HartState, Memory};
+use rrs_lib::{instruction_executor::InstructionExecutor, HartState, Memory};
 use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
 
-pub use self::env::{ExecutorEnv, ExecutorEnvBuilder};
-use self::monitor::MemoryMonitor;
+#[cfg(any(feature = "bls12_381", feature = "bn254"))]
+use self::pairing::PairingCurve;
+pub use self::{
+    cosim::{Divergence, ReferenceStep, ReferenceTrace},
+    env::{ExecutorEnv, ExecutorEnvBuilder},
+    float::FloatSupport,
+    gdb::{GdbOutcome, GdbStub},
+    hooks::{ExecutorHooks, Progress},
+    memcheck::{MemTuple, MemoryCheck},
+    monitor::{WatchKind, WatchpointHit},
+    muldiv::MulDivAdvice,
+    panic::{BacktraceFrame, GuestPanic},
+    registry::{CustomInsnHandler, CustomOpcode, EcallContext, EcallHandler},
+    replay::{SyscallEvent, SyscallTrace},
+    trace::{MemAccess, MemTrace},
+    uninit::UninitLoad,
+    witness::{RegAccess, StepActivity, WitnessCollector, WitnessGenerator},
+};
+use self::{
+    counter::Counters, float::Fpu, monitor::MemoryMonitor, panic::CallStack, replay::Replay,
+    uninit::InitMap,
+};
+#[cfg(feature = "profiler")]
+pub use self::{
+    coverage::{BasicBlock, Coverage},
+    profiler::{Frame, Profiler},
+};
 use crate::{
-    opcode::{MajorType, OpCode},
-    ExitCode, MemoryImage, Program, Session,
+    binfmt::{builder::arg_layout, digest::BLOCK_BYTES},
+    claim::{assumptions_digest, chain_journal_digest, Assumption, SegmentIo},
+    opcode::{BitOp, MajorType, OpCode},
+    session::SegmentRecord,
+    Digest, ExitCode, GuestFault, LoadOptions, MemoryImage, OpcodeStats, PagedMemory, Program,
+    RegionStats, Segment, Session,
 };
 
 /// The number of cycles required to compress a SHA-256 block.
 const SHA_CYCLES: usize = 72;
 
+/// The most bytes one host call moves between the guest and the host, as the
+/// guest may ask for more than either side can hold.
+const MAX_IO_LEN: u64 = 64 * 1024;
+
+/// Out of memory, returned negated by `mmap`
+const ENOMEM: i64 = 12;
+
+/// A host call handled by the executor itself
+type BuiltinEcall = fn(&mut Executor) -> Result<OpCodeResult>;
+
+/// The built-in host calls, by number and name.
+const BUILTIN_ECALLS: &[(u64, &str, BuiltinEcall)] = &[
+    (ecall::HALT, "halt", |exec| exec.ecall_halt()),
+    (ecall::EXIT, "exit", |exec| exec.ecall_halt()),
+    (ecall::OUTPUT, "output", |exec| exec.ecall_output()),
+    (ecall::PANIC, "panic", |exec| exec.ecall_panic()),
+    (ecall::GETENV, "getenv", |exec| exec.ecall_getenv()),
+    (ecall::SOFTWARE, "software", |exec| exec.ecall_software()),
+    (ecall::LOG, "log", |exec| exec.ecall_log()),
+    (ecall::REGION_START, "region_start", |exec| {
+        exec.ecall_region_start()
+    }),
+    (ecall::REGION_END, "region_end", |exec| {
+        exec.ecall_region_end()
+    }),
+    (ecall::VERIFY, "verify", |exec| exec.ecall_verify()),
+    (ecall::CYCLE_COUNT, "cycle_count", |exec| {
+        exec.ecall_cycle_count()
+    }),
+    (ecall::BIGINT, "bigint", |exec| exec.ecall_bigint()),
+    (ecall::SHA, "sha", |exec| exec.ecall_sha()),
+    #[cfg(feature = "poseidon")]
+    (ecall::POSEIDON, "poseidon", |exec| exec.ecall_poseidon()),
+    #[cfg(feature = "bls12_381")]
+    (ecall::BLS12_381, "bls12_381", |exec| {
+        exec.ecall_pairing::<bls12_381::Bls12_381>()
+    }),
+    #[cfg(feature = "bn254")]
+    (ecall::BN254, "bn254", |exec| {
+        exec.ecall_pairing::<bn254::Bn254>()
+    }),
+    (ecall::FCNTL, "fcntl", |exec| exec.ecall_fcntl()),
+    (ecall::OPEN, "open", |exec| exec.ecall_open()),
+    (ecall::CLOSE, "close", |exec| exec.ecall_close()),
+    (ecall::LSEEK, "lseek", |exec| exec.ecall_lseek()),
+    (ecall::READ, "read", |exec| exec.ecall_read()),
+    (ecall::WRITE, "write", |exec| exec.ecall_write()),
+    (ecall::MMAP, "mmap", |exec| exec.ecall_mmap()),
+    (ecall::MUNMAP, "munmap", |exec| exec.ecall_munmap()),
+    (ecall::MINCORE, "mincore", |exec| exec.ecall_mincore()),
+    (ecall::GETTID, "gettid", |exec| exec.ecall_gettid()),
+    (ecall::GETRANDOM, "getrandom", |exec| exec.ecall_getrandom()),
+    (ecall::SIGPROCMASK, "sigprocmask", |exec| {
+        exec.ecall_do_nth()
+    }),
+    (ecall::SIGNALSTACK, "sigaltstack", |exec| {
+        exec.ecall_do_nth()
+    }),
+    (ecall::SIGACTION, "sigaction", |exec| exec.ecall_do_nth()),
+    (ecall::GETAFFINITY, "sched_getaffinity", |exec| {
+        exec.ecall_do_nth()
+    }),
+    (ecall::CLOCKGETTIME, "clock_gettime", |exec| {
+        exec.ecall_do_nth()
+    }),
+    (ecall::MADVICE, "madvise", |exec| exec.ecall_do_nth()),
+    // return error so it will skip ratelimit set https://github.com/golang/go/blob/f5015b5164d6948266df74943f26c4007c6bea50/src/syscall/rlimit.go#L34
+    (ecall::GETRLIMIT, "getrlimit", |exec| {
+        exec.ecall_do_return_error(-1i64 as u64)
+    }),
+];
+
 /// The Executor provides an implementation for the execution phase.
 ///
 /// The proving phase uses an execution trace generated by the Executor.
 pub struct Executor<'a> {
     env: ExecutorEnv<'a>,
-    // pre_image: MemoryImage,
+    pre_image_id: Digest,
     /// MemoryMonitor
     pub monitor: MemoryMonitor,
     pre_pc: u64,
     /// program counter
     pub pc: u64,
     anonymous_heap_watermark: u64,
-    // segments: Vec<Segment>,
-    insn_counter: u32,
+    heap_end: u64,
+    segments: Vec<Segment>,
+    segment_cycle: usize,
+    session_cycle: usize,
+    insn_counter: u64,
+    insn_cycles: u64,
+    watch_hit: Option<WatchpointHit>,
+    syscall_trace: Option<SyscallTrace>,
+    replay: Option<Replay>,
+    breakpoints: BTreeSet<u64>,
+    opcode_counts: HashMap<(&'static str, &'static str), u64>,
+    /// The profiling regions the guest is in, by id, with their names and
+    /// the session cycle they were entered at
+    open_regions: BTreeMap<u64, (String, usize)>,
+    region_stats: BTreeMap<String, RegionStats>,
+    /// The assumptions the guest has made with `env::verify`
+    assumptions: Vec<Assumption>,
+    /// The assumptions the guest has made during the current segment
+    segment_assumptions: Vec<Assumption>,
+    call_stack: CallStack,
+    elf: Option<Vec<u8>>,
+    /// The first load from uninitialized memory made by each instruction
+    uninit_loads: BTreeMap<u64, UninitLoad>,
+    /// The address reserved by the last load reserved, if any
+    reservation: Option<u64>,
+    fpu: Fpu,
+    /// The source of the guest's random bytes, if seeded
+    rng: ChaCha12Rng,
+    /// What the guest committed since the session started
+    journal: JournalWriter,
+    /// The journal digest of the segments finished so far, as chained by
+    /// [chain_journal_digest]
+    journal_digest: Digest,
+    /// The length of the journal the segments finished so far committed
+    journal_len: usize,
+    /// The Poseidon hasher, once the guest uses it
+    /// When the executor was constructed, which progress is timed from
+    started: Instant,
+    #[cfg(feature = "poseidon")]
+    poseidon_hasher: Option<poseidon::Hasher>,
+    #[cfg(feature = "profiler")]
+    profiler: Option<Profiler>,
+    #[cfg(feature = "profiler")]
+    coverage: Option<Coverage>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// A host call made by the guest, and where in the execution it was made
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SyscallRecord {
+    /// The data the host returned to the guest in memory
     pub to_guest: Vec<u64>,
+
+    /// The values of a0 and a1 once the call returned
     pub regs: (u64, u64),
+
+    /// The host call number, from a7, or 0 for a custom instruction
+    pub number: u64,
+
+    /// The custom instruction executed, if this records one rather than an
+    /// `ecall`
+    pub insn: Option<u32>,
+
+    /// The registers a custom instruction wrote, by index, with the values it
+    /// left in them
+    pub reg_writes: Vec<(usize, u64)>,
+
+    /// The address of the `ecall`
+    pub pc: u64,
+
+    /// The cycle within its segment at which the `ecall` started, not
+    /// counting paging
+    pub cycle: u64,
+
+    /// The position of the call among all those of the session, from 0
+    pub index: u64,
+
+    /// The name of the host callback the call invoked, if any
+    pub callback: Option<String>,
+
+    /// The data the guest sent to the host callback
+    pub from_guest: Vec<u8>,
+
+    /// The SHA-256 compressions made by the call, which the proving backend
+    /// checks in place of the instructions computing them
+    pub sha_compressions: Vec<ShaCompression>,
+
+    /// The 2-to-1 Poseidon hashes made by the call, which the proving
+    /// backend checks in the same way
+    pub poseidon_hashes: Vec<PoseidonHash>,
+}
+
+/// A SHA-256 block compressed by the `sha` host call
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShaCompression {
+    /// The state the block was compressed into
+    pub in_state: Digest,
+
+    /// The 64 bytes of the block
+    pub block: Vec<u8>,
+
+    /// The state after compressing the block
+    pub out_state: Digest,
+}
+
+/// A pair of field elements hashed by the `poseidon` host call
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoseidonHash {
+    /// The first element, in 32 little-endian bytes
+    pub left: Vec<u8>,
+
+    /// The second element
+    pub right: Vec<u8>,
+
+    /// The hash of the pair
+    pub out: Vec<u8>,
 }
 
 #[derive(Clone)]
@@ -72,6 +331,7 @@ pub struct OpCodeResult {
     exit_code: Option<ExitCode>,
     extra_cycles: usize,
     syscall: Option<SyscallRecord>,
+    muldiv: Option<MulDivAdvice>,
 }
 
 impl OpCodeResult {
@@ -86,17 +346,52 @@ impl OpCodeResult {
             exit_code,
             extra_cycles,
             syscall,
+            muldiv: None,
         }
     }
 }
 
+/// Returns the integer registers that the RV64I instruction `insn` reads, as
+/// its format has them: none for LUI, AUIPC and JAL, rs1 for JALR, loads and
+/// immediate operations, and rs1 and rs2 otherwise.
+fn source_registers(insn: u32) -> impl Iterator<Item = usize> {
+    let rs1 = ((insn >> 15) & 0x1f) as usize;
+    let rs2 = ((insn >> 20) & 0x1f) as usize;
+    let count = match insn & 0x7f {
+        0b0110111 | 0b0010111 | 0b1101111 => 0,
+        0b1100111 | 0b0000011 | 0b0010011 | 0b0011011 | 0b1110011 => 1,
+        _ => 2,
+    };
+    [rs1, rs2].into_iter().take(count)
+}
+
+/// Pack `bytes` into little-endian double words, padding the last with zeros,
+/// as for [SyscallRecord::to_guest].
+fn to_double_words(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks(DOUBLE_WORD_SIZE)
+        .map(|chunk| {
+            let mut word = [0; DOUBLE_WORD_SIZE];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        })
+        .collect()
+}
+
 // Capture the journal output in a buffer that we can access afterwards.
 #[derive(Clone, Default)]
-struct Journal {
+struct JournalWriter {
     buf: Rc<RefCell<Vec<u8>>>,
 }
 
-impl Write for Journal {
+impl JournalWriter {
+    /// Returns what was written so far, and start over.
+    fn take(&self) -> Vec<u8> {
+        self.buf.take()
+    }
+}
+
+impl Write for JournalWriter {
     fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
         self.buf.borrow_mut().write(bytes)
     }
@@ -108,20 +403,89 @@ impl Write for Journal {
 
 impl<'a> Executor<'a> {
     /// Construct a new [Executor] from a [MemoryImage] and entry point.
-    pub fn new(env: ExecutorEnv<'a>, image: MemoryImage, pc: u64) -> Self {
-        // let pre_image = image.clone();
-        let monitor = MemoryMonitor::new(image);
+    ///
+    /// The program arguments of `env` are written to the image, replacing
+    /// any it was built with. Fails if the image cannot hold them.
+    pub fn new(env: ExecutorEnv<'a>, mut image: MemoryImage, pc: u64) -> Result<Self> {
+        if !env.args.is_empty() {
+            let (strings, vector) = arg_layout(&env.args)?;
+            image
+                .write_region(ARGS.start() as u64, &strings)
+                .and_then(|_| image.write_region(STACK_INITIAL_ADDRESS as u64, &vector))
+                .context("guest memory is too small to hold the program arguments")?;
+        }
+        image.update_page_table();
+        let pre_image_id = image.get_root();
+        let heap = image.heap();
+        let mut monitor = MemoryMonitor::new(image);
+        monitor.emulate_misaligned = env.emulate_misaligned;
+        monitor.write_xor_execute = env.write_xor_execute;
+        monitor.record_accesses = !env.hooks.is_empty();
+        if env.rv32 {
+            monitor.use_rv32_registers();
+        }
+        if env.detect_uninit {
+            monitor.init_map = Some(InitMap::new(&monitor.image));
+        }
+        #[cfg(feature = "profiler")]
+        let profiler = env
+            .profile_path
+            .as_ref()
+            .map(|_| Profiler::new(pc).sample_period(env.profile_sample_period));
+        #[cfg(feature = "profiler")]
+        let coverage = env.coverage_path.as_ref().map(|_| Coverage::new());
+        let fpu = Fpu::load(&mut monitor.image);
+        // An unseeded generator still draws its seed from the host's entropy
+        // only once, so that a saved and resumed execution stays on the same
+        // stream.
+        let rng = match env.random_seed {
+            Some(seed) => ChaCha12Rng::seed_from_u64(seed),
+            None => ChaCha12Rng::from_entropy(),
+        };
+        let journal = JournalWriter::default();
+        env.files
+            .borrow_mut()
+            .insert_output(fileno::JOURNAL as u64, journal.clone());
 
-        Self {
+        Ok(Self {
             env,
-            // pre_image,
+            pre_image_id,
             monitor,
             pre_pc: pc,
             pc,
-            anonymous_heap_watermark: HEAP_INITIAL_ADDRESS as u64,
-            // segments: Vec::new(),
+            anonymous_heap_watermark: heap.start,
+            heap_end: heap.end(),
+            segments: Vec::new(),
+            segment_cycle: 0,
+            session_cycle: 0,
             insn_counter: 0,
-        }
+            insn_cycles: 0,
+            watch_hit: None,
+            syscall_trace: None,
+            replay: None,
+            breakpoints: BTreeSet::new(),
+            opcode_counts: HashMap::new(),
+            open_regions: BTreeMap::new(),
+            region_stats: BTreeMap::new(),
+            assumptions: Vec::new(),
+            segment_assumptions: Vec::new(),
+            call_stack: CallStack::default(),
+            elf: None,
+            uninit_loads: BTreeMap::new(),
+            reservation: None,
+            fpu,
+            rng,
+            journal,
+            journal_digest: Digest::ZERO,
+            journal_len: 0,
+            started: Instant::now(),
+            #[cfg(feature = "poseidon")]
+            poseidon_hasher: None,
+            #[cfg(feature = "profiler")]
+            profiler,
+            #[cfg(feature = "profiler")]
+            coverage,
+        })
     }
 
     /// Construct a new [Executor] from an ELF binary.
@@ -130,82 +494,268 @@ impl<'a> Executor<'a> {
         elf: &[u8],
         memory_data: Option<Vec<u8>>,
     ) -> Result<Self> {
-        let program = Program::load_elf(&elf, MEM_SIZE as u64)?;
-        let image = MemoryImage::new(&program, PAGE_SIZE as u64, memory_data);
-        Ok(Self::new(env, image, program.entry))
+        let options = LoadOptions::new(env.mem_size).with_rv32(env.rv32);
+        let program = Program::load_elf(&elf, &options)?;
+        let mut builder = MemoryImage::builder();
+        // Executor::new lays out the arguments.
+        builder.program(&program).mem_size(env.mem_size);
+        if let Some((start, size)) = env.heap {
+            builder.heap(start, size);
+        }
+        if let Some(hasher) = env.page_hasher.clone() {
+            builder.hasher(hasher);
+        }
+        if let Some(memory_data) = memory_data {
+            builder.memory_data(memory_data);
+        }
+        let image = builder.build()?;
+        let mut exec = Self::new(env, image, program.entry)?;
+        exec.elf = Some(elf.to_vec());
+        #[cfg(feature = "profiler")]
+        if exec.profiler.is_some() {
+            let profiler = Profiler::from_elf("guest", elf)?;
+            exec.profiler = Some(profiler.sample_period(exec.env.profile_sample_period));
+        }
+        Ok(exec)
     }
 
     /// Run the executor until [ExitCode::Paused] or [ExitCode::Halted] is
     /// reached, producing a [Session] as a result.
+    ///
+    /// Execution is split into [Segment]s of at most the segment limit of the
+    /// [ExecutorEnv] cycles each.
     pub fn run(&mut self) -> Result<Session> {
         self.monitor.clear_session();
+        self.sync_registers();
+        self.monitor.image.update_page_table();
+        self.pre_image_id = self.monitor.image.get_root();
+        self.pre_pc = self.pc;
+        self.segment_cycle = 0;
+        self.session_cycle = 0;
 
-        //        let journal = Journal::default();
-        // self.env
-        //     .io
-        //     .borrow_mut()
-        //     .with_write_fd(fileno::JOURNAL, journal.clone());
+        self.journal.take();
+        self.journal_digest = Digest::ZERO;
+        self.journal_len = 0;
+        self.open_regions.clear();
 
         let mut run_loop = || -> Result<ExitCode> {
+            let mut segment = tracing::info_span!("segment", index = self.segments.len()).entered();
             loop {
                 if let Some(exit_code) = self.step()? {
-                    // let total_cycles = self.total_cycles();
-                    // log::debug!("exit_code: {exit_code:?}, total_cycles: {total_cycles}");
-                    // assert!(total_cycles <= (1 << self.env.segment_limit_po2));
-                    // let pre_image = self.pre_image.clone();
-                    let syscalls = take(&mut self.monitor.syscalls);
-                    // let faults = take(&mut self.monitor.faults);
-                    // self.segments.push(Segment::new(
-                    //     pre_image,
-                    //     post_image_id,
-                    //     self.pre_pc,
-                    //     // faults,
-                    //     syscalls,
-                    //     exit_code,
-                    //     // log2_ceil(total_cycles.next_power_of_two()),
-                    // ));
                     match exit_code {
-                        ExitCode::SessionLimit => bail!("Session limit exceeded"),
-                        ExitCode::Halted(inner) => {
-                            println!("success!");
-                            return Ok(exit_code);
+                        // The segment was already finished by the split.
+                        ExitCode::SystemSplit => {
+                            segment.exit();
+                            segment = tracing::info_span!("segment", index = self.segments.len())
+                                .entered();
+                            continue;
+                        }
+                        ExitCode::SessionLimit => {
+                            tracing::debug!("session limit reached at pc 0x{:08x}", self.pc);
+                        }
+                        ExitCode::Halted(code) => {
+                            tracing::debug!("guest halted with exit code {code}");
+                        }
+                        ExitCode::Paused => {}
+                        ExitCode::Fault(fault @ GuestFault::StackOverflow(_)) => {
+                            tracing::warn!("guest fault at pc 0x{:08x}: {fault}", self.pc);
+                        }
+                        ExitCode::Fault(fault) => {
+                            tracing::debug!("guest fault at pc 0x{:08x}: {fault}", self.pc);
                         }
                     };
+                    self.finish_segment(exit_code);
+                    return Ok(exit_code);
                 };
             }
         };
 
         let exit_code = run_loop()?;
-        let mut segments = Vec::new();
-        // std::mem::swap(&mut segments, &mut self.segments);
-        Ok(Session::new(segments, exit_code))
+        #[cfg(feature = "profiler")]
+        if let (Some(profiler), Some(path)) = (self.profiler.as_mut(), &self.env.profile_path) {
+            profiler.write_collapsed(path)?;
+        }
+        #[cfg(feature = "profiler")]
+        if let (Some(coverage), Some(path)) = (&self.coverage, &self.env.coverage_path) {
+            match &self.elf {
+                Some(elf) => coverage.write_lcov(elf, path)?,
+                None => tracing::warn!("coverage needs the guest ELF, and was not written"),
+            }
+        }
+        let segments = take(&mut self.segments);
+        let mut opcode_stats = OpcodeStats::default();
+        for ((mnemonic, extension), count) in self.opcode_counts.drain() {
+            opcode_stats.record(mnemonic, extension, count);
+        }
+        let journal = self.journal.take();
+        let region_stats = take(&mut self.region_stats);
+        let assumptions = take(&mut self.assumptions);
+        Ok(Session::new(
+            segments,
+            journal,
+            exit_code,
+            opcode_stats,
+            region_stats,
+            assumptions,
+        ))
     }
 
     /// Execute a single instruction.
     ///
     /// This can be directly used by debuggers.
     pub fn step(&mut self) -> Result<Option<ExitCode>> {
-        // if self.session_cycle() > self.env.get_session_limit() {
-        //     return Ok(Some(ExitCode::SessionLimit));
-        // }
+        if let Some(limit) = self.env.get_session_limit() {
+            if self.monitor.restore_op().is_none() && self.session_cycles() >= limit {
+                return Ok(Some(ExitCode::SessionLimit));
+            }
+        }
+        self.watch_hit = None;
+        let _span = tracing::trace_span!("instruction", pc = self.pc).entered();
 
-        let insn = self.monitor.load_u32(self.pc);
+        if let Some(fault) = self.monitor.check_fetch(self.pc) {
+            return Ok(Some(ExitCode::Fault(fault)));
+        }
+        let insn = match self.monitor.load_u32(self.pc) {
+            Ok(insn) => insn,
+            Err(_) => return Ok(Some(ExitCode::Fault(GuestFault::Fetch(self.pc)))),
+        };
         let opcode = OpCode::decode(insn, self.pc)?;
+        if self.env.rv32 {
+            rv32::check(insn)?;
+        }
 
         if let Some(op_result) = self.monitor.restore_op() {
             return Ok(self.advance(opcode, op_result));
         }
 
         let op_result = if opcode.major == MajorType::ECall {
-            self.ecall()?
+            let number = self.monitor.register(REG_A7);
+            let _span = tracing::debug_span!("syscall", number).entered();
+            if !self.env.hooks.is_empty() {
+                let [a0, a1, a2, a3] =
+                    [REG_A0, REG_A1, REG_A2, REG_A3].map(|idx| self.monitor.register(idx));
+                for hook in self.env.hooks.iter() {
+                    hook.borrow_mut()
+                        .on_syscall(self.pc, number, [a0, a1, a2, a3]);
+                }
+            }
+            let event = self.begin_syscall(number, None)?;
+            let mut op_result = match self.replay_syscall(number) {
+                Some(op_result) => op_result,
+                None => self.ecall().or_else(|err| self.raise_guest_fault(err))?,
+            };
+            op_result.syscall.get_or_insert_with(|| SyscallRecord {
+                number,
+                pc: self.pc,
+                ..Default::default()
+            });
+            if let Some(event) = event {
+                self.end_syscall(event, op_result.exit_code)?;
+            }
+            op_result
+        } else if let Some(custom) = CustomOpcode::decode(insn) {
+            let event = self.begin_syscall(0, Some(insn))?;
+            // The results of the handler come from the host, so a replay serves
+            // them from the recording.
+            let mut op_result = if self.replay.is_some() {
+                // Read the operands as the handler would have been given them.
+                let [rs1, rs2] = [(insn >> 15) & 0x1f, (insn >> 20) & 0x1f];
+                self.monitor.load_registers([rs1 as usize, rs2 as usize]);
+                self.serve_recorded(0)
+            } else {
+                let Some(handler) = self.env.custom_insns.get(&custom).cloned() else {
+                    bail!("No handler for custom instruction {insn:#010x}");
+                };
+                self.execute_rtype(insn, |exec, rs1, rs2| {
+                    let mut ctx = EcallContext {
+                        monitor: &mut exec.monitor,
+                        pc: exec.pc,
+                    };
+                    handler.borrow_mut().execute(insn, rs1, rs2, &mut ctx)
+                })?
+            };
+            if let Some(event) = event {
+                self.end_syscall(event, None)?;
+            }
+            // The prover takes the effects of the handler as given, as it does
+            // those of a host call.
+            let (reg_writes, to_guest) = self.monitor.pending_effects();
+            op_result.syscall = Some(SyscallRecord {
+                to_guest: to_double_words(&to_guest),
+                insn: Some(insn),
+                reg_writes,
+                pc: self.pc,
+                ..Default::default()
+            });
+            op_result
+        } else if self.env.rv32 && rv32::differs(insn) {
+            self.execute_rtype(insn, |_, rs1, rs2| rv32::execute(insn, rs1, rs2))?
+        } else if opcode.extension() == "M" {
+            let mut advice = None;
+            let mut op_result = self.execute_rtype(insn, |_, rs1, rs2| {
+                advice = MulDivAdvice::new(insn, rs1, rs2);
+                muldiv::execute(insn, rs1, rs2)
+            })?;
+            op_result.muldiv = advice;
+            op_result
+        } else if opcode.extension() == "A" {
+            self.execute_rtype(insn, |exec, addr, src| {
+                atomic::execute(&mut exec.monitor, &mut exec.reservation, insn, addr, src)
+            })?
+        } else if let Some(op) = BitOp::decode(insn) {
+            self.execute_rtype(insn, |_, rs1, rs2| Ok(op.execute(insn, rs1, rs2)))?
+        } else if counter::is_counter(insn) {
+            let rd = (insn >> 7) & 0x1f;
+            let counters = Counters {
+                cycle: self.session_cycles() as u64,
+                time: self.insn_cycles,
+                instret: self.insn_counter,
+            };
+            match counter::read(insn, &counters) {
+                Some(result) => {
+                    if rd != 0 {
+                        self.monitor.store_register(rd as usize, result);
+                    }
+                    OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None)
+                }
+                None => {
+                    self.monitor
+                        .raise_fault(GuestFault::IllegalInstruction(self.pc));
+                    OpCodeResult::new(self.pc, None, 0, None)
+                }
+            }
+        } else if float::is_float(insn) {
+            let rd = (insn >> 7) & 0x1f;
+            let operands = float::operands(insn);
+            let rs1 = match operands.int_rs1 {
+                true => self.monitor.load_register(((insn >> 15) & 0x1f) as usize),
+                false => 0,
+            };
+            for &idx in operands.reads.iter() {
+                let value = self.fpu.regs[idx];
+                self.monitor.on_float_register_access(idx, value, false);
+            }
+            let support = self.env.float_support;
+            if let Some(result) = self.fpu.execute(&mut self.monitor, support, insn, rs1)? {
+                if rd != 0 {
+                    self.monitor.store_register(rd as usize, result);
+                }
+            }
+            if let Some(idx) = operands.write {
+                let value = self.fpu.regs[idx];
+                self.monitor.on_float_register_access(idx, value, true);
+            }
+            OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None)
+        } else if matches!(opcode.mnemonic, "FENCE" | "FENCE.I") {
+            // The guest runs on a single hart, so there is no memory access to
+            // order. A fence.i is handled once it retires.
+            OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None)
         } else {
-            let registers = self.monitor.load_registers(array::from_fn(|idx| idx));
-            if self.pc >= 0x00073138 && self.pc <= 0x000731fc {
-                let pc = self.pc;
-                println!("decode: 0x{insn:08x} at pc 0x{pc:08x}");
-                registers.iter().enumerate().for_each(|(idx, value)| {
-                    println!("value loaded {:08x}, idx: {:?}", value, idx,);
-                });
+            // Only the registers the instruction reads are loaded, so that
+            // only those are recorded.
+            let mut registers = [0; 32];
+            for idx in source_registers(insn) {
+                registers[idx] = self.monitor.load_register(idx);
             }
             let mut hart = HartState {
                 registers,
@@ -226,99 +776,479 @@ impl<'a> Executor<'a> {
 
             OpCodeResult::new(hart.pc, None, 0, None)
         };
+        if let Some(fault) = self.monitor.take_fault() {
+            return Ok(Some(ExitCode::Fault(fault)));
+        }
         self.monitor.save_op(op_result.clone());
 
-        // try to execute the next instruction
-        // if the segment limit is exceeded:
-        // * don't increment the PC
-        // * don't record any activity
-        // * return ExitCode::SystemSplit
-        // otherwise, commit memory and hart
-
+        // If the pending instruction would take the segment past its limit,
+        // finish the segment without it. It stays pending, and is committed as
+        // the first instruction of the next segment.
         let segment_limit = self.env.get_segment_limit();
-        // let total_pending_cycles = self.total_pending_cycles(&opcode);
-        // log::debug!(
-        //     "cycle: {}, segment: {}, total: {}",
-        //     self.segment_cycle,
-        //     total_pending_cycles,
-        //     self.total_cycles()
-        // );
-        // let exit_code = if total_pending_cycles > segment_limit {
-        //     Some(ExitCode::SystemSplit(self.insn_counter))
-        // } else {
+        if self.segment_cycle > 0 && self.total_pending_cycles(&opcode, &op_result) > segment_limit
+        {
+            self.finish_segment(ExitCode::SystemSplit);
+            return Ok(Some(ExitCode::SystemSplit));
+        }
+
+        let pc = self.pc;
         let exit_code = self.advance(opcode, op_result);
-        // };
+        if let (Some(ExitCode::Halted(_)), Some(replay)) = (&exit_code, &self.replay) {
+            let remaining = replay.trace.events.len() - replay.next;
+            ensure!(
+                remaining == 0,
+                "replay diverged: guest halted at cycle {} with {remaining} recorded syscalls left",
+                self.insn_counter
+            );
+        }
+        if let Some(hit) = self.monitor.take_watch_hit(pc) {
+            self.watch_hit = Some(hit);
+            return Ok(exit_code.or(Some(ExitCode::Paused)));
+        }
         Ok(exit_code)
     }
 
-    fn advance(&mut self, opcode: OpCode, op_result: OpCodeResult) -> Option<ExitCode> {
-        log::debug!(
+    /// Stop [Executor::continue_until_break] before the instruction at `pc`
+    /// is executed.
+    pub fn add_breakpoint(&mut self, pc: u64) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Remove the breakpoint at `pc`, returning whether it existed.
+    pub fn remove_breakpoint(&mut self, pc: u64) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    /// Execute instructions until a breakpoint or watchpoint is reached, or
+    /// the guest exits.
+    ///
+    /// The instruction at the current pc is always executed, so execution can
+    /// be resumed from a breakpoint. [ExitCode::Paused] is returned when
+    /// stopping at a breakpoint, with [Executor::pc] at its address.
+    pub fn continue_until_break(&mut self) -> Result<ExitCode> {
+        loop {
+            match self.step()? {
+                None | Some(ExitCode::SystemSplit) => {}
+                Some(exit_code) => return Ok(exit_code),
+            }
+            if self.breakpoints.contains(&self.pc) {
+                return Ok(ExitCode::Paused);
+            }
+        }
+    }
+
+    /// Watch guest loads and/or stores to any address in `range`, returning
+    /// an id for [Executor::remove_watchpoint].
+    ///
+    /// When a watched access is made, the accessing instruction completes and
+    /// execution stops with [ExitCode::Paused]; the access is then available
+    /// from [Executor::watchpoint_hit].
+    pub fn add_watchpoint(&mut self, range: Range<u64>, kind: WatchKind) -> usize {
+        self.monitor.add_watchpoint(range, kind)
+    }
+
+    /// Remove the watchpoint `id`, returning whether it existed.
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.monitor.remove_watchpoint(id)
+    }
+
+    /// Start recording every guest load and store, along with the cycle it
+    /// was made in.
+    pub fn record_memory_trace(&mut self) {
+        self.monitor.record_trace();
+    }
+
+    /// Stop recording guest memory accesses, returning those recorded since
+    /// [Executor::record_memory_trace].
+    pub fn take_memory_trace(&mut self) -> Option<MemTrace> {
+        self.monitor.take_trace()
+    }
+
+    /// Start recording every syscall made by the guest, along with its effect
+    /// on guest memory.
+    pub fn record_syscalls(&mut self) {
+        self.syscall_trace.get_or_insert_with(SyscallTrace::default);
+    }
+
+    /// Stop recording syscalls, returning those recorded since
+    /// [Executor::record_syscalls].
+    pub fn take_syscall_trace(&mut self) -> Option<SyscallTrace> {
+        self.syscall_trace.take()
+    }
+
+    /// Replay the syscalls of a previous execution from `trace`.
+    ///
+    /// Syscalls whose results come from the host environment, such as reads,
+    /// random numbers and calls to custom handlers, are not handled: the guest
+    /// sees their recorded effects instead. The others are handled, so the
+    /// executor's own state (such as mapped regions) is kept up to date.
+    /// Execution fails as soon as the guest makes a syscall that differs from
+    /// the recording in its cycle, pc, number or arguments, or in the effect
+    /// of a handled one, or halts before every recorded syscall was made. The
+    /// executor must start from the same image and pc as the recording.
+    pub fn replay(&mut self, trace: SyscallTrace) {
+        self.replay = Some(Replay { trace, next: 0 });
+    }
+
+    /// Returns the profiler, if profiling was enabled with
+    /// [ExecutorEnvBuilder::profile].
+    #[cfg(feature = "profiler")]
+    pub fn profiler(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Returns the coverage collected so far, if enabled with
+    /// [ExecutorEnvBuilder::coverage].
+    #[cfg(feature = "profiler")]
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Returns the access which triggered a watchpoint during the last
+    /// [Executor::step], if any.
+    pub fn watchpoint_hit(&self) -> Option<&WatchpointHit> {
+        self.watch_hit.as_ref()
+    }
+
+    /// Returns the loads from uninitialized memory made so far, at most one
+    /// per instruction, ordered by PC.
+    ///
+    /// This is empty unless enabled with [ExecutorEnvBuilder::detect_uninit].
+    pub fn uninit_loads(&self) -> impl Iterator<Item = &UninitLoad> {
+        self.uninit_loads.values()
+    }
+
+    /// Returns the raw contents of floating-point register `idx`.
+    pub fn float_register(&self, idx: usize) -> u64 {
+        self.fpu.regs[idx]
+    }
+
+    /// Returns the floating-point control and status register.
+    pub fn fcsr(&self) -> u32 {
+        self.fpu.fcsr
+    }
+
+    fn advance(&mut self, opcode: OpCode, mut op_result: OpCodeResult) -> Option<ExitCode> {
+        tracing::debug!(
             "pc: 0x{:08x}, insn: 0x{:08x} => {:?}",
             self.pc,
             opcode.insn,
             opcode
         );
 
+        *self
+            .opcode_counts
+            .entry((opcode.mnemonic, opcode.extension()))
+            .or_default() += 1;
+        self.call_stack.record(self.pc, opcode.insn);
+        for (addr, len) in self.monitor.take_uninit_loads() {
+            if !self.uninit_loads.contains_key(&self.pc) {
+                tracing::warn!(
+                    "load of {len} bytes from uninitialized memory at 0x{addr:x}, pc: 0x{:08x}",
+                    self.pc
+                );
+                let pc = self.pc;
+                self.uninit_loads.insert(pc, UninitLoad { pc, addr, len });
+            }
+        }
+        let step = StepActivity {
+            cycle: self.insn_counter,
+            pc: self.pc,
+            insn: opcode.insn,
+            next_pc: op_result.pc,
+            cycles: opcode.cycles + op_result.extra_cycles,
+            registers: self.monitor.pending_registers(),
+            memory: self.monitor.pending_accesses(),
+            syscall: (opcode.mnemonic == "ECALL").then(|| self.monitor.register(REG_A7)),
+            host_call: op_result.syscall.as_ref(),
+            muldiv: op_result.muldiv,
+        };
+        for hook in self.env.hooks.iter() {
+            let mut hook = hook.borrow_mut();
+            hook.on_instruction(self.pc, opcode.insn, step.cycles);
+            for access in step.memory {
+                hook.on_memory_access(access);
+            }
+            hook.on_step(&step);
+            if opcode.mnemonic == "FENCE.I" {
+                hook.on_fence_i(self.pc);
+            }
+        }
+        #[cfg(feature = "profiler")]
+        if let Some(profiler) = self.profiler.as_mut() {
+            let cycles = opcode.cycles + op_result.extra_cycles;
+            profiler.record(self.pc, opcode.insn, op_result.pc, cycles as u64);
+        }
+        #[cfg(feature = "profiler")]
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(self.pc, opcode.insn, op_result.pc);
+        }
+        if let Some(syscall) = op_result.syscall.as_mut() {
+            syscall.cycle = self.segment_cycle as u64;
+            // The monitor commits its own copy of the pending instruction.
+            self.monitor.save_op(op_result.clone());
+        }
         self.pc = op_result.pc;
+        self.segment_cycle += opcode.cycles + op_result.extra_cycles;
+        self.insn_cycles += (opcode.cycles + op_result.extra_cycles) as u64;
         self.insn_counter += 1;
         self.monitor.commit();
         op_result.exit_code
     }
 
-    // fn total_cycles(&self) -> usize {
-    //     self.init_cycles
-    //         + self.monitor.total_fault_cycles()
-    //         + self.body_cycles
-    //         + self.fini_cycles
-    //         + SHA_CYCLES
-    //         + ZK_CYCLES
-    // }
-
-    // fn total_pending_cycles(&self, opcode: &OpCode) -> usize {
-    //     // How many cycles are required for the entire segment?
-    //     // This sum is based on:
-    //     // - ensure we don't split in the middle of a SHA compress
-    //     // - each page fault requires 1 PageFault cycle + CYCLES_PER_PAGE cycles
-    //     // - leave room for fini_cycles
-    //     // - leave room for ZK cycles
-    //     self.init_cycles
-    //         + self.monitor.total_pending_fault_cycles()
-    //         + opcode.cycles
-    //         + self.body_cycles
-    //         + self.fini_cycles
-    //         + SHA_CYCLES
-    //         + ZK_CYCLES
-    // }
-
-    // fn session_cycle(&self) -> usize {
-    //     self.segments.len() * self.env.get_segment_limit() + self.segment_cycle
-    // }
+    /// Returns the cycles used by the current segment, including paging and
+    /// room to finish a SHA compression.
+    fn total_cycles(&self) -> usize {
+        self.monitor.total_fault_cycles() + self.segment_cycle + SHA_CYCLES
+    }
+
+    /// Returns [Executor::total_cycles] as it would be once the pending
+    /// instruction is committed.
+    fn total_pending_cycles(&self, opcode: &OpCode, op_result: &OpCodeResult) -> usize {
+        self.monitor.total_pending_fault_cycles()
+            + self.segment_cycle
+            + opcode.cycles
+            + op_result.extra_cycles
+            + SHA_CYCLES
+    }
+
+    /// Write the cached integer and floating-point registers back to SYSTEM
+    /// memory.
+    fn sync_registers(&mut self) {
+        self.monitor.sync_registers();
+        self.fpu.sync(&mut self.monitor.image);
+    }
+
+    /// Record the current segment as ending with `exit_code`, and start the
+    /// next one from its post-state.
+    fn finish_segment(&mut self, exit_code: ExitCode) {
+        let cycles = self.total_cycles();
+        tracing::debug!("exit_code: {exit_code:?}, total_cycles: {cycles}");
+        self.sync_registers();
+        self.monitor.image.update_page_table();
+        let post_image_id = self.monitor.image.get_root();
+        let faults = take(&mut self.monitor.faults);
+        let syscalls = take(&mut self.monitor.syscalls);
+        let (pre_journal_len, pre_journal_digest) = (self.journal_len, self.journal_digest);
+        let journal = self.journal.buf.borrow();
+        self.journal_digest =
+            chain_journal_digest(&self.journal_digest, &journal[self.journal_len..]);
+        self.journal_len = journal.len();
+        drop(journal);
+        let assumptions = take(&mut self.segment_assumptions);
+        let io = SegmentIo {
+            pre_image_id: self.pre_image_id,
+            pre_pc: self.pre_pc,
+            pre_journal_len: pre_journal_len as u64,
+            pre_journal_digest,
+            post_image_id,
+            post_pc: self.pc,
+            journal_len: self.journal_len as u64,
+            journal_digest: self.journal_digest,
+            exit_code,
+            assumptions_digest: assumptions_digest(&assumptions),
+        };
+        let record = SegmentRecord {
+            faults,
+            syscalls,
+            cycles,
+            assumptions,
+        };
+        self.segments.push(Segment::new(io, record));
+        self.session_cycle += cycles;
+        let progress = self.progress();
+        for hook in self.env.hooks.iter() {
+            let mut hook = hook.borrow_mut();
+            hook.on_segment(self.segments.last().unwrap());
+            hook.on_progress(&progress);
+        }
+        self.monitor.clear_segment();
+        self.pre_image_id = post_image_id;
+        self.pre_pc = self.pc;
+        self.segment_cycle = 0;
+    }
+
+    /// Returns the progress of the session, as of the last finished segment.
+    fn progress(&self) -> Progress {
+        let elapsed = self.started.elapsed();
+        let remaining = self.env.get_session_limit().map(|limit| {
+            let left = limit.saturating_sub(self.session_cycle);
+            elapsed.mul_f64(left as f64 / self.session_cycle.max(1) as f64)
+        });
+        Progress {
+            segments: self.segments.len(),
+            cycles: self.session_cycle,
+            elapsed,
+            remaining,
+        }
+    }
+
+    /// Returns the cycles used by the session so far, including those of the
+    /// current segment.
+    fn session_cycles(&self) -> usize {
+        self.session_cycle + self.total_cycles()
+    }
+
+    /// Capture the syscall `number` about to be made, or the custom
+    /// instruction `insn` about to be executed, if syscalls are being recorded
+    /// or replayed, checking it against the replay.
+    fn begin_syscall(&mut self, number: u64, insn: Option<u32>) -> Result<Option<SyscallEvent>> {
+        if self.syscall_trace.is_none() && self.replay.is_none() {
+            return Ok(None);
+        }
+        let args = match insn {
+            Some(insn) => {
+                let [rs1, rs2] = [(insn >> 15) & 0x1f, (insn >> 20) & 0x1f]
+                    .map(|idx| self.monitor.register(idx as usize));
+                [rs1, rs2, 0, 0]
+            }
+            None => {
+                let [a0, a1, a2, a3, _] = self
+                    .monitor
+                    .load_registers([REG_A0, REG_A1, REG_A2, REG_A3, REG_A7]);
+                [a0, a1, a2, a3]
+            }
+        };
+        let event = SyscallEvent {
+            cycle: self.insn_counter,
+            pc: self.pc,
+            number,
+            args,
+            insn,
+            writes: Vec::new(),
+            exit_code: None,
+        };
+        if let Some(replay) = &self.replay {
+            let Some(expected) = replay.trace.events.get(replay.next) else {
+                bail!(
+                    "replay diverged: unexpected syscall {number} at pc 0x{:08x}, cycle {}",
+                    event.pc,
+                    event.cycle
+                );
+            };
+            ensure!(
+                expected.same_call(&event),
+                "replay diverged: expected {expected:?}, found {event:?}"
+            );
+        }
+        Ok(Some(event))
+    }
+
+    /// Serve call `number` from the recording, if it is being replayed and
+    /// its result comes from the host environment.
+    fn replay_syscall(&mut self, number: u64) -> Option<OpCodeResult> {
+        self.replay.as_ref()?;
+        let custom = self.env.ecalls.get(number).is_some()
+            || !BUILTIN_ECALLS
+                .iter()
+                .any(|(builtin, ..)| *builtin == number);
+        if !Replay::serves(number, custom) {
+            return None;
+        }
+        Some(self.serve_recorded(number))
+    }
+
+    /// Apply the effect of the next recorded call, `number`, which
+    /// `begin_syscall` checked is the one being made.
+    fn serve_recorded(&mut self, number: u64) -> OpCodeResult {
+        let replay = self.replay.as_ref().unwrap();
+        let recorded = &replay.trace.events[replay.next];
+        let exit_code = recorded.exit_code;
+        let writes = recorded.writes.clone();
+        let to_guest = self.monitor.store_writes(&writes);
+        let syscall = SyscallRecord {
+            to_guest: to_double_words(&to_guest),
+            number,
+            pc: self.pc,
+            ..Default::default()
+        };
+        match exit_code {
+            Some(ExitCode::Fault(fault)) => {
+                self.monitor.raise_fault(fault);
+                OpCodeResult::new(self.pc, None, 0, Some(syscall))
+            }
+            Some(_) => OpCodeResult::new(self.pc, exit_code, 0, Some(syscall)),
+            None => OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, Some(syscall)),
+        }
+    }
+
+    /// Record the effect of a syscall, checking it against the recording when
+    /// replaying.
+    fn end_syscall(&mut self, mut event: SyscallEvent, exit_code: Option<ExitCode>) -> Result<()> {
+        event.writes = self.monitor.pending_writes();
+        event.exit_code = exit_code.or(self.monitor.pending_fault().map(ExitCode::Fault));
+        if let Some(replay) = self.replay.as_mut() {
+            let recorded = &replay.trace.events[replay.next];
+            ensure!(
+                (&recorded.writes, recorded.exit_code) == (&event.writes, event.exit_code),
+                "replay diverged: syscall {} at pc 0x{:08x}, cycle {} had a different effect",
+                event.number,
+                event.pc,
+                event.cycle
+            );
+            replay.next += 1;
+        }
+        if let Some(trace) = self.syscall_trace.as_mut() {
+            trace.events.push(event);
+        }
+        Ok(())
+    }
+
+    /// Execute the register-register instruction `insn` by applying `op` to
+    /// the values of its rs1 and rs2, and writing the result to its rd. A
+    /// [GuestFault] that `op` fails with faults the guest.
+    fn execute_rtype(
+        &mut self,
+        insn: u32,
+        op: impl FnOnce(&mut Self, u64, u64) -> Result<u64>,
+    ) -> Result<OpCodeResult> {
+        let rd = (insn >> 7) & 0x1f;
+        let rs1 = (insn >> 15) & 0x1f;
+        let rs2 = (insn >> 20) & 0x1f;
+        let [rs1, rs2] = self.monitor.load_registers([rs1 as usize, rs2 as usize]);
+        match op(self, rs1, rs2) {
+            Ok(result) => {
+                if rd != 0 {
+                    self.monitor.store_register(rd as usize, result);
+                }
+                Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
+            }
+            Err(err) => self.raise_guest_fault(err),
+        }
+    }
+
+    /// Turn an error that a host call or custom instruction raised from a
+    /// [GuestFault] into a fault of the guest; any other error is the host's.
+    fn raise_guest_fault(&mut self, err: anyhow::Error) -> Result<OpCodeResult> {
+        let fault = err.downcast::<GuestFault>()?;
+        self.monitor.raise_fault(fault);
+        Ok(OpCodeResult::new(self.pc, None, 0, None))
+    }
 
     fn ecall(&mut self) -> Result<OpCodeResult> {
         // previously it used REG_TO. Seems it's for RIV32E (embedded version spec). A reference https://github.com/chipsalliance/VeeR-ISS/blob/main/Syscall.cpp#L788-L791
         // here in RIV64 we switch to REG_A7
-        match self.monitor.load_register(REG_A7) {
-            ecall::HALT => self.ecall_halt(),
-            ecall::EXIT => self.ecall_halt(),
-            ecall::OUTPUT => self.ecall_output(),
-            // ecall::SOFTWARE => self.ecall_software(),
-            ecall::FCNTL => self.ecall_fcntl(),
-            ecall::OPEN => self.ecall_open(),
-            ecall::CLOSE => self.ecall_do_nth(),
-            ecall::WRITE => self.ecall_write(),
-            ecall::MMAP => self.ecall_mmap(),
-            ecall::MUNMAP => self.ecall_munmap(),
-            ecall::MINCORE => self.ecall_mincore(),
-            ecall::GETTID => self.ecall_gettid(),
-            ecall::SIGPROCMASK => self.ecall_do_nth(),
-            ecall::SIGNALSTACK => self.ecall_do_nth(),
-            ecall::SIGACTION => self.ecall_do_nth(),
-            ecall::GETAFFINITY => self.ecall_do_nth(),
-            ecall::CLOCKGETTIME => self.ecall_do_nth(),
-            ecall::MADVICE => self.ecall_do_nth(),
-            // return error so it will skip ratelimit set https://github.com/golang/go/blob/f5015b5164d6948266df74943f26c4007c6bea50/src/syscall/rlimit.go#L34
-            ecall::GETRLIMIT => self.ecall_do_return_error(-1i64 as u64),
-            ecall => bail!("Unknown ecall {ecall:08x} in decimal {ecall:?}"),
+        let number = self.monitor.load_register(REG_A7);
+        if let Some((name, handler)) = self.env.ecalls.get(number) {
+            tracing::debug!("ecall {name}");
+            let mut ctx = EcallContext {
+                monitor: &mut self.monitor,
+                pc: self.pc,
+            };
+            return Ok(match handler.borrow_mut().ecall(&mut ctx)? {
+                Some(exit_code) => OpCodeResult::new(self.pc, Some(exit_code), 0, None),
+                None => OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None),
+            });
+        }
+        match BUILTIN_ECALLS
+            .iter()
+            .find(|(builtin, ..)| *builtin == number)
+        {
+            Some((_, name, handler)) => {
+                tracing::debug!("ecall {name}");
+                handler(self)
+            }
+            None => bail!("Unknown ecall {number:08x} in decimal {number:?}"),
         }
     }
 
@@ -331,8 +1261,8 @@ impl<'a> Executor<'a> {
     fn ecall_munmap(&mut self) -> Result<OpCodeResult> {
         let addr = self.monitor.load_register(REG_A0);
         let size = self.monitor.load_register(REG_A1);
-        println!("munmap base: {:08x}, size {:08x}", addr, size);
-        match self.monitor.image.memory_space.remove_memory_by_base(addr) {
+        tracing::debug!("munmap base: {:08x}, size {:08x}", addr, size);
+        match self.monitor.image.remove_region(addr) {
             Ok(_) => {
                 self.monitor.store_register(REG_A0, 0u64);
                 Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
@@ -353,9 +1283,11 @@ impl<'a> Executor<'a> {
         let page_size_align = 1u64 << 12u64;
         let page_size_mask = page_size_align - 1;
         let addr = self.monitor.load_register(REG_A0);
-        println!(
+        tracing::debug!(
             "mincore addr {:08b}, in dec {:?}, page_size_mask {:08b}",
-            addr, addr, page_size_mask
+            addr,
+            addr,
+            page_size_mask
         );
         if addr & page_size_mask != 0 {
             // error
@@ -382,19 +1314,29 @@ impl<'a> Executor<'a> {
         let page_size_mask = page_size_align - 1;
         let desired_addr = self.monitor.load_register(REG_A0);
         let mut desired_page_size = self.monitor.load_register(REG_A1);
-        println!(
+        tracing::debug!(
             "desired_addr: {:08x}, desired_page_size {:08x}, in dec {:?}, page_size_mask {:08x}",
-            desired_addr, desired_page_size, desired_page_size, page_size_mask
+            desired_addr,
+            desired_page_size,
+            desired_page_size,
+            page_size_mask
         );
-        if desired_page_size & page_size_mask != 0 {
-            // adjust size to align with page size
-            desired_page_size += page_size_align - (desired_page_size & page_size_mask)
-        }
         let base = if desired_addr > 0 {
             desired_addr
         } else {
             self.anonymous_heap_watermark
         };
+        // The length is up to the guest, so a mapping that does not fit in the
+        // address space, or past the heap if it is anonymous, fails with
+        // ENOMEM rather than overflowing.
+        let end = desired_page_size
+            .checked_next_multiple_of(page_size_align)
+            .and_then(|size| base.checked_add(size))
+            .filter(|end| desired_addr > 0 || *end <= self.heap_end);
+        let Some(end) = end else {
+            return self.ecall_do_return_error(-ENOMEM as u64);
+        };
+        desired_page_size = end - base;
         if let Some(_) = self
             .monitor
             .image
@@ -414,21 +1356,21 @@ impl<'a> Executor<'a> {
             // base);     return Ok(OpCodeResult::new(self.pc +
             // WORD_SIZE as u64, None, 0, None)); }
         }
-        let _ = self
+        let region = Box::new(PagedMemory::new(desired_page_size));
+        if let Err(err) = self
             .monitor
             .image
-            .memory_space
-            .add_memory(
-                base,
-                desired_page_size,
-                Box::new(VecMemory::new(vec![0_u64; desired_page_size as usize / 8])),
-            )
-            .unwrap();
+            .add_region("mmap", base, desired_page_size, region)
+        {
+            tracing::debug!("mmap failed: {err}");
+            return self.ecall_do_return_error(-ENOMEM as u64);
+        }
         self.monitor.store_register(REG_A0, base);
-        println!("return addr {:16x}, in dec {:?}", base, base);
-        if desired_addr == 0 {
-            // increase heap addr as it came from heap addr watermark
-            self.anonymous_heap_watermark = base + desired_page_size;
+        tracing::debug!("return addr {:16x}, in dec {:?}", base, base);
+        if desired_addr == 0 || (base >= self.anonymous_heap_watermark && base < self.heap_end) {
+            // increase heap addr as it came from heap addr watermark, or past
+            // the part of the heap the guest allocator mapped itself
+            self.anonymous_heap_watermark = end;
         }
         Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
     }
@@ -439,7 +1381,7 @@ impl<'a> Executor<'a> {
         let a2 = self.monitor.load_register(REG_A2); // write A3 length to A0 return value as write convention
         let O_RDONLY = 0;
         let O_WRONLY = 1;
-        println!("ecall_fcntl a0 {:16x}, a1 {:16x}, a2 {:16x}", a0, a1, a2);
+        tracing::debug!("ecall_fcntl a0 {:16x}, a1 {:16x}, a2 {:16x}", a0, a1, a2);
         let ret_status = match (a0, a1) {
             (1000, 3) => -1, /* return error to stick to blocking-mode, https://github.com/golang/go/blob/688d75b14fd7646d66c18825f22f0a67e9fafd9e/src/internal/syscall/unix/nonblocking_unix.go#L11 */
             _ => 0,
@@ -455,9 +1397,14 @@ impl<'a> Executor<'a> {
         let a3 = self.monitor.load_register(REG_A3); // write A3 length to A0 return value as write convention
         let a4 = self.monitor.load_register(REG_A4); // write A3 length to A0 return value as write convention
         let a7 = self.monitor.load_register(REG_A7); // write A3 length to A0 return value as write convention
-        println!(
+        tracing::debug!(
             "ecall_open a0 {:16x}, a1 {:16x}, a2 {:16x}, a3 {:16x}, a4 {:16x}, a7 {:16x}",
-            a0, a1, a2, a3, a4, a7
+            a0,
+            a1,
+            a2,
+            a3,
+            a4,
+            a7
         );
         let MAX_KEY_LENGTH = 20;
         let raw: Vec<u8> = (0..MAX_KEY_LENGTH)
@@ -476,14 +1423,14 @@ impl<'a> Executor<'a> {
             })
             .collect();
         let key = str::from_utf8(&raw);
-        println!("ecall_open key {:?}", key);
+        tracing::debug!("ecall_open key {:?}", key);
         let result_code = match key {
             Ok(msg) => {
                 if msg.starts_with("DBG") {
-                    println!("open success {:?}", key);
+                    tracing::debug!("open success {:?}", key);
                     1000i64
                 } else {
-                    println!("open failed {:?}", key);
+                    tracing::debug!("open failed {:?}", key);
                     -100i64
                 }
             }
@@ -500,7 +1447,7 @@ impl<'a> Executor<'a> {
         let a2 = self.monitor.load_register(REG_A2);
         let a3 = self.monitor.load_register(REG_A3);
 
-        log::debug!(
+        tracing::debug!(
             "ecall_write a0 {:16x}, a1 {:16x}, a2 {:16x}, a3 {:16x}",
             a0,
             a1,
@@ -508,23 +1455,91 @@ impl<'a> Executor<'a> {
             a3
         );
 
-        let raw: Vec<u8> = (0..a2)
-            .map(|n| {
-                self.monitor
-                    .image
-                    .memory_space
-                    .read_mem(a1 + n, rrs_lib::MemAccessSize::Byte)
-                    .unwrap() as u8
-            })
-            .collect();
-
-        println!("ecall_write res {:?}", String::from_utf8_lossy(&raw));
+        let len = a2.min(MAX_IO_LEN);
+        let raw = self.monitor.load_region(a1, len)?;
+        if self.env.files.borrow().contains(a0) {
+            let result = self.env.files.borrow_mut().write(a0, &raw);
+            let ret = result.map_or_else(|err| files::error_return(&err), |_| len);
+            self.monitor.store_register(REG_A0, ret);
+            // What the guest commits is recorded, so that the segment's proof
+            // can bind it to the journal digest.
+            let syscall = (a0 == fileno::JOURNAL as u64).then(|| SyscallRecord {
+                number: ecall::WRITE,
+                pc: self.pc,
+                from_guest: raw,
+                ..Default::default()
+            });
+            return Ok(OpCodeResult::new(
+                self.pc + WORD_SIZE as u64,
+                None,
+                0,
+                syscall,
+            ));
+        }
+        match u32::try_from(a0) {
+            Ok(fileno::STDOUT) => std::io::stdout().write_all(&raw)?,
+            Ok(fileno::STDERR) => std::io::stderr().write_all(&raw)?,
+            _ => tracing::debug!(
+                "ecall_write to fd {a0}: {:?}",
+                String::from_utf8_lossy(&raw)
+            ),
+        }
 
-        let value = self.monitor.load_register(REG_A2); // write A2 length to A0 return value as write convention
-        self.monitor.store_register(REG_A0, value);
+        // Return the length written, as write does.
+        self.monitor.store_register(REG_A0, len);
         Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
     }
 
+    fn ecall_read(&mut self) -> Result<OpCodeResult> {
+        let [fd, buf_ptr, buf_len] = self.monitor.load_registers([REG_A0, REG_A1, REG_A2]);
+        let result = self
+            .env
+            .files
+            .borrow_mut()
+            .read(fd, buf_len.min(MAX_IO_LEN));
+        let bytes = match result {
+            Ok(bytes) => bytes,
+            Err(err) => return self.ecall_do_return_error(files::error_return(&err)),
+        };
+        self.monitor.store_region(buf_ptr, &bytes);
+        self.monitor.store_register(REG_A0, bytes.len() as u64);
+        // The file is not part of the proof, so what was read is recorded.
+        let syscall = SyscallRecord {
+            to_guest: to_double_words(&bytes),
+            number: ecall::READ,
+            pc: self.pc,
+            ..Default::default()
+        };
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            0,
+            Some(syscall),
+        ))
+    }
+
+    fn ecall_lseek(&mut self) -> Result<OpCodeResult> {
+        let [fd, offset, whence] = self.monitor.load_registers([REG_A0, REG_A1, REG_A2]);
+        let result = self.env.files.borrow_mut().seek(fd, offset as i64, whence);
+        self.ecall_do_return_error(result.unwrap_or_else(|err| files::error_return(&err)))
+    }
+
+    fn ecall_close(&mut self) -> Result<OpCodeResult> {
+        let fd = self.monitor.load_register(REG_A0);
+        // The standard streams the host does not provide stay open; closing
+        // any other unknown file fails with EBADF.
+        let mut files = self.env.files.borrow_mut();
+        let ret = if fd <= fileno::STDERR as u64 && !files.contains(fd) {
+            0
+        } else {
+            files
+                .close(fd)
+                .map_or_else(|err| files::error_return(&err), |_| 0)
+        };
+        drop(files);
+        self.ecall_do_return_error(ret)
+    }
+
     fn ecall_halt(&mut self) -> Result<OpCodeResult> {
         let halt_type = self.monitor.load_register(REG_A0);
         match halt_type {
@@ -538,34 +1553,421 @@ impl<'a> Executor<'a> {
         }
     }
 
-    fn ecall_output(&mut self) -> Result<OpCodeResult> {
-        log::debug!("ecall(output)");
+    fn ecall_cycle_count(&mut self) -> Result<OpCodeResult> {
+        let cycle = self.session_cycles();
+        self.monitor.store_register(REG_A0, cycle as u64);
         Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
     }
-}
 
-/// An event traced from the running VM.
-#[allow(dead_code)] // TODO
-#[derive(PartialEq)]
-pub enum TraceEvent {
-    /// An instruction has started at the given program counter
-    InstructionStart {
-        /// Cycle number since startup
-        cycle: u32,
-        /// Program counter of the instruction being executed
-        pc: u32,
-    },
+    /// Unknown operations and widths are reported to the guest with u64::MAX
+    /// in a0, as the pairing accelerators report bad operands.
+    fn ecall_bigint(&mut self) -> Result<OpCodeResult> {
+        let [op, result_ptr, x_ptr, y_ptr, modulus_ptr, width] = self
+            .monitor
+            .load_registers([REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_A5]);
+        let result = match bigint::byte_len(width) {
+            Ok(len) => bigint::compute(
+                op,
+                &self.monitor.load_region(x_ptr, len)?,
+                &self.monitor.load_region(y_ptr, len)?,
+                &self.monitor.load_region(modulus_ptr, len)?,
+            ),
+            Err(err) => Err(err),
+        };
+        let ret = match result {
+            Ok(result) => {
+                self.monitor.store_region(result_ptr, &result);
+                0
+            }
+            Err(err) => {
+                tracing::debug!("bigint: {err}");
+                u64::MAX
+            }
+        };
+        self.monitor.store_register(REG_A0, ret);
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            bigint::BIGINT_CYCLES,
+            None,
+        ))
+    }
 
-    /// A register has been set
-    RegisterSet {
-        /// Register ID (0-16)
-        reg: usize,
-        /// New value in the register
-        value: u32,
-    },
+    fn ecall_sha(&mut self) -> Result<OpCodeResult> {
+        let [out_state_ptr, in_state_ptr, mut block1_ptr, mut block2_ptr, count] = self
+            .monitor
+            .load_registers([REG_A0, REG_A1, REG_A2, REG_A3, REG_A4]);
+        ensure!(
+            count as usize <= MAX_SHA_COMPRESS_BLOCKS,
+            "Too many SHA-256 blocks: {count}"
+        );
+        // States are laid out as digests, in big-endian words.
+        let mut state = [0; DIGEST_WORDS];
+        let in_state = self
+            .monitor
+            .load_region(in_state_ptr, DIGEST_BYTES as u64)?;
+        for (word, bytes) in state.iter_mut().zip(in_state.chunks_exact(WORD_SIZE)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        let mut sha_compressions = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut block = self.monitor.load_region(block1_ptr, DIGEST_BYTES as u64)?;
+            block.extend(self.monitor.load_region(block2_ptr, DIGEST_BYTES as u64)?);
+            let in_state = Digest::new(state);
+            sha2::compress256(&mut state, &[GenericArray::clone_from_slice(&block)]);
+            sha_compressions.push(ShaCompression {
+                in_state,
+                block,
+                out_state: Digest::new(state),
+            });
+            block1_ptr += BLOCK_BYTES as u64;
+            block2_ptr += BLOCK_BYTES as u64;
+        }
+        let out_state: Vec<u8> = state.iter().flat_map(|word| word.to_be_bytes()).collect();
+        self.monitor.store_region(out_state_ptr, &out_state);
+        let syscall = SyscallRecord {
+            number: ecall::SHA,
+            pc: self.pc,
+            sha_compressions,
+            ..Default::default()
+        };
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            SHA_CYCLES * count as usize,
+            Some(syscall),
+        ))
+    }
 
-    /// A memory location has been written
-    MemorySet {
+    #[cfg(feature = "poseidon")]
+    fn ecall_poseidon(&mut self) -> Result<OpCodeResult> {
+        use risc0_zkvm_platform::syscall::MAX_POSEIDON_ELEMENTS;
+
+        use self::poseidon::{ELEMENT_LEN, POSEIDON_CYCLES};
+
+        let [out_ptr, elements_ptr, count] = self.monitor.load_registers([REG_A0, REG_A1, REG_A2]);
+        ensure!(
+            count as usize <= MAX_POSEIDON_ELEMENTS,
+            "poseidon: hash of {count} elements exceeds {MAX_POSEIDON_ELEMENTS}"
+        );
+        let elements = self
+            .monitor
+            .load_region(elements_ptr, count * ELEMENT_LEN)?;
+        let hasher = self.poseidon_hasher.get_or_insert_with(Default::default);
+        let (ret, poseidon_hashes) = match hasher.hash(&elements) {
+            Ok(hashes) => {
+                self.monitor
+                    .store_region(out_ptr, &hashes.last().unwrap().out);
+                (0, hashes)
+            }
+            Err(err) => {
+                tracing::debug!("poseidon: {err}");
+                (u64::MAX, Vec::new())
+            }
+        };
+        self.monitor.store_register(REG_A0, ret);
+        let syscall = SyscallRecord {
+            number: ecall::POSEIDON,
+            pc: self.pc,
+            poseidon_hashes,
+            ..Default::default()
+        };
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            POSEIDON_CYCLES * count.saturating_sub(1) as usize,
+            Some(syscall),
+        ))
+    }
+
+    /// Run an operation of the pairing accelerator for curve `C`. Bad
+    /// operands, including an unknown operation, are reported to the guest
+    /// with u64::MAX in a0, as the guest may be checking untrusted points.
+    #[cfg(any(feature = "bls12_381", feature = "bn254"))]
+    fn ecall_pairing<C: PairingCurve>(&mut self) -> Result<OpCodeResult> {
+        let [op, a1, a2, a3] = self
+            .monitor
+            .load_registers([REG_A0, REG_A1, REG_A2, REG_A3]);
+        let Ok(op) = pairing::Op::decode(op) else {
+            tracing::debug!("pairing: unknown operation {op}");
+            self.monitor.store_register(REG_A0, u64::MAX);
+            return Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None));
+        };
+        let mut extra_cycles = op.cycles();
+        let result = match op {
+            pairing::Op::G1Add => C::g1_add(
+                &self.monitor.load_region(a2, C::G1_LEN)?,
+                &self.monitor.load_region(a3, C::G1_LEN)?,
+            ),
+            pairing::Op::G1Mul => C::g1_mul(
+                &self.monitor.load_region(a2, C::G1_LEN)?,
+                &self.monitor.load_region(a3, C::SCALAR_LEN)?,
+            ),
+            pairing::Op::G2Add => C::g2_add(
+                &self.monitor.load_region(a2, C::G2_LEN)?,
+                &self.monitor.load_region(a3, C::G2_LEN)?,
+            ),
+            pairing::Op::G2Mul => C::g2_mul(
+                &self.monitor.load_region(a2, C::G2_LEN)?,
+                &self.monitor.load_region(a3, C::SCALAR_LEN)?,
+            ),
+            pairing::Op::PairingCheck if a2 > pairing::MAX_PAIRS => Err(anyhow!(
+                "pairing check of {a2} pairs exceeds {}",
+                pairing::MAX_PAIRS
+            )),
+            pairing::Op::PairingCheck => {
+                extra_cycles *= a2 as usize;
+                let pairs = self.monitor.load_region(a1, a2 * (C::G1_LEN + C::G2_LEN))?;
+                C::pairing_check(&pairs).map(|holds| vec![holds as u8])
+            }
+        };
+        let ret = match result {
+            Ok(result) if op == pairing::Op::PairingCheck => result[0] as u64,
+            Ok(point) => {
+                self.monitor.store_region(a1, &point);
+                0
+            }
+            Err(err) => {
+                tracing::debug!("pairing: {err}");
+                u64::MAX
+            }
+        };
+        self.monitor.store_register(REG_A0, ret);
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            extra_cycles,
+            None,
+        ))
+    }
+
+    fn ecall_getrandom(&mut self) -> Result<OpCodeResult> {
+        let [buf_ptr, buf_len] = self.monitor.load_registers([REG_A0, REG_A1]);
+        let mut bytes = vec![0; buf_len.min(MAX_IO_LEN) as usize];
+        self.rng.fill_bytes(&mut bytes);
+        self.monitor.store_region(buf_ptr, &bytes);
+        self.monitor.store_register(REG_A0, bytes.len() as u64);
+        let syscall = SyscallRecord {
+            to_guest: to_double_words(&bytes),
+            number: ecall::GETRANDOM,
+            pc: self.pc,
+            ..Default::default()
+        };
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            0,
+            Some(syscall),
+        ))
+    }
+
+    fn ecall_software(&mut self) -> Result<OpCodeResult> {
+        /// The longest callback name read from the guest.
+        const MAX_NAME_LEN: u64 = 1024;
+
+        let [name_ptr, name_len, from_guest_ptr, from_guest_len, buf_ptr, buf_len] = self
+            .monitor
+            .load_registers([REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_A5]);
+        ensure!(
+            name_len <= MAX_NAME_LEN,
+            "software: callback name of {name_len} bytes is too long"
+        );
+        let name = self.monitor.load_region(name_ptr, name_len)?;
+        let name = str::from_utf8(&name).context("software: callback name is not UTF-8")?;
+        let from_guest = self.monitor.load_region(from_guest_ptr, from_guest_len)?;
+        // As with getenv, the guest gets as much of the reply as fits, and
+        // its full length.
+        let reply = self.env.io_callbacks.call(name, &from_guest)?;
+        let copied = &reply[..reply.len().min(buf_len as usize)];
+        self.monitor.store_region(buf_ptr, copied);
+        tracing::debug!("software {name}: {} bytes", reply.len());
+        self.monitor.store_register(REG_A0, reply.len() as u64);
+        let syscall = SyscallRecord {
+            to_guest: to_double_words(copied),
+            number: ecall::SOFTWARE,
+            pc: self.pc,
+            callback: Some(name.to_string()),
+            from_guest,
+            ..Default::default()
+        };
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            0,
+            Some(syscall),
+        ))
+    }
+
+    fn ecall_log(&mut self) -> Result<OpCodeResult> {
+        let [level, msg_ptr, msg_len] = self.monitor.load_registers([REG_A0, REG_A1, REG_A2]);
+        ensure!(
+            (log_level::ERROR..=log_level::TRACE).contains(&level),
+            "log: unknown level {level}"
+        );
+        let msg = self.monitor.load_region(msg_ptr, msg_len.min(MAX_IO_LEN))?;
+        let msg = String::from_utf8_lossy(&msg);
+        let guest = self.env.guest_name.as_str();
+        match level {
+            log_level::ERROR => tracing::error!(guest, "{msg}"),
+            log_level::WARN => tracing::warn!(guest, "{msg}"),
+            log_level::INFO => tracing::info!(guest, "{msg}"),
+            log_level::DEBUG => tracing::debug!(guest, "{msg}"),
+            _ => tracing::trace!(guest, "{msg}"),
+        }
+        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
+    }
+
+    fn ecall_region_start(&mut self) -> Result<OpCodeResult> {
+        /// The longest region name read from the guest.
+        const MAX_NAME_LEN: u64 = 1024;
+
+        let [name_ptr, name_len, id] = self.monitor.load_registers([REG_A0, REG_A1, REG_A2]);
+        ensure!(
+            name_len <= MAX_NAME_LEN,
+            "region_start: region name of {name_len} bytes is too long"
+        );
+        let name = self.monitor.load_region(name_ptr, name_len)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let cycle = self.session_cycles();
+        if let Some((name, _)) = self.open_regions.insert(id, (name, cycle)) {
+            tracing::warn!("region_start: region {id} ({name}) was never ended");
+        }
+        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
+    }
+
+    fn ecall_region_end(&mut self) -> Result<OpCodeResult> {
+        let id = self.monitor.load_register(REG_A0);
+        match self.open_regions.remove(&id) {
+            Some((name, start)) => {
+                let cycles = (self.session_cycles() - start) as u64;
+                let stats = self.region_stats.entry(name).or_default();
+                stats.count += 1;
+                stats.cycles += cycles;
+            }
+            None => tracing::warn!("region_end: region {id} was never started"),
+        }
+        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
+    }
+
+    fn ecall_verify(&mut self) -> Result<OpCodeResult> {
+        let [image_id_ptr, journal_digest_ptr] = self.monitor.load_registers([REG_A0, REG_A1]);
+        let image_id = self
+            .monitor
+            .load_region(image_id_ptr, DIGEST_BYTES as u64)?;
+        let journal_digest = self
+            .monitor
+            .load_region(journal_digest_ptr, DIGEST_BYTES as u64)?;
+        let assumption = Assumption {
+            image_id: Digest::from_bytes(&image_id).unwrap(),
+            journal_digest: Digest::from_bytes(&journal_digest).unwrap(),
+        };
+        ensure!(
+            self.env.assumptions.contains(&assumption),
+            "verify: no assumption was added for {assumption:?}"
+        );
+        if !self.assumptions.contains(&assumption) {
+            self.assumptions.push(assumption);
+        }
+        if !self.segment_assumptions.contains(&assumption) {
+            self.segment_assumptions.push(assumption);
+        }
+        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
+    }
+
+    fn ecall_getenv(&mut self) -> Result<OpCodeResult> {
+        /// The longest variable name read from the guest.
+        const MAX_NAME_LEN: u64 = 1024;
+
+        let [name_ptr, name_len, buf_ptr, buf_len] = self
+            .monitor
+            .load_registers([REG_A0, REG_A1, REG_A2, REG_A3]);
+        ensure!(
+            name_len <= MAX_NAME_LEN,
+            "getenv: variable name of {name_len} bytes is too long"
+        );
+        let name = self.monitor.load_region(name_ptr, name_len)?;
+        let name = str::from_utf8(&name).context("getenv: variable name is not UTF-8")?;
+        // The value is returned to the guest as part of the syscall record, so
+        // that it is committed along with the rest of the session's inputs.
+        let (len, value) = match self.env.env_vars.get(name) {
+            Some(value) => {
+                let value = value.as_bytes();
+                let copied = &value[..value.len().min(buf_len as usize)];
+                self.monitor.store_region(buf_ptr, copied);
+                (value.len() as u64, copied)
+            }
+            None => (u64::MAX, &[][..]),
+        };
+        tracing::debug!("getenv {name}: {len} bytes");
+        self.monitor.store_register(REG_A0, len);
+        let syscall = SyscallRecord {
+            to_guest: to_double_words(value),
+            number: ecall::GETENV,
+            pc: self.pc,
+            ..Default::default()
+        };
+        Ok(OpCodeResult::new(
+            self.pc + WORD_SIZE as u64,
+            None,
+            0,
+            Some(syscall),
+        ))
+    }
+
+    fn ecall_panic(&mut self) -> Result<OpCodeResult> {
+        let [msg_ptr, msg_len, file_ptr, file_len, line] = self
+            .monitor
+            .load_registers([REG_A0, REG_A1, REG_A2, REG_A3, REG_A4]);
+        let mut read_str = |ptr: u64, len: u64| {
+            self.monitor
+                .image
+                .read_region(ptr, len.min(MAX_IO_LEN))
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        };
+        let message = read_str(msg_ptr, msg_len)
+            .unwrap_or_else(|_| format!("<unreadable message at 0x{msg_ptr:x}>"));
+        let file = match file_len {
+            0 => None,
+            _ => read_str(file_ptr, file_len).ok(),
+        };
+        let pcs = self.call_stack.unwind(self.pc);
+        Err(GuestPanic {
+            message,
+            file,
+            line: line as u32,
+            backtrace: panic::symbolize(self.elf.as_deref(), &pcs),
+        }
+        .into())
+    }
+
+    fn ecall_output(&mut self) -> Result<OpCodeResult> {
+        tracing::debug!("ecall(output)");
+        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u64, None, 0, None))
+    }
+}
+
+/// An event traced from the running VM.
+#[allow(dead_code)] // TODO
+#[derive(PartialEq)]
+pub enum TraceEvent {
+    /// An instruction has started at the given program counter
+    InstructionStart {
+        /// Cycle number since startup
+        cycle: u32,
+        /// Program counter of the instruction being executed
+        pc: u32,
+    },
+
+    /// A register has been set
+    RegisterSet {
+        /// Register ID (0-16)
+        reg: usize,
+        /// New value in the register
+        value: u32,
+    },
+
+    /// A memory location has been written
+    MemorySet {
         /// Address of word that's been written
         addr: u32,
         /// Value of word that's been written
@@ -584,3 +1986,1415 @@ impl Debug for TraceEvent {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Cursor, mem::take, rc::Rc};
+
+    use risc0_zkvm_platform::{
+        fileno,
+        memory::SYSTEM,
+        syscall::{
+            ecall,
+            reg_abi::{REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_A5, REG_A7},
+        },
+    };
+
+    use super::{
+        CustomInsnHandler, CustomOpcode, EcallContext, EcallHandler, Executor, ExecutorHooks,
+        FloatSupport, GuestPanic, MemAccess, MulDivAdvice, Progress, RegAccess, StepActivity,
+        WitnessCollector, WitnessGenerator, ENOMEM, MAX_IO_LEN,
+    };
+    use crate::{
+        binfmt::digest::{sha256, sha_compress_raw, BLOCK_BYTES},
+        chain_journal_digest,
+        testutil::{ecall, image, ECALL, ENTRY},
+        Digest, ExecutorEnv, ExitCode, GuestFault, Journal, Seal, Segment, SegmentIo, Session,
+        PAGE_SIZE,
+    };
+
+    #[test]
+    fn breakpoints() {
+        // A run of `ecall`s, each a gettid.
+        let image = image(&[ECALL; 8], &[(REG_A7, ecall::GETTID)]);
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+
+        exec.add_breakpoint(0x1000);
+        exec.add_breakpoint(0x1008);
+        exec.add_breakpoint(0x1010);
+        assert_eq!(exec.step().unwrap(), None);
+        assert_eq!(exec.pc, 0x1004);
+        assert_eq!(exec.continue_until_break().unwrap(), ExitCode::Paused);
+        assert_eq!(exec.pc, 0x1008);
+
+        // Continuing from a breakpoint moves past it.
+        assert!(exec.remove_breakpoint(0x1010));
+        assert!(!exec.remove_breakpoint(0x1010));
+        exec.add_breakpoint(0x100c);
+        assert_eq!(exec.continue_until_break().unwrap(), ExitCode::Paused);
+        assert_eq!(exec.pc, 0x100c);
+    }
+
+    #[test]
+    fn segments() {
+        let image = image(&vec![ECALL; 20_000], &[(REG_A7, ecall::GETTID)]);
+        let a7 = (SYSTEM.start() + REG_A7 * 8) as u64;
+        let env = ExecutorEnv::builder()
+            .segment_limit_po2(14)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        let mut split_pcs = Vec::new();
+        while split_pcs.len() < 2 {
+            if exec.step().unwrap() == Some(ExitCode::SystemSplit) {
+                split_pcs.push(exec.pc);
+            }
+        }
+        let segments = &exec.segments;
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].pc(), ENTRY);
+        assert_eq!(segments[1].pc(), split_pcs[0]);
+        assert_eq!(segments[1].pre_image_id, segments[0].post_image_id);
+        for segment in segments {
+            assert_eq!(segment.exit_code(), ExitCode::SystemSplit);
+            assert!(segment.cycles <= 1 << 14);
+            assert_eq!(segment.po2, 14);
+        }
+        // The instruction pending at the first split is committed in the second
+        // segment, which pages in everything it accesses again.
+        let page_idx = |addr: u64| addr / PAGE_SIZE as u64;
+        assert!(segments[1].faults.reads.contains(&page_idx(split_pcs[0])));
+        assert!(segments[1].faults.writes.contains(&page_idx(a7)));
+
+        // The public IO of the segments chains, and round-trips through its
+        // layout.
+        let io = segments[0].io();
+        assert_eq!(io.post_pc, split_pcs[0]);
+        assert_eq!(io.to_words().len(), SegmentIo::WORDS);
+        assert_eq!(SegmentIo::from_words(&io.to_words()).unwrap(), io);
+        for exit_code in [
+            ExitCode::Halted(7),
+            ExitCode::Fault(GuestFault::Load(0x1234_5678_9abc)),
+        ] {
+            let io = SegmentIo { exit_code, ..io };
+            assert_eq!(SegmentIo::from_words(&io.to_words()).unwrap(), io);
+        }
+        assert!(ExitCode::from_words([5, 0, 0, 0]).is_err());
+        let session = |segments| {
+            Session::new(
+                segments,
+                Vec::new(),
+                ExitCode::SystemSplit,
+                Default::default(),
+                Default::default(),
+                Vec::new(),
+            )
+        };
+        let mut segments = take(&mut exec.segments);
+        session(segments.clone()).verify_io().unwrap();
+        segments.swap(0, 1);
+        assert!(session(segments).verify_io().is_err());
+    }
+
+    #[test]
+    fn opcode_stats() {
+        let image = image(&[ECALL], &[]);
+        // a7 and a0 are zero: halt with TERMINATE.
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        assert_eq!(session.opcode_stats.total(), 1);
+        assert_eq!(session.opcode_stats.by_mnemonic["ECALL"], 1);
+        assert_eq!(session.opcode_stats.by_extension["I"], 1);
+    }
+
+    #[test]
+    fn regions() {
+        let mut image = image(&[ECALL; 8], &[]);
+        image.write_region(0x2000, b"hash").unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+
+        // The region is entered twice, and left twice; ending an unknown
+        // region is ignored.
+        for _ in 0..2 {
+            ecall(&mut exec, ecall::REGION_START, &[0x2000, 4, 7]);
+            ecall(&mut exec, ecall::GETTID, &[]);
+            ecall(&mut exec, ecall::REGION_END, &[7]);
+        }
+        ecall(&mut exec, ecall::REGION_END, &[8]);
+        exec.monitor.set_register(REG_A7, ecall::HALT);
+        exec.monitor.set_register(REG_A0, 0);
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        let stats = session.region_stats["hash"];
+        assert_eq!(stats.count, 2);
+        assert!(stats.cycles >= 4);
+    }
+
+    #[test]
+    fn journal_digest() {
+        let mut image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::WRITE),
+                (REG_A0, fileno::JOURNAL as u64),
+                (REG_A1, 0x2000),
+                (REG_A2, 6),
+            ],
+        );
+        image.write_region(0x2000, b"public").unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        exec.step().unwrap();
+        // The write is recorded for the segment's proof.
+        assert_eq!(exec.monitor.syscalls[0].number, ecall::WRITE);
+        assert_eq!(exec.monitor.syscalls[0].from_guest, b"public");
+        exec.finish_segment(ExitCode::SystemSplit);
+        exec.monitor.set_register(REG_A0, fileno::JOURNAL as u64);
+        exec.step().unwrap();
+        exec.finish_segment(ExitCode::SystemSplit);
+        exec.finish_segment(ExitCode::Paused);
+
+        // Each segment extends the digest with what it committed.
+        let first = chain_journal_digest(&Digest::ZERO, b"public");
+        let byte = |digest: Digest, byte: u8| sha256(&[digest.as_bytes(), &[byte]].concat());
+        assert_eq!(
+            first,
+            b"public".iter().fold(Digest::ZERO, |d, b| byte(d, *b))
+        );
+        let second = chain_journal_digest(&first, b"public");
+        let segments = &exec.segments;
+        assert_eq!(segments[0].journal_len, 6);
+        assert_eq!(segments[0].journal_digest, first);
+        assert_eq!(segments[1].journal_len, 12);
+        assert_eq!(segments[1].journal_digest, second);
+        assert_eq!(segments[2].journal_digest, second);
+
+        let journal = exec.journal.take();
+        let session = |segments, journal| {
+            Session::new(
+                segments,
+                journal,
+                ExitCode::Paused,
+                Default::default(),
+                Default::default(),
+                Vec::new(),
+            )
+        };
+        let segments = take(&mut exec.segments);
+        let valid = session(segments.clone(), journal.clone());
+        valid.verify_io().unwrap();
+        assert_eq!(valid.journal_digest(), second);
+        assert_eq!(Journal::new(journal.clone()).digest(), second);
+        assert!(session(segments.clone(), b"publicpublix".to_vec())
+            .verify_io()
+            .is_err());
+        assert!(session(segments.clone(), [&journal[..], b"!"].concat())
+            .verify_io()
+            .is_err());
+        let mut shifted = segments.clone();
+        shifted[0].journal_len = 0;
+        assert!(session(shifted, journal.clone()).verify_io().is_err());
+
+        // Each segment binds what it committed, but the second does not extend
+        // the journal of the first.
+        let mut unlinked = segments;
+        unlinked[1].pre_journal_digest = Digest::ZERO;
+        unlinked[1].journal_digest = first;
+        unlinked[2].pre_journal_digest = first;
+        unlinked[2].journal_digest = first;
+        assert!(session(unlinked, journal).verify_io().is_err());
+    }
+
+    #[test]
+    fn verify() {
+        // The other guest halts at once, with an empty journal.
+        let mut other =
+            Executor::new(ExecutorEnv::default(), image(&[ECALL; 4], &[]), ENTRY).unwrap();
+        let assumption = other.run().unwrap().assumption().unwrap();
+        assert_eq!(assumption.journal_digest, Digest::ZERO);
+
+        let image = || {
+            let mut image = image(
+                &[ECALL; 4],
+                &[(REG_A7, ecall::VERIFY), (REG_A0, 0x2000), (REG_A1, 0x2020)],
+            );
+            image
+                .write_region(0x2000, assumption.image_id.as_bytes())
+                .unwrap();
+            image
+                .write_region(0x2020, assumption.journal_digest.as_bytes())
+                .unwrap();
+            image
+        };
+
+        let env = ExecutorEnv::builder()
+            .add_assumption(assumption)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image(), ENTRY).unwrap();
+        exec.step().unwrap();
+        exec.step().unwrap();
+        exec.monitor.set_register(REG_A7, ecall::HALT);
+        exec.monitor.set_register(REG_A0, 0);
+        let session = exec.run().unwrap();
+        assert_eq!(session.assumptions, [assumption]);
+        // The segment that assumed the claim binds it in its public IO.
+        let segment = &session.segments[0];
+        assert_eq!(segment.assumptions, [assumption]);
+        assert_eq!(
+            segment.io().assumptions_digest,
+            sha256(
+                &[
+                    assumption.image_id.as_bytes(),
+                    assumption.journal_digest.as_bytes()
+                ]
+                .concat()
+            )
+        );
+        session.verify_io().unwrap();
+        let mut unbound = session;
+        unbound.segments[0].assumptions.clear();
+        assert!(unbound.verify_io().is_err());
+
+        // Without the claim, the guest cannot assume it.
+        let mut exec = Executor::new(ExecutorEnv::default(), image(), ENTRY).unwrap();
+        assert!(exec.step().is_err());
+    }
+
+    #[test]
+    fn assumption_binds_segment_io() {
+        // The other guest commits its journal over two segments, and halts.
+        let mut image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::WRITE),
+                (REG_A0, fileno::JOURNAL as u64),
+                (REG_A1, 0x2000),
+                (REG_A2, 3),
+            ],
+        );
+        image.write_region(0x2000, b"public").unwrap();
+        let mut other = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        other.step().unwrap();
+        other.finish_segment(ExitCode::SystemSplit);
+        other.monitor.set_register(REG_A0, fileno::JOURNAL as u64);
+        other.monitor.set_register(REG_A1, 0x2003);
+        other.step().unwrap();
+        other.finish_segment(ExitCode::Halted(0));
+        let session = Session::new(
+            take(&mut other.segments),
+            other.journal.take(),
+            ExitCode::Halted(0),
+            Default::default(),
+            Default::default(),
+            Vec::new(),
+        );
+        session.verify_io().unwrap();
+
+        // The claim is the public IO of its segments.
+        let assumption = session.assumption().unwrap();
+        let ios: Vec<SegmentIo> = session.segments.iter().map(Segment::io).collect();
+        assert_eq!(assumption.image_id, ios[0].pre_image_id);
+        assert_eq!(assumption.journal_digest, ios[1].journal_digest);
+        assert_ne!(ios[0].journal_digest, ios[1].journal_digest);
+
+        // The guest names the claim from the journal alone, as `env::verify`
+        // does, one byte at a time from zero.
+        let guest_digest = b"public".iter().fold(Digest::ZERO, |digest, byte| {
+            sha256(&[digest.as_bytes(), &[*byte]].concat())
+        });
+        assert_eq!(guest_digest, assumption.journal_digest);
+        assert_eq!(session.journal.digest(), assumption.journal_digest);
+
+        // A dev receipt carries the same public IO, and nothing to prove it.
+        let receipt = session.dev_receipt().unwrap();
+        assert_eq!(receipt.segments, ios);
+        assert_eq!(receipt.journal, session.journal);
+        assert_eq!(receipt.seal, Seal::Fake);
+    }
+
+    #[test]
+    fn multiply_divide() {
+        struct Advice;
+
+        impl WitnessGenerator for Advice {
+            type Witness = Option<MulDivAdvice>;
+
+            fn generate(&mut self, step: &StepActivity) -> Self::Witness {
+                step.muldiv
+            }
+        }
+
+        let image = image(
+            &[
+                0x02c585b3, // mul a1, a1, a2
+                0x02d5c5bb, // divw a1, a1, a3
+                ECALL,
+            ],
+            &[(REG_A1, -6i64 as u64), (REG_A2, 7), (REG_A3, -4i64 as u64)],
+        );
+        let collector = WitnessCollector::new(Advice);
+        let env = ExecutorEnv::builder()
+            .hooks(collector.clone())
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        assert_eq!(exec.monitor.register(REG_A1), 10);
+        let product = MulDivAdvice::Product {
+            high: u64::MAX,
+            low: -42i64 as u64,
+        };
+        let quotient = MulDivAdvice::Quotient {
+            quotient: 10,
+            remainder: 0xffff_fffe,
+        };
+        assert_eq!(
+            collector.take_segments(),
+            [vec![Some(product), Some(quotient), None]]
+        );
+        assert_eq!(session.opcode_stats.by_mnemonic["MUL"], 1);
+        assert_eq!(session.opcode_stats.by_mnemonic["DIVW"], 1);
+        assert_eq!(session.opcode_stats.by_extension["M"], 2);
+    }
+
+    #[test]
+    fn floating_point() {
+        let image = || {
+            image(
+                &[
+                    0xf20580d3, // fmv.d.x ft1, a1
+                    0x0210f153, // fadd.d ft2, ft1, ft1
+                    0xe20105d3, // fmv.x.d a1, ft2
+                    ECALL,
+                ],
+                &[(REG_A1, 1.25f64.to_bits())],
+            )
+        };
+        let mut exec = Executor::new(ExecutorEnv::default(), image(), ENTRY).unwrap();
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        assert_eq!(f64::from_bits(exec.monitor.register(REG_A1)), 2.5);
+        assert_eq!(f64::from_bits(exec.float_register(2)), 2.5);
+        assert_eq!(session.opcode_stats.by_extension["D"], 3);
+
+        // FADD is outside of the provable subset.
+        let env = ExecutorEnv::builder()
+            .float_support(FloatSupport::Provable)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image(), ENTRY).unwrap();
+        assert!(exec.run().is_err());
+    }
+
+    #[test]
+    fn counters() {
+        let insns = [
+            0xc02025f3, // rdinstret a1
+            0xc0002673, // rdcycle a2
+            0xc00026f3, // rdcycle a3
+            0xc0102773, // rdtime a4
+            ECALL,
+        ];
+        let mut exec = Executor::new(ExecutorEnv::default(), image(&insns, &[]), ENTRY).unwrap();
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        let [instret, cycle1, cycle2, time] =
+            [REG_A1, REG_A2, REG_A3, REG_A4].map(|idx| exec.monitor.register(idx));
+        assert_eq!(instret, 0);
+        assert!(cycle1 < cycle2);
+        // Time leaves out paging, and counts only the three instructions before.
+        assert_eq!(time, 3);
+        assert!(time < cycle2);
+
+        // The counters are read-only: csrw cycle, a0
+        let mut exec =
+            Executor::new(ExecutorEnv::default(), image(&[0xc0051073], &[]), ENTRY).unwrap();
+        assert_eq!(
+            exec.step().unwrap(),
+            Some(ExitCode::Fault(GuestFault::IllegalInstruction(ENTRY)))
+        );
+    }
+
+    #[test]
+    fn cycle_count() {
+        let image = image(
+            &[
+                // rdcycle a2
+                0xc0002673, ECALL,
+            ],
+            &[(REG_A7, ecall::CYCLE_COUNT)],
+        );
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        exec.step().unwrap();
+        exec.step().unwrap();
+        // The host call counts the cycles the counter does.
+        assert!(exec.monitor.register(REG_A0) > exec.monitor.register(REG_A2));
+        assert_eq!(exec.monitor.syscalls[0].number, ecall::CYCLE_COUNT);
+    }
+
+    #[test]
+    fn guest_panic() {
+        let mut image = image(
+            &[ECALL],
+            &[
+                (REG_A0, 0x3000),
+                (REG_A1, 4),
+                (REG_A2, 0x3100),
+                (REG_A3, 7),
+                (REG_A4, 42),
+                (REG_A7, ecall::PANIC),
+            ],
+        );
+        image.write_region(0x3000, b"boom").unwrap();
+        image.write_region(0x3100, b"main.go").unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+
+        let err = exec.run().err().unwrap();
+        let panic = err.downcast_ref::<GuestPanic>().unwrap();
+        assert_eq!(panic.message, "boom");
+        assert_eq!((panic.file.as_deref(), panic.line), (Some("main.go"), 42));
+        assert_eq!(panic.backtrace.len(), 1);
+        assert_eq!(panic.backtrace[0].pc, 0x1000);
+        assert!(err
+            .to_string()
+            .starts_with("guest panicked at main.go:42: boom"));
+    }
+
+    #[test]
+    fn session_limit() {
+        let image = image(&vec![ECALL; 20_000], &[(REG_A7, ecall::GETTID)]);
+        let env = ExecutorEnv::builder()
+            .segment_limit_po2(14)
+            .session_limit(1 << 15)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::SessionLimit);
+        assert!(exec.pc > ENTRY && exec.pc < ENTRY + 80_000);
+        let last = session.segments.last().unwrap();
+        assert_eq!(last.exit_code(), ExitCode::SessionLimit);
+        let cycles: usize = session.segments.iter().map(|segment| segment.cycles).sum();
+        assert!(cycles >= 1 << 15);
+
+        // Running again resumes from where the limit was hit.
+        let pc = exec.pc;
+        exec.run().unwrap();
+        assert!(exec.pc > pc);
+    }
+
+    #[test]
+    fn syscall_records() {
+        let image = image(&vec![ECALL; 20_000], &[(REG_A7, ecall::GETTID)]);
+        let env = ExecutorEnv::builder()
+            .segment_limit_po2(14)
+            .session_limit(1 << 15)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        let session = exec.run().unwrap();
+        assert!(session.segments.len() > 1);
+
+        let mut last_cycle = None;
+        let mut last_segment = 0;
+        for (idx, (segment, syscall)) in session.syscalls().enumerate() {
+            assert_eq!(syscall.index, idx as u64);
+            assert_eq!(syscall.pc, 0x1000 + idx as u64 * 4);
+            assert_eq!(syscall.number, ecall::GETTID);
+            assert_eq!(syscall.regs.0, 1000);
+            // Cycles count up within each segment.
+            if segment != last_segment {
+                last_segment = segment;
+                last_cycle = None;
+            }
+            assert!(last_cycle < Some(syscall.cycle));
+            last_cycle = Some(syscall.cycle);
+        }
+
+        let mut csv = Vec::new();
+        session.write_syscalls(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("index,segment,cycle,pc,number,a0,a1"));
+        assert_eq!(lines.next(), Some("0,0,0,0x1000,178,0x3e8,0x0"));
+        assert_eq!(lines.count(), session.syscalls().count() - 1);
+    }
+
+    #[test]
+    fn hooks() {
+        #[derive(Default)]
+        struct Events(Vec<String>);
+
+        struct Recorder(Rc<RefCell<Events>>);
+
+        impl ExecutorHooks for Recorder {
+            fn on_instruction(&mut self, pc: u64, insn: u32, _cycles: usize) {
+                self.0.borrow_mut().0.push(format!("insn {pc:x} {insn:x}"));
+            }
+
+            fn on_syscall(&mut self, pc: u64, number: u64, _args: [u64; 4]) {
+                self.0
+                    .borrow_mut()
+                    .0
+                    .push(format!("syscall {pc:x} {number}"));
+            }
+
+            fn on_segment(&mut self, segment: &Segment) {
+                let exit_code = segment.exit_code();
+                self.0.borrow_mut().0.push(format!("segment {exit_code:?}"));
+            }
+
+            fn on_progress(&mut self, progress: &Progress) {
+                let Progress {
+                    segments,
+                    remaining,
+                    ..
+                } = progress;
+                self.0
+                    .borrow_mut()
+                    .0
+                    .push(format!("progress {segments} {remaining:?}"));
+            }
+        }
+
+        let image = image(&[ECALL; 2], &[(REG_A7, ecall::GETTID)]);
+        let events = Rc::new(RefCell::new(Events::default()));
+        let env = ExecutorEnv::builder()
+            .hooks(Recorder(events.clone()))
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        exec.step().unwrap();
+        // The next ecall halts.
+        exec.monitor.set_register(REG_A7, ecall::HALT);
+        exec.monitor.set_register(REG_A0, 0);
+        exec.run().unwrap();
+
+        assert_eq!(
+            events.borrow().0,
+            [
+                "syscall 1000 178",
+                "insn 1000 73",
+                "syscall 1004 0",
+                "insn 1004 73",
+                "segment Halted(0)",
+                "progress 1 None",
+            ]
+        );
+    }
+
+    #[test]
+    fn witnesses() {
+        struct Steps;
+
+        impl WitnessGenerator for Steps {
+            type Witness = (u64, Vec<RegAccess>, Vec<MemAccess>, Option<Vec<u64>>);
+
+            fn generate(&mut self, step: &StepActivity) -> Self::Witness {
+                let host_writes = step.host_call.map(|syscall| syscall.to_guest.clone());
+                (
+                    step.pc,
+                    step.registers.to_vec(),
+                    step.memory.to_vec(),
+                    host_writes,
+                )
+            }
+        }
+
+        // fmv.d.x f1, a1; fadd.d f2, f1, f1; ecall; ecall
+        let insns = [0xf20580d3, 0x02108153, ECALL, ECALL];
+        let image = image(&insns, &[(11, 42)]);
+        let collector = WitnessCollector::new(Steps);
+        let env = ExecutorEnv::builder()
+            .hooks(collector.clone())
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        for _ in 0..2 {
+            exec.step().unwrap();
+        }
+        ecall(&mut exec, ecall::GETRANDOM, &[0x2000, 8]);
+        exec.monitor.set_register(REG_A7, ecall::HALT);
+        exec.monitor.set_register(REG_A0, 0);
+        exec.run().unwrap();
+
+        let segments = collector.take_segments();
+        assert_eq!(segments.len(), 1);
+        let [fmv, fadd, getrandom, halt] = &segments[0][..] else {
+            panic!("expected four steps, got {}", segments[0].len());
+        };
+        let access = |idx, value, write, float| RegAccess {
+            idx,
+            value,
+            write,
+            float,
+        };
+        // Only the registers an instruction reads are recorded, be they integer
+        // or floating-point ones.
+        assert_eq!(
+            fmv.1,
+            [access(11, 42, false, false), access(1, 42, true, true)]
+        );
+        // The raw bits of f1 are subnormal, so their sum is exact.
+        assert_eq!(
+            fadd.1,
+            [
+                access(1, 42, false, true),
+                access(1, 42, false, true),
+                access(2, 84, true, true)
+            ]
+        );
+        assert!(fadd.3.is_none());
+
+        // The host's writes are recorded along with what it returned.
+        let (pc, registers, memory, host_writes) = getrandom;
+        assert_eq!(*pc, 0x1008);
+        assert_eq!(registers[0], access(REG_A7, ecall::GETRANDOM, false, false));
+        assert!(registers.contains(&access(REG_A0, 8, true, false)));
+        let to_guest = host_writes.as_ref().unwrap();
+        assert_eq!(to_guest.len(), 1);
+        assert_eq!(
+            memory,
+            &[MemAccess {
+                cycle: 2,
+                addr: 0x2000,
+                size: 8,
+                value: to_guest[0],
+                write: true,
+            }]
+        );
+        assert_eq!(halt.0, 0x100c);
+        assert!(collector.take_segments().is_empty());
+    }
+
+    #[test]
+    fn source_registers() {
+        let sources = |insn| super::source_registers(insn).collect::<Vec<_>>();
+        // lui a0, 1; jal ra, 0
+        assert!(sources(0x00001537).is_empty());
+        assert!(sources(0x000000ef).is_empty());
+        // addi a1, a0, 1; ld a1, 0(a0)
+        assert_eq!(sources(0x00150593), [REG_A0]);
+        assert_eq!(sources(0x00053583), [REG_A0]);
+        // add a1, a0, a2; sd a1, 0(a0); beq a0, a1, 0
+        assert_eq!(sources(0x00c505b3), [REG_A0, REG_A2]);
+        assert_eq!(sources(0x00b53023), [REG_A0, 11]);
+        assert_eq!(sources(0x00b50063), [REG_A0, 11]);
+    }
+
+    #[test]
+    fn fence_i() {
+        struct Invalidations(Rc<RefCell<Vec<u64>>>);
+
+        impl ExecutorHooks for Invalidations {
+            fn on_fence_i(&mut self, pc: u64) {
+                self.0.borrow_mut().push(pc);
+            }
+        }
+
+        // fence; fence.i; ecall
+        let image = image(&[0x0ff0000f, 0x0000100f, ECALL], &[]);
+        let invalidations = Rc::new(RefCell::new(Vec::new()));
+        let env = ExecutorEnv::builder()
+            .hooks(Invalidations(invalidations.clone()))
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        let session = exec.run().unwrap();
+
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        assert_eq!(*invalidations.borrow(), [0x1004]);
+    }
+
+    #[test]
+    fn ecall_handlers() {
+        struct Double;
+
+        impl EcallHandler for Double {
+            fn ecall(&mut self, ctx: &mut EcallContext) -> anyhow::Result<Option<ExitCode>> {
+                let value = ctx.load_register(REG_A0);
+                ctx.store_register(REG_A0, value * 2);
+                ctx.store_region(0x2000, &ctx.pc().to_le_bytes());
+                Ok(None)
+            }
+        }
+
+        struct Exit;
+
+        impl EcallHandler for Exit {
+            fn ecall(&mut self, _ctx: &mut EcallContext) -> anyhow::Result<Option<ExitCode>> {
+                Ok(Some(ExitCode::Halted(7)))
+            }
+        }
+
+        const DOUBLE: u64 = 0x1000;
+        let image = image(&[ECALL; 3], &[(REG_A7, DOUBLE), (REG_A0, 21)]);
+        // A registered handler replaces a built-in host call.
+        let env = ExecutorEnv::builder()
+            .ecall(DOUBLE, "double", Double)
+            .ecall(ecall::GETTID, "gettid", Exit)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        assert_eq!(exec.step().unwrap(), None);
+        assert_eq!(exec.monitor.register(REG_A0), 42);
+        assert_eq!(exec.monitor.load_u64(0x2000).unwrap(), 0x1000);
+        assert_eq!(exec.step().unwrap(), None);
+        assert_eq!(exec.monitor.register(REG_A0), 84);
+        exec.monitor.set_register(REG_A7, ecall::GETTID);
+        assert_eq!(exec.step().unwrap(), Some(ExitCode::Halted(7)));
+    }
+
+    #[test]
+    fn rv32() {
+        // srl a0, a1, a2; mul a3, a1, a4; ld a0, 0(a1)
+        let mut image = image(&[0x00c5d533, 0x02e586b3, 0x0005b503], &[]);
+        // The registers are words in SYSTEM memory.
+        for (idx, value) in [(REG_A1, -16i32 as u32), (REG_A2, 34), (REG_A4, 1 << 28)] {
+            image
+                .write_u32((SYSTEM.start() + idx * 4) as u64, value)
+                .unwrap();
+        }
+        let env = ExecutorEnv::builder().rv32(true).build().unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        assert_eq!(exec.monitor.register(REG_A1), -16i64 as u64);
+
+        // Shifts only use 5 bits of their amount, and results are truncated to
+        // 32 bits and sign-extended.
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), 0x3fff_fffc);
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A3), 0);
+        assert!(exec.step().is_err());
+
+        exec.monitor.sync_registers();
+        let a0 = (SYSTEM.start() + REG_A0 * 4) as u64;
+        assert_eq!(exec.monitor.image.read_u32(a0).unwrap(), 0x3fff_fffc);
+        assert_eq!(exec.monitor.image.read_u32(a0 + 4).unwrap(), -16i32 as u32);
+    }
+
+    #[test]
+    fn custom_insns() {
+        // Adds its operands, or with funct3 = 1 sums the rs2 bytes at rs1 and
+        // stores the sum after them.
+        struct Accelerator;
+
+        impl CustomInsnHandler for Accelerator {
+            fn execute(
+                &mut self,
+                insn: u32,
+                rs1: u64,
+                rs2: u64,
+                ctx: &mut EcallContext,
+            ) -> anyhow::Result<u64> {
+                Ok(match (insn >> 12) & 0x7 {
+                    0 => rs1 + rs2,
+                    _ => {
+                        let sum = ctx.load_region(rs1, rs2)?.iter().map(|b| *b as u64).sum();
+                        ctx.store_region(rs1 + rs2, &[sum as u8]);
+                        sum
+                    }
+                })
+            }
+        }
+
+        // custom-0 a0, a1, a2; custom-0.1 a3, a1, a2; custom-1 a0, a1, a2
+        let mut image = image(
+            &[0x00c5850b, 0x00c5968b, 0x00c5852b],
+            &[(REG_A1, 0x2000), (REG_A2, 3)],
+        );
+        image.write_u32(0x2000, 0x04030201).unwrap();
+        let env = ExecutorEnv::builder()
+            .custom_insn(CustomOpcode::Custom0, Accelerator)
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), 0x2003);
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A3), 6);
+        assert_eq!(exec.monitor.image.read_u32(0x2000).unwrap(), 0x06030201);
+        // Their effects are recorded for the prover.
+        let syscalls = &exec.monitor.syscalls;
+        assert_eq!(syscalls[0].insn, Some(0x00c5850b));
+        assert_eq!(syscalls[0].reg_writes, vec![(REG_A0, 0x2003)]);
+        assert!(syscalls[0].to_guest.is_empty());
+        assert_eq!(syscalls[1].reg_writes, vec![(REG_A3, 6)]);
+        assert_eq!(syscalls[1].to_guest, vec![6]);
+        // There is no handler for custom-1.
+        assert!(exec.step().is_err());
+
+        // A handler that loads from unmapped memory faults the guest.
+        exec.pc = ENTRY + 4;
+        exec.monitor.set_register(REG_A1, u64::MAX);
+        assert_eq!(
+            exec.step().unwrap(),
+            Some(ExitCode::Fault(GuestFault::Load(u64::MAX)))
+        );
+    }
+
+    #[test]
+    fn io_callback() {
+        let mut image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::SOFTWARE),
+                (REG_A0, 0x2000),
+                (REG_A1, 6),
+                (REG_A2, 0x3000),
+                (REG_A3, 3),
+                (REG_A4, 0x4000),
+                (REG_A5, 0),
+            ],
+        );
+        image.write_region(0x2000, b"oracle").unwrap();
+        image.write_region(0x3000, b"abc").unwrap();
+        let env = ExecutorEnv::builder()
+            .io_callback("oracle", |data| [data, data].concat())
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        // Each call returns the length of the reply, and as much of it as fits.
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), 6);
+        exec.monitor.set_register(REG_A0, 0x2000);
+        exec.monitor.set_register(REG_A5, 4);
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), 6);
+        assert_eq!(exec.monitor.load_region(0x4000, 6).unwrap(), b"abca\0\0");
+
+        let syscalls = &exec.monitor.syscalls;
+        assert_eq!(syscalls[0].callback.as_deref(), Some("oracle"));
+        assert_eq!(syscalls[0].from_guest, b"abc");
+        assert!(syscalls[0].to_guest.is_empty());
+        assert_eq!(syscalls[1].to_guest, super::to_double_words(b"abca"));
+    }
+
+    #[test]
+    fn program_args() {
+        use risc0_zkvm_platform::memory::{ARGS, STACK_INITIAL_ADDRESS};
+
+        let env = ExecutorEnv::builder()
+            .args(&["prog", "-v"])
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image(&[ECALL], &[]), ENTRY).unwrap();
+        let sp = STACK_INITIAL_ADDRESS as u64;
+        assert_eq!(exec.monitor.load_u64(sp).unwrap(), 2);
+        assert_eq!(exec.monitor.load_u64(sp + 8).unwrap(), ARGS.start() as u64);
+        assert_eq!(
+            exec.monitor.load_u64(sp + 16).unwrap(),
+            ARGS.start() as u64 + 5
+        );
+        assert_eq!(
+            exec.monitor.load_region(ARGS.start() as u64, 8).unwrap(),
+            b"prog\0-v\0"
+        );
+    }
+
+    #[test]
+    fn too_many_args() {
+        let mut builder = ExecutorEnv::builder();
+        builder.args(&vec![""; crate::MAX_ARGS + 1]);
+        assert!(builder.build().is_err());
+        builder.args(&["x".repeat(risc0_zkvm_platform::memory::ARGS.len_bytes())]);
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn log_levels() {
+        use risc0_zkvm_platform::syscall::log_level;
+
+        let mut image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::LOG),
+                (REG_A0, log_level::WARN),
+                (REG_A1, 0x2000),
+                (REG_A2, 5),
+            ],
+        );
+        image.write_region(0x2000, b"hello").unwrap();
+        let env = ExecutorEnv::builder().guest_name("logger").build().unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        #[derive(Clone, Default)]
+        struct Output(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Output {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Output::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::WARN)
+            .with_ansi(false)
+            .with_writer({
+                let output = output.clone();
+                move || output.clone()
+            })
+            .finish();
+        tracing::subscriber::with_default(subscriber, || exec.step().unwrap());
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("WARN"), "{output}");
+        assert!(output.contains("guest=\"logger\""), "{output}");
+        assert!(output.contains("hello"), "{output}");
+        assert_eq!(exec.monitor.register(REG_A0), log_level::WARN);
+        exec.monitor.set_register(REG_A0, 0);
+        assert!(exec.step().is_err());
+    }
+
+    #[test]
+    fn getenv() {
+        let mut image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::GETENV),
+                (REG_A0, 0x2000),
+                (REG_A1, 4),
+                (REG_A2, 0x3000),
+                (REG_A3, 3),
+            ],
+        );
+        image.write_region(0x2000, b"NAME").unwrap();
+        let env = ExecutorEnv::builder()
+            .env_var("NAME", "value")
+            .build()
+            .unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+
+        // Only as much of the value as fits is copied, and it is recorded.
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), 5);
+        assert_eq!(exec.monitor.load_region(0x3000, 4).unwrap(), b"val\0");
+        let syscall = &exec.monitor.syscalls[0];
+        assert_eq!(syscall.number, ecall::GETENV);
+        assert_eq!(syscall.to_guest, [u64::from_le_bytes(*b"val\0\0\0\0\0")]);
+
+        exec.monitor.set_register(REG_A0, 0x2000);
+        exec.monitor.set_register(REG_A1, 3);
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), u64::MAX);
+        assert!(exec.monitor.syscalls[1].to_guest.is_empty());
+    }
+
+    #[test]
+    fn heap_mmap() {
+        let image = image(&[ECALL; 2], &[]);
+        let heap = image.heap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        exec.monitor.set_register(REG_A7, ecall::MMAP);
+
+        // The guest allocator maps the start of the heap itself, and anonymous
+        // mappings are then served past it.
+        exec.monitor.set_register(REG_A0, heap.start);
+        exec.monitor.set_register(REG_A1, 0x10_0000);
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), heap.start);
+        exec.monitor.set_register(REG_A0, 0);
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.register(REG_A0), heap.start + 0x10_0000);
+    }
+
+    #[test]
+    fn mmap_failures() {
+        let image = image(&[ECALL; 5], &[]);
+        let heap = image.heap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        let mut mmap = |addr, len| ecall(&mut exec, ecall::MMAP, &[addr, len]);
+        let enomem = -ENOMEM as u64;
+
+        // Lengths the address space or the heap cannot hold fail, rather than
+        // overflowing on the host.
+        assert_eq!(mmap(0, u64::MAX - 10), enomem);
+        assert_eq!(mmap(heap.start, u64::MAX - 10), enomem);
+        assert_eq!(mmap(0, heap.size + 1), enomem);
+        // So does a mapping that overlaps one already there.
+        assert_eq!(mmap(heap.start + 0x1000, 0x2000), heap.start + 0x1000);
+        assert_eq!(mmap(heap.start, 0x2000), enomem);
+    }
+
+    #[test]
+    fn files() {
+        let mut image = image(&[ECALL; 8], &[]);
+        image.write_region(0x3000, b"out").unwrap();
+        let mut output = Vec::new();
+        {
+            let env = ExecutorEnv::builder()
+                .file(4, Cursor::new(b"input"))
+                .writable_file(5, Cursor::new(&mut output))
+                .build()
+                .unwrap();
+            let mut exec = Executor::new(env, image, ENTRY).unwrap();
+            let mut file_ecall = |number, args: [u64; 3]| ecall(&mut exec, number, &args);
+
+            assert_eq!(file_ecall(ecall::LSEEK, [4, 2, 0]), 2);
+            assert_eq!(file_ecall(ecall::READ, [4, 0x2000, 16]), 3);
+            // Files are read-only unless the host made them writable.
+            assert_eq!(file_ecall(ecall::WRITE, [4, 0x3000, 3]), -9i64 as u64);
+            assert_eq!(file_ecall(ecall::WRITE, [5, 0x3000, 3]), 3);
+            assert_eq!(file_ecall(ecall::READ, [6, 0x2000, 16]), -9i64 as u64);
+            // Closing an unknown file fails, except for a standard stream.
+            assert_eq!(file_ecall(ecall::CLOSE, [4, 0, 0]), 0);
+            assert_eq!(file_ecall(ecall::CLOSE, [4, 0, 0]), -9i64 as u64);
+            assert_eq!(file_ecall(ecall::CLOSE, [1, 0, 0]), 0);
+            assert_eq!(exec.monitor.load_region(0x2000, 4).unwrap(), b"put\0");
+            let syscall = &exec.monitor.syscalls[1];
+            assert_eq!(syscall.to_guest, [u64::from_le_bytes(*b"put\0\0\0\0\0")]);
+        }
+        assert_eq!(output, b"out");
+    }
+
+    #[test]
+    fn env_files() {
+        // Each environment built from a builder has its own file table, with
+        // its own copy of the input on stdin.
+        let mut builder = ExecutorEnv::builder();
+        builder.add_input(b"in");
+        let first = builder.build().unwrap();
+        let second = builder.build().unwrap();
+        let exec = Executor::new(first, image(&[ECALL], &[]), ENTRY).unwrap();
+        assert_eq!(exec.env.files.borrow_mut().read(0, 2).unwrap(), b"in");
+        assert!(exec.env.files.borrow().contains(fileno::JOURNAL as u64));
+        assert!(!second.files.borrow().contains(fileno::JOURNAL as u64));
+        assert_eq!(second.files.borrow_mut().read(0, 2).unwrap(), b"in");
+    }
+
+    #[test]
+    #[should_panic(expected = "reserved for the journal")]
+    fn journal_fd() {
+        ExecutorEnv::builder().file(fileno::JOURNAL as u64, Cursor::new(b""));
+    }
+
+    #[test]
+    fn write_faults() {
+        let mut exec = Executor::new(
+            ExecutorEnv::default(),
+            image(
+                &[ECALL],
+                &[
+                    (REG_A7, ecall::WRITE),
+                    (REG_A0, 1),
+                    (REG_A1, u64::MAX - 3),
+                    (REG_A2, 8),
+                ],
+            ),
+            ENTRY,
+        )
+        .unwrap();
+
+        // A range that wraps around faults the guest rather than the host.
+        assert_eq!(
+            exec.step().unwrap(),
+            Some(ExitCode::Fault(GuestFault::Load(u64::MAX - 3)))
+        );
+
+        // A write longer than the host accepts in one call is cut short.
+        let mut stdout = Vec::new();
+        {
+            let image = image(
+                &[ECALL],
+                &[
+                    (REG_A7, ecall::WRITE),
+                    (REG_A0, 1),
+                    (REG_A1, 0x2000),
+                    (REG_A2, MAX_IO_LEN + 1),
+                ],
+            );
+            let env = ExecutorEnv::builder().stdout(&mut stdout).build().unwrap();
+            let mut exec = Executor::new(env, image, ENTRY).unwrap();
+            exec.step().unwrap();
+            assert_eq!(exec.monitor.register(REG_A0), MAX_IO_LEN);
+        }
+        assert_eq!(stdout.len() as u64, MAX_IO_LEN);
+    }
+
+    #[test]
+    fn standard_streams() {
+        let image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::READ),
+                (REG_A0, 0),
+                (REG_A1, 0x2000),
+                (REG_A2, 4),
+            ],
+        );
+        let mut stdout = Vec::new();
+        {
+            let env = ExecutorEnv::builder()
+                .stdin(&b"ping"[..])
+                .stdout(&mut stdout)
+                .build()
+                .unwrap();
+            let mut exec = Executor::new(env, image, ENTRY).unwrap();
+            exec.step().unwrap();
+            assert_eq!(exec.monitor.load_region(0x2000, 4).unwrap(), b"ping");
+
+            // Echo what was read.
+            exec.monitor.set_register(REG_A7, ecall::WRITE);
+            exec.monitor.set_register(REG_A0, 1);
+            exec.step().unwrap();
+            assert_eq!(exec.monitor.register(REG_A0), 4);
+        }
+        assert_eq!(stdout, b"ping");
+    }
+
+    #[test]
+    fn serde_channel() {
+        let image = image(
+            &[ECALL; 2],
+            &[
+                (REG_A7, ecall::READ),
+                (REG_A0, 0),
+                (REG_A1, 0x2000),
+                (REG_A2, 16),
+            ],
+        );
+        // The guest commits what it reads from stdin: a double word, which is
+        // two words, and a string padded to words.
+        let value = (u64::MAX - 1, String::from("ab"));
+        let input = crate::serde::to_vec(&value).unwrap();
+        let env = ExecutorEnv::builder().add_input(&input).build().unwrap();
+        let mut exec = Executor::new(env, image, ENTRY).unwrap();
+        exec.step().unwrap();
+        exec.monitor.set_register(REG_A7, ecall::WRITE);
+        exec.monitor.set_register(REG_A0, fileno::JOURNAL as u64);
+        exec.step().unwrap();
+        let journal = exec.journal.buf.take();
+        assert_eq!(journal, bytemuck::cast_slice::<u32, u8>(&input));
+        let journal: (u64, String) = crate::serde::from_slice(&journal).unwrap();
+        assert_eq!(journal, value);
+    }
+
+    #[test]
+    fn journal_decode() {
+        let mut words = crate::serde::to_vec(&(7u32, String::from("abc"))).unwrap();
+        words.extend(crate::serde::to_vec(&u64::MAX).unwrap());
+        let journal = Journal::new(bytemuck::cast_slice(&words).to_vec());
+
+        // The guest committed two values, which the cursor decodes in turn.
+        let mut cursor = journal.cursor();
+        let first: (u32, String) = cursor.decode().unwrap();
+        assert_eq!(first, (7, String::from("abc")));
+        assert_eq!(cursor.remaining_bytes(), 8);
+        assert_eq!(cursor.decode::<u64>().unwrap(), u64::MAX);
+        assert!(cursor.is_empty());
+        assert!(cursor.decode::<u32>().is_err());
+
+        // Decoding the whole journal as the first value leaves the second.
+        assert!(journal.decode::<(u32, String)>().is_err());
+        let journal = Journal::new(bytemuck::cast_slice(&words[..3]).to_vec());
+        assert_eq!(journal.decode::<(u32, String)>().unwrap(), first);
+    }
+
+    #[test]
+    fn bigint() {
+        use risc0_zkvm_platform::syscall::bigint::OP_MUL;
+
+        let mut image = image(&[ECALL; 3], &[]);
+        image.write_region(0x2000, &[6]).unwrap();
+        image.write_region(0x3000, &[7]).unwrap();
+        image.write_region(0x4000, &[11]).unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        let mut bigint = |op: u64, width: u64| {
+            ecall(
+                &mut exec,
+                ecall::BIGINT,
+                &[op, 0x5000, 0x2000, 0x3000, 0x4000, width],
+            )
+        };
+
+        assert_eq!(bigint(OP_MUL, 256), 0);
+        // Unknown operations and widths are reported to the guest.
+        assert_eq!(bigint(99, 256), u64::MAX);
+        assert_eq!(bigint(OP_MUL, 128), u64::MAX);
+        let mut expected = [0; 32];
+        expected[0] = 42 % 11;
+        assert_eq!(exec.monitor.load_region(0x5000, 32).unwrap(), expected);
+    }
+
+    #[test]
+    fn sha() {
+        let mut image = image(&[ECALL; 2], &[]);
+        let init: Vec<u8> = sha_compress_raw(&[])
+            .as_words()
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        image.write_region(0x2000, &init).unwrap();
+        // "abc", padded to a single block
+        let mut block = [0; BLOCK_BYTES];
+        block[..4].copy_from_slice(b"abc\x80");
+        block[BLOCK_BYTES - 1] = 24;
+        image.write_region(0x3000, &block).unwrap();
+        let data: Vec<u8> = (0..2 * BLOCK_BYTES as u8).collect();
+        image.write_region(0x4000, &data).unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        ecall(&mut exec, ecall::SHA, &[0x5000, 0x2000, 0x3000, 0x3020, 1]);
+        ecall(&mut exec, ecall::SHA, &[0x5020, 0x2000, 0x4000, 0x4020, 2]);
+        assert_eq!(
+            hex::encode(exec.monitor.load_region(0x5000, 32).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let expected: Vec<u8> = sha_compress_raw(&data)
+            .as_words()
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        assert_eq!(exec.monitor.load_region(0x5020, 32).unwrap(), expected);
+
+        // The compressions are recorded for the proving backend.
+        let compressions = &exec.monitor.syscalls[1].sha_compressions;
+        assert_eq!(compressions.len(), 2);
+        assert_eq!(compressions[0].block, &data[..BLOCK_BYTES]);
+        assert_eq!(compressions[0].out_state, compressions[1].in_state);
+        assert_eq!(compressions[1].out_state, sha_compress_raw(&data));
+    }
+
+    #[cfg(feature = "poseidon")]
+    #[test]
+    fn poseidon() {
+        use pasta_curves::{group::ff::PrimeField, Fp};
+
+        let mut image = image(&[ECALL; 3], &[]);
+        let elements = [Fp::from(1).to_repr(), Fp::from(2).to_repr(), [0xff; 32]].concat();
+        image.write_region(0x2000, &elements).unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        let mut poseidon = |args: [u64; 3]| ecall(&mut exec, ecall::POSEIDON, &args);
+        assert_eq!(poseidon([0x3000, 0x2000, 2]), 0);
+        // The third element is not canonical.
+        assert_eq!(poseidon([0x3020, 0x2000, 3]), u64::MAX);
+        assert_eq!(poseidon([0x3020, 0x2020, 1]), u64::MAX);
+        let hash = exec.monitor.load_region(0x3000, 32).unwrap();
+        assert_ne!(hash, [0; 32]);
+        assert_eq!(exec.monitor.load_region(0x3020, 32).unwrap(), [0; 32]);
+
+        // The hashes are recorded for the proving backend.
+        let hashes = &exec.monitor.syscalls[0].poseidon_hashes;
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[0].left, &elements[..32]);
+        assert_eq!(hashes[0].right, &elements[32..64]);
+        assert_eq!(hashes[0].out, hash);
+        assert!(exec.monitor.syscalls[1].poseidon_hashes.is_empty());
+    }
+
+    #[cfg(feature = "bls12_381")]
+    #[test]
+    fn bls12_381() {
+        use blstrs::G1Affine;
+        use group::prime::PrimeCurveAffine;
+        use risc0_zkvm_platform::syscall::bls12_381::{G1_ADD, G1_MUL};
+
+        let mut image = image(&[ECALL; 4], &[]);
+        image
+            .write_region(0x2000, &G1Affine::generator().to_uncompressed())
+            .unwrap();
+        image.write_region(0x3000, &[2]).unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        let mut bls12_381 = |args: [u64; 4]| ecall(&mut exec, ecall::BLS12_381, &args);
+
+        // Doubling the generator is multiplying it by the scalar 2.
+        assert_eq!(bls12_381([G1_ADD, 0x4000, 0x2000, 0x2000]), 0);
+        assert_eq!(bls12_381([G1_MUL, 0x5000, 0x2000, 0x3000]), 0);
+        // The scalar is not a valid point.
+        assert_eq!(bls12_381([G1_MUL, 0x6000, 0x3000, 0x3000]), u64::MAX);
+        // Neither is an unknown operation.
+        assert_eq!(bls12_381([99, 0x6000, 0x2000, 0x2000]), u64::MAX);
+        let double = exec.monitor.load_region(0x4000, 96).unwrap();
+        assert_eq!(exec.monitor.load_region(0x5000, 96).unwrap(), double);
+    }
+
+    #[cfg(feature = "bn254")]
+    #[test]
+    fn bn254() {
+        use risc0_zkvm_platform::syscall::bn254::{G1_ADD, G1_MUL, PAIRING_CHECK};
+
+        // The generator of G1 is (1, 2).
+        let mut image = image(&[ECALL; 3], &[]);
+        image.write_region(0x201f, &[1]).unwrap();
+        image.write_region(0x203f, &[2]).unwrap();
+        image.write_region(0x301f, &[2]).unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        let mut bn254 = |args: [u64; 4]| ecall(&mut exec, ecall::BN254, &args);
+
+        assert_eq!(bn254([G1_ADD, 0x4000, 0x2000, 0x2000]), 0);
+        assert_eq!(bn254([G1_MUL, 0x5000, 0x2000, 0x3000]), 0);
+        // A pairing check may not take too many pairs.
+        assert_eq!(bn254([PAIRING_CHECK, 0x2000, 65, 0]), u64::MAX);
+        let double = exec.monitor.load_region(0x4000, 64).unwrap();
+        assert_eq!(exec.monitor.load_region(0x5000, 64).unwrap(), double);
+    }
+
+    #[test]
+    fn getrandom() {
+        let run = |seed| {
+            let image = image(
+                &[ECALL],
+                &[(REG_A7, ecall::GETRANDOM), (REG_A0, 0x2000), (REG_A1, 12)],
+            );
+            let env = ExecutorEnv::builder().random_seed(seed).build().unwrap();
+            let mut exec = Executor::new(env, image, ENTRY).unwrap();
+            exec.step().unwrap();
+            assert_eq!(exec.monitor.register(REG_A0), 12);
+            let bytes = exec.monitor.load_region(0x2000, 16).unwrap();
+            // The bytes are recorded, and nothing past the buffer is written.
+            let syscall = &exec.monitor.syscalls[0];
+            assert_eq!(syscall.to_guest.len(), 2);
+            assert_eq!(syscall.to_guest[0].to_le_bytes(), bytes[..8]);
+            assert_eq!(bytes[12..], [0; 4]);
+            bytes
+        };
+
+        // A seed makes the bytes reproducible.
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn unmapped_stores() {
+        const UNMAPPED: u64 = 0x7fff_0000_0000;
+        const FSD: u32 = 0x00053027; // fsd f0, 0(a0)
+
+        // A host call writing outside of the mapped memory faults the guest.
+        let mut exec = Executor::new(
+            ExecutorEnv::default(),
+            image(
+                &[ECALL],
+                &[(REG_A7, ecall::GETRANDOM), (REG_A0, UNMAPPED), (REG_A1, 8)],
+            ),
+            ENTRY,
+        )
+        .unwrap();
+        assert_eq!(
+            exec.step().unwrap(),
+            Some(ExitCode::Fault(GuestFault::Store(UNMAPPED)))
+        );
+
+        // So does a guest store, like the load from the same address would.
+        let mut exec = Executor::new(
+            ExecutorEnv::default(),
+            image(&[FSD], &[(REG_A0, UNMAPPED)]),
+            ENTRY,
+        )
+        .unwrap();
+        assert_eq!(
+            exec.step().unwrap(),
+            Some(ExitCode::Fault(GuestFault::Store(UNMAPPED)))
+        );
+        assert!(exec.monitor.load_region(UNMAPPED, 8).is_err());
+    }
+}
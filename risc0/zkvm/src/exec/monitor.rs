@@ -12,92 +12,282 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{array, collections::BTreeSet};
+use std::{
+    array,
+    collections::{BTreeMap, BTreeSet},
+    mem::take,
+    ops::Range,
+};
 
 use anyhow::Result;
 use risc0_zkvm_platform::{
-    memory::{STACK_INITIAL_ADDRESS, SYSTEM},
-    DOUBLE_WORD_SIZE, WORD_SIZE,
+    memory::{STACK_GUARD, STACK_INITIAL_ADDRESS, SYSTEM},
+    syscall::reg_abi::{REG_A0, REG_A1, REG_MAX},
+    DOUBLE_WORD_SIZE, PAGE_SIZE, WORD_SIZE,
 };
 use rrs_lib::{MemAccessSize, Memory};
 
-use super::{OpCodeResult, SyscallRecord};
-use crate::MemoryImage;
+use super::{
+    trace::{MemAccess, MemTrace},
+    uninit::InitMap,
+    witness::RegAccess,
+    OpCodeResult, SyscallRecord,
+};
+use crate::{
+    binfmt::{digest::BLOCK_BYTES, paged::access_len},
+    session::PageFaults,
+    GuestFault, MemoryImage, PageTableInfo, SegmentFlags,
+};
 
 const SHA_INIT: usize = 5;
 const SHA_LOAD: usize = 16;
 const SHA_MAIN: usize = 52;
 
-#[derive(Eq, Ord, PartialEq, PartialOrd)]
-struct MemStore {
-    addr: u64,
-    data: u8,
+/// The number of SHA-256 blocks in a page.
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_BYTES;
+
+/// Consecutive bytes written, and the address of the first
+type ByteRun = (u64, Vec<u8>);
+
+/// Returns the number of cycles needed to hash a page of `blocks_per_page`
+/// SHA-256 blocks, including the page fault cycle itself.
+pub const fn cycles_per_page(blocks_per_page: usize) -> usize {
+    1 + SHA_INIT + (SHA_LOAD + SHA_MAIN) * blocks_per_page
+}
+
+/// The kind of guest memory access a watchpoint is triggered by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Loads only
+    Read,
+
+    /// Stores only
+    Write,
+
+    /// Both loads and stores
+    Access,
+}
+
+/// A guest memory access which triggered a watchpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// The program counter of the accessing instruction
+    pub pc: u64,
+
+    /// The address accessed
+    pub addr: u64,
+
+    /// The size of the access in bytes
+    pub size: u64,
+
+    /// The value loaded or stored
+    pub value: u64,
+
+    /// Whether the access was a store
+    pub write: bool,
+}
+
+struct Watchpoint {
+    id: usize,
+    range: Range<u64>,
+    kind: WatchKind,
+}
+
+/// The bytes of a double word written by the pending instruction
+#[derive(Clone, Copy, Default)]
+struct PendingWord {
+    data: u64,
+    /// Bit `i` is set if byte `i` of `data` has been written
+    mask: u8,
 }
 
 pub struct MemoryMonitor {
     pub image: MemoryImage,
-    // pub faults: PageFaults,
-    // pending_faults: PageFaults,
-    pending_writes: BTreeSet<MemStore>,
+    pub faults: PageFaults,
+    pending_faults: PageFaults,
+    pending_pages: BTreeSet<(u64, IncludeDir)>,
+    /// Pending writes, keyed by the address of their double word
+    pending_writes: BTreeMap<u64, PendingWord>,
     op_result: Option<OpCodeResult>,
     pub syscalls: Vec<SyscallRecord>,
-    initial: bool,
+    /// The number of syscalls committed during the session
+    pub(crate) syscall_count: u64,
+    /// Whether the stack pointer has been initialized
+    pub(crate) initial: bool,
+    fault: Option<GuestFault>,
+    watchpoints: Vec<Watchpoint>,
+    next_watchpoint: usize,
+    watch_hit: Option<WatchpointHit>,
+    cycle: u64,
+    trace: Option<MemTrace>,
+    pending_accesses: Vec<MemAccess>,
+    /// The register accesses of the pending instruction, if recording
+    pending_registers: Vec<RegAccess>,
+    /// The guest registers, which are authoritative over their copy in SYSTEM
+    /// memory until written back by [MemoryMonitor::sync_registers]
+    registers: [u64; REG_MAX],
+    registers_dirty: bool,
+    /// The size of each register in SYSTEM memory: a double word, or a word
+    /// for RV32 guests, whose registers are sign-extended when committed
+    register_size: usize,
+    /// Whether misaligned accesses are split into bytes rather than trapped
+    pub(crate) emulate_misaligned: bool,
+    /// Whether pages that are both writable and executable are made read-only
+    pub(crate) write_xor_execute: bool,
+    /// Whether accesses are collected for hooks even when not tracing
+    pub(crate) record_accesses: bool,
+    /// The initialized bytes of memory, if detecting uninitialized loads
+    pub(crate) init_map: Option<InitMap>,
+    /// Loads by the pending instruction from uninitialized memory, as address
+    /// and length
+    pending_uninit: Vec<(u64, u64)>,
 }
 
 impl MemoryMonitor {
-    pub fn new(image: MemoryImage) -> Self {
+    pub fn new(mut image: MemoryImage) -> Self {
+        let registers =
+            array::from_fn(|idx| image.read_u64(get_register_addr(idx)).unwrap_or_default());
         Self {
             image,
-            // faults: PageFaults::default(),
-            // pending_faults: PageFaults::default(),
-            pending_writes: BTreeSet::new(),
+            faults: PageFaults::default(),
+            pending_faults: PageFaults::default(),
+            pending_pages: BTreeSet::new(),
+            pending_writes: BTreeMap::new(),
             op_result: None,
             syscalls: Vec::new(),
+            syscall_count: 0,
             initial: false,
+            fault: None,
+            watchpoints: Vec::new(),
+            next_watchpoint: 0,
+            watch_hit: None,
+            cycle: 0,
+            trace: None,
+            pending_accesses: Vec::new(),
+            pending_registers: Vec::new(),
+            registers,
+            registers_dirty: false,
+            register_size: DOUBLE_WORD_SIZE,
+            emulate_misaligned: false,
+            write_xor_execute: false,
+            record_accesses: false,
+            init_map: None,
+            pending_uninit: Vec::new(),
         }
     }
 
-    pub fn load_u8(&mut self, addr: u64) -> u8 {
-        self.image
-            .memory_space
-            .read_mem(addr, MemAccessSize::Byte)
-            .unwrap() as u8
+    /// Lay the registers out as 32-bit words in SYSTEM memory, as RV32 guests
+    /// expect, and reload them from there.
+    pub(crate) fn use_rv32_registers(&mut self) {
+        self.register_size = WORD_SIZE;
+        for idx in 0..REG_MAX {
+            let addr = self.register_addr(idx);
+            self.registers[idx] = self.image.read_u32(addr).unwrap_or_default() as i32 as u64;
+        }
+    }
+
+    /// Returns the address of register `idx` in SYSTEM memory.
+    fn register_addr(&self, idx: usize) -> u64 {
+        (SYSTEM.start() + idx * self.register_size) as u64
     }
 
-    pub fn load_u16(&mut self, addr: u64) -> u16 {
-        assert_eq!(addr % 2, 0, "unaligned load");
-        u16::from_le_bytes(self.load_array(addr))
+    pub fn load_u8(&mut self, addr: u64) -> Result<u8, GuestFault> {
+        Ok(self.load_region(addr, 1)?[0])
     }
 
-    pub fn load_u32(&mut self, addr: u64) -> u32 {
-        assert_eq!(addr % WORD_SIZE as u64, 0, "unaligned load");
-        // log::debug!("load_u32: 0x{addr:08x}");
-        u32::from_le_bytes(self.load_array(addr))
+    pub fn load_u16(&mut self, addr: u64) -> Result<u16, GuestFault> {
+        check_aligned(addr, 2)?;
+        Ok(u16::from_le_bytes(self.load_array(addr)?))
     }
 
-    pub fn load_u64(&mut self, addr: u64) -> u64 {
-        assert_eq!(addr % DOUBLE_WORD_SIZE as u64, 0, "unaligned load");
-        // log::debug!("load_u32: 0x{addr:08x}");
-        u64::from_le_bytes(self.load_array(addr))
+    pub fn load_u32(&mut self, addr: u64) -> Result<u32, GuestFault> {
+        check_aligned(addr, WORD_SIZE)?;
+        Ok(u32::from_le_bytes(self.load_array(addr)?))
     }
 
-    pub fn load_array<const N: usize>(&mut self, addr: u64) -> [u8; N] {
-        array::from_fn(|idx| self.load_u8(addr + idx as u64))
+    pub fn load_u64(&mut self, addr: u64) -> Result<u64, GuestFault> {
+        check_aligned(addr, DOUBLE_WORD_SIZE)?;
+        Ok(u64::from_le_bytes(self.load_array(addr)?))
+    }
+
+    pub fn load_array<const N: usize>(&mut self, addr: u64) -> Result<[u8; N], GuestFault> {
+        Ok(self.load_region(addr, N as u64)?.try_into().unwrap())
+    }
+
+    /// Load `len` bytes starting at `addr`.
+    ///
+    /// The range may be unaligned. It is read in the largest aligned chunks
+    /// that fit, and each page it touches is paged in once. Fails with
+    /// [GuestFault::Load] if the range is not all mapped, as the guest may ask
+    /// for any range.
+    pub fn load_region(&mut self, addr: u64, len: u64) -> Result<Vec<u8>, GuestFault> {
+        let end = addr.checked_add(len).ok_or(GuestFault::Load(addr))?;
+        // The range is read before it is paged in, so that a huge unmapped
+        // range fails at its first unmapped byte.
+        let mut bytes = Vec::new();
+        let start = addr;
+        let mut addr = addr;
+        while addr < end {
+            let (size, num_bytes) = [
+                (MemAccessSize::DoubleWord, 8),
+                (MemAccessSize::Word, 4),
+                (MemAccessSize::HalfWord, 2),
+            ]
+            .into_iter()
+            .find(|(_, num_bytes)| addr % num_bytes == 0 && end - addr >= *num_bytes)
+            .unwrap_or((MemAccessSize::Byte, 1));
+            let value = self
+                .image
+                .memory_space
+                .read_mem(addr, size)
+                .ok_or(GuestFault::Load(addr))?;
+            bytes.extend_from_slice(&value.to_le_bytes()[..num_bytes as usize]);
+            addr += num_bytes;
+        }
+        let mut page_addr = start;
+        while page_addr < end {
+            self.include_fault(page_addr, IncludeDir::Read);
+            page_addr = (page_addr / PAGE_SIZE as u64 + 1) * PAGE_SIZE as u64;
+        }
+
+        // Registers are read from the cache rather than from SYSTEM memory.
+        let registers_start = self.register_addr(0);
+        let registers_end = self.register_addr(REG_MAX);
+        for reg_addr in start.max(registers_start)..end.min(registers_end) {
+            let offset = reg_addr - registers_start;
+            let register = self.registers[offset as usize / self.register_size];
+            let shift = offset % self.register_size as u64 * 8;
+            bytes[(reg_addr - start) as usize] = (register >> shift) as u8;
+        }
+        Ok(bytes)
+    }
+
+    /// Load `n` consecutive words starting at `addr`, which must be 4-byte
+    /// aligned.
+    pub fn load_words(&mut self, addr: u64, n: usize) -> Result<Vec<u32>, GuestFault> {
+        check_aligned(addr, WORD_SIZE)?;
+        Ok(self
+            .load_region(addr, (n * WORD_SIZE) as u64)?
+            .chunks_exact(WORD_SIZE)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect())
     }
 
     pub fn load_register(&mut self, idx: usize) -> u64 {
-        if idx == 2 && self.initial == false {
+        let value = if idx == 2 && self.initial == false {
             // sp address
             // set stack address at the end
             self.initial = true;
             // FIXME: it will take effect at next instructoin
-            self.store_u64(get_register_addr(idx), STACK_INITIAL_ADDRESS as u64);
+            self.store_register(idx, STACK_INITIAL_ADDRESS as u64);
             // cant call load_u64 here since it haven't updated
             STACK_INITIAL_ADDRESS as u64
         } else {
-            let register_addr = get_register_addr(idx);
-            self.load_u64(register_addr)
-        }
+            self.include_fault(self.register_addr(idx), IncludeDir::Read);
+            self.registers[idx]
+        };
+        self.on_register_access(idx, value, false);
+        value
     }
 
     pub fn load_registers<const N: usize>(&mut self, idxs: [usize; N]) -> [u64; N] {
@@ -107,18 +297,34 @@ impl MemoryMonitor {
     pub fn load_string(&mut self, mut addr: u64) -> Result<String> {
         let mut s: Vec<u8> = Vec::new();
         loop {
-            let b = self.load_u8(addr);
-            if b == 0 {
+            // Read up to the next double word boundary, so as not to run past
+            // the end of the region holding the string.
+            let len = DOUBLE_WORD_SIZE as u64 - addr % DOUBLE_WORD_SIZE as u64;
+            let chunk = self.load_region(addr, len)?;
+            if let Some(nul) = chunk.iter().position(|b| *b == 0) {
+                s.extend_from_slice(&chunk[..nul]);
                 break;
             }
-            s.push(b);
-            addr += 1;
+            s.extend_from_slice(&chunk);
+            addr += len;
         }
         String::from_utf8(s).map_err(anyhow::Error::msg)
     }
 
     pub fn store_u8(&mut self, addr: u64, data: u8) {
-        self.pending_writes.insert(MemStore { addr, data });
+        self.store_region(addr, &[data]);
+    }
+
+    fn include_fault(&mut self, addr: u64, dir: IncludeDir) {
+        let page_idx = self.image.info.get_page_index(addr);
+        self.pending_pages.insert((page_idx, dir));
+        let seen = match dir {
+            IncludeDir::Read => self.faults.reads.contains(&page_idx),
+            IncludeDir::Write => self.faults.writes.contains(&page_idx),
+        };
+        if !seen {
+            self.pending_faults.include(&self.image.info, addr, dir);
+        }
     }
 
     pub fn store_u16(&mut self, addr: u64, data: u16) {
@@ -136,20 +342,141 @@ impl MemoryMonitor {
         self.store_region(addr, &data.to_le_bytes());
     }
 
+    /// Store `slice` starting at `addr`, which may be unaligned.
+    ///
+    /// The bytes are buffered a double word at a time until the pending
+    /// instruction is committed. Each double word written is traced and checked
+    /// against the watchpoints like a guest store, so that the writes of host
+    /// calls are seen too.
     pub fn store_region(&mut self, addr: u64, slice: &[u8]) {
-        slice
-            .iter()
-            .enumerate()
-            .for_each(|(i, x)| self.store_u8(addr + i as u64, *x));
+        self.store_bytes(addr, slice, true);
+    }
+
+    fn store_bytes(&mut self, mut addr: u64, mut slice: &[u8], record: bool) {
+        while !slice.is_empty() {
+            let word_addr = addr - addr % DOUBLE_WORD_SIZE as u64;
+            let offset = (addr - word_addr) as usize;
+            let len = slice.len().min(DOUBLE_WORD_SIZE - offset);
+            // A double word never straddles a page, so the whole chunk shares
+            // the permissions, page and region of its first byte.
+            if !self.is_mapped(addr) || !self.page_permissions(addr).write {
+                self.fault.get_or_insert(GuestFault::Store(addr));
+                return;
+            }
+            self.include_fault(addr, IncludeDir::Write);
+            if record {
+                let mut bytes = [0; DOUBLE_WORD_SIZE];
+                bytes[..len].copy_from_slice(&slice[..len]);
+                self.on_access(addr, len as u64, u64::from_le_bytes(bytes), true);
+            }
+            let word = self.pending_writes.entry(word_addr).or_default();
+            for (i, byte) in slice[..len].iter().enumerate() {
+                let shift = (offset + i) * 8;
+                word.data = word.data & !(0xff << shift) | (*byte as u64) << shift;
+                word.mask |= 1 << (offset + i);
+            }
+            addr += len as u64;
+            slice = &slice[len..];
+        }
     }
 
     pub fn store_register(&mut self, idx: usize, data: u64) {
-        if idx == 2 && data == 0u64 {
-            println!("reset sp happened here!");
-            self.store_u64(get_register_addr(idx), STACK_INITIAL_ADDRESS as u64)
+        let data = if idx == 2 && data == 0u64 {
+            tracing::debug!("reset sp happened here!");
+            STACK_INITIAL_ADDRESS as u64
         } else {
-            self.store_u64(get_register_addr(idx), data);
+            data
+        };
+        let addr = self.register_addr(idx);
+        self.store_bytes(addr, &data.to_le_bytes()[..self.register_size], false);
+        self.on_register_access(idx, data, true);
+    }
+
+    /// Returns the committed value of register `idx`, without paging it in.
+    pub fn register(&self, idx: usize) -> u64 {
+        self.registers[idx]
+    }
+
+    /// Set register `idx` to `value` immediately, outside of any instruction.
+    pub fn set_register(&mut self, idx: usize, value: u64) {
+        self.registers[idx] = value;
+        self.registers_dirty = true;
+    }
+
+    /// Write the cached registers back to SYSTEM memory, so that the image
+    /// reflects them.
+    pub fn sync_registers(&mut self) {
+        if !take(&mut self.registers_dirty) {
+            return;
+        }
+        let size = match self.register_size {
+            WORD_SIZE => MemAccessSize::Word,
+            _ => MemAccessSize::DoubleWord,
+        };
+        for idx in 0..REG_MAX {
+            let addr = self.register_addr(idx);
+            self.image.write_mem(addr, size, self.registers[idx]);
+        }
+    }
+
+    /// Returns the bytes written by the pending instruction, in address order.
+    pub fn pending_writes(&self) -> Vec<(u64, u8)> {
+        self.pending_writes
+            .iter()
+            .flat_map(|(word_addr, word)| {
+                (0..DOUBLE_WORD_SIZE)
+                    .filter(|i| word.mask & 1 << i != 0)
+                    .map(move |i| (word_addr + i as u64, (word.data >> (i * 8)) as u8))
+            })
+            .collect()
+    }
+
+    /// Make the pending instruction write `writes`, as returned by
+    /// [MemoryMonitor::pending_writes] for an earlier one. Registers are
+    /// stored as registers, and the rest a run of consecutive bytes at a time.
+    /// Returns the bytes stored outside of the registers, in address order.
+    pub fn store_writes(&mut self, writes: &[(u64, u8)]) -> Vec<u8> {
+        let (register_values, runs) = self.split_writes(writes);
+        for (idx, value) in register_values {
+            self.store_register(idx, value);
+        }
+        for (addr, bytes) in runs.iter() {
+            self.store_region(*addr, bytes);
+        }
+        runs.into_iter().flat_map(|(_, bytes)| bytes).collect()
+    }
+
+    /// Returns the registers written by the pending instruction, by index and
+    /// with the values they are left holding, and the bytes it wrote outside
+    /// of the registers, in address order.
+    pub fn pending_effects(&self) -> (Vec<(usize, u64)>, Vec<u8>) {
+        let (register_values, runs) = self.split_writes(&self.pending_writes());
+        (
+            register_values.into_iter().collect(),
+            runs.into_iter().flat_map(|(_, bytes)| bytes).collect(),
+        )
+    }
+
+    /// Split `writes` into the values they leave in the registers they touch,
+    /// and runs of consecutive bytes written elsewhere.
+    fn split_writes(&self, writes: &[(u64, u8)]) -> (BTreeMap<usize, u64>, Vec<ByteRun>) {
+        let registers = self.register_addr(0)..self.register_addr(REG_MAX);
+        let mut register_values = BTreeMap::new();
+        let mut runs: Vec<ByteRun> = Vec::new();
+        for &(addr, byte) in writes {
+            if registers.contains(&addr) {
+                let idx = ((addr - registers.start) / self.register_size as u64) as usize;
+                let shift = (addr - self.register_addr(idx)) * 8;
+                let value = register_values.entry(idx).or_insert(self.registers[idx]);
+                *value = *value & !(0xff << shift) | (byte as u64) << shift;
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() as u64 == addr => bytes.push(byte),
+                _ => runs.push((addr, vec![byte])),
+            }
         }
+        (register_values, runs)
     }
 
     pub fn save_op(&mut self, op_result: OpCodeResult) {
@@ -160,101 +487,375 @@ impl MemoryMonitor {
         self.op_result.clone()
     }
 
+    /// Watch guest accesses of `kind` to any address in `range`, returning an
+    /// id for [MemoryMonitor::remove_watchpoint].
+    pub fn add_watchpoint(&mut self, range: Range<u64>, kind: WatchKind) -> usize {
+        let id = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    /// Remove the watchpoint `id`, returning whether it existed.
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != len
+    }
+
+    /// Returns the first watchpoint hit by the instruction at `pc`, if any.
+    pub fn take_watch_hit(&mut self, pc: u64) -> Option<WatchpointHit> {
+        self.watch_hit.take().map(|hit| WatchpointHit { pc, ..hit })
+    }
+
+    /// Returns the loads and stores made by the pending instruction, if they
+    /// are being recorded.
+    pub fn pending_accesses(&self) -> &[MemAccess] {
+        &self.pending_accesses
+    }
+
+    /// Returns the register reads and writes made by the pending
+    /// instruction, if they are being recorded.
+    pub fn pending_registers(&self) -> &[RegAccess] {
+        &self.pending_registers
+    }
+
+    /// Start recording every guest load and store into a [MemTrace].
+    pub fn record_trace(&mut self) {
+        self.trace.get_or_insert_with(MemTrace::new);
+    }
+
+    /// Stop recording, returning the accesses recorded so far.
+    pub fn take_trace(&mut self) -> Option<MemTrace> {
+        self.trace.take()
+    }
+
+    /// Record a load or store of `size` bytes, in the trace if enabled and
+    /// against any watchpoints.
+    fn on_access(&mut self, addr: u64, size: u64, value: u64, write: bool) {
+        if self.trace.is_some() || self.record_accesses {
+            self.pending_accesses.push(MemAccess {
+                cycle: self.cycle,
+                addr,
+                size: size as u8,
+                value,
+                write,
+            });
+        }
+        self.check_watchpoints(addr, size, value, write);
+    }
+
+    fn on_register_access(&mut self, idx: usize, value: u64, write: bool) {
+        if self.record_accesses {
+            self.pending_registers.push(RegAccess {
+                idx,
+                value,
+                write,
+                float: false,
+            });
+        }
+    }
+
+    /// Record an access of floating-point register `idx` by the pending
+    /// instruction, which the [Fpu](super::float::Fpu) made.
+    pub(crate) fn on_float_register_access(&mut self, idx: usize, value: u64, write: bool) {
+        if self.record_accesses {
+            self.pending_registers.push(RegAccess {
+                idx,
+                value,
+                write,
+                float: true,
+            });
+        }
+    }
+
+    fn check_watchpoints(&mut self, addr: u64, size: u64, value: u64, write: bool) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let hit = self.watchpoints.iter().any(|watchpoint| {
+            let kind = match watchpoint.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true,
+            };
+            kind && overlaps(addr, size, &watchpoint.range)
+        });
+        if hit {
+            self.watch_hit = Some(WatchpointHit {
+                pc: 0,
+                addr,
+                size,
+                value,
+                write,
+            });
+        }
+    }
+
+    /// Returns the permissions the guest has on the page containing `addr`.
+    ///
+    /// Under [MemoryMonitor::write_xor_execute], code takes precedence: a page
+    /// holding any executable segment is not writable. An image without
+    /// segments cannot tell code from data, and remains unrestricted.
+    pub fn page_permissions(&self, addr: u64) -> SegmentFlags {
+        let mut flags = self.image.page_permissions(addr);
+        if self.write_xor_execute && flags.execute && !self.image.segments().is_empty() {
+            flags.write = false;
+        }
+        flags
+    }
+
+    /// Returns whether `addr` lies in one of the regions mapped into the image.
+    fn is_mapped(&self, addr: u64) -> bool {
+        self.image
+            .regions()
+            .iter()
+            .any(|region| region.contains(addr))
+    }
+
+    /// Returns a fault if the instruction at `pc` may not be executed.
+    pub fn check_fetch(&self, pc: u64) -> Option<GuestFault> {
+        if pc % WORD_SIZE as u64 != 0 {
+            Some(GuestFault::MisalignedFetch(pc))
+        } else if self.page_permissions(pc).execute {
+            None
+        } else {
+            Some(GuestFault::Fetch(pc))
+        }
+    }
+
+    /// Fault the pending instruction with `fault`, unless it already faulted.
+    pub(crate) fn raise_fault(&mut self, fault: GuestFault) {
+        self.fault.get_or_insert(fault);
+    }
+
+    /// Returns the fault raised by the pending instruction so far, if any.
+    pub(crate) fn pending_fault(&self) -> Option<GuestFault> {
+        self.fault
+    }
+
+    /// Returns the loads from uninitialized memory made by the pending
+    /// instruction, as address and length.
+    pub(crate) fn take_uninit_loads(&mut self) -> Vec<(u64, u64)> {
+        take(&mut self.pending_uninit)
+    }
+
+    /// Returns the fault raised by the pending instruction, if any, discarding
+    /// all of its pending activity.
+    pub fn take_fault(&mut self) -> Option<GuestFault> {
+        let fault = self.fault.take()?;
+        self.pending_writes.clear();
+        self.pending_faults.clear();
+        self.pending_pages.clear();
+        self.pending_accesses.clear();
+        self.pending_registers.clear();
+        self.pending_uninit.clear();
+        self.op_result = None;
+        self.watch_hit = None;
+        Some(fault)
+    }
+
     // commit all pending activity
     pub fn commit(&mut self) {
         // cycle: usize) {
-        for op in self.pending_writes.iter() {
-            let res =
+        let registers = self.register_addr(0)..self.register_addr(REG_MAX);
+        for (word_addr, word) in take(&mut self.pending_writes) {
+            if registers.contains(&word_addr) {
+                for i in (0..DOUBLE_WORD_SIZE).filter(|i| word.mask & 1 << i != 0) {
+                    let offset = (word_addr - registers.start) as usize + i;
+                    let register = &mut self.registers[offset / self.register_size];
+                    let shift = offset % self.register_size * 8;
+                    *register =
+                        *register & !(0xff << shift) | (word.data >> (i * 8) & 0xff) << shift;
+                    if self.register_size == WORD_SIZE {
+                        *register = *register as i32 as u64;
+                    }
+                }
+                self.registers_dirty = true;
+                continue;
+            }
+            if let Some(init_map) = self.init_map.as_mut() {
+                for i in (0..DOUBLE_WORD_SIZE).filter(|i| word.mask & 1 << i != 0) {
+                    init_map.mark(word_addr + i as u64, 1);
+                }
+            }
+            let res = if word.mask == 0xff {
                 self.image
-                    .memory_space
-                    .write_mem(op.addr, MemAccessSize::Byte, u64::from(op.data));
-            if res == false {
-                println!("addr out of bound, addr {:16x}", op.addr);
+                    .write_mem(word_addr, MemAccessSize::DoubleWord, word.data)
+            } else {
+                (0..DOUBLE_WORD_SIZE)
+                    .filter(|i| word.mask & 1 << i != 0)
+                    .all(|i| {
+                        let byte = (word.data >> (i * 8)) & 0xff;
+                        self.image
+                            .write_mem(word_addr + i as u64, MemAccessSize::Byte, byte)
+                    })
+            };
+            // Stores outside of the mapped regions fault before they get here.
+            debug_assert!(res, "store to unmapped address {word_addr:#x}");
+        }
+        self.faults.append(&mut self.pending_faults);
+        self.pending_pages.clear();
+        if let Some(trace) = self.trace.as_mut() {
+            for access in self.pending_accesses.iter() {
+                trace.push(access);
             }
-            // self.image.buf[op.addr as usize] = op.data;
         }
-        self.pending_writes.clear();
-        // self.faults.append(&mut self.pending_faults);
+        self.pending_accesses.clear();
+        self.pending_registers.clear();
+        self.cycle += 1;
         // self.cycle = cycle;
         let op_result = self.op_result.take().unwrap();
-        if let Some(syscall) = op_result.syscall {
+        if let Some(mut syscall) = op_result.syscall {
+            syscall.regs = (self.registers[REG_A0], self.registers[REG_A1]);
+            syscall.index = self.syscall_count;
+            self.syscall_count += 1;
             self.syscalls.push(syscall);
         }
-        // self.faults.dump();
     }
 
-    // pub fn pending_page_reads(&self) -> Vec<u32> {
-    //     self.pending_faults
-    //         .reads
-    //         .difference(&self.faults.reads)
-    //         .into_iter()
-    //         .cloned()
-    //         .collect()
-    // }
+    /// Returns the pages read by the pending instruction that have not yet
+    /// been read in this segment.
+    pub fn pending_page_reads(&self) -> Vec<u64> {
+        self.pending_faults
+            .reads
+            .difference(&self.faults.reads)
+            .cloned()
+            .collect()
+    }
 
-    // pub fn total_page_read_cycles(&self) -> usize {
-    //     self.compute_page_cycles(self.faults.reads.union(&self.pending_faults.
-    // reads)) }
+    /// Returns the cycles needed to page in every page read in this segment,
+    /// including those read by the pending instruction.
+    pub fn total_page_read_cycles(&self) -> usize {
+        self.compute_page_cycles(self.faults.reads.union(&self.pending_faults.reads))
+    }
 
-    // pub fn total_fault_cycles(&self) -> usize {
-    //     let reads = self.compute_page_cycles(self.faults.reads.iter());
-    //     let writes = self.compute_page_cycles(self.faults.writes.iter());
-    //     reads + writes
-    // }
+    /// Returns the cycles needed to page in and out every page accessed in
+    /// this segment.
+    pub fn total_fault_cycles(&self) -> usize {
+        let reads = self.compute_page_cycles(self.faults.reads.iter());
+        let writes = self.compute_page_cycles(self.faults.writes.iter());
+        reads + writes
+    }
 
-    // pub fn total_pending_fault_cycles(&self) -> usize {
-    //     let reads =
-    // self.compute_page_cycles(self.faults.reads.union(&self.pending_faults.
-    // reads));     let writes =
-    //         self.compute_page_cycles(self.faults.writes.union(&self.
-    // pending_faults.writes));     reads + writes
-    // }
+    /// Returns [MemoryMonitor::total_fault_cycles] as it would be once the
+    /// pending instruction is committed.
+    pub fn total_pending_fault_cycles(&self) -> usize {
+        let reads = self.compute_page_cycles(self.faults.reads.union(&self.pending_faults.reads));
+        let writes =
+            self.compute_page_cycles(self.faults.writes.union(&self.pending_faults.writes));
+        reads + writes
+    }
 
-    // pub fn pending_page_read_cycles(&self) -> usize {
-    //     self.compute_page_cycles(self.pending_page_reads().iter())
-    // }
+    /// Returns the cycles needed to page in the pages first read by the
+    /// pending instruction.
+    pub fn pending_page_read_cycles(&self) -> usize {
+        self.compute_page_cycles(self.pending_page_reads().iter())
+    }
 
-    // fn compute_page_cycles<'a, I: Iterator<Item = &'a u32>>(&self, page_idxs: I)
-    // -> usize {     let root_idx = self.image.info.root_idx;
-    //     let num_root_entries = self.image.info.num_root_entries as usize;
-    //     page_idxs.fold(0, |acc, page_idx| {
-    //         acc + if *page_idx == root_idx {
-    //             cycles_per_page(num_root_entries / 2)
-    //         } else {
-    //             cycles_per_page(BLOCKS_PER_PAGE)
-    //         }
-    //     })
-    // }
+    fn compute_page_cycles<'a, I: Iterator<Item = &'a u64>>(&self, page_idxs: I) -> usize {
+        let root_idx = self.image.info.root_idx;
+        let num_root_entries = self.image.info.num_root_entries as usize;
+        page_idxs.fold(0, |acc, page_idx| {
+            acc + if *page_idx == root_idx {
+                cycles_per_page((num_root_entries + 1) / 2)
+            } else {
+                cycles_per_page(BLOCKS_PER_PAGE)
+            }
+        })
+    }
 
     pub fn clear_segment(&mut self) {
-        // self.faults.clear();
+        self.faults.clear();
         self.syscalls.clear();
+        // A pending instruction is carried over into the next segment, which
+        // must page in everything it accesses again.
+        self.pending_faults.clear();
+        for (page_idx, dir) in self.pending_pages.iter() {
+            let addr = page_idx * PAGE_SIZE as u64;
+            self.pending_faults.include(&self.image.info, addr, *dir);
+        }
     }
 
     pub fn clear_session(&mut self) {
         self.clear_segment();
-        // self.pending_faults.clear();
+        self.syscall_count = 0;
+        self.pending_faults.clear();
+        self.pending_pages.clear();
         self.pending_writes.clear();
+        self.fault = None;
+        self.watch_hit = None;
+        self.pending_accesses.clear();
+        self.pending_registers.clear();
+        self.pending_uninit.clear();
     }
 }
 
+/// Fails with [GuestFault::MisalignedLoad] unless `addr` is a multiple of
+/// `align`.
+fn check_aligned(addr: u64, align: usize) -> Result<(), GuestFault> {
+    match addr % align as u64 {
+        0 => Ok(()),
+        _ => Err(GuestFault::MisalignedLoad(addr)),
+    }
+}
+
+/// Returns whether an access of `len` bytes at `addr` touches the guard page
+/// below the stack.
+fn is_stack_guard(addr: u64, len: u64) -> bool {
+    overlaps(
+        addr,
+        len,
+        &(STACK_GUARD.start() as u64..STACK_GUARD.end() as u64),
+    )
+}
+
+/// Returns whether the `len` bytes at `addr` overlap `range`. An access that
+/// runs past the end of the address space overlaps everything above `addr`.
+fn overlaps(addr: u64, len: u64, range: &Range<u64>) -> bool {
+    addr < range.end && addr.checked_add(len).map_or(true, |end| end > range.start)
+}
+
 impl Memory for MemoryMonitor {
     fn read_mem(&mut self, addr: u64, size: MemAccessSize) -> Option<u64> {
-        match size {
-            MemAccessSize::Byte => Some(self.load_u8(addr) as u64),
-            MemAccessSize::HalfWord => Some(self.load_u16(addr) as u64),
-            MemAccessSize::Word => Some(self.load_u32(addr) as u64),
-            MemAccessSize::DoubleWord => Some(self.load_u64(addr)),
+        let len = access_len(size);
+        if addr % len != 0 && !self.emulate_misaligned {
+            self.fault.get_or_insert(GuestFault::MisalignedLoad(addr));
+            return Some(0);
+        }
+        if is_stack_guard(addr, len) {
+            self.fault.get_or_insert(GuestFault::StackOverflow(addr));
+            return Some(0);
+        }
+        if let Some(init_map) = &self.init_map {
+            if !init_map.is_initialized(addr, len) {
+                self.pending_uninit.push((addr, len));
+            }
+        }
+        let mut bytes = [0; 8];
+        match self.load_region(addr, len) {
+            Ok(region) => bytes[..len as usize].copy_from_slice(&region),
+            Err(fault) => {
+                self.raise_fault(fault);
+                return Some(0);
+            }
         }
+        let value = u64::from_le_bytes(bytes);
+        self.on_access(addr, len, value, false);
+        Some(value)
     }
 
     fn write_mem(&mut self, addr: u64, size: MemAccessSize, store_data: u64) -> bool {
-        match size {
-            MemAccessSize::Byte => self.store_u8(addr, store_data as u8),
-            MemAccessSize::HalfWord => self.store_u16(addr, store_data as u16),
-            MemAccessSize::Word => self.store_u32(addr, store_data as u32),
-            MemAccessSize::DoubleWord => self.store_u64(addr, store_data),
-        };
+        let len = access_len(size);
+        if addr % len != 0 && !self.emulate_misaligned {
+            self.fault.get_or_insert(GuestFault::MisalignedStore(addr));
+            return true;
+        }
+        if is_stack_guard(addr, len) {
+            self.fault.get_or_insert(GuestFault::StackOverflow(addr));
+            return true;
+        }
+        self.store_region(addr, &store_data.to_le_bytes()[..len as usize]);
         true
     }
 }
@@ -281,48 +882,394 @@ fn get_register_addr(idx: usize) -> u64 {
     (SYSTEM.start() + idx * DOUBLE_WORD_SIZE) as u64
 }
 
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
 enum IncludeDir {
     Read,
     Write,
 }
 
-// impl PageFaults {
-//     fn include(&mut self, info: &PageTableInfo, addr: u64, dir: IncludeDir) {
-//         let mut addr = addr;
-//         loop {
-//             let page_idx = info.get_page_index(addr);
-//             let entry_addr = info.get_page_entry_addr(page_idx);
-//             match dir {
-//                 IncludeDir::Read => self.reads.insert(page_idx),
-//                 IncludeDir::Write => self.writes.insert(page_idx),
-//             };
-//             if page_idx == info.root_idx {
-//                 break;
-//             }
-//             addr = entry_addr;
-//         }
-//     }
-
-//     fn clear(&mut self) {
-//         self.reads.clear();
-//         self.writes.clear();
-//     }
-
-//     fn append(&mut self, rhs: &mut Self) {
-//         self.reads.append(&mut rhs.reads);
-//         self.writes.append(&mut rhs.writes);
-//     }
-
-//     #[allow(dead_code)]
-//     fn dump(&self) {
-//         log::debug!("PageFaultInfo");
-//         log::debug!("  reads>");
-//         for idx in self.reads.iter().rev() {
-//             log::debug!("  0x{:08X}", idx);
-//         }
-//         log::debug!("  writes>");
-//         for idx in self.writes.iter() {
-//             log::debug!("  0x{:08X}", idx);
-//         }
-//     }
-// }
+impl PageFaults {
+    /// Record an access to `addr`, along with the page table pages above it.
+    ///
+    /// Addresses outside of the memory committed to by the page table are
+    /// ignored.
+    fn include(&mut self, info: &PageTableInfo, addr: u64, dir: IncludeDir) {
+        let page_idx = info.get_page_index(addr);
+        if page_idx >= info.layers()[0].num_entries {
+            return;
+        }
+        let pages = match dir {
+            IncludeDir::Read => &mut self.reads,
+            IncludeDir::Write => &mut self.writes,
+        };
+        if !pages.insert(page_idx) || info.is_table_page(page_idx) {
+            return;
+        }
+        for entry_addr in info.get_entry_path(page_idx) {
+            pages.insert(info.get_page_index(entry_addr));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.reads.clear();
+        self.writes.clear();
+    }
+
+    fn append(&mut self, rhs: &mut Self) {
+        self.reads.append(&mut rhs.reads);
+        self.writes.append(&mut rhs.writes);
+    }
+
+    #[allow(dead_code)]
+    fn dump(&self) {
+        tracing::debug!("PageFaultInfo");
+        tracing::debug!("  reads>");
+        for idx in self.reads.iter().rev() {
+            tracing::debug!("  0x{:08X}", idx);
+        }
+        tracing::debug!("  writes>");
+        for idx in self.writes.iter() {
+            tracing::debug!("  0x{:08X}", idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use risc0_zkvm_platform::{memory::STACK_GUARD, syscall::reg_abi::REG_A0};
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use super::{
+        cycles_per_page, get_register_addr, MemAccess, MemoryMonitor, WatchKind, BLOCKS_PER_PAGE,
+    };
+    use crate::{
+        testutil::{image, program, RET},
+        GuestFault, HeapRegion, MemoryImage, Program, ProgramSegment, SegmentFlags, PAGE_SIZE,
+    };
+
+    #[test]
+    fn page_faults() {
+        let image = MemoryImage::new(&program(&[RET]), PAGE_SIZE as u64, Some(vec![1; 8]));
+        let info = image.info.clone();
+        let mut monitor = MemoryMonitor::new(image);
+
+        monitor.load_u32(0x1000);
+        monitor.store_u64(0x8000, 1);
+        assert!(monitor.faults.reads.is_empty());
+        monitor.save_op(super::OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+
+        let path = |page_idx| {
+            let mut pages: Vec<u64> = info
+                .get_entry_path(page_idx)
+                .into_iter()
+                .map(|entry_addr| info.get_page_index(entry_addr))
+                .collect();
+            pages.insert(0, page_idx);
+            pages
+        };
+        for page_idx in path(0x1000 / PAGE_SIZE as u64) {
+            assert!(monitor.faults.reads.contains(&page_idx));
+        }
+        for page_idx in path(0x8000 / PAGE_SIZE as u64) {
+            assert!(monitor.faults.writes.contains(&page_idx));
+        }
+        assert!(monitor.faults.reads.contains(&info.root_idx));
+
+        // Accesses outside of committed memory are not tracked.
+        let num_reads = monitor.faults.reads.len();
+        monitor.load_u8(HeapRegion::MEMORY_DATA_ADDR);
+        monitor.save_op(super::OpCodeResult::new(0x1008, None, 0, None));
+        monitor.commit();
+        assert_eq!(monitor.faults.reads.len(), num_reads);
+
+        monitor.clear_segment();
+        assert!(monitor.faults.writes.is_empty());
+    }
+
+    #[test]
+    fn paging_cycles() {
+        let image = image(&[RET], &[]);
+        let info = image.info.clone();
+        let mut monitor = MemoryMonitor::new(image);
+
+        monitor.load_u8(0x8000);
+        let reads = monitor.pending_page_reads();
+        assert_eq!(reads.len(), info.num_layers() + 1);
+        let root_cycles = cycles_per_page((info.num_root_entries as usize + 1) / 2);
+        let read_cycles = info.num_layers() * cycles_per_page(BLOCKS_PER_PAGE) + root_cycles;
+        assert_eq!(monitor.pending_page_read_cycles(), read_cycles);
+        assert_eq!(monitor.total_pending_fault_cycles(), read_cycles);
+        assert_eq!(monitor.total_fault_cycles(), 0);
+
+        monitor.save_op(super::OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+        assert_eq!(monitor.total_fault_cycles(), read_cycles);
+        assert_eq!(monitor.total_page_read_cycles(), read_cycles);
+
+        // Pages already read in this segment cost nothing more.
+        monitor.load_u8(0x8008);
+        assert_eq!(monitor.pending_page_read_cycles(), 0);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let image = image(&[RET], &[]);
+        let mut monitor = MemoryMonitor::new(image);
+        let id = monitor.add_watchpoint(0x704..0x708, WatchKind::Read);
+        monitor.add_watchpoint(0x6fc..0x708, WatchKind::Write);
+
+        monitor.read_mem(0x6fc, MemAccessSize::Word);
+        assert_eq!(monitor.take_watch_hit(0x1000), None);
+        monitor.write_mem(0x6f8, MemAccessSize::DoubleWord, 5);
+        let hit = monitor.take_watch_hit(0x1004).unwrap();
+        assert_eq!(
+            (hit.pc, hit.addr, hit.size, hit.value),
+            (0x1004, 0x6f8, 8, 5)
+        );
+        assert!(hit.write);
+
+        monitor.read_mem(0x706, MemAccessSize::HalfWord);
+        assert!(!monitor.take_watch_hit(0x1008).unwrap().write);
+        assert!(monitor.remove_watchpoint(id));
+        assert!(!monitor.remove_watchpoint(id));
+        monitor.read_mem(0x706, MemAccessSize::HalfWord);
+        assert_eq!(monitor.take_watch_hit(0x100c), None);
+
+        // Host writes are watched too, a double word at a time.
+        monitor.store_region(0x6fe, &[1, 2, 3]);
+        let hit = monitor.take_watch_hit(0x1010).unwrap();
+        assert_eq!((hit.addr, hit.size, hit.value), (0x6fe, 2, 0x0201));
+    }
+
+    #[test]
+    fn memory_trace() {
+        let image = image(&[RET], &[]);
+        let mut monitor = MemoryMonitor::new(image);
+
+        // Accesses before recording starts are not traced.
+        monitor.read_mem(0x1000, MemAccessSize::Word);
+        monitor.save_op(super::OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+
+        monitor.record_trace();
+        monitor.write_mem(0x2000, MemAccessSize::HalfWord, 0xbeef);
+        monitor.read_mem(0x1000, MemAccessSize::Word);
+        // A host write spanning two double words.
+        monitor.store_region(0x3006, &[1, 2, 3]);
+        monitor.save_op(super::OpCodeResult::new(0x1008, None, 0, None));
+        monitor.commit();
+
+        let trace = monitor.take_trace().unwrap();
+        let accesses: Vec<MemAccess> = trace.iter().collect();
+        assert_eq!(
+            accesses,
+            [
+                MemAccess {
+                    cycle: 1,
+                    addr: 0x2000,
+                    size: 2,
+                    value: 0xbeef,
+                    write: true,
+                },
+                MemAccess {
+                    cycle: 1,
+                    addr: 0x1000,
+                    size: 4,
+                    value: 0x00008067,
+                    write: false,
+                },
+                MemAccess {
+                    cycle: 1,
+                    addr: 0x3006,
+                    size: 2,
+                    value: 0x0201,
+                    write: true,
+                },
+                MemAccess {
+                    cycle: 1,
+                    addr: 0x3008,
+                    size: 1,
+                    value: 0x03,
+                    write: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn load_region() {
+        let mut image = image(&[RET], &[]);
+        let data: Vec<u8> = (1..=40).collect();
+        let addr = 0x2000 - 13;
+        image.write_region(addr, &data).unwrap();
+        image.write_region(0x3000, b"hello, world\0").unwrap();
+        let mut monitor = MemoryMonitor::new(image);
+
+        assert_eq!(monitor.load_region(addr, 40).unwrap(), data);
+        assert_eq!(monitor.load_region(addr + 3, 5).unwrap(), data[3..8]);
+        assert_eq!(monitor.load_array::<3>(addr + 1).unwrap(), [2, 3, 4]);
+        let words = monitor.load_words(addr + 1, 2).unwrap();
+        assert_eq!(words, [0x05040302, 0x09080706]);
+        assert_eq!(monitor.load_string(0x3000).unwrap(), "hello, world");
+        assert_eq!(monitor.load_string(0x3007).unwrap(), "world");
+        assert_eq!(
+            monitor.load_u32(addr + 2),
+            Err(GuestFault::MisalignedLoad(addr + 2))
+        );
+        assert_eq!(
+            monitor.load_region(u64::MAX, 2),
+            Err(GuestFault::Load(u64::MAX))
+        );
+
+        // Both pages spanned by the region are paged in.
+        let pages = monitor.pending_page_reads();
+        assert!(pages.contains(&(0x1000 / PAGE_SIZE as u64 + 3)));
+        assert!(pages.contains(&(0x2000 / PAGE_SIZE as u64)));
+    }
+
+    #[test]
+    fn pending_writes() {
+        let image = image(&[RET], &[]);
+        let mut monitor = MemoryMonitor::new(image);
+
+        monitor.store_u64(0x2000, u64::MAX);
+        monitor.store_region(0x2006, &[1, 2, 3, 4]);
+        // The last store to a byte wins.
+        monitor.store_u8(0x2009, 5);
+        assert_eq!(
+            monitor.pending_writes()[5..],
+            [
+                (0x2005, 0xff),
+                (0x2006, 1),
+                (0x2007, 2),
+                (0x2008, 3),
+                (0x2009, 5)
+            ]
+        );
+        // Loads see memory as of the start of the instruction.
+        assert_eq!(monitor.load_u64(0x2000).unwrap(), 0);
+
+        monitor.save_op(super::OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+        assert!(monitor.pending_writes().is_empty());
+        assert_eq!(monitor.load_u64(0x2000).unwrap(), 0x0201_ffff_ffff_ffff);
+        assert_eq!(monitor.load_u64(0x2008).unwrap(), 0x0503);
+    }
+
+    #[test]
+    fn register_cache() {
+        let image = image(&[RET], &[]);
+        let mut monitor = MemoryMonitor::new(image);
+        let a0 = get_register_addr(REG_A0);
+
+        monitor.store_register(REG_A0, 5);
+        assert_eq!(monitor.load_register(REG_A0), 0);
+        monitor.save_op(super::OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+        assert_eq!(monitor.load_register(REG_A0), 5);
+        assert_eq!(monitor.load_u64(a0).unwrap(), 5);
+        assert_eq!(monitor.load_u8(a0 - 1).unwrap(), 0);
+
+        // SYSTEM memory is only updated once the registers are synced.
+        assert_eq!(monitor.image.read_u64(a0).unwrap(), 0);
+        monitor.sync_registers();
+        assert_eq!(monitor.image.read_u64(a0).unwrap(), 5);
+    }
+
+    #[test]
+    fn misaligned() {
+        let mut image = image(&[RET], &[]);
+        image.write_region(0x2000, &[1, 2, 3, 4, 5]).unwrap();
+        let mut monitor = MemoryMonitor::new(image);
+
+        assert_eq!(monitor.read_mem(0x2001, MemAccessSize::Word), Some(0));
+        assert_eq!(
+            monitor.take_fault(),
+            Some(GuestFault::MisalignedLoad(0x2001))
+        );
+        monitor.write_mem(0x2002, MemAccessSize::DoubleWord, 0);
+        assert_eq!(
+            monitor.take_fault(),
+            Some(GuestFault::MisalignedStore(0x2002))
+        );
+        assert_eq!(
+            monitor.check_fetch(0x1002),
+            Some(GuestFault::MisalignedFetch(0x1002))
+        );
+
+        monitor.emulate_misaligned = true;
+        let value = monitor.read_mem(0x2001, MemAccessSize::Word);
+        assert_eq!(value, Some(0x05040302));
+        monitor.write_mem(0x2003, MemAccessSize::HalfWord, 0xbeef);
+        assert_eq!(monitor.take_fault(), None);
+        assert_eq!(monitor.pending_writes(), [(0x2003, 0xef), (0x2004, 0xbe)]);
+    }
+
+    #[test]
+    fn stack_guard() {
+        let image = image(&[RET], &[]);
+        let mut monitor = MemoryMonitor::new(image);
+        let guard = STACK_GUARD.start() as u64;
+
+        monitor.write_mem(guard + 8, MemAccessSize::DoubleWord, 1);
+        assert_eq!(
+            monitor.take_fault(),
+            Some(GuestFault::StackOverflow(guard + 8))
+        );
+        assert!(monitor.pending_writes().is_empty());
+        assert_eq!(
+            monitor.read_mem(guard - 8, MemAccessSize::DoubleWord),
+            Some(0)
+        );
+        assert_eq!(monitor.take_fault(), None);
+        let end = STACK_GUARD.end() as u64;
+        monitor.read_mem(end - 4, MemAccessSize::Word);
+        assert_eq!(
+            monitor.take_fault(),
+            Some(GuestFault::StackOverflow(end - 4))
+        );
+        monitor.write_mem(end, MemAccessSize::Word, 1);
+        assert_eq!(monitor.take_fault(), None);
+
+        assert!(super::is_stack_guard(guard - 8, u64::MAX));
+        assert!(!super::is_stack_guard(u64::MAX - 3, 8));
+    }
+
+    #[test]
+    fn write_xor_execute() {
+        let segment = |vaddr, write, execute| ProgramSegment {
+            vaddr,
+            mem_size: 0x800,
+            file_size: 0x800,
+            align: PAGE_SIZE as u64,
+            flags: SegmentFlags {
+                read: true,
+                write,
+                execute,
+            },
+        };
+        let program = Program {
+            entry: 0x1000,
+            image: BTreeMap::from([(0x1000, 0x00008067)]),
+            // The data segment shares the last page of the text segment.
+            segments: vec![segment(0x1000, false, true), segment(0x1400, true, false)],
+        };
+        let image = MemoryImage::new(&program, PAGE_SIZE as u64, None);
+        let mut monitor = MemoryMonitor::new(image);
+
+        monitor.write_mem(0x1400, MemAccessSize::Word, 1);
+        assert_eq!(monitor.take_fault(), None);
+        assert!(monitor.page_permissions(0x1400).execute);
+
+        monitor.write_xor_execute = true;
+        monitor.write_mem(0x1400, MemAccessSize::Word, 1);
+        assert_eq!(monitor.take_fault(), Some(GuestFault::Store(0x1400)));
+        monitor.write_mem(0x1c00, MemAccessSize::Word, 1);
+        assert_eq!(monitor.take_fault(), None);
+        assert_eq!(monitor.check_fetch(0x1c00), Some(GuestFault::Fetch(0x1c00)));
+        assert_eq!(monitor.check_fetch(0x1000), None);
+    }
+}
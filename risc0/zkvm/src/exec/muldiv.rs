@@ -0,0 +1,357 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The RV64M integer multiplication and division extension.
+//!
+//! Division never traps: dividing by zero and the signed overflow of
+//! `MIN / -1` have the results the ISA defines for them. The `W` variants
+//! operate on the low 32 bits of their operands, and sign-extend the 32-bit
+//! result to 64 bits.
+//!
+//! A step circuit does not divide, or compute the high half of a product.
+//! It takes the [MulDivAdvice] of the instruction from the prover instead, and
+//! checks it with multiplications, additions and comparisons alone, as
+//! [MulDivAdvice::check] does.
+
+use anyhow::{bail, Result};
+
+use crate::opcode::{OPCODE_OP, OPCODE_OP_32};
+
+/// Compute the result of the M instruction `insn` for the operands `rs1` and
+/// `rs2`.
+pub(crate) fn execute(insn: u32, rs1: u64, rs2: u64) -> Result<u64> {
+    let opcode = insn & 0x7f;
+    let funct3 = (insn >> 12) & 0x7;
+    Ok(match opcode {
+        OPCODE_OP => match funct3 {
+            // MUL
+            0b000 => rs1.wrapping_mul(rs2),
+            // MULH
+            0b001 => ((rs1 as i64 as i128 * rs2 as i64 as i128) >> 64) as u64,
+            // MULHSU
+            0b010 => ((rs1 as i64 as i128 * rs2 as i128) >> 64) as u64,
+            // MULHU
+            0b011 => ((rs1 as u128 * rs2 as u128) >> 64) as u64,
+            // DIV
+            0b100 => match rs2 {
+                0 => u64::MAX,
+                _ => (rs1 as i64).wrapping_div(rs2 as i64) as u64,
+            },
+            // DIVU
+            0b101 => rs1.checked_div(rs2).unwrap_or(u64::MAX),
+            // REM
+            0b110 => match rs2 {
+                0 => rs1,
+                _ => (rs1 as i64).wrapping_rem(rs2 as i64) as u64,
+            },
+            // REMU
+            _ => rs1.checked_rem(rs2).unwrap_or(rs1),
+        },
+        OPCODE_OP_32 => {
+            let (rs1, rs2) = (rs1 as u32, rs2 as u32);
+            let result = match funct3 {
+                // MULW
+                0b000 => rs1.wrapping_mul(rs2),
+                // DIVW
+                0b100 => match rs2 {
+                    0 => u32::MAX,
+                    _ => (rs1 as i32).wrapping_div(rs2 as i32) as u32,
+                },
+                // DIVUW
+                0b101 => rs1.checked_div(rs2).unwrap_or(u32::MAX),
+                // REMW
+                0b110 => match rs2 {
+                    0 => rs1,
+                    _ => (rs1 as i32).wrapping_rem(rs2 as i32) as u32,
+                },
+                // REMUW
+                0b111 => rs1.checked_rem(rs2).unwrap_or(rs1),
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            };
+            result as i32 as i64 as u64
+        }
+        _ => bail!("Not an M instruction: {insn:#010x}"),
+    })
+}
+
+/// The values a step circuit takes as advice to check the result of an M
+/// instruction, rather than computing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MulDivAdvice {
+    /// The 128-bit product of the operands of a multiplication
+    Product {
+        /// The high 64 bits
+        high: u64,
+        /// The low 64 bits
+        low: u64,
+    },
+
+    /// The quotient and remainder of the operands of a division, in the
+    /// width of the instruction
+    Quotient {
+        /// The quotient, rounded towards zero
+        quotient: u64,
+        /// The remainder, with the sign of the dividend
+        remainder: u64,
+    },
+}
+
+impl MulDivAdvice {
+    /// Returns the advice for the M instruction `insn` with the operands
+    /// `rs1` and `rs2`, or `None` if `insn` is not one.
+    pub fn new(insn: u32, rs1: u64, rs2: u64) -> Option<Self> {
+        let form = Form::decode(insn)?;
+        let (a, b) = form.operands(rs1, rs2);
+        Some(match form.funct3 {
+            0b000..=0b011 => {
+                let product = a.wrapping_mul(b);
+                Self::Product {
+                    high: (product >> 64) as u64,
+                    low: product as u64,
+                }
+            }
+            _ => {
+                let (quotient, remainder) = match b {
+                    0 => (-1, a),
+                    _ => (a / b, a % b),
+                };
+                Self::Quotient {
+                    quotient: quotient as u64 & form.mask(),
+                    remainder: remainder as u64 & form.mask(),
+                }
+            }
+        })
+    }
+
+    /// Returns whether the advice is that of the M instruction `insn` with
+    /// the operands `rs1` and `rs2`, and `rd` is its result.
+    pub fn check(&self, insn: u32, rs1: u64, rs2: u64, rd: u64) -> bool {
+        let Some(form) = Form::decode(insn) else {
+            return false;
+        };
+        let (a, b) = form.operands(rs1, rs2);
+        let result = match (*self, form.funct3) {
+            (Self::Product { high, low }, 0b000..=0b011) => {
+                if ((high as u128) << 64 | low as u128) as i128 != a.wrapping_mul(b) {
+                    return false;
+                }
+                match form.funct3 {
+                    0b000 => low,
+                    _ => high,
+                }
+            }
+            (
+                Self::Quotient {
+                    quotient,
+                    remainder,
+                },
+                0b100..=0b111,
+            ) => {
+                if (quotient | remainder) & !form.mask() != 0 {
+                    return false;
+                }
+                let (q, r) = (form.extend(quotient), form.extend(remainder));
+                let holds = match b {
+                    0 => quotient == form.mask() && r == a,
+                    // The quotient of MIN / -1 overflows to MIN.
+                    -1 if a == form.min() => q == a && r == 0,
+                    _ => {
+                        q.checked_mul(b).and_then(|qb| qb.checked_add(r)) == Some(a)
+                            && r.abs() < b.abs()
+                            && (r == 0 || (r < 0) == (a < 0))
+                    }
+                };
+                if !holds {
+                    return false;
+                }
+                match form.funct3 {
+                    0b100 | 0b101 => quotient,
+                    _ => remainder,
+                }
+            }
+            _ => return false,
+        };
+        let result = match form.word {
+            true => result as i32 as i64 as u64,
+            false => result,
+        };
+        rd == result
+    }
+}
+
+/// How an M instruction treats its operands
+struct Form {
+    funct3: u32,
+
+    /// Whether the instruction operates on the low 32 bits of its operands
+    word: bool,
+}
+
+impl Form {
+    fn decode(insn: u32) -> Option<Self> {
+        let funct3 = (insn >> 12) & 0x7;
+        let word = match insn & 0x7f {
+            OPCODE_OP => false,
+            OPCODE_OP_32 if matches!(funct3, 0b000 | 0b100..=0b111) => true,
+            _ => return None,
+        };
+        (insn >> 25 == 0b0000001).then_some(Self { funct3, word })
+    }
+
+    /// The number of bits of the operands
+    fn bits(&self) -> u32 {
+        match self.word {
+            true => 32,
+            false => 64,
+        }
+    }
+
+    /// The mask of a value in the width of the operands
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+
+    /// Whether the operands are signed: both, except for MULHU, DIVU and
+    /// REMU, which treat both as unsigned, and MULHSU, which treats the second
+    /// as unsigned.
+    fn signed(&self) -> (bool, bool) {
+        match self.funct3 {
+            0b010 => (true, false),
+            0b011 | 0b101 | 0b111 => (false, false),
+            _ => (true, true),
+        }
+    }
+
+    /// The least value of a signed operand
+    fn min(&self) -> i128 {
+        -(1 << (self.bits() - 1))
+    }
+
+    /// Returns the operands as the instruction treats them.
+    fn operands(&self, rs1: u64, rs2: u64) -> (i128, i128) {
+        let (signed1, signed2) = self.signed();
+        (self.extend_as(rs1, signed1), self.extend_as(rs2, signed2))
+    }
+
+    /// Extend `value`, in the width of the operands, as the first operand is.
+    fn extend(&self, value: u64) -> i128 {
+        self.extend_as(value, self.signed().0)
+    }
+
+    fn extend_as(&self, value: u64, signed: bool) -> i128 {
+        let shift = 128 - self.bits();
+        match signed {
+            true => (value as i128) << shift >> shift,
+            false => ((value as u128) << shift >> shift) as i128,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute, MulDivAdvice};
+
+    // Encode the M instruction `funct3` with x10 = x11 op x12.
+    fn insn(opcode: u32, funct3: u32) -> u32 {
+        0b0000001 << 25 | 12 << 20 | 11 << 15 | funct3 << 12 | 10 << 7 | opcode
+    }
+
+    fn op(funct3: u32, rs1: i64, rs2: i64) -> i64 {
+        execute(insn(0b0110011, funct3), rs1 as u64, rs2 as u64).unwrap() as i64
+    }
+
+    fn op_32(funct3: u32, rs1: i64, rs2: i64) -> i64 {
+        execute(insn(0b0111011, funct3), rs1 as u64, rs2 as u64).unwrap() as i64
+    }
+
+    #[test]
+    fn multiply() {
+        assert_eq!(op(0b000, -3, 7), -21);
+        assert_eq!(op(0b001, i64::MIN, i64::MIN), 1 << 62);
+        assert_eq!(op(0b001, -1, 1), -1);
+        assert_eq!(op(0b010, -1, -1), -1);
+        assert_eq!(op(0b010, 2, -1), 1);
+        assert_eq!(op(0b011, -1, -1), -2);
+        assert_eq!(op_32(0b000, 0x7fff_ffff, 2), -2);
+        assert_eq!(op_32(0b000, 1 << 32 | 3, 5), 15);
+    }
+
+    #[test]
+    fn divide() {
+        assert_eq!(op(0b100, -7, 2), -3);
+        assert_eq!(op(0b100, 7, 0), -1);
+        assert_eq!(op(0b100, i64::MIN, -1), i64::MIN);
+        assert_eq!(op(0b101, -1, 2), i64::MAX);
+        assert_eq!(op(0b101, 7, 0), -1);
+        assert_eq!(op(0b110, -7, 2), -1);
+        assert_eq!(op(0b110, 7, 0), 7);
+        assert_eq!(op(0b110, i64::MIN, -1), 0);
+        assert_eq!(op(0b111, 7, 0), 7);
+        assert_eq!(op(0b111, -1, 16), 15);
+
+        assert_eq!(op_32(0b100, -7, 2), -3);
+        assert_eq!(op_32(0b100, i32::MIN as i64, -1), i32::MIN as i64);
+        assert_eq!(op_32(0b100, 7, 0), -1);
+        assert_eq!(op_32(0b101, 0xffff_fffe, 2), 0x7fff_ffff);
+        assert_eq!(op_32(0b101, 7, 0), -1);
+        assert_eq!(op_32(0b110, -7, 2), -1);
+        assert_eq!(op_32(0b110, 1 << 32 | 7, 0), 7);
+        assert_eq!(op_32(0b111, 0xffff_ffff, 0), -1);
+        assert_eq!(op_32(0b111, 0xffff_ffff, 16), 15);
+    }
+
+    #[test]
+    fn advice() {
+        let cases = [
+            (0b0110011, 0b000, -3, 7),
+            (0b0110011, 0b001, i64::MIN, i64::MIN),
+            (0b0110011, 0b010, -1, -1),
+            (0b0110011, 0b011, -1, -1),
+            (0b0110011, 0b100, -7, 2),
+            (0b0110011, 0b100, 7, 0),
+            (0b0110011, 0b100, i64::MIN, -1),
+            (0b0110011, 0b101, -1, 2),
+            (0b0110011, 0b110, -7, 2),
+            (0b0110011, 0b110, i64::MIN, -1),
+            (0b0110011, 0b111, -1, 16),
+            (0b0111011, 0b000, 0x7fff_ffff, 2),
+            (0b0111011, 0b100, i32::MIN as i64, -1),
+            (0b0111011, 0b101, 0xffff_fffe, 2),
+            (0b0111011, 0b110, 1 << 32 | 7, 0),
+            (0b0111011, 0b111, 0xffff_ffff, 16),
+        ];
+        for (opcode, funct3, rs1, rs2) in cases {
+            let (insn, rs1, rs2) = (insn(opcode, funct3), rs1 as u64, rs2 as u64);
+            let rd = execute(insn, rs1, rs2).unwrap();
+            let advice = MulDivAdvice::new(insn, rs1, rs2).unwrap();
+            assert!(advice.check(insn, rs1, rs2, rd), "{advice:?}");
+            assert!(!advice.check(insn, rs1, rs2, rd ^ 1));
+        }
+
+        // 10 / 2 has only one quotient and remainder, though 5 + 2^63 is also
+        // congruent to 10 modulo 2^64 when multiplied by 2.
+        let div = insn(0b0110011, 0b101);
+        let wrong = MulDivAdvice::Quotient {
+            quotient: 5 + (1 << 63),
+            remainder: 0,
+        };
+        assert!(!wrong.check(div, 10, 2, 5 + (1 << 63)));
+        let wrong = MulDivAdvice::Quotient {
+            quotient: 4,
+            remainder: 2,
+        };
+        assert!(!wrong.check(div, 10, 2, 4));
+        assert_eq!(MulDivAdvice::new(0x00b50533, 1, 2), None);
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The pairing accelerators.
+//!
+//! The guest adds and multiplies points of G1 and G2, and checks products of
+//! pairings, with one host call per curve, which charges a fixed number of
+//! cycles per operation instead of the millions emulating it would take. The
+//! curves share the operations, and differ in how points and scalars are
+//! encoded.
+
+use anyhow::{bail, Result};
+use risc0_zkvm_platform::syscall::bls12_381::{G1_ADD, G1_MUL, G2_ADD, G2_MUL, PAIRING_CHECK};
+
+/// The most pairs a pairing check may take.
+pub(crate) const MAX_PAIRS: u64 = 64;
+
+/// An operation of the accelerator, selected by a0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    /// Add the G1 points at a2 and a3 into a1.
+    G1Add,
+    /// Multiply the G1 point at a2 by the scalar at a3 into a1.
+    G1Mul,
+    /// Add the G2 points at a2 and a3 into a1.
+    G2Add,
+    /// Multiply the G2 point at a2 by the scalar at a3 into a1.
+    G2Mul,
+    /// Check that the product of the pairings of the a2 pairs of a G1 and a
+    /// G2 point at a1 is the identity.
+    PairingCheck,
+}
+
+impl Op {
+    pub(crate) fn decode(op: u64) -> Result<Self> {
+        Ok(match op {
+            G1_ADD => Op::G1Add,
+            G1_MUL => Op::G1Mul,
+            G2_ADD => Op::G2Add,
+            G2_MUL => Op::G2Mul,
+            PAIRING_CHECK => Op::PairingCheck,
+            _ => bail!("Unknown pairing operation: {op}"),
+        })
+    }
+
+    /// The cycles charged for the operation, or per pair for a pairing
+    /// check.
+    pub(crate) fn cycles(self) -> usize {
+        match self {
+            Op::G1Add => 64,
+            Op::G2Add => 128,
+            Op::G1Mul => 4 * 1024,
+            Op::G2Mul => 8 * 1024,
+            Op::PairingCheck => 32 * 1024,
+        }
+    }
+}
+
+/// A pairing-friendly curve, with its encoding of points and scalars
+pub(crate) trait PairingCurve {
+    /// The length of an encoded G1 point
+    const G1_LEN: u64;
+    /// The length of an encoded G2 point
+    const G2_LEN: u64;
+    /// The length of an encoded scalar
+    const SCALAR_LEN: u64;
+
+    /// Returns the sum of the G1 points `lhs` and `rhs`.
+    fn g1_add(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>>;
+
+    /// Returns the G1 point `point` multiplied by `scalar`.
+    fn g1_mul(point: &[u8], scalar: &[u8]) -> Result<Vec<u8>>;
+
+    /// Returns the sum of the G2 points `lhs` and `rhs`.
+    fn g2_add(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>>;
+
+    /// Returns the G2 point `point` multiplied by `scalar`.
+    fn g2_mul(point: &[u8], scalar: &[u8]) -> Result<Vec<u8>>;
+
+    /// Returns whether the product of the pairings of `pairs`, each a G1
+    /// point followed by a G2 point, is the identity.
+    fn pairing_check(pairs: &[u8]) -> Result<bool>;
+}
@@ -0,0 +1,241 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reporting guest panics.
+//!
+//! The guest reports a panic with the [ecall::PANIC] host call, passing the
+//! message and the source location it panicked at. The host adds a backtrace,
+//! unwound from a shadow stack of the return addresses of the calls the guest
+//! has made, and resolved through the symbols and DWARF info of its ELF.
+//!
+//! [ecall::PANIC]: risc0_zkvm_platform::syscall::ecall::PANIC
+
+use std::fmt;
+
+use elf::{abi::STT_FUNC, endian::LittleEndian, ElfBytes};
+
+use crate::opcode::{OPCODE_JAL, OPCODE_JALR};
+
+const REG_RA: u32 = 1;
+const REG_T0: u32 = 5;
+
+/// The deepest call stack tracked; older frames are dropped.
+const MAX_DEPTH: usize = 4096;
+
+/// A panic reported by the guest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuestPanic {
+    /// The panic message
+    pub message: String,
+
+    /// The source file the guest panicked in, if it reported one
+    pub file: Option<String>,
+
+    /// The line the guest panicked at, or 0 if unknown
+    pub line: u32,
+
+    /// The frames of the guest call stack, innermost first
+    pub backtrace: Vec<BacktraceFrame>,
+}
+
+/// A frame of a guest backtrace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// The program counter: the panicking instruction for the innermost frame,
+    /// and the call instruction for the others
+    pub pc: u64,
+
+    /// The function containing `pc`, if the guest ELF has symbols
+    pub function: Option<String>,
+
+    /// The source file and line of `pc`, if the guest ELF has debug info
+    pub location: Option<(String, u32)>,
+}
+
+impl fmt::Display for GuestPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "guest panicked at {file}:{}: ", self.line)?,
+            None => write!(f, "guest panicked: ")?,
+        }
+        write!(f, "{}", self.message)?;
+        if !self.backtrace.is_empty() {
+            write!(f, "\nbacktrace:")?;
+        }
+        for (idx, frame) in self.backtrace.iter().enumerate() {
+            let function = frame.function.as_deref().unwrap_or("<unknown>");
+            write!(f, "\n{idx:4}: 0x{:08x} - {function}", frame.pc)?;
+            if let Some((file, line)) = &frame.location {
+                write!(f, "\n          at {file}:{line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GuestPanic {}
+
+/// How an instruction moves through the guest call stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CallKind {
+    /// A call, which returns to the instruction after it
+    Call,
+    /// A return
+    Return,
+    /// A return straight into another call, as a coroutine switch does
+    ReturnCall,
+}
+
+/// Returns how `insn` moves through the call stack, if it does.
+///
+/// Calls and returns are recognized from the RISC-V calling convention hints:
+/// a `jal`/`jalr` linking through `ra` or `t0` is a call, and a `jalr` through
+/// either of them that does not link is a return. A `jalr` that links through
+/// one of them and jumps through the other is both.
+pub(crate) fn call_kind(insn: u32) -> Option<CallKind> {
+    let opcode = insn & 0x7f;
+    let rd = (insn >> 7) & 0x1f;
+    let rs1 = (insn >> 15) & 0x1f;
+    let is_link = |reg| reg == REG_RA || reg == REG_T0;
+    match opcode {
+        OPCODE_JAL if is_link(rd) => Some(CallKind::Call),
+        OPCODE_JALR if is_link(rd) && is_link(rs1) && rs1 != rd => Some(CallKind::ReturnCall),
+        OPCODE_JALR if is_link(rd) => Some(CallKind::Call),
+        OPCODE_JALR if is_link(rs1) => Some(CallKind::Return),
+        _ => None,
+    }
+}
+
+/// The return addresses of the calls the guest is in, as told by [call_kind].
+#[derive(Default)]
+pub(crate) struct CallStack {
+    return_addrs: Vec<u64>,
+}
+
+impl CallStack {
+    /// Track the execution of `insn` at `pc`.
+    pub(crate) fn record(&mut self, pc: u64, insn: u32) {
+        let Some(kind) = call_kind(insn) else {
+            return;
+        };
+        if matches!(kind, CallKind::Return | CallKind::ReturnCall) {
+            self.return_addrs.pop();
+        }
+        if matches!(kind, CallKind::Call | CallKind::ReturnCall) {
+            if self.return_addrs.len() == MAX_DEPTH {
+                self.return_addrs.remove(0);
+            }
+            self.return_addrs.push(pc + 4);
+        }
+    }
+
+    /// Returns the program counters of the call stack when executing `pc`,
+    /// innermost first.
+    pub(crate) fn unwind(&self, pc: u64) -> Vec<u64> {
+        let calls = self.return_addrs.iter().rev().map(|addr| addr - 4);
+        std::iter::once(pc).chain(calls).collect()
+    }
+}
+
+/// Resolve `pcs` to frames through the symbols and, with the `profiler`
+/// feature, the DWARF info of `elf`.
+pub(crate) fn symbolize(elf: Option<&[u8]>, pcs: &[u64]) -> Vec<BacktraceFrame> {
+    let functions = elf.map(functions).unwrap_or_default();
+    #[cfg(feature = "profiler")]
+    let locations = elf.and_then(|elf| Locations::new(elf));
+    pcs.iter()
+        .map(|&pc| BacktraceFrame {
+            pc,
+            function: functions
+                .iter()
+                .find(|(start, size, _)| pc >= *start && pc - start < (*size).max(1))
+                .map(|(_, _, name)| name.clone()),
+            #[cfg(feature = "profiler")]
+            location: locations.as_ref().and_then(|locations| locations.find(pc)),
+            #[cfg(not(feature = "profiler"))]
+            location: None,
+        })
+        .collect()
+}
+
+/// Returns the start, size and name of every function symbol in `elf`.
+fn functions(elf: &[u8]) -> Vec<(u64, u64, String)> {
+    let Ok(elf) = ElfBytes::<LittleEndian>::minimal_parse(elf) else {
+        return Vec::new();
+    };
+    let Ok(Some((symtab, strtab))) = elf.symbol_table() else {
+        return Vec::new();
+    };
+    symtab
+        .iter()
+        .filter(|symbol| symbol.st_symtype() == STT_FUNC)
+        .filter_map(|symbol| {
+            let name = strtab.get(symbol.st_name as usize).ok()?;
+            Some((symbol.st_value, symbol.st_size, name.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(feature = "profiler")]
+struct Locations(addr2line::Context<gimli::EndianRcSlice<gimli::RunTimeEndian>>);
+
+#[cfg(feature = "profiler")]
+impl Locations {
+    fn new(elf: &[u8]) -> Option<Self> {
+        let file = addr2line::object::read::File::parse(elf).ok()?;
+        addr2line::Context::new(&file).ok().map(Self)
+    }
+
+    fn find(&self, pc: u64) -> Option<(String, u32)> {
+        let location = self.0.find_location(pc).ok()??;
+        Some((location.file?.to_string(), location.line.unwrap_or(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{call_kind, CallKind, CallStack};
+
+    #[test]
+    fn call_stack() {
+        let mut stack = CallStack::default();
+        // jal ra, 0x100
+        stack.record(0x1000, 0x100000ef);
+        // jalr t0, 0(a5)
+        stack.record(0x1100, 0x000782e7);
+        assert_eq!(stack.unwind(0x2000), [0x2000, 0x1100, 0x1000]);
+        // jalr zero, 0(t0)
+        stack.record(0x2004, 0x00028067);
+        // j 0x10, which neither calls nor returns
+        stack.record(0x1104, 0x0100006f);
+        assert_eq!(stack.unwind(0x1114), [0x1114, 0x1000]);
+        // ret
+        stack.record(0x1118, 0x00008067);
+        assert_eq!(stack.unwind(0x1004), [0x1004]);
+    }
+
+    #[test]
+    fn call_kinds() {
+        // jal ra, 0x100
+        assert_eq!(call_kind(0x100000ef), Some(CallKind::Call));
+        // ret
+        assert_eq!(call_kind(0x00008067), Some(CallKind::Return));
+        // jalr ra, 0(t0)
+        assert_eq!(call_kind(0x000280e7), Some(CallKind::ReturnCall));
+        // jalr ra, 0(ra)
+        assert_eq!(call_kind(0x000080e7), Some(CallKind::Call));
+        // jalr zero, 0(a5)
+        assert_eq!(call_kind(0x00078067), None);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The Poseidon accelerator.
+//!
+//! The `poseidon` host call hashes elements of the Pallas base field with the
+//! 2-to-1 Poseidon instance of
+//! [PoseidonPageHasher](crate::binfmt::hash::PoseidonPageHasher), which a
+//! folding backend over the Pasta cycle proves for a fraction of the
+//! cost of SHA-256. Longer inputs are chained: each element is hashed with the
+//! hash of those before it, so that two elements hash as a Merkle tree node.
+//! Elements are 32 little-endian bytes, and must be canonical.
+
+use anyhow::{bail, ensure, Result};
+use generic_array::typenum::U2;
+use neptune::{poseidon::PoseidonConstants, Poseidon};
+use pasta_curves::{group::ff::PrimeField, Fp};
+
+use super::PoseidonHash;
+
+/// The length of a field element
+pub(crate) const ELEMENT_LEN: u64 = 32;
+
+/// The cycles charged for each 2-to-1 hash.
+pub(crate) const POSEIDON_CYCLES: usize = 16;
+
+/// The round constants of the hash, which are costly to generate, so that
+/// they are only generated once a guest uses it.
+pub(crate) struct Hasher {
+    constants: PoseidonConstants<Fp, U2>,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self {
+            constants: PoseidonConstants::new(),
+        }
+    }
+}
+
+fn element(bytes: &[u8]) -> Result<Fp> {
+    match Option::from(Fp::from_repr(bytes.try_into()?)) {
+        Some(elem) => Ok(elem),
+        None => bail!("Field element is not canonical"),
+    }
+}
+
+impl Hasher {
+    /// Returns the 2-to-1 hashes of the chained hash of `elements`, of which
+    /// there are at least two, the last of which is the result.
+    pub(crate) fn hash(&self, elements: &[u8]) -> Result<Vec<PoseidonHash>> {
+        ensure!(
+            elements.len() as u64 >= 2 * ELEMENT_LEN,
+            "Poseidon hash of fewer than two elements"
+        );
+        let mut elements = elements.chunks(ELEMENT_LEN as usize);
+        let mut acc = elements.next().unwrap().to_vec();
+        let mut hashes = Vec::with_capacity(elements.len());
+        for elem in elements {
+            let preimage = [element(&acc)?, element(elem)?];
+            let out = Poseidon::new_with_preimage(&preimage, &self.constants).hash();
+            let out = out.to_repr().to_vec();
+            hashes.push(PoseidonHash {
+                left: acc,
+                right: elem.to_vec(),
+                out: out.clone(),
+            });
+            acc = out;
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pasta_curves::{group::ff::PrimeField, Fp};
+
+    use super::Hasher;
+
+    fn bytes(value: u64) -> [u8; 32] {
+        Fp::from(value).to_repr()
+    }
+
+    #[test]
+    fn chained_hash() {
+        let hasher = Hasher::default();
+        let hash = |elements: &[u8]| {
+            hasher
+                .hash(elements)
+                .map(|hashes| hashes.last().unwrap().out.clone())
+        };
+        let node = hash(&[bytes(1), bytes(2)].concat()).unwrap();
+        assert_ne!(node, hash(&[bytes(2), bytes(1)].concat()).unwrap());
+        let chained = hasher
+            .hash(&[bytes(1), bytes(2), bytes(3)].concat())
+            .unwrap();
+        assert_eq!(chained.len(), 2);
+        assert_eq!(chained[0].out, node);
+        assert_eq!(chained[1].left, node);
+        let expected = hash(&[&node[..], &bytes(3)].concat());
+        assert_eq!(chained[1].out, expected.unwrap());
+
+        assert!(hash(&bytes(1)).is_err());
+        assert!(hash(&[[0xff; 32], bytes(1)].concat()).is_err());
+    }
+}
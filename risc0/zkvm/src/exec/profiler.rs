@@ -14,30 +14,27 @@
 
 //! Support for profiling the guest.
 //!
-//! This counts cycles spent at each location when executing the
-//! guest.  This is a preliminary experimental implementation.  It
-//! does not trace full stack traces, but only provides the top level
-//! stack frame.  (More than one stack frame may show up in the case
-//! of inlined functions).
-
-// TODO:
-//
-//   * Count the full stack instead of the top frame; the "gimli"
-//   crate's UnwindSection and Evaluation should help us do this
-//
-//  * Demangle symbols
+//! This counts cycles spent at each location when executing the guest, along
+//! with the call stack leading to it. The stack is tracked from the calls and
+//! returns the guest makes, as recognized by [call_kind].
+//!
+//! Counts can be exported in the collapsed-stack format read by flamegraph
+//! tools (`inferno-flamegraph`, `flamegraph.pl`, speedscope), or as a pprof
+//! protobuf.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Write as _, fs, path::Path};
 
 use addr2line::{
+    demangle_auto,
+    fallible_iterator::FallibleIterator,
     object::{read::File, Object, ObjectSegment},
     Context,
 };
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use gimli::{EndianRcSlice, RunTimeEndian};
 use prost::Message;
 
-use super::TraceEvent;
+use super::panic::{call_kind, CallKind};
 
 mod proto {
     // Generated proto interface.
@@ -46,16 +43,25 @@ mod proto {
 
 /// Manages profiling state
 pub struct Profiler {
-    // Current program counter
-    pc: u32,
+    // Cycles executed so far
+    cycle: u64,
+
+    // Only every `sample_period`th cycle is counted
+    sample_period: u64,
+
+    // Interned call stacks: the parent stack and the entry of the function
+    // called from it
+    stacks: Vec<(usize, u64)>,
 
-    // Cycle count when the last instruction started
-    cycle: u32,
+    stack_ids: HashMap<(usize, u64), usize>,
 
-    // Counts per program counter
-    counts: HashMap<u32, usize>,
+    // The current call stack, as interned stack ids
+    stack: Vec<usize>,
 
-    ctx: Context<EndianRcSlice<RunTimeEndian>>,
+    // Counts per call stack and program counter
+    counts: HashMap<(usize, u64), u64>,
+
+    symbols: Symbols,
 
     profile: ProfileBuilder,
 }
@@ -72,108 +78,283 @@ pub struct Frame {
     pub filename: String,
 }
 
-fn decode_frame(fr: addr2line::Frame<EndianRcSlice<RunTimeEndian>>) -> Option<Frame> {
-    Some(Frame {
-        name: fr.function.as_ref()?.raw_name().ok()?.to_string(),
-        lineno: fr.location.as_ref()?.line? as i64,
-        filename: fr.location.as_ref()?.file?.to_string(),
-    })
+/// Resolves guest addresses to source locations.
+struct Symbols {
+    ctx: Option<Context<EndianRcSlice<RunTimeEndian>>>,
+
+    // Function symbols, sorted by address
+    functions: Vec<(u64, String)>,
+
+    cache: HashMap<u64, Vec<Frame>>,
 }
 
-fn lookup_pc(pc: u32, ctx: &Context<EndianRcSlice<RunTimeEndian>>) -> Vec<Frame> {
-    use addr2line::fallible_iterator::FallibleIterator;
-    match ctx.find_frames(pc as u64) {
-        Ok(frames) => frames
-            .filter_map(|fr| Ok(decode_frame(fr)))
-            .collect::<Vec<Frame>>()
-            .unwrap(),
-        Err(err) => {
-            eprintln!("Error finding frames!  {:?}", err);
-            [].into()
+impl Symbols {
+    fn empty() -> Self {
+        Self {
+            ctx: None,
+            functions: Vec::new(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Returns the frames at `pc`, in the function starting at `entry`,
+    /// outermost first. Inlined functions have a frame each; addresses without
+    /// debug info resolve through the symbol table, or to `entry` itself.
+    fn lookup(&mut self, pc: u64, entry: u64) -> &[Frame] {
+        if !self.cache.contains_key(&pc) {
+            let mut frames = self.find_frames(pc);
+            if frames.is_empty() {
+                frames.push(Frame {
+                    name: self.function_name(pc, entry),
+                    lineno: 0,
+                    filename: String::new(),
+                });
+            }
+            frames.reverse();
+            self.cache.insert(pc, frames);
+        }
+        &self.cache[&pc]
+    }
+
+    fn find_frames(&self, pc: u64) -> Vec<Frame> {
+        let Some(ctx) = self.ctx.as_ref() else {
+            return Vec::new();
+        };
+        match ctx.find_frames(pc) {
+            Ok(frames) => frames
+                .filter_map(|fr| Ok(decode_frame(fr)))
+                .collect()
+                .unwrap_or_default(),
+            Err(err) => {
+                tracing::debug!("no frames for pc 0x{pc:08x}: {err:?}");
+                Vec::new()
+            }
+        }
+    }
+
+    fn function_name(&self, pc: u64, entry: u64) -> String {
+        let idx = self.functions.partition_point(|(addr, _)| *addr <= pc);
+        match idx.checked_sub(1).map(|idx| &self.functions[idx]) {
+            Some((_, name)) => name.clone(),
+            None => format!("0x{entry:08x}"),
         }
     }
 }
 
+fn decode_frame(fr: addr2line::Frame<EndianRcSlice<RunTimeEndian>>) -> Option<Frame> {
+    Some(Frame {
+        name: fr.function.as_ref()?.demangle().ok()?.to_string(),
+        lineno: fr.location.as_ref().and_then(|loc| loc.line).unwrap_or(0) as i64,
+        filename: fr
+            .location
+            .as_ref()
+            .and_then(|loc| loc.file)
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
 impl Profiler {
-    /// Return a new profile from the given RISCV ELF.
-    pub fn new(filename: &str, elf_data: &[u8]) -> Result<Self> {
-        let file = File::parse(elf_data)?;
-        let ctx = Context::new(&file)?;
-        let mut profiler = Profiler {
-            pc: u32::MAX,
+    /// Return a new profiler for a guest without symbols, starting at `pc`.
+    ///
+    /// Locations are reported by address.
+    pub fn new(pc: u64) -> Self {
+        Self {
             cycle: 0,
+            sample_period: 1,
+            stacks: vec![(0, pc)],
+            stack_ids: HashMap::new(),
+            stack: vec![0],
             counts: HashMap::new(),
-            ctx,
+            symbols: Symbols::empty(),
             profile: ProfileBuilder::new(),
+        }
+    }
+
+    /// Return a new profiler for the given RISC-V ELF, starting at its entry
+    /// point.
+    pub fn from_elf(filename: &str, elf_data: &[u8]) -> Result<Self> {
+        let file = File::parse(elf_data)?;
+        let mut profiler = Self::new(file.entry());
+        let mut functions: Vec<(u64, String)> = file
+            .symbol_map()
+            .symbols()
+            .iter()
+            .map(|symbol| {
+                let name = demangle_auto(symbol.name().into(), None);
+                (symbol.address(), name.into_owned())
+            })
+            .collect();
+        functions.sort();
+        profiler.symbols = Symbols {
+            ctx: Context::new(&file).ok(),
+            functions,
+            cache: HashMap::new(),
         };
 
         // Save the main binary name
         let bin_name = profiler.profile.get_string(filename);
-        for segment in file.segments() {
-            if segment.address() == risc0_zkvm_platform::memory::TEXT.start() as u64 {
-                profiler.profile.profile.mapping.push(proto::Mapping {
-                    id: 1,
-                    memory_start: segment.address(),
-                    memory_limit: segment.address() + segment.size(),
-                    file_offset: segment.file_range().0,
-                    filename: bin_name,
-                    ..Default::default()
-                });
-            }
+        let text = file
+            .segments()
+            .find(|segment| {
+                (segment.address()..segment.address() + segment.size()).contains(&file.entry())
+            })
+            .map(|segment| (segment.address(), segment.size(), segment.file_range().0));
+        if let Some((address, size, file_offset)) = text {
+            profiler.profile.profile.mapping.push(proto::Mapping {
+                id: 1,
+                memory_start: address,
+                memory_limit: address + size,
+                file_offset,
+                filename: bin_name,
+                ..Default::default()
+            });
         }
 
         Ok(profiler)
     }
 
-    /// Dereferences strings, etc. in the protobuf for testing purposes.
-    /// Returns a tuple of (frames, program counter, cycles)
-    pub fn iter(&self) -> impl Iterator<Item = (Vec<Frame>, usize, usize)> + '_ {
-        self.profile.iter()
+    /// Count only every `period`th cycle, weighting each sample by `period`.
+    pub fn sample_period(mut self, period: u64) -> Self {
+        self.sample_period = period.max(1);
+        self
     }
 
-    /// Returns a callback to populate this profiler, suitable for
-    /// passing to ProverOpts::with_trace_callback.
-    pub fn make_trace_callback<'a>(
-        &'a mut self,
-    ) -> impl FnMut(TraceEvent) -> anyhow::Result<()> + 'a {
-        |event| {
-            match event {
-                TraceEvent::InstructionStart { cycle, pc } => {
-                    // Count against the last program counter.
-                    let cycles = cycle - self.cycle;
-                    let orig_pc = self.pc;
-                    *self.counts.entry(orig_pc).or_insert(0) += cycles as usize;
-                    self.pc = pc;
-                    self.cycle = cycle;
-                }
-                _ => (),
+    /// Record the execution of `insn` at `pc`, which took `cycles` cycles and
+    /// continues at `next_pc`.
+    pub fn record(&mut self, pc: u64, insn: u32, next_pc: u64, cycles: u64) {
+        let stack = *self.stack.last().unwrap();
+        let samples = (self.cycle + cycles) / self.sample_period - self.cycle / self.sample_period;
+        if samples > 0 {
+            *self.counts.entry((stack, pc)).or_default() += samples * self.sample_period;
+        }
+        self.cycle += cycles;
+
+        match call_kind(insn) {
+            Some(CallKind::Call) => self.call(next_pc),
+            Some(CallKind::Return) => self.ret(),
+            Some(CallKind::ReturnCall) => {
+                self.ret();
+                self.call(next_pc);
             }
-            Ok(())
+            None => {}
         }
     }
 
+    fn call(&mut self, entry: u64) {
+        let parent = *self.stack.last().unwrap();
+        let next_id = self.stacks.len();
+        let id = *self.stack_ids.entry((parent, entry)).or_insert(next_id);
+        if id == next_id {
+            self.stacks.push((parent, entry));
+        }
+        self.stack.push(id);
+    }
+
+    fn ret(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    /// Returns the total number of cycles recorded.
+    pub fn total_cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Returns the entries of the functions on the interned stack `id`,
+    /// outermost first.
+    fn entries(&self, mut id: usize) -> Vec<u64> {
+        let mut entries = vec![self.stacks[id].1];
+        while id != 0 {
+            id = self.stacks[id].0;
+            entries.push(self.stacks[id].1);
+        }
+        entries.reverse();
+        entries
+    }
+
+    /// Returns the counts in the collapsed-stack format: one line per distinct
+    /// stack, with its frames from the outermost in, separated by `;`, and the
+    /// number of cycles spent in it.
+    pub fn collapsed(&mut self) -> String {
+        let mut lines: HashMap<String, u64> = HashMap::new();
+        let counts: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(key, count)| (*key, *count))
+            .collect();
+        for ((stack, pc), count) in counts {
+            let mut entries = self.entries(stack);
+            let entry = entries.pop().unwrap();
+            let mut names: Vec<String> = entries
+                .into_iter()
+                .map(|entry| self.symbols.lookup(entry, entry)[0].name.clone())
+                .collect();
+            names.extend(
+                self.symbols
+                    .lookup(pc, entry)
+                    .iter()
+                    .map(|frame| frame.name.clone()),
+            );
+            *lines.entry(names.join(";")).or_default() += count;
+        }
+        let mut lines: Vec<_> = lines.into_iter().collect();
+        lines.sort();
+        let mut out = String::new();
+        for (stack, count) in lines {
+            writeln!(out, "{stack} {count}").unwrap();
+        }
+        out
+    }
+
+    /// Write the counts in collapsed-stack format to the file at `path`.
+    pub fn write_collapsed(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.collapsed()).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Dereferences strings, etc. in the protobuf for testing purposes.
+    /// Returns a tuple of (frames, program counter, cycles)
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<Frame>, usize, usize)> + '_ {
+        self.profile.iter()
+    }
+
     /// Count and save the profiling samples
     pub fn finalize(&mut self) {
         if !self.profile.profile.sample.is_empty() {
             return;
         }
 
-        for (pc, count) in self.counts.iter() {
-            let frames = lookup_pc(*pc, &self.ctx);
-            let loc = proto::Location {
-                address: *pc as u64,
-                line: frames
-                    .into_iter()
-                    .map(|fr| proto::Line {
-                        function_id: self.profile.get_function(&fr.name, &fr.filename),
-                        line: fr.lineno,
-                    })
-                    .collect(),
-                ..Default::default()
-            };
+        let counts: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(key, count)| (*key, *count))
+            .collect();
+        for ((stack, pc), count) in counts {
+            // Locations are listed innermost first.
+            let mut entries = self.entries(stack);
+            let entry = entries.pop().unwrap();
+            let mut addresses = vec![(pc, entry)];
+            addresses.extend(entries.into_iter().rev().map(|entry| (entry, entry)));
+            let location_id = addresses
+                .into_iter()
+                .map(|(address, entry)| {
+                    let frames = self.symbols.lookup(address, entry);
+                    let line = frames
+                        .iter()
+                        .rev()
+                        .map(|fr| proto::Line {
+                            function_id: self.profile.get_function(&fr.name, &fr.filename),
+                            line: fr.lineno,
+                        })
+                        .collect();
+                    self.profile.get_location(address, line)
+                })
+                .collect();
             let sample = proto::Sample {
-                location_id: vec![self.profile.get_location(loc)],
-                value: vec![*count as i64],
+                location_id,
+                value: vec![count as i64],
                 ..Default::default()
             };
             self.profile.add_sample(sample);
@@ -201,6 +382,8 @@ struct ProfileBuilder {
 
     functions: HashMap<(String, String), u64>,
 
+    locations: HashMap<u64, u64>,
+
     profile: proto::Profile,
 }
 
@@ -209,6 +392,7 @@ impl ProfileBuilder {
         let mut builder = Self {
             strings: HashMap::new(),
             functions: HashMap::new(),
+            locations: HashMap::new(),
             profile: Default::default(),
         };
 
@@ -226,13 +410,24 @@ impl ProfileBuilder {
         builder
     }
 
-    fn get_location(&mut self, mut loc: proto::Location) -> u64 {
-        let id = self.profile.location.len() as u64 + 1;
-        loc.id = id;
-        if !self.profile.mapping.is_empty() {
-            loc.mapping_id = 1;
+    fn get_location(&mut self, address: u64, line: Vec<proto::Line>) -> u64 {
+        if let Some(&id) = self.locations.get(&address) {
+            return id;
         }
-        self.profile.location.push(loc);
+        let id = self.profile.location.len() as u64 + 1;
+        let mapping_id = if self.profile.mapping.is_empty() {
+            0
+        } else {
+            1
+        };
+        self.profile.location.push(proto::Location {
+            id,
+            mapping_id,
+            address,
+            line,
+            ..Default::default()
+        });
+        self.locations.insert(address, id);
         id
     }
 
@@ -289,3 +484,41 @@ impl ProfileBuilder {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+
+    // jal ra, <offset>
+    const CALL: u32 = 0x000000ef;
+    // jalr zero, 0(ra)
+    const RET: u32 = 0x00008067;
+    // addi a0, a0, 1
+    const ADDI: u32 = 0x00150513;
+
+    #[test]
+    fn collapsed_stacks() {
+        let mut profiler = Profiler::new(0x1000);
+        profiler.record(0x1000, ADDI, 0x1004, 1);
+        profiler.record(0x1004, CALL, 0x2000, 1);
+        profiler.record(0x2000, ADDI, 0x2004, 2);
+        profiler.record(0x2004, CALL, 0x3000, 1);
+        profiler.record(0x3000, RET, 0x2008, 1);
+        profiler.record(0x2008, RET, 0x1008, 1);
+        profiler.record(0x1008, ADDI, 0x100c, 1);
+        assert_eq!(profiler.total_cycles(), 8);
+        assert_eq!(
+            profiler.collapsed(),
+            "0x00001000 3\n\
+             0x00001000;0x00002000 4\n\
+             0x00001000;0x00002000;0x00003000 1\n"
+        );
+
+        // Samples are taken every other cycle.
+        let mut profiler = Profiler::new(0x1000).sample_period(2);
+        for _ in 0..3 {
+            profiler.record(0x1000, ADDI, 0x1000, 1);
+        }
+        assert_eq!(profiler.collapsed(), "0x00001000 2\n");
+    }
+}
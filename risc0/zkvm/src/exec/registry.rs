@@ -0,0 +1,187 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host calls and custom instructions handled outside the executor.
+//!
+//! The host registers an [EcallHandler] for a host call number with
+//! [ExecutorEnvBuilder::ecall](super::ExecutorEnvBuilder::ecall). Registered
+//! handlers take precedence over the executor's built-in host calls, so they
+//! can also replace one of those.
+//!
+//! Accelerators such as hashes or field arithmetic can instead be exposed as
+//! instructions in one of the opcode spaces RISC-V reserves for custom
+//! extensions, with a [CustomInsnHandler] registered with
+//! [ExecutorEnvBuilder::custom_insn](super::ExecutorEnvBuilder::custom_insn).
+//! These take their operands straight from registers, without the marshalling
+//! of a host call.
+//!
+//! Embedders that only need to pass bytes back and forth can instead register
+//! a callback by name with
+//! [ExecutorEnvBuilder::io_callback](super::ExecutorEnvBuilder::io_callback),
+//! which the guest invokes through the `software` host call. What the guest
+//! sent and what the callback returned are recorded with the other host calls.
+
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use anyhow::{bail, Result};
+
+use super::monitor::MemoryMonitor;
+use crate::{ExitCode, GuestFault};
+
+/// A host-side implementation of a host call.
+pub trait EcallHandler {
+    /// Handle the host call made by the guest, whose number is in a7 and
+    /// arguments in a0 onwards.
+    ///
+    /// Returns the exit code if the call ends execution; otherwise the guest
+    /// continues after the `ecall`.
+    fn ecall(&mut self, ctx: &mut EcallContext) -> Result<Option<ExitCode>>;
+}
+
+/// The guest state an [EcallHandler] or [CustomInsnHandler] may access.
+///
+/// Registers and memory written here become visible to the guest once the
+/// instruction retires. Those written by host calls are recorded in syscall
+/// traces like those of the built-in host calls.
+pub struct EcallContext<'c> {
+    pub(crate) monitor: &'c mut MemoryMonitor,
+    pub(crate) pc: u64,
+}
+
+impl EcallContext<'_> {
+    /// The address of the `ecall` or custom instruction.
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    /// Returns the value of register `idx`, e.g. REG_A0.
+    pub fn load_register(&mut self, idx: usize) -> u64 {
+        self.monitor.load_register(idx)
+    }
+
+    /// Set register `idx` to `value`.
+    pub fn store_register(&mut self, idx: usize, value: u64) {
+        self.monitor.store_register(idx, value)
+    }
+
+    /// Returns the `len` bytes of guest memory at `addr`.
+    ///
+    /// Fails with a [GuestFault] if the range is not all mapped. A handler
+    /// that returns it faults the guest, ending execution with
+    /// [ExitCode::Fault].
+    pub fn load_region(&mut self, addr: u64, len: u64) -> Result<Vec<u8>, GuestFault> {
+        self.monitor.load_region(addr, len)
+    }
+
+    /// Write `data` to guest memory at `addr`.
+    pub fn store_region(&mut self, addr: u64, data: &[u8]) {
+        self.monitor.store_region(addr, data)
+    }
+}
+
+/// One of the major opcodes RISC-V reserves for custom instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CustomOpcode {
+    /// `custom-0`, 0b0001011
+    Custom0,
+    /// `custom-1`, 0b0101011
+    Custom1,
+    /// `custom-2`, 0b1011011
+    Custom2,
+    /// `custom-3`, 0b1111011
+    Custom3,
+}
+
+impl CustomOpcode {
+    /// Returns the major opcode, the low 7 bits of the instruction.
+    pub fn opcode(self) -> u32 {
+        match self {
+            CustomOpcode::Custom0 => 0b0001011,
+            CustomOpcode::Custom1 => 0b0101011,
+            CustomOpcode::Custom2 => 0b1011011,
+            CustomOpcode::Custom3 => 0b1111011,
+        }
+    }
+
+    /// Returns the custom opcode of `insn`, if it has one.
+    pub(crate) fn decode(insn: u32) -> Option<Self> {
+        [
+            CustomOpcode::Custom0,
+            CustomOpcode::Custom1,
+            CustomOpcode::Custom2,
+            CustomOpcode::Custom3,
+        ]
+        .into_iter()
+        .find(|custom| custom.opcode() == insn & 0x7f)
+    }
+}
+
+/// A host-side implementation of the instructions of a custom opcode.
+pub trait CustomInsnHandler {
+    /// Execute `insn`, an R-type instruction whose source registers hold `rs1`
+    /// and `rs2`, and return the value of its destination register.
+    ///
+    /// The handler is free to use `funct3` and `funct7` to select between
+    /// several operations.
+    fn execute(&mut self, insn: u32, rs1: u64, rs2: u64, ctx: &mut EcallContext) -> Result<u64>;
+}
+
+/// The host calls registered by the host, by number.
+#[derive(Clone, Default)]
+pub(crate) struct EcallRegistry<'a> {
+    handlers: BTreeMap<u64, (String, Rc<RefCell<dyn EcallHandler + 'a>>)>,
+}
+
+impl<'a> EcallRegistry<'a> {
+    /// Handle host call `number`, named `name`, with `handler`, replacing
+    /// any handler registered for it before.
+    pub(crate) fn register(&mut self, number: u64, name: &str, handler: impl EcallHandler + 'a) {
+        self.handlers
+            .insert(number, (name.to_string(), Rc::new(RefCell::new(handler))));
+    }
+
+    /// Returns the name of host call `number` and its handler, if one is
+    /// registered.
+    pub(crate) fn get(&self, number: u64) -> Option<(&str, &Rc<RefCell<dyn EcallHandler + 'a>>)> {
+        self.handlers
+            .get(&number)
+            .map(|(name, handler)| (name.as_str(), handler))
+    }
+}
+
+/// A host callback, which replies to the bytes the guest sends it
+type IoCallback<'a> = Rc<RefCell<dyn FnMut(&[u8]) -> Vec<u8> + 'a>>;
+
+/// The host callbacks registered by the host, by name.
+#[derive(Clone, Default)]
+pub(crate) struct IoCallbacks<'a> {
+    callbacks: BTreeMap<String, IoCallback<'a>>,
+}
+
+impl<'a> IoCallbacks<'a> {
+    /// Handle calls of `name` with `handler`, replacing any callback
+    /// registered for it before.
+    pub(crate) fn register(&mut self, name: &str, handler: impl FnMut(&[u8]) -> Vec<u8> + 'a) {
+        self.callbacks
+            .insert(name.to_string(), Rc::new(RefCell::new(handler)));
+    }
+
+    /// Returns the reply of callback `name` to `from_guest`.
+    pub(crate) fn call(&self, name: &str, from_guest: &[u8]) -> Result<Vec<u8>> {
+        let Some(callback) = self.callbacks.get(name) else {
+            bail!("Unknown host callback: {name}");
+        };
+        Ok((callback.borrow_mut())(from_guest))
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording and replaying the host calls made by a guest.
+//!
+//! Host calls are the only source of nondeterminism in an execution: given the
+//! same initial image and the same effect of every host call, the guest runs
+//! identically. A [SyscallTrace] captures those effects so an execution can be
+//! reproduced later, independently of the host environment it first ran in.
+//!
+//! A replay serves the calls whose results come from the host environment,
+//! such as reads and callbacks, from the recording without handling them. The
+//! others only depend on the guest, and are handled as usual so that the
+//! executor's own state (such as mapped regions and the journal) is kept up to
+//! date; their effects must match the recording.
+//!
+//! Custom instructions are handled by the host too, and are recorded and
+//! served like host calls whose results come from the host environment.
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use risc0_zkvm_platform::syscall::ecall;
+use serde::{Deserialize, Serialize};
+
+use crate::ExitCode;
+
+/// The built-in host calls whose results come from the host environment rather
+/// than from the guest. Those the executor does not handle itself are too.
+const HOST_INPUTS: &[u64] = &[
+    ecall::GETENV,
+    ecall::SOFTWARE,
+    ecall::FCNTL,
+    ecall::OPEN,
+    ecall::CLOSE,
+    ecall::LSEEK,
+    ecall::READ,
+    ecall::GETRANDOM,
+];
+
+/// A host call made by the guest, and its effect on guest memory
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyscallEvent {
+    /// The index of the `ecall` instruction, counting from the start of
+    /// execution
+    pub cycle: u64,
+
+    /// The program counter of the `ecall` instruction
+    pub pc: u64,
+
+    /// The call number, from register a7, or 0 for a custom instruction
+    pub number: u64,
+
+    /// The arguments, from registers a0 to a3, or the values of rs1 and rs2
+    /// for a custom instruction
+    pub args: [u64; 4],
+
+    /// The custom instruction, if this is one rather than a host call
+    pub insn: Option<u32>,
+
+    /// Every byte written to guest memory by the host, including registers
+    pub writes: Vec<(u64, u8)>,
+
+    /// The exit code the call ended execution with, if any, including a fault
+    /// it raised
+    pub exit_code: Option<ExitCode>,
+}
+
+impl SyscallEvent {
+    /// Returns true if `other` is the same call as this one, regardless of its
+    /// effect.
+    pub(crate) fn same_call(&self, other: &Self) -> bool {
+        (self.cycle, self.pc, self.number, self.args, self.insn)
+            == (other.cycle, other.pc, other.number, other.args, other.insn)
+    }
+}
+
+/// The host calls made during an execution, in order
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyscallTrace {
+    /// The recorded calls
+    pub events: Vec<SyscallEvent>,
+}
+
+impl SyscallTrace {
+    /// Serialize this trace.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let words = crate::serde::to_vec(self).map_err(|err| anyhow!("{err}"))?;
+        Ok(bytemuck::cast_slice(&words).to_vec())
+    }
+
+    /// Deserialize a trace written by [SyscallTrace::to_bytes].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        crate::serde::from_slice(bytes).map_err(|err| anyhow!("invalid syscall trace: {err}"))
+    }
+
+    /// Write this trace to the file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()?).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Load a trace previously written by [SyscallTrace::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Progress through a [SyscallTrace] being replayed.
+pub(crate) struct Replay {
+    pub(crate) trace: SyscallTrace,
+    pub(crate) next: usize,
+}
+
+impl Replay {
+    /// Returns whether call `number` is served from the recording rather than
+    /// handled. `custom` is whether the executor does not handle the call
+    /// itself, either as it has no built-in handler for it or as the
+    /// environment replaces that.
+    pub(crate) fn serves(number: u64, custom: bool) -> bool {
+        custom || HOST_INPUTS.contains(&number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm_platform::{
+        memory::SYSTEM,
+        syscall::{
+            ecall,
+            reg_abi::{REG_A0, REG_A1, REG_A7},
+        },
+    };
+
+    use super::{SyscallEvent, SyscallTrace};
+    use crate::{
+        testutil::{image, ECALL, ENTRY},
+        CustomInsnHandler, CustomOpcode, EcallContext, EcallHandler, Executor, ExecutorEnv,
+        ExitCode, GuestFault,
+    };
+
+    /// A host call the executor does not handle itself.
+    const COUNT: u64 = 0x1_0000;
+
+    /// Counts the calls made, returning the count in a0 and at 0x2000.
+    struct Count(u64);
+
+    impl EcallHandler for Count {
+        fn ecall(&mut self, ctx: &mut EcallContext) -> anyhow::Result<Option<ExitCode>> {
+            self.0 += 1;
+            ctx.store_register(REG_A0, self.0);
+            ctx.store_region(0x2000, &[self.0 as u8]);
+            Ok(None)
+        }
+    }
+
+    fn executor(env: ExecutorEnv<'static>, number: u64) -> Executor<'static> {
+        let image = image(&[ECALL, ECALL], &[(REG_A7, number)]);
+        Executor::new(env, image, ENTRY).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let trace = SyscallTrace {
+            events: vec![SyscallEvent {
+                cycle: 7,
+                pc: 0x1000,
+                number: 64,
+                args: [1, 0x2000, 5, 0],
+                insn: None,
+                writes: vec![(0x0c00_0050, 5), (0x0c00_0051, 0)],
+                exit_code: Some(ExitCode::Halted(1)),
+            }],
+        };
+        let bytes = trace.to_bytes().unwrap();
+        assert_eq!(SyscallTrace::from_bytes(&bytes).unwrap(), trace);
+    }
+
+    #[test]
+    fn replay() {
+        let env = ExecutorEnv::builder()
+            .ecall(COUNT, "count", Count(0))
+            .build()
+            .unwrap();
+        let mut exec = executor(env, COUNT);
+        exec.record_syscalls();
+        exec.step().unwrap();
+        exec.step().unwrap();
+        let trace = exec.take_syscall_trace().unwrap();
+        assert_eq!(trace.events.len(), 2);
+        assert_eq!((trace.events[1].cycle, trace.events[1].pc), (1, 0x1004));
+        assert_eq!(trace.events[1].args[0], 1);
+
+        // Host inputs are served from the recording, without a handler.
+        let mut exec = executor(ExecutorEnv::default(), COUNT);
+        exec.replay(trace.clone());
+        exec.step().unwrap();
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.load_register(REG_A0), 2);
+        assert_eq!(exec.monitor.load_region(0x2000, 1).unwrap(), [2]);
+
+        let mut exec = executor(ExecutorEnv::default(), ecall::CLOSE);
+        exec.replay(trace.clone());
+        assert!(exec.step().is_err());
+
+        let mut exec = executor(ExecutorEnv::default(), COUNT);
+        exec.replay(SyscallTrace::default());
+        assert!(exec.step().is_err());
+    }
+
+    #[test]
+    fn replay_custom_insns() {
+        /// Returns a count of the executions, or faults on a zero rs1.
+        struct Counter(u64);
+
+        impl CustomInsnHandler for Counter {
+            fn execute(
+                &mut self,
+                _insn: u32,
+                rs1: u64,
+                _rs2: u64,
+                _ctx: &mut EcallContext,
+            ) -> anyhow::Result<u64> {
+                anyhow::ensure!(rs1 != 0, GuestFault::Load(0));
+                self.0 += 1;
+                Ok(self.0)
+            }
+        }
+
+        // custom-0 a0, a1, a2; twice
+        let executor = |env| {
+            let image = image(&[0x00c5850b; 2], &[(REG_A1, 1)]);
+            Executor::new(env, image, ENTRY).unwrap()
+        };
+        let env = ExecutorEnv::builder()
+            .custom_insn(CustomOpcode::Custom0, Counter(0))
+            .build()
+            .unwrap();
+        let mut exec = executor(env);
+        exec.record_syscalls();
+        exec.step().unwrap();
+        exec.monitor.set_register(REG_A1, 0);
+        let fault = Some(ExitCode::Fault(GuestFault::Load(0)));
+        assert_eq!(exec.step().unwrap(), fault);
+        let trace = exec.take_syscall_trace().unwrap();
+        assert_eq!(trace.events[0].insn, Some(0x00c5850b));
+        assert_eq!(trace.events[0].args, [1, 0, 0, 0]);
+        assert_eq!(trace.events[1].exit_code, fault);
+
+        // The results, and the fault, are served without a handler.
+        let mut exec = executor(ExecutorEnv::default());
+        exec.replay(trace.clone());
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.load_register(REG_A0), 1);
+        assert_eq!(exec.monitor.syscalls[0].reg_writes, vec![(REG_A0, 1)]);
+        exec.monitor.set_register(REG_A1, 0);
+        assert_eq!(exec.step().unwrap(), fault);
+
+        // The operands must match the recording.
+        let mut exec = executor(ExecutorEnv::default());
+        exec.replay(trace);
+        exec.monitor.set_register(REG_A1, 2);
+        assert!(exec.step().is_err());
+    }
+
+    #[test]
+    fn replay_handled() {
+        let mut exec = executor(ExecutorEnv::default(), ecall::GETTID);
+        exec.record_syscalls();
+        exec.step().unwrap();
+        let mut trace = exec.take_syscall_trace().unwrap();
+
+        let mut exec = executor(ExecutorEnv::default(), ecall::GETTID);
+        exec.replay(trace.clone());
+        exec.step().unwrap();
+        assert_eq!(exec.monitor.load_register(REG_A0), 1000);
+
+        // A call the executor handles must have the recorded effect.
+        let a0 = (SYSTEM.start() + REG_A0 * 8) as u64;
+        trace.events[0].writes = (0..8).map(|i| (a0 + i, (i == 0) as u8 * 42)).collect();
+        let mut exec = executor(ExecutorEnv::default(), ecall::GETTID);
+        exec.replay(trace);
+        assert!(exec.step().is_err());
+    }
+}
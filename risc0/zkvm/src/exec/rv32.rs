@@ -0,0 +1,180 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running RV32 guests on the RV64 machine.
+//!
+//! The registers of an RV32 guest are words in SYSTEM memory, and are held
+//! sign-extended to 64 bits in between, which is how RV64 keeps 32-bit values.
+//! Every register write is truncated to 32 bits, so most RV64 instructions
+//! compute the RV32 result already. The exceptions are the shifts, whose
+//! amount is 5 bits rather than 6 and whose right shifts must not shift in the
+//! sign extension, and the multiplications and divisions that treat their
+//! operands as 32-bit values. The instructions that only exist in RV64 are
+//! illegal, as is bit manipulation, whose RV32 encodings differ.
+
+use anyhow::{bail, Result};
+
+use super::muldiv;
+use crate::opcode::{BitOp, OPCODE_OP, OPCODE_OP_32, OPCODE_OP_IMM, OPCODE_OP_IMM_32};
+
+const OPCODE_LOAD: u32 = 0b0000011;
+const OPCODE_STORE: u32 = 0b0100011;
+const OPCODE_AMO: u32 = 0b0101111;
+const OPCODE_OP_FP: u32 = 0b1010011;
+
+/// Fails if `insn` is not an RV32 instruction.
+pub(crate) fn check(insn: u32) -> Result<()> {
+    let opcode = insn & 0x7f;
+    let funct3 = (insn >> 12) & 0x7;
+    let funct7 = insn >> 25;
+    let rs2 = (insn >> 20) & 0x1f;
+    let rv64_only = match opcode {
+        // LD and LWU
+        OPCODE_LOAD => funct3 == 0b011 || funct3 == 0b110,
+        // SD
+        OPCODE_STORE => funct3 == 0b011,
+        // The double word atomics
+        OPCODE_AMO => funct3 == 0b011,
+        // The shifts by 32 or more
+        OPCODE_OP_IMM if funct3 & 0b11 == 0b01 => insn & 1 << 25 != 0,
+        // The W instructions
+        OPCODE_OP_IMM_32 | OPCODE_OP_32 => true,
+        // FCVT.L[U].{S,D}, FCVT.{S,D}.L[U], FMV.X.D and FMV.D.X
+        OPCODE_OP_FP => match funct7 {
+            0b1100000 | 0b1100001 | 0b1101000 | 0b1101001 => rs2 & 0b10 != 0,
+            0b1110001 | 0b1111001 => true,
+            _ => false,
+        },
+        _ => false,
+    };
+    if rv64_only || BitOp::decode(insn).is_some() {
+        bail!("Illegal RV32 instruction: {insn:#010x}");
+    }
+    Ok(())
+}
+
+/// Returns whether the RV32 result of `insn` differs from the RV64 one
+/// truncated to 32 bits, so that it must be computed with [execute].
+pub(crate) fn differs(insn: u32) -> bool {
+    let opcode = insn & 0x7f;
+    let funct3 = (insn >> 12) & 0x7;
+    let funct7 = insn >> 25;
+    match opcode {
+        // SLLI, SRLI and SRAI
+        OPCODE_OP_IMM => funct3 & 0b11 == 0b01,
+        // SLL, SRL and SRA
+        OPCODE_OP if funct7 != 0b0000001 => funct3 & 0b11 == 0b01,
+        // MULH, MULHSU, MULHU, DIVU and REMU
+        OPCODE_OP => matches!(funct3, 0b001 | 0b010 | 0b011 | 0b101 | 0b111),
+        _ => false,
+    }
+}
+
+/// Compute the RV32 result of the instruction `insn` for the operands `rs1`
+/// and `rs2`, sign-extended to 64 bits.
+pub(crate) fn execute(insn: u32, rs1: u64, rs2: u64) -> Result<u64> {
+    let opcode = insn & 0x7f;
+    let funct3 = (insn >> 12) & 0x7;
+    let funct7 = insn >> 25;
+    let shamt = match opcode {
+        OPCODE_OP_IMM => (insn >> 20) & 0x1f,
+        _ => rs2 as u32 & 0x1f,
+    };
+    let (rs1, rs2) = (rs1 as u32, rs2 as u32);
+    let result = match (opcode, funct7, funct3) {
+        (OPCODE_OP, 0b0000001, 0b001) => ((rs1 as i32 as i64 * rs2 as i32 as i64) >> 32) as u32,
+        (OPCODE_OP, 0b0000001, 0b010) => ((rs1 as i32 as i64 * rs2 as i64) >> 32) as u32,
+        (OPCODE_OP, 0b0000001, 0b011) => ((rs1 as u64 * rs2 as u64) >> 32) as u32,
+        // DIVU and REMU are DIVUW and REMUW on the low words.
+        (OPCODE_OP, 0b0000001, _) => {
+            muldiv::execute(insn & !0x7f | OPCODE_OP_32, rs1 as u64, rs2 as u64)? as u32
+        }
+        (_, _, 0b001) => rs1 << shamt,
+        (_, 0b0000000, 0b101) => rs1 >> shamt,
+        (_, 0b0100000, 0b101) => ((rs1 as i32) >> shamt) as u32,
+        _ => bail!("Illegal instruction: {insn:#010x}"),
+    };
+    Ok(result as i32 as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, differs, execute};
+
+    // Encode the R-type instruction x10 = x11 op x12.
+    fn op(funct7: u32, funct3: u32) -> u32 {
+        funct7 << 25 | 12 << 20 | 11 << 15 | funct3 << 12 | 10 << 7 | 0b0110011
+    }
+
+    // Encode the I-type instruction x10 = x11 op imm.
+    fn op_imm(imm: u32, funct3: u32) -> u32 {
+        imm << 20 | 11 << 15 | funct3 << 12 | 10 << 7 | 0b0010011
+    }
+
+    fn rv32(insn: u32, rs1: i32, rs2: i32) -> i32 {
+        assert!(differs(insn));
+        execute(insn, rs1 as i64 as u64, rs2 as i64 as u64).unwrap() as i32
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(rv32(op(0, 0b001), 1, 33), 2);
+        assert_eq!(rv32(op(0, 0b101), -16, 2), 0x3fff_fffc);
+        assert_eq!(rv32(op(0x20, 0b101), -16, 34), -4);
+        assert_eq!(rv32(op_imm(31, 0b001), 1, 0), i32::MIN);
+        assert_eq!(rv32(op_imm(4, 0b101), -1, 0), 0x0fff_ffff);
+        assert_eq!(rv32(op_imm(0x400 | 4, 0b101), -256, 0), -16);
+        // The shift amount of an immediate shift is at most 31.
+        assert!(check(op_imm(32, 0b001)).is_err());
+        assert!(check(op_imm(31, 0b001)).is_ok());
+    }
+
+    #[test]
+    fn multiply_divide() {
+        assert_eq!(rv32(op(1, 0b001), i32::MIN, i32::MIN), 1 << 30);
+        assert_eq!(rv32(op(1, 0b001), -1, 1), -1);
+        assert_eq!(rv32(op(1, 0b010), -1, -1), -1);
+        assert_eq!(rv32(op(1, 0b011), -1, -1), -2);
+        assert_eq!(rv32(op(1, 0b101), -1, 2), i32::MAX);
+        assert_eq!(rv32(op(1, 0b101), 7, 0), -1);
+        assert_eq!(rv32(op(1, 0b111), -1, 16), 15);
+        // MUL, DIV and REM compute the RV32 result in the low word.
+        assert!(!differs(op(1, 0b000)));
+        assert!(!differs(op(1, 0b100)));
+        assert!(!differs(op(1, 0b110)));
+        assert!(!differs(op(0, 0b000)));
+    }
+
+    #[test]
+    fn rv64_only() {
+        // ld a0, 0(a1)
+        assert!(check(0x0005b503).is_err());
+        // lw a0, 0(a1)
+        assert!(check(0x0005a503).is_ok());
+        // sd a0, 0(a1)
+        assert!(check(0x00a5b023).is_err());
+        // addiw a0, a1, 1
+        assert!(check(0x0015851b).is_err());
+        // amoadd.d a0, a2, (a1)
+        assert!(check(0x00c5b52f).is_err());
+        // fcvt.l.d a0, fa1
+        assert!(check(0xc2258553).is_err());
+        // fcvt.w.d a0, fa1
+        assert!(check(0xc2058553).is_ok());
+        // fmv.x.d a0, fa1
+        assert!(check(0xe2058553).is_err());
+        // sh1add a0, a1, a2
+        assert!(check(0x20c5a533).is_err());
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-disk format for a suspended [Executor].
+//!
+//! A saved state lets a long-running execution be stopped and resumed later,
+//! possibly on another machine, without re-executing from the start. All
+//! integers are little-endian.
+//!
+//! | field              | size                          |
+//! |--------------------|-------------------------------|
+//! | magic (`R0EX`)     | 4                             |
+//! | version            | 4                             |
+//! | state len          | 8                             |
+//! | state              | state len                     |
+//! | region count       | 8                             |
+//! | regions            | see below                     |
+//! | image              | a [MemoryImage] (see `save`)  |
+//!
+//! The state holds the registers that live outside of guest memory (the PC,
+//! cycle counters and heap bounds), the progress of the current segment and
+//! the segments completed so far, encoded with [crate::serde]. It also holds
+//! what the executor keeps on behalf of the guest: the reservation of a load
+//! reserved, the journal written in the session along with the length and
+//! digest of what the finished segments committed, the seed and position of
+//! the random number generator, the profiling regions the guest is in, the
+//! claims it assumed in the session and in the current segment, and the
+//! offsets of the guest's files. The guest integer and floating-point
+//! registers themselves live in SYSTEM memory, and are saved with the image.
+//!
+//! The files are those of the environment the execution resumes with, which
+//! must map every file descriptor the guest had open. Those the guest had
+//! closed are unmapped, and the seekable ones are moved back to their saved
+//! offsets.
+//!
+//! Each region mapped at runtime and backed by [PagedMemory] (e.g. by mmap) is
+//! stored as its name, base, size, and a zstd stream of its non-zero pages
+//! prefixed by the length of the stream. Other regions, such as device MMIO
+//! windows, belong to the host and must be mapped again after resuming.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use risc0_zkvm_platform::PAGE_SIZE;
+use serde::{Deserialize, Serialize};
+
+use super::{Executor, ExecutorEnv, SyscallRecord};
+use crate::{
+    binfmt::persist::{read_name, read_u32, read_u64, write_name},
+    session::PageFaults,
+    Assumption, Digest, MemoryImage, PagedMemory, ProgramSegment, RegionStats, Segment,
+};
+
+const MAGIC: &[u8; 4] = b"R0EX";
+const VERSION: u32 = 13;
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct ExecutorState {
+    pc: u64,
+    pre_pc: u64,
+    pre_image_id: Digest,
+    anonymous_heap_watermark: u64,
+    heap_end: u64,
+    insn_counter: u64,
+    insn_cycles: u64,
+    segment_cycle: u64,
+    session_cycle: u64,
+    stack_initialized: bool,
+    faults: PageFaults,
+    syscalls: Vec<SyscallRecord>,
+    syscall_count: u64,
+    segments: Vec<Segment>,
+    program_segments: Vec<ProgramSegment>,
+    reservation: Option<u64>,
+    journal: Vec<u8>,
+    journal_digest: Digest,
+    journal_len: u64,
+    rng: RngState,
+    open_regions: BTreeMap<u64, (String, u64)>,
+    region_stats: BTreeMap<String, RegionStats>,
+    assumptions: Vec<Assumption>,
+    segment_assumptions: Vec<Assumption>,
+    file_offsets: BTreeMap<u64, Option<u64>>,
+}
+
+/// The seed and position of a [ChaCha12Rng]
+#[derive(Serialize, Deserialize)]
+struct RngState {
+    seed: [u8; 32],
+    /// The low and high halves of the word position
+    word_pos: (u64, u64),
+}
+
+impl RngState {
+    fn new(rng: &ChaCha12Rng) -> Self {
+        let word_pos = rng.get_word_pos();
+        Self {
+            seed: rng.get_seed(),
+            word_pos: (word_pos as u64, (word_pos >> 64) as u64),
+        }
+    }
+
+    fn restore(&self) -> ChaCha12Rng {
+        let mut rng = ChaCha12Rng::from_seed(self.seed);
+        rng.set_word_pos((self.word_pos.1 as u128) << 64 | self.word_pos.0 as u128);
+        rng
+    }
+}
+
+impl<'a> Executor<'a> {
+    /// Save the state of this execution to the file at `path`.
+    ///
+    /// See [Executor::save_state_to].
+    pub fn save_state(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.save_state_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Resume an execution previously saved by [Executor::save_state].
+    pub fn load_state(env: ExecutorEnv<'a>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        Self::load_state_from(env, BufReader::new(file))
+    }
+
+    /// Serialize the state of this execution to `writer`.
+    ///
+    /// This must be called between instructions: after a step that returns
+    /// [ExitCode::SystemSplit](crate::ExitCode::SystemSplit), the instruction
+    /// that triggered the split is still pending and must be stepped first.
+    /// Host-side debugging state, such as breakpoints, watchpoints and syscall
+    /// recording, is not saved.
+    pub fn save_state_to(&mut self, mut writer: impl Write) -> Result<()> {
+        ensure!(
+            self.monitor.restore_op().is_none(),
+            "cannot save state in the middle of an instruction"
+        );
+        let state = ExecutorState {
+            pc: self.pc,
+            pre_pc: self.pre_pc,
+            pre_image_id: self.pre_image_id,
+            anonymous_heap_watermark: self.anonymous_heap_watermark,
+            heap_end: self.heap_end,
+            insn_counter: self.insn_counter,
+            insn_cycles: self.insn_cycles,
+            segment_cycle: self.segment_cycle as u64,
+            session_cycle: self.session_cycle as u64,
+            stack_initialized: self.monitor.initial,
+            faults: self.monitor.faults.clone(),
+            syscalls: self.monitor.syscalls.clone(),
+            syscall_count: self.monitor.syscall_count,
+            segments: self.segments.clone(),
+            program_segments: self.monitor.image.segments().to_vec(),
+            reservation: self.reservation,
+            journal: self.journal.buf.borrow().clone(),
+            journal_digest: self.journal_digest,
+            journal_len: self.journal_len as u64,
+            rng: RngState::new(&self.rng),
+            open_regions: self
+                .open_regions
+                .iter()
+                .map(|(id, (name, cycle))| (*id, (name.clone(), *cycle as u64)))
+                .collect(),
+            region_stats: self.region_stats.clone(),
+            assumptions: self.assumptions.clone(),
+            segment_assumptions: self.segment_assumptions.clone(),
+            file_offsets: self.env.files.borrow_mut().offsets()?,
+        };
+        let words = crate::serde::to_vec(&state).map_err(|err| anyhow!("{err}"))?;
+        let state: &[u8] = bytemuck::cast_slice(&words);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(state.len() as u64).to_le_bytes())?;
+        writer.write_all(state)?;
+
+        let image = &self.monitor.image;
+        let regions: Vec<_> = image
+            .regions()
+            .iter()
+            .filter(|region| !region.committed)
+            .filter_map(|region| {
+                let memory = image
+                    .memory_space
+                    .get_memory_region_by_addr(region.base)?
+                    .memory
+                    .downcast_ref::<PagedMemory>()?;
+                Some((region, memory))
+            })
+            .collect();
+        writer.write_all(&(regions.len() as u64).to_le_bytes())?;
+        for (region, memory) in regions {
+            write_name(&mut writer, &region.name)?;
+            writer.write_all(&region.base.to_le_bytes())?;
+            writer.write_all(&region.size.to_le_bytes())?;
+            let mut encoder = zstd::stream::Encoder::new(Vec::new(), COMPRESSION_LEVEL)?;
+            for (page_idx, page) in memory.pages() {
+                if page.iter().any(|byte| *byte != 0) {
+                    encoder.write_all(&page_idx.to_le_bytes())?;
+                    encoder.write_all(page)?;
+                }
+            }
+            let pages = encoder.finish()?;
+            writer.write_all(&(pages.len() as u64).to_le_bytes())?;
+            writer.write_all(&pages)?;
+        }
+
+        self.sync_registers();
+        self.monitor.image.save_to(writer)
+    }
+
+    /// Resume an execution from a state written by [Executor::save_state_to].
+    ///
+    /// The execution continues with the host environment `env`, which need
+    /// not be the one it was started with.
+    pub fn load_state_from(env: ExecutorEnv<'a>, mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a saved executor state");
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            bail!("unsupported executor state version: {version}");
+        }
+        let mut state = vec![0; read_u64(&mut reader)? as usize];
+        reader.read_exact(&mut state)?;
+        let state: ExecutorState = crate::serde::from_slice(&state)
+            .map_err(|err| anyhow!("invalid executor state: {err}"))?;
+
+        let num_regions = read_u64(&mut reader)?;
+        let mut regions = Vec::new();
+        for _ in 0..num_regions {
+            let name = read_name(&mut reader)?;
+            let base = read_u64(&mut reader)?;
+            let size = read_u64(&mut reader)?;
+            let mut pages = vec![0; read_u64(&mut reader)? as usize];
+            reader.read_exact(&mut pages)?;
+            let pages = zstd::stream::decode_all(pages.as_slice())?;
+            ensure!(
+                pages.len() % (8 + PAGE_SIZE) == 0,
+                "truncated region {name} at 0x{base:x}"
+            );
+            let mut memory = PagedMemory::new(size);
+            for chunk in pages.chunks(8 + PAGE_SIZE) {
+                let page_idx = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+                ensure!(
+                    memory.store_page(page_idx, chunk[8..].try_into().unwrap()),
+                    "page out of range in region {name} at 0x{base:x}"
+                );
+            }
+            regions.push((name, base, size, memory));
+        }
+
+        let mut image = MemoryImage::load_from(reader)?;
+        for (name, base, size, memory) in regions {
+            if image.regions().iter().any(|region| region.base == base) {
+                image.remove_region(base)?;
+            }
+            image.add_region(&name, base, size, Box::new(memory))?;
+        }
+        image.set_segments(state.program_segments);
+
+        let mut exec = Self::new(env, image, state.pc)?;
+        exec.pre_pc = state.pre_pc;
+        exec.pre_image_id = state.pre_image_id;
+        exec.anonymous_heap_watermark = state.anonymous_heap_watermark;
+        exec.heap_end = state.heap_end;
+        exec.insn_counter = state.insn_counter;
+        exec.insn_cycles = state.insn_cycles;
+        exec.segment_cycle = state.segment_cycle as usize;
+        exec.session_cycle = state.session_cycle as usize;
+        exec.monitor.initial = state.stack_initialized;
+        exec.monitor.faults = state.faults;
+        exec.monitor.syscalls = state.syscalls;
+        exec.monitor.syscall_count = state.syscall_count;
+        exec.segments = state.segments;
+        exec.reservation = state.reservation;
+        exec.journal.write_all(&state.journal)?;
+        exec.journal_digest = state.journal_digest;
+        exec.journal_len = state.journal_len as usize;
+        exec.rng = state.rng.restore();
+        exec.open_regions = state
+            .open_regions
+            .into_iter()
+            .map(|(id, (name, cycle))| (id, (name, cycle as usize)))
+            .collect();
+        exec.region_stats = state.region_stats;
+        exec.assumptions = state.assumptions;
+        exec.segment_assumptions = state.segment_assumptions;
+        exec.env
+            .files
+            .borrow_mut()
+            .restore_offsets(&state.file_offsets)
+            .context("restoring the guest's files")?;
+        Ok(exec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use rand::RngCore;
+    use risc0_zkvm_platform::syscall::{
+        ecall,
+        reg_abi::{REG_A0, REG_A1, REG_A7},
+    };
+    use rrs_lib::MemAccessSize;
+
+    use crate::{
+        testutil::{image, ECALL, ENTRY},
+        Assumption, Digest, Executor, ExecutorEnv, ExitCode,
+    };
+
+    #[test]
+    fn save_resume() {
+        // A run of `ecall`s, each an anonymous mmap of a page.
+        let image = image(&[ECALL; 4], &[(REG_A7, ecall::MMAP), (REG_A1, 0x1000)]);
+        let mut exec = Executor::new(ExecutorEnv::default(), image, ENTRY).unwrap();
+        exec.step().unwrap();
+        let base = exec.monitor.load_register(REG_A0);
+        exec.monitor
+            .image
+            .write_mem(base + 8, MemAccessSize::Byte, 42);
+        exec.monitor.set_register(REG_A0, 0);
+
+        let mut state = Vec::new();
+        exec.save_state_to(&mut state).unwrap();
+        let mut resumed =
+            Executor::load_state_from(ExecutorEnv::default(), state.as_slice()).unwrap();
+        assert_eq!(resumed.pc, exec.pc);
+        assert_eq!(resumed.insn_counter, exec.insn_counter);
+        assert_eq!(resumed.insn_cycles, exec.insn_cycles);
+        let byte = resumed.monitor.image.read_region(base + 8, 1).unwrap();
+        assert_eq!(byte, vec![42]);
+
+        // Both continue identically, with the next mmap above the first.
+        exec.step().unwrap();
+        resumed.step().unwrap();
+        let next = exec.monitor.load_register(REG_A0);
+        assert_eq!(next, base + 0x1000);
+        assert_eq!(resumed.monitor.load_register(REG_A0), next);
+        assert_eq!(resumed.pc, exec.pc);
+
+        assert!(Executor::load_state_from(ExecutorEnv::default(), &state[1..]).is_err());
+    }
+
+    #[test]
+    fn host_state() {
+        let env = || {
+            ExecutorEnv::builder()
+                .random_seed(7)
+                .file(4, Cursor::new(b"hello world".to_vec()))
+                .file(5, Cursor::new(Vec::new()))
+                .build()
+                .unwrap()
+        };
+        let mut exec = Executor::new(env(), image(&[ECALL], &[]), ENTRY).unwrap();
+        exec.reservation = Some(0x2000);
+        exec.journal.write_all(b"committed").unwrap();
+        exec.finish_segment(ExitCode::SystemSplit);
+        exec.journal.write_all(b" since").unwrap();
+        exec.rng.next_u64();
+        exec.open_regions.insert(1, ("hash".to_string(), 10));
+        let assumption = Assumption {
+            image_id: Digest::ZERO,
+            journal_digest: Digest::ZERO,
+        };
+        exec.assumptions.push(assumption);
+        exec.segment_assumptions.push(assumption);
+        exec.env.files.borrow_mut().read(4, 6).unwrap();
+        exec.env.files.borrow_mut().close(5).unwrap();
+
+        let mut state = Vec::new();
+        exec.save_state_to(&mut state).unwrap();
+        let mut resumed = Executor::load_state_from(env(), state.as_slice()).unwrap();
+        assert_eq!(resumed.reservation, Some(0x2000));
+        assert_eq!(resumed.journal.buf, exec.journal.buf);
+        assert_eq!(resumed.journal_len, b"committed".len());
+        assert_eq!(resumed.journal_digest, exec.journal_digest);
+        assert_eq!(resumed.rng.next_u64(), exec.rng.next_u64());
+        assert_eq!(resumed.open_regions, exec.open_regions);
+        assert_eq!(resumed.assumptions, [assumption]);
+        assert_eq!(resumed.segment_assumptions, [assumption]);
+        let mut files = resumed.env.files.borrow_mut();
+        assert_eq!(files.read(4, 16).unwrap(), b"world");
+        assert!(!files.contains(5));
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A compact record of the guest's memory accesses.
+//!
+//! A trace starts with a header of the magic bytes `R0MA` and a little-endian
+//! u32 version, followed by one record per access:
+//!
+//! | field  | encoding                                             |
+//! |--------|------------------------------------------------------|
+//! | flags  | 1 byte: log2 of the size in bits 0-1, store in bit 2 |
+//! | cycle  | LEB128 delta from the previous record                |
+//! | addr   | zigzag LEB128 delta from the previous record         |
+//! | value  | LEB128                                               |
+//!
+//! Deltas start from zero. Consecutive accesses are usually close together in
+//! both time and space, so most records take only a few bytes.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, ensure, Context, Result};
+
+const MAGIC: &[u8; 4] = b"R0MA";
+const VERSION: u32 = 1;
+const HEADER_BYTES: usize = 8;
+const WRITE_FLAG: u8 = 1 << 2;
+
+/// A single guest load or store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    /// The index of the accessing instruction, counting from the start of
+    /// execution
+    pub cycle: u64,
+
+    /// The address accessed
+    pub addr: u64,
+
+    /// The size of the access in bytes: 1, 2, 4 or 8
+    pub size: u8,
+
+    /// The value loaded or stored
+    pub value: u64,
+
+    /// Whether the access was a store
+    pub write: bool,
+}
+
+/// An encoded sequence of [MemAccess]es, in the order they were made.
+#[derive(Clone, Debug)]
+pub struct MemTrace {
+    bytes: Vec<u8>,
+    len: usize,
+    last_cycle: u64,
+    last_addr: u64,
+}
+
+impl Default for MemTrace {
+    fn default() -> Self {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        Self {
+            bytes,
+            len: 0,
+            last_cycle: 0,
+            last_addr: 0,
+        }
+    }
+}
+
+impl MemTrace {
+    /// Construct an empty trace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `access` to this trace.
+    ///
+    /// Accesses must be pushed in cycle order.
+    pub fn push(&mut self, access: &MemAccess) {
+        assert!(access.cycle >= self.last_cycle, "trace out of order");
+        let mut flags = access.size.trailing_zeros() as u8;
+        if access.write {
+            flags |= WRITE_FLAG;
+        }
+        self.bytes.push(flags);
+        write_varint(&mut self.bytes, access.cycle - self.last_cycle);
+        let delta = access.addr.wrapping_sub(self.last_addr) as i64;
+        write_varint(&mut self.bytes, ((delta << 1) ^ (delta >> 63)) as u64);
+        write_varint(&mut self.bytes, access.value);
+        self.last_cycle = access.cycle;
+        self.last_addr = access.addr;
+        self.len += 1;
+    }
+
+    /// Returns the number of accesses in this trace.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no accesses have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the encoded trace, including its header.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decode a trace produced by [MemTrace::as_bytes].
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        ensure!(
+            bytes.len() >= HEADER_BYTES && &bytes[..4] == MAGIC,
+            "not a memory trace"
+        );
+        let version = u32::from_le_bytes(bytes[4..HEADER_BYTES].try_into().unwrap());
+        ensure!(
+            version == VERSION,
+            "unsupported memory trace version: {version}"
+        );
+        let mut trace = Self {
+            bytes,
+            ..Self::default()
+        };
+        let mut reader = Reader::new(&trace.bytes);
+        while let Some(access) = reader.next_access()? {
+            trace.len += 1;
+            trace.last_cycle = access.cycle;
+            trace.last_addr = access.addr;
+        }
+        Ok(trace)
+    }
+
+    /// Write this trace to the file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, &self.bytes).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Load a trace previously written by [MemTrace::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::from_bytes(bytes)
+    }
+
+    /// Iterate over the accesses of this trace.
+    pub fn iter(&self) -> impl Iterator<Item = MemAccess> + '_ {
+        let mut reader = Reader::new(&self.bytes);
+        // The encoding was checked when the trace was built or decoded.
+        std::iter::from_fn(move || reader.next_access().unwrap())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    cycle: u64,
+    addr: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: HEADER_BYTES,
+            cycle: 0,
+            addr: 0,
+        }
+    }
+
+    fn next_access(&mut self) -> Result<Option<MemAccess>> {
+        let Some(flags) = self.bytes.get(self.pos).copied() else {
+            return Ok(None);
+        };
+        self.pos += 1;
+        ensure!(
+            flags & !(WRITE_FLAG | 3) == 0,
+            "invalid memory trace record"
+        );
+        self.cycle += self.read_varint()?;
+        let delta = self.read_varint()?;
+        let delta = ((delta >> 1) as i64) ^ -((delta & 1) as i64);
+        self.addr = self.addr.wrapping_add(delta as u64);
+        let value = self.read_varint()?;
+        Ok(Some(MemAccess {
+            cycle: self.cycle,
+            addr: self.addr,
+            size: 1 << (flags & 3),
+            value,
+            write: flags & WRITE_FLAG != 0,
+        }))
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some(byte) = self.bytes.get(self.pos).copied() else {
+                bail!("truncated memory trace");
+            };
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("invalid varint in memory trace")
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemAccess, MemTrace};
+
+    #[test]
+    fn round_trip() {
+        let accesses = [
+            MemAccess {
+                cycle: 3,
+                addr: 0x1000_0000,
+                size: 8,
+                value: u64::MAX,
+                write: true,
+            },
+            MemAccess {
+                cycle: 3,
+                addr: 0x0fff_fff8,
+                size: 4,
+                value: 7,
+                write: false,
+            },
+            MemAccess {
+                cycle: 900,
+                addr: 0x10,
+                size: 1,
+                value: 0,
+                write: false,
+            },
+        ];
+        let mut trace = MemTrace::new();
+        for access in accesses.iter() {
+            trace.push(access);
+        }
+        assert_eq!(trace.len(), 3);
+        assert!(trace.iter().eq(accesses.iter().copied()));
+
+        let decoded = MemTrace::from_bytes(trace.as_bytes().to_vec()).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded.iter().eq(accesses.iter().copied()));
+
+        let truncated = trace.as_bytes()[..trace.as_bytes().len() - 1].to_vec();
+        assert!(MemTrace::from_bytes(truncated).is_err());
+    }
+}
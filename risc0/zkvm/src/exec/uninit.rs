@@ -0,0 +1,174 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detecting loads from uninitialized guest memory.
+//!
+//! Main memory starts out zeroed, so a load from a byte that nothing has
+//! written still has a well-defined result. It is nearly always a bug in the
+//! guest though, and one that can make its result depend on the host that
+//! built the memory image rather than on the program alone.
+//!
+//! Memory counts as initialized once it has been stored to, by the guest or
+//! by the host on its behalf, or if it was loaded from the program: every
+//! `PT_LOAD` segment including its zero-filled tail, or every non-zero page of
+//! an image without segments. The registers and program arguments in [SYSTEM],
+//! the argument vector at [STACK_INITIAL_ADDRESS], and all memory outside of
+//! main memory, such as mmap regions, which are zeroed by definition, are
+//! always initialized.
+
+use std::collections::BTreeMap;
+
+use risc0_zkvm_platform::{
+    memory::{MEM_SIZE, STACK_INITIAL_ADDRESS, SYSTEM},
+    PAGE_SIZE,
+};
+
+use crate::MemoryImage;
+
+/// The number of 64-bit chunks in the bitmap of a page, one bit per byte
+const BITMAP_LEN: usize = PAGE_SIZE / 64;
+
+/// A guest load that read memory nothing had initialized
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UninitLoad {
+    /// The address of the loading instruction
+    pub pc: u64,
+
+    /// The first address loaded
+    pub addr: u64,
+
+    /// The number of bytes loaded
+    pub len: u64,
+}
+
+/// The initialized bytes of main memory, as a bitmap per page.
+pub(crate) struct InitMap {
+    pages: BTreeMap<u64, [u64; BITMAP_LEN]>,
+    mem_size: u64,
+}
+
+impl InitMap {
+    /// Construct the map of the bytes initialized by loading `image`.
+    pub(crate) fn new(image: &MemoryImage) -> Self {
+        let memory = image.main_memory();
+        let mut map = Self {
+            pages: BTreeMap::new(),
+            mem_size: memory.size(),
+        };
+        map.mark(SYSTEM.start() as u64, SYSTEM.len_bytes() as u64);
+        // The startup code reads argc even when there are no arguments.
+        map.mark(
+            STACK_INITIAL_ADDRESS as u64,
+            (MEM_SIZE - STACK_INITIAL_ADDRESS) as u64,
+        );
+        if image.segments().is_empty() {
+            for (page_idx, page) in memory.pages() {
+                if page.iter().any(|byte| *byte != 0) {
+                    map.mark(page_idx * PAGE_SIZE as u64, PAGE_SIZE as u64);
+                }
+            }
+        }
+        for segment in image.segments() {
+            map.mark(segment.vaddr, segment.mem_size);
+        }
+        map
+    }
+
+    /// Mark the `len` bytes at `addr` as initialized.
+    pub(crate) fn mark(&mut self, addr: u64, len: u64) {
+        for addr in addr..self.end(addr, len) {
+            let (page_idx, chunk, bit) = Self::locate(addr);
+            let page = self.pages.entry(page_idx).or_insert([0; BITMAP_LEN]);
+            page[chunk] |= 1 << bit;
+        }
+    }
+
+    /// Returns whether all `len` bytes at `addr` are initialized.
+    pub(crate) fn is_initialized(&self, addr: u64, len: u64) -> bool {
+        // Bytes past the end of main memory are always initialized.
+        (addr..self.end(addr, len)).all(|addr| {
+            let (page_idx, chunk, bit) = Self::locate(addr);
+            self.pages
+                .get(&page_idx)
+                .map_or(false, |page| page[chunk] & 1 << bit != 0)
+        })
+    }
+
+    /// The end of the `len` bytes at `addr`, clamped to main memory.
+    fn end(&self, addr: u64, len: u64) -> u64 {
+        addr.checked_add(len)
+            .map_or(self.mem_size, |end| end.min(self.mem_size))
+    }
+
+    fn locate(addr: u64) -> (u64, usize, usize) {
+        let offset = (addr % PAGE_SIZE as u64) as usize;
+        (addr / PAGE_SIZE as u64, offset / 64, offset % 64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm_platform::memory::{STACK_INITIAL_ADDRESS, SYSTEM};
+    use rrs_lib::{MemAccessSize, Memory};
+
+    use super::InitMap;
+    use crate::{
+        exec::{monitor::MemoryMonitor, OpCodeResult},
+        testutil::{image, RET},
+        PAGE_SIZE,
+    };
+
+    #[test]
+    fn init_map() {
+        let image = image(&[RET], &[]);
+        let mut map = InitMap::new(&image);
+
+        // The page holding the program, and the registers.
+        assert!(map.is_initialized(0x1000, 4));
+        assert!(map.is_initialized(0x1000 + PAGE_SIZE as u64 - 8, 8));
+        assert!(map.is_initialized(SYSTEM.start() as u64, 8));
+        // The argument vector.
+        assert!(map.is_initialized(STACK_INITIAL_ADDRESS as u64, 8));
+
+        assert!(!map.is_initialized(0x8000, 1));
+        map.mark(0x8001, 2);
+        assert!(map.is_initialized(0x8001, 2));
+        assert!(!map.is_initialized(0x8000, 2));
+        assert!(!map.is_initialized(0x8002, 2));
+
+        // Beyond main memory.
+        assert!(map.is_initialized(image.main_memory().size(), 8));
+        map.mark(u64::MAX - 3, 8);
+        assert!(map.is_initialized(u64::MAX - 3, 8));
+    }
+
+    #[test]
+    fn monitor_loads() {
+        let image = image(&[RET], &[]);
+        let mut monitor = MemoryMonitor::new(image);
+        monitor.init_map = Some(InitMap::new(&monitor.image));
+
+        monitor.read_mem(0x8000, MemAccessSize::Word);
+        monitor.read_mem(0x1000, MemAccessSize::Word);
+        monitor.write_mem(0x8000, MemAccessSize::HalfWord, 1);
+        assert_eq!(monitor.take_uninit_loads(), [(0x8000, 4)]);
+        monitor.save_op(OpCodeResult::new(0x1004, None, 0, None));
+        monitor.commit();
+
+        monitor.read_mem(0x8000, MemAccessSize::HalfWord);
+        assert!(monitor.take_uninit_loads().is_empty());
+        monitor.read_mem(0x8000, MemAccessSize::Word);
+        assert_eq!(monitor.take_uninit_loads(), [(0x8000, 4)]);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generating the witnesses of step circuits from an execution.
+//!
+//! The executor describes each instruction it retires as a [StepActivity]:
+//! the integer and floating-point registers and the memory it accessed, and
+//! the host call it made. A
+//! [WitnessGenerator] turns that into the witness of a step circuit. A
+//! circuit sees nothing of the executor but [StepActivity], so either can
+//! change without the other.
+
+use std::{cell::RefCell, mem::take, rc::Rc};
+
+use super::{ExecutorHooks, MemAccess, MulDivAdvice, SyscallRecord};
+use crate::Segment;
+
+/// A read or write of a guest register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegAccess {
+    /// The index of the register, from 0 to 31
+    pub idx: usize,
+
+    /// The value read or written
+    pub value: u64,
+
+    /// Whether the access was a write
+    pub write: bool,
+
+    /// Whether the register is a floating-point one, rather than an integer
+    /// one
+    pub float: bool,
+}
+
+/// What a retired instruction did, from which the witness of its step is
+/// generated
+#[derive(Clone, Copy, Debug)]
+pub struct StepActivity<'a> {
+    /// The index of the instruction, counting from the start of execution
+    pub cycle: u64,
+
+    /// The program counter of the instruction
+    pub pc: u64,
+
+    /// The instruction
+    pub insn: u32,
+
+    /// The program counter of the next instruction
+    pub next_pc: u64,
+
+    /// The cycles the instruction took, not including paging
+    pub cycles: usize,
+
+    /// The register reads and writes of the instruction, in order, including
+    /// those the host made on its behalf
+    pub registers: &'a [RegAccess],
+
+    /// The loads and stores of the instruction, in order, including the
+    /// stores the host made on its behalf
+    pub memory: &'a [MemAccess],
+
+    /// The host call made by the instruction, from register a7, if it is an
+    /// `ecall`
+    pub syscall: Option<u64>,
+
+    /// The record of the host call or custom instruction, holding what the
+    /// host returned to the guest, which a circuit takes as given
+    pub host_call: Option<&'a SyscallRecord>,
+
+    /// The advice from which a circuit checks the result of the instruction,
+    /// if it multiplies or divides
+    pub muldiv: Option<MulDivAdvice>,
+}
+
+/// Generates the witness of a step circuit from the activity of an
+/// instruction.
+pub trait WitnessGenerator {
+    /// The witness of a step, such as a vector of field elements
+    type Witness;
+
+    /// Returns the witness of the step that executes `step`.
+    fn generate(&mut self, step: &StepActivity) -> Self::Witness;
+}
+
+struct Collected<G: WitnessGenerator> {
+    generator: G,
+    pending: Vec<G::Witness>,
+    segments: Vec<Vec<G::Witness>>,
+}
+
+/// Runs a [WitnessGenerator] over an execution, keeping the witnesses of
+/// each segment.
+///
+/// Register a clone with
+/// [ExecutorEnvBuilder::hooks](super::ExecutorEnvBuilder::hooks), and take
+/// the witnesses from this one once the session is done.
+pub struct WitnessCollector<G: WitnessGenerator> {
+    inner: Rc<RefCell<Collected<G>>>,
+}
+
+impl<G: WitnessGenerator> Clone for WitnessCollector<G> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<G: WitnessGenerator> WitnessCollector<G> {
+    /// Construct a collector generating witnesses with `generator`.
+    pub fn new(generator: G) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Collected {
+                generator,
+                pending: Vec::new(),
+                segments: Vec::new(),
+            })),
+        }
+    }
+
+    /// Returns the witnesses of the steps of each segment finished so far,
+    /// in order, leaving none.
+    pub fn take_segments(&self) -> Vec<Vec<G::Witness>> {
+        take(&mut self.inner.borrow_mut().segments)
+    }
+}
+
+impl<G: WitnessGenerator> ExecutorHooks for WitnessCollector<G> {
+    fn on_step(&mut self, step: &StepActivity) {
+        let mut inner = self.inner.borrow_mut();
+        let witness = inner.generator.generate(step);
+        inner.pending.push(witness);
+    }
+
+    fn on_segment(&mut self, _segment: &Segment) {
+        let mut inner = self.inner.borrow_mut();
+        let witnesses = take(&mut inner.pending);
+        inner.segments.push(witnesses);
+    }
+}
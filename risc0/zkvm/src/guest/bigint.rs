@@ -0,0 +1,245 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Modular arithmetic on 256 and 384 bit integers, accelerated by the host.
+//!
+//! Integers are arrays of little-endian 64-bit limbs, the representation
+//! most field implementations use. A zero modulus stands for 2 to the width,
+//! which truncates the result.
+//!
+//! [Fp] is a prime field element computed with these functions, and
+//! [Secp256k1], [P256] and [Bls12_381] are the base fields of the curves
+//! guests most often verify signatures or proofs over. Forks of `k256`,
+//! `p256` and `bls12_381` replace their field element with [Fp] of the
+//! matching modulus when built for the zkVM, and a guest picks them up with
+//! a `[patch.crates-io]` section in its manifest, naming its checkout of the
+//! fork:
+//!
+//! ```toml
+//! [patch.crates-io]
+//! k256 = { path = "../patches/k256" }
+//! ```
+
+use core::{
+    marker::PhantomData,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+use risc0_zkvm_platform::syscall::{
+    bigint::{OP_ADD, OP_MUL},
+    sys_bigint,
+};
+
+fn bigint<const N: usize>(op: u64, x: &[u64; N], y: &[u64; N], modulus: &[u64; N]) -> [u64; N] {
+    let mut result = [0; N];
+    let ret = unsafe {
+        sys_bigint(
+            op,
+            result.as_mut_ptr().cast(),
+            x.as_ptr().cast(),
+            y.as_ptr().cast(),
+            modulus.as_ptr().cast(),
+            N * 64,
+        )
+    };
+    assert_eq!(ret, 0, "Unsupported bigint width: {}", N * 64);
+    result
+}
+
+/// Returns `x - y`, which is at most `x`.
+fn sub<const N: usize>(x: &[u64; N], y: &[u64; N]) -> [u64; N] {
+    let mut result = [0; N];
+    let mut borrow = false;
+    for i in 0..N {
+        let (limb, borrow1) = x[i].overflowing_sub(y[i]);
+        let (limb, borrow2) = limb.overflowing_sub(borrow as u64);
+        result[i] = limb;
+        borrow = borrow1 || borrow2;
+    }
+    result
+}
+
+/// Returns `x * y mod modulus` for 256-bit integers.
+pub fn mul_mod_256(x: &[u64; 4], y: &[u64; 4], modulus: &[u64; 4]) -> [u64; 4] {
+    bigint(OP_MUL, x, y, modulus)
+}
+
+/// Returns `x + y mod modulus` for 256-bit integers.
+pub fn add_mod_256(x: &[u64; 4], y: &[u64; 4], modulus: &[u64; 4]) -> [u64; 4] {
+    bigint(OP_ADD, x, y, modulus)
+}
+
+/// Returns `x * y mod modulus` for 384-bit integers.
+pub fn mul_mod_384(x: &[u64; 6], y: &[u64; 6], modulus: &[u64; 6]) -> [u64; 6] {
+    bigint(OP_MUL, x, y, modulus)
+}
+
+/// Returns `x + y mod modulus` for 384-bit integers.
+pub fn add_mod_384(x: &[u64; 6], y: &[u64; 6], modulus: &[u64; 6]) -> [u64; 6] {
+    bigint(OP_ADD, x, y, modulus)
+}
+
+/// A prime modulus of `N` limbs, 4 or 6
+pub trait Modulus<const N: usize> {
+    /// The modulus, in little-endian limbs
+    const MODULUS: [u64; N];
+}
+
+/// The base field of secp256k1, 2^256 - 2^32 - 977
+pub struct Secp256k1;
+
+impl Modulus<4> for Secp256k1 {
+    const MODULUS: [u64; 4] = [0xfffffffefffffc2f, u64::MAX, u64::MAX, u64::MAX];
+}
+
+/// The base field of P-256, 2^256 - 2^224 + 2^192 + 2^96 - 1
+pub struct P256;
+
+impl Modulus<4> for P256 {
+    const MODULUS: [u64; 4] = [u64::MAX, 0x00000000ffffffff, 0, 0xffffffff00000001];
+}
+
+/// The base field of BLS12-381
+pub struct Bls12_381;
+
+impl Modulus<6> for Bls12_381 {
+    const MODULUS: [u64; 6] = [
+        0xb9feffffffffaaab,
+        0x1eabfffeb153ffff,
+        0x6730d2a0f6b0f624,
+        0x64774b84f38512bf,
+        0x4b1ba7b6434bacd7,
+        0x1a0111ea397fe69a,
+    ];
+}
+
+/// An element of the field of integers modulo `M`, kept reduced
+pub struct Fp<M: Modulus<N>, const N: usize> {
+    limbs: [u64; N],
+    modulus: PhantomData<M>,
+}
+
+impl<M: Modulus<N>, const N: usize> Fp<M, N> {
+    /// The element 0
+    pub const ZERO: Self = Self::from_reduced([0; N]);
+
+    /// The element 1
+    pub const ONE: Self = {
+        let mut limbs = [0; N];
+        limbs[0] = 1;
+        Self::from_reduced(limbs)
+    };
+
+    const fn from_reduced(limbs: [u64; N]) -> Self {
+        Self {
+            limbs,
+            modulus: PhantomData,
+        }
+    }
+
+    /// Returns the element of the integer with little-endian `limbs`, or
+    /// `None` if it is not less than the modulus.
+    pub fn new(limbs: [u64; N]) -> Option<Self> {
+        let reduced = limbs.iter().rev().lt(M::MODULUS.iter().rev());
+        reduced.then_some(Self::from_reduced(limbs))
+    }
+
+    /// Returns the little-endian limbs of the least integer of the element.
+    pub fn limbs(&self) -> [u64; N] {
+        self.limbs
+    }
+
+    /// Returns whether the element is 0.
+    pub fn is_zero(&self) -> bool {
+        self.limbs == [0; N]
+    }
+
+    /// Returns the element raised to the power with little-endian limbs
+    /// `exp`.
+    pub fn pow(&self, exp: &[u64]) -> Self {
+        let mut acc = Self::ONE;
+        for bit in (0..exp.len() * 64).rev() {
+            acc = acc * acc;
+            if (exp[bit / 64] >> (bit % 64)) & 1 == 1 {
+                acc = acc * *self;
+            }
+        }
+        acc
+    }
+
+    /// Returns the inverse of the element, or `None` if it is 0.
+    pub fn invert(&self) -> Option<Self> {
+        // By Fermat's little theorem, x^(p - 2) is the inverse of x.
+        let mut two = [0; N];
+        two[0] = 2;
+        (!self.is_zero()).then(|| self.pow(&sub(&M::MODULUS, &two)))
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Clone for Fp<M, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Copy for Fp<M, N> {}
+
+impl<M: Modulus<N>, const N: usize> PartialEq for Fp<M, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.limbs == other.limbs
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Eq for Fp<M, N> {}
+
+impl<M: Modulus<N>, const N: usize> core::fmt::Debug for Fp<M, N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("Fp").field(&self.limbs).finish()
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Add for Fp<M, N> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::from_reduced(bigint(OP_ADD, &self.limbs, &rhs.limbs, &M::MODULUS))
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Neg for Fp<M, N> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self.is_zero() {
+            true => self,
+            false => Self::from_reduced(sub(&M::MODULUS, &self.limbs)),
+        }
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Sub for Fp<M, N> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl<M: Modulus<N>, const N: usize> Mul for Fp<M, N> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::from_reduced(bigint(OP_MUL, &self.limbs, &rhs.limbs, &M::MODULUS))
+    }
+}
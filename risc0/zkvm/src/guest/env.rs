@@ -14,15 +14,17 @@
 
 //! Functions for interacting with the host environment.
 
-use core::{cell::UnsafeCell, default::Default, mem::MaybeUninit, ptr, ptr::null_mut, slice};
+use alloc::vec::Vec;
+use core::{cell::UnsafeCell, default::Default, mem::MaybeUninit, ptr, slice};
 
 use bytemuck::Pod;
 use risc0_zkp::core::digest::{Digest, DIGEST_BYTES, DIGEST_WORDS};
 use risc0_zkvm_platform::{
     fileno, memory, syscall,
     syscall::{
-        nr::SYS_LOG, sys_alloc_words, sys_cycle_count, sys_halt, sys_log, sys_output, sys_pause,
-        sys_read, sys_read_words, sys_write, syscall_0, syscall_2, SyscallName,
+        log_level, sys_alloc_words, sys_cycle_count, sys_halt, sys_io, sys_log, sys_output,
+        sys_pause, sys_read, sys_read_words, sys_region_end, sys_region_start, sys_verify,
+        sys_write, syscall_0, syscall_2, SyscallName,
     },
     WORD_SIZE,
 };
@@ -109,6 +111,26 @@ pub fn send_recv_slice<T: Pod, U: Pod>(syscall_name: SyscallName, to_host: &[T])
     &bytemuck::cast_slice(from_host_buf)[..nelem as usize]
 }
 
+/// Send `to_host` to the host callback registered as `name` with
+/// `ExecutorEnvBuilder::io_callback`, write as much of its reply as fits to
+/// `from_host`, and return the full length of the reply.
+///
+/// The callback runs once per call, so a reply longer than `from_host` is cut
+/// short rather than kept for a later call.
+pub fn send_recv(name: &str, to_host: &[u8], from_host: &mut [u8]) -> usize {
+    let len = unsafe {
+        sys_io(
+            name.as_ptr(),
+            name.len(),
+            to_host.as_ptr(),
+            to_host.len(),
+            from_host.as_mut_ptr(),
+            from_host.len(),
+        )
+    };
+    len as usize
+}
+
 /// Read private data from the host and deserializes it.
 pub fn read<T: DeserializeOwned>() -> T {
     stdin().read()
@@ -153,17 +175,75 @@ pub fn commit_slice<T: Pod>(slice: &[T]) {
     journal().write_slice(slice);
 }
 
+/// Returns the program arguments the host passed, starting with the program
+/// name.
+///
+/// They are read from where the Linux ABI has them at the initial stack
+/// pointer, above the stack.
+pub fn args() -> Vec<&'static str> {
+    unsafe {
+        let vector = memory::STACK_INITIAL_ADDRESS as *const usize;
+        (1..=*vector)
+            .map(|idx| {
+                let arg = core::ffi::CStr::from_ptr(*vector.add(idx) as *const _);
+                arg.to_str().unwrap()
+            })
+            .collect()
+    }
+}
+
 /// Return the number of processor cycles that have occured since the guest
 /// began.
 pub fn get_cycle_count() -> usize {
     unsafe { sys_cycle_count() }
 }
 
-/// Print a message to the debug console.
+/// Enter the profiling region `id`, named `name`.
+///
+/// The host adds up the cycles spent between this and the [end_region] of the
+/// same id under `name`, and reports them with the session. Regions with
+/// different ids may overlap.
+pub fn start_region(name: &str, id: u64) {
+    unsafe { sys_region_start(name.as_ptr(), name.len(), id) }
+}
+
+/// Leave the profiling region `id`.
+pub fn end_region(id: u64) {
+    unsafe { sys_region_end(id) }
+}
+
+/// Assume that the guest with image ID `image_id` ran to completion, with
+/// exit code 0, and committed `journal`.
+///
+/// The host must have been given that claim, e.g. from the session of the
+/// other guest with `ExecutorEnvBuilder::add_assumption`, and otherwise ends
+/// execution with an error. The claim is listed among the assumptions of
+/// the session, which holds only if they do.
+pub fn verify(image_id: Digest, journal: &[u8]) {
+    // The claim names the journal digest the segments of the other guest bind:
+    // the SHA-256 digest of the digest so far and each byte in turn, from zero.
+    let journal_digest = journal.iter().fold([0u8; DIGEST_BYTES], |digest, byte| {
+        let mut hasher = Sha256::new();
+        hasher.update(digest);
+        hasher.update([*byte]);
+        hasher.finalize().into()
+    });
+    unsafe { sys_verify(image_id.as_ref(), &journal_digest) }
+}
+
+/// Log a message on the host at the info level.
 pub fn log(msg: &str) {
+    log_at(log_level::INFO, msg)
+}
+
+/// Log a message on the host at `level`, one of [log_level].
+///
+/// The host logs it with the `log` crate, under the name of the guest, so
+/// that guest diagnostics are filtered along with the host's own.
+pub fn log_at(level: u64, msg: &str) {
     let msg = msg.as_bytes();
     unsafe {
-        sys_log(msg.as_ptr(), msg.len());
+        sys_log(level, msg.as_ptr(), msg.len());
     }
 }
 
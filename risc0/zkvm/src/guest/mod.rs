@@ -58,32 +58,24 @@
 #![deny(missing_docs)]
 
 mod alloc;
+pub mod bigint;
 pub mod env;
+pub mod poseidon;
 pub mod sha;
 
 use core::{arch::asm, mem, ptr};
 
 use getrandom::{register_custom_getrandom, Error};
-use risc0_zkvm_platform::{
-    syscall::{nr::SYS_PANIC, sys_panic, sys_rand},
-    WORD_SIZE,
-};
+use risc0_zkvm_platform::syscall::{sys_getrandom, sys_panic, sys_panic_at};
 
 pub use crate::entry;
 
 /// This is a getrandom handler for the zkvm. It's intended to hook into a
 /// getrandom crate or a depdent of the getrandom crate used by the guest code.
 pub fn zkvm_getrandom(dest: &mut [u8]) -> Result<(), Error> {
-    if dest.is_empty() {
-        return Ok(());
-    }
-
-    let words = (dest.len() + WORD_SIZE - 1) / WORD_SIZE;
-    let mut buf = ::alloc::vec![0u32; words];
     unsafe {
-        sys_rand(buf.as_mut_ptr(), words);
+        sys_getrandom(dest.as_mut_ptr(), dest.len());
     }
-    dest.clone_from_slice(&bytemuck::cast_slice(buf.as_slice())[..dest.len()]);
     Ok(())
 }
 
@@ -113,14 +105,30 @@ pub fn abort(msg: &str) -> ! {
     _fault()
 }
 
+/// Aborts the guest with the given message, reporting it as raised at `line`
+/// of `file`.
+pub fn abort_at(msg: &str, file: &str, line: u32) -> ! {
+    unsafe {
+        sys_panic_at(msg.as_ptr(), msg.len(), file.as_ptr(), file.len(), line);
+    }
+}
+
 #[cfg(all(not(feature = "std"), target_os = "zkvm"))]
 mod handlers {
     use core::{alloc::Layout, panic::PanicInfo};
 
     #[panic_handler]
     fn panic_fault(panic_info: &PanicInfo) -> ! {
-        let msg = ::alloc::format!("{}", panic_info);
-        crate::guest::abort(&msg)
+        match (panic_info.message(), panic_info.location()) {
+            (Some(msg), Some(location)) => {
+                let msg = ::alloc::format!("{msg}");
+                crate::guest::abort_at(&msg, location.file(), location.line())
+            }
+            _ => {
+                let msg = ::alloc::format!("{}", panic_info);
+                crate::guest::abort(&msg)
+            }
+        }
     }
 
     #[alloc_error_handler]
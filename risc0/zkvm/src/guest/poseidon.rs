@@ -0,0 +1,59 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Poseidon hashing over the Pallas base field, accelerated by the host.
+//!
+//! Field elements are 32 little-endian bytes, and must be canonical. This is
+//! the hash to use for Merkle trees whose proofs a guest verifies, as it is
+//! far cheaper than SHA-256 to prove with a folding backend over the Pasta
+//! cycle.
+
+use alloc::vec::Vec;
+
+use risc0_zkvm_platform::syscall::{sys_poseidon, MAX_POSEIDON_ELEMENTS};
+
+/// A field element, in 32 little-endian bytes
+pub type Element = [u8; 32];
+
+/// Returns the hash of the Merkle tree node with children `left` and
+/// `right`, or `None` if either is not canonical.
+pub fn hash_pair(left: &Element, right: &Element) -> Option<Element> {
+    hash(&[*left, *right])
+}
+
+/// Returns the hash of `elements`, of which there are at least two, each
+/// hashed with the hash of those before it, or `None` if one is not
+/// canonical.
+pub fn hash(elements: &[Element]) -> Option<Element> {
+    assert!(
+        elements.len() >= 2,
+        "Poseidon hash of fewer than two elements"
+    );
+    let (first, rest) = elements.split_at(elements.len().min(MAX_POSEIDON_ELEMENTS));
+    let mut acc = call(first)?;
+    // Longer inputs continue from the hash of the elements so far.
+    for chunk in rest.chunks(MAX_POSEIDON_ELEMENTS - 1) {
+        let mut next = Vec::with_capacity(chunk.len() + 1);
+        next.push(acc);
+        next.extend_from_slice(chunk);
+        acc = call(&next)?;
+    }
+    Some(acc)
+}
+
+fn call(elements: &[Element]) -> Option<Element> {
+    let mut out = [0; 32];
+    let ret = unsafe { sys_poseidon(out.as_mut_ptr(), elements.as_ptr().cast(), elements.len()) };
+    (ret == 0).then_some(out)
+}
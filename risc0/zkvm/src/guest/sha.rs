@@ -72,7 +72,7 @@ fn compress_slice(out_state: *mut Digest, in_state: *const Digest, blocks: &[Blo
             out_state.cast(),
             in_state.cast(),
             bytemuck::cast_slice(blocks).as_ptr(),
-            blocks.len() as u32,
+            blocks.len(),
         );
     }
 }
@@ -151,7 +151,7 @@ fn copy_and_update(
                         out_state.cast(),
                         in_state.cast(),
                         bytemuck::cast_slice(blocks).as_ptr(),
-                        blocks.len() as u32,
+                        blocks.len(),
                     );
                 }
                 in_state = out_state;
@@ -183,7 +183,7 @@ pub(crate) fn update_u32(
                         out_state.cast(),
                         in_state.cast(),
                         bytemuck::cast_slice(blocks).as_ptr(),
-                        blocks.len() as u32,
+                        blocks.len(),
                     );
                 }
                 in_state = out_state;
@@ -211,7 +211,7 @@ fn update_u8(out_state: *mut Digest, mut in_state: *const Digest, bytes: &[u8],
                         out_state.cast(),
                         in_state.cast(),
                         bytemuck::cast_slice(blocks).as_ptr(),
-                        blocks.len() as u32,
+                        blocks.len(),
                     );
                 }
                 in_state = out_state;
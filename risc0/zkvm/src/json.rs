@@ -0,0 +1,210 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The JSON form of receipts, for tooling, explorers and debugging, where the
+//! [CBOR](crate::Receipt::to_cbor) is inconvenient. Digests and byte strings
+//! are hex encoded.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    claim::{ExitCode, Journal, SegmentIo},
+    receipt::{check_version, Receipt, Seal},
+    Digest,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReceiptJson {
+    version: u32,
+    segments: Vec<SegmentIoJson>,
+    #[serde(with = "hex_bytes")]
+    journal: Vec<u8>,
+    exit_code: ExitCode,
+    seal: SealJson,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SegmentIoJson {
+    #[serde(with = "hex_digest")]
+    pre_image_id: Digest,
+    pre_pc: u64,
+    pre_journal_len: u64,
+    #[serde(with = "hex_digest")]
+    pre_journal_digest: Digest,
+    #[serde(with = "hex_digest")]
+    post_image_id: Digest,
+    post_pc: u64,
+    journal_len: u64,
+    #[serde(with = "hex_digest")]
+    journal_digest: Digest,
+    exit_code: ExitCode,
+    #[serde(with = "hex_digest")]
+    assumptions_digest: Digest,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SealJson {
+    Fake,
+}
+
+impl Receipt {
+    /// Returns the receipt as pretty-printed JSON: an object with the format
+    /// `version`, as [Receipt::version], the public IO of each segment under
+    /// `segments`, the hex of the journal bytes under `journal`, the
+    /// `exit_code`, and the `seal`. Digests are the hex of their bytes, as
+    /// [Digest] displays.
+    pub fn to_json(&self) -> String {
+        let io = |io: &SegmentIo| SegmentIoJson {
+            pre_image_id: io.pre_image_id,
+            pre_pc: io.pre_pc,
+            pre_journal_len: io.pre_journal_len,
+            pre_journal_digest: io.pre_journal_digest,
+            post_image_id: io.post_image_id,
+            post_pc: io.post_pc,
+            journal_len: io.journal_len,
+            journal_digest: io.journal_digest,
+            exit_code: io.exit_code,
+            assumptions_digest: io.assumptions_digest,
+        };
+        let json = ReceiptJson {
+            version: self.version,
+            segments: self.segments.iter().map(io).collect(),
+            journal: self.journal.bytes.clone(),
+            exit_code: self.exit_code,
+            seal: match self.seal {
+                Seal::Fake => SealJson::Fake,
+            },
+        };
+        serde_json::to_string_pretty(&json).expect("a receipt is always valid JSON")
+    }
+
+    /// Reads a receipt from JSON, as by [Receipt::to_json]. Fails on receipts
+    /// of a format version that this release does not support.
+    pub fn from_json(json: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        // Check the version first, as the rest may be laid out differently.
+        let Version { version } =
+            serde_json::from_str(json).context("decoding receipt JSON version")?;
+        check_version(version)?;
+        let json: ReceiptJson = serde_json::from_str(json).context("decoding receipt JSON")?;
+        let io = |io: SegmentIoJson| SegmentIo {
+            pre_image_id: io.pre_image_id,
+            pre_pc: io.pre_pc,
+            pre_journal_len: io.pre_journal_len,
+            pre_journal_digest: io.pre_journal_digest,
+            post_image_id: io.post_image_id,
+            post_pc: io.post_pc,
+            journal_len: io.journal_len,
+            journal_digest: io.journal_digest,
+            exit_code: io.exit_code,
+            assumptions_digest: io.assumptions_digest,
+        };
+        Ok(Self {
+            version: json.version,
+            segments: json.segments.into_iter().map(io).collect(),
+            journal: Journal::new(json.journal),
+            exit_code: json.exit_code,
+            seal: match json.seal {
+                SealJson::Fake => Seal::Fake,
+            },
+        })
+    }
+}
+
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        hex::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+mod hex_digest {
+    use serde::{de::Error, Deserializer, Serializer};
+
+    use crate::Digest;
+
+    pub fn serialize<S: Serializer>(digest: &Digest, serializer: S) -> Result<S::Ok, S::Error> {
+        super::hex_bytes::serialize(digest.as_bytes(), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Digest, D::Error> {
+        let bytes = super::hex_bytes::deserialize(deserializer)?;
+        Digest::from_bytes(&bytes)
+            .ok_or_else(|| D::Error::custom(format!("digest has {} bytes", bytes.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        binfmt::digest::sha256,
+        claim::{chain_journal_digest, ExitCode, Journal, SegmentIo},
+        receipt::{Receipt, Seal, RECEIPT_VERSION},
+        Digest,
+    };
+
+    #[test]
+    fn round_trip() {
+        let journal = b"public".to_vec();
+        let io = SegmentIo {
+            pre_image_id: sha256(b"pre"),
+            pre_pc: 0x1000,
+            pre_journal_len: 0,
+            pre_journal_digest: Digest::ZERO,
+            post_image_id: sha256(b"post"),
+            post_pc: 0x1100,
+            journal_len: journal.len() as u64,
+            journal_digest: chain_journal_digest(&Digest::ZERO, &journal),
+            exit_code: ExitCode::Halted(0),
+            assumptions_digest: Digest::ZERO,
+        };
+        let receipt = Receipt {
+            version: RECEIPT_VERSION,
+            segments: vec![io],
+            journal: Journal::new(journal),
+            exit_code: ExitCode::Halted(0),
+            seal: Seal::Fake,
+        };
+        let json = receipt.to_json();
+        assert!(json.contains(&format!("\"pre_image_id\": \"{}\"", io.pre_image_id)));
+        assert!(json.contains("\"journal\": \"7075626c6963\""));
+        assert_eq!(Receipt::from_json(&json).unwrap(), receipt);
+
+        // A digest must be whole.
+        let truncated = json.replace(&io.post_image_id.to_string(), "00");
+        assert!(Receipt::from_json(&truncated).is_err());
+        assert!(Receipt::from_json(&json.replace("7075", "zz")).is_err());
+        let newer = json.replace(
+            &format!("\"version\": {RECEIPT_VERSION}"),
+            &format!("\"version\": {}", RECEIPT_VERSION + 1),
+        );
+        assert!(Receipt::from_json(&newer)
+            .unwrap_err()
+            .to_string()
+            .contains("format version"));
+    }
+}
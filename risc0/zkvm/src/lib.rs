@@ -14,33 +14,74 @@
 
 #![doc = include_str!("../README.md")]
 #![cfg_attr(not(feature = "std"), no_std)]
-#![cfg_attr(not(feature = "std"), feature(alloc_error_handler))]
+#![cfg_attr(not(feature = "std"), feature(alloc_error_handler, panic_info_message))]
 #![deny(rustdoc::broken_intra_doc_links)]
 #![deny(missing_docs)]
 
 extern crate alloc;
 
-#[cfg(feature = "binfmt")]
+#[cfg(any(feature = "binfmt", feature = "verify"))]
 pub mod binfmt;
+#[cfg(feature = "verify")]
+mod cbor;
+#[cfg(feature = "verify")]
+mod claim;
 #[cfg(feature = "prove")]
 mod exec;
 #[cfg(any(target_os = "zkvm", doc))]
 pub mod guest;
+#[cfg(feature = "verify")]
+mod json;
 #[cfg(feature = "prove")]
 mod opcode;
+#[cfg(feature = "verify")]
+mod receipt;
 pub mod serde;
 #[cfg(feature = "prove")]
 mod session;
+#[cfg(all(test, feature = "binfmt"))]
+mod testutil;
 
 pub use anyhow::Result;
 pub use risc0_zkvm_platform::{declare_syscall, memory::MEM_SIZE, PAGE_SIZE};
 
+#[cfg(any(feature = "binfmt", feature = "verify"))]
+pub use self::binfmt::digest::Digest;
 #[cfg(feature = "binfmt")]
-pub use self::binfmt::{elf::Program, image::MemoryImage};
+pub use self::binfmt::{
+    builder::{MemoryImageBuilder, MAX_ARGS},
+    checkpoint::Checkpoint,
+    diff::PageDelta,
+    elf::{LoadOptions, Program, ProgramSegment, SegmentFlags},
+    image::{MemoryImage, PageTableInfo, PageTableLayer},
+    integrity::{EntryMismatch, IntegrityReport},
+    merkle::MerkleTree,
+    paged::{MemoryBackend, Page, PagedMemory},
+    region::{HeapRegion, MmioMemory, ReadOnlyMemory, RegionInfo},
+    store::PageStore,
+};
+#[cfg(feature = "verify")]
+pub use self::claim::{
+    assumptions_digest, chain_journal_digest, verify_segments, Assumption, ExitCode, GuestFault,
+    Journal, JournalCursor, SegmentIo,
+};
+#[cfg(all(feature = "prove", feature = "profiler"))]
+pub use self::exec::{BasicBlock, Coverage, Frame, Profiler};
+#[cfg(feature = "verify")]
+pub use self::receipt::{
+    dev_mode, Receipt, Seal, DEV_MODE_ENV, MIN_RECEIPT_VERSION, RECEIPT_VERSION,
+};
 #[cfg(feature = "prove")]
 pub use self::{
-    exec::{Executor, ExecutorEnv, ExecutorEnvBuilder},
-    session::{ExitCode, Segment, Session},
+    exec::{
+        BacktraceFrame, CustomInsnHandler, CustomOpcode, Divergence, EcallContext, EcallHandler,
+        Executor, ExecutorEnv, ExecutorEnvBuilder, ExecutorHooks, FloatSupport, GdbOutcome,
+        GdbStub, GuestPanic, MemAccess, MemTrace, MemTuple, MemoryCheck, MulDivAdvice,
+        PoseidonHash, Progress, ReferenceStep, ReferenceTrace, RegAccess, ShaCompression,
+        StepActivity, SyscallEvent, SyscallRecord, SyscallTrace, UninitLoad, WatchKind,
+        WatchpointHit, WitnessCollector, WitnessGenerator,
+    },
+    session::{OpcodeStats, RegionStats, Segment, Session},
 };
 
 /// Align the given address `addr` upwards to alignment `align`.
@@ -16,8 +16,25 @@ use anyhow::{bail, Result};
 use num_traits::FromPrimitive;
 use rrs_lib::{instruction_string_outputter::InstructionStringOutputter, process_instruction};
 
+/// The major opcode of the conditional branches
+pub(crate) const OPCODE_BRANCH: u32 = 0b1100011;
+/// The major opcode of `jal`
+pub(crate) const OPCODE_JAL: u32 = 0b1101111;
+/// The major opcode of `jalr`
+pub(crate) const OPCODE_JALR: u32 = 0b1100111;
+/// The major opcode of `ecall`, `ebreak` and the CSR instructions
+pub(crate) const OPCODE_SYSTEM: u32 = 0b1110011;
+/// The major opcode of the ALU instructions with an immediate operand
+pub(crate) const OPCODE_OP_IMM: u32 = 0b0010011;
+/// The major opcode of the 32-bit ALU instructions with an immediate operand
+pub(crate) const OPCODE_OP_IMM_32: u32 = 0b0011011;
+/// The major opcode of the ALU instructions with register operands
+pub(crate) const OPCODE_OP: u32 = 0b0110011;
+/// The major opcode of the 32-bit ALU instructions with register operands
+pub(crate) const OPCODE_OP_32: u32 = 0b0111011;
+
 #[allow(dead_code)]
-#[derive(Debug, num_derive::FromPrimitive, PartialEq)]
+#[derive(Clone, Copy, Debug, num_derive::FromPrimitive, PartialEq)]
 #[repr(u32)]
 pub enum MajorType {
     Compute0,
@@ -46,8 +63,8 @@ pub struct OpCode {
 }
 
 impl MajorType {
-    pub fn as_u32(self) -> u32 {
-        self as u32
+    pub fn as_u32(&self) -> u32 {
+        *self as u32
     }
 }
 
@@ -89,6 +106,10 @@ impl OpCode {
         // RV64 bit 25 is used as shamt[5]
         let funct7_rv64 = (insn & 0xfc000000) >> 26;
         let funct5 = (insn & 0xf8000000) >> 27;
+        // Bit manipulation shares encodings with the base instructions.
+        if let Some(op) = BitOp::decode(insn) {
+            return Ok(OpCode::new(insn, insn_pc, op.mnemonic(), 0, 1));
+        }
         Ok(match opcode {
             0b0000011 => match funct3 {
                 0x0 => OpCode::new(insn, insn_pc, "LB", 24, 1),
@@ -99,12 +120,11 @@ impl OpCode {
                 0x5 => OpCode::new(insn, insn_pc, "LHU", 29, 1),
                 0x6 => OpCode::new(insn, insn_pc, "LWU", 30, 1),
                 _ => {
-                    println!("opcode {:#8x}", insn);
-                    log::debug!("opcode {:?}", opcode);
+                    tracing::debug!("opcode {:#8x}: {:?}", insn, opcode);
                     unreachable!()
                 }
             },
-            0b0010011 => match funct3 {
+            OPCODE_OP_IMM => match funct3 {
                 0x0 => OpCode::new(insn, insn_pc, "ADDI", 7, 1),
                 0x1 => OpCode::new(insn, insn_pc, "SLLI", 37, 1),
                 0x2 => OpCode::new(insn, insn_pc, "SLTI", 11, 1),
@@ -114,7 +134,7 @@ impl OpCode {
                     0b000000 => OpCode::new(insn, insn_pc, "SRLI", 46, 2),
                     0b010000 => OpCode::new(insn, insn_pc, "SRAI", 47, 2),
                     _ => {
-                        println!("funct7 {:8x}", funct7);
+                        tracing::debug!("funct7 {:8x}", funct7);
                         unreachable!()
                     }
                 },
@@ -130,7 +150,7 @@ impl OpCode {
                 0x3 => OpCode::new(insn, insn_pc, "SD", 31, 1),
                 _ => unreachable!(),
             },
-            0b0110011 => match (funct3, funct7) {
+            OPCODE_OP => match (funct3, funct7) {
                 (0x0, 0x00) => OpCode::new(insn, insn_pc, "ADD", 0, 1),
                 (0x0, 0x20) => OpCode::new(insn, insn_pc, "SUB", 1, 1),
                 (0x1, 0x00) => OpCode::new(insn, insn_pc, "SLL", 36, 1),
@@ -143,29 +163,39 @@ impl OpCode {
                 (0x7, 0x00) => OpCode::new(insn, insn_pc, "AND", 4, 2),
                 (0x0, 0x01) => OpCode::new(insn, insn_pc, "MUL", 32, 1),
                 (0x1, 0x01) => OpCode::new(insn, insn_pc, "MULH", 33, 1),
-                (0x2, 0x01) => OpCode::new(insn, insn_pc, "MULSU", 34, 1),
-                (0x3, 0x01) => OpCode::new(insn, insn_pc, "MULU", 35, 1),
+                (0x2, 0x01) => OpCode::new(insn, insn_pc, "MULHSU", 34, 1),
+                (0x3, 0x01) => OpCode::new(insn, insn_pc, "MULHU", 35, 1),
                 (0x4, 0x01) => OpCode::new(insn, insn_pc, "DIV", 40, 2),
                 (0x5, 0x01) => OpCode::new(insn, insn_pc, "DIVU", 41, 2),
                 (0x6, 0x01) => OpCode::new(insn, insn_pc, "REM", 42, 2),
                 (0x7, 0x01) => OpCode::new(insn, insn_pc, "REMU", 43, 2),
                 _ => unreachable!(),
             },
-            0b0101111 => match (funct3, funct5) {
-                (0b010, 0b00001) => OpCode::new(insn, insn_pc, "AMOSWAP.W", 0, 1),
-                (0b010, 0b00010) => OpCode::new(insn, insn_pc, "LR.W", 2, 1),
-                (0b010, 0b00011) => OpCode::new(insn, insn_pc, "SC.W", 3, 1),
-                (0b010, 0b01000) => OpCode::new(insn, insn_pc, "AMOOR.W", 0, 1),
-                (0b010, 0b00000) => OpCode::new(insn, insn_pc, "AMOADD.W", 1, 1),
-                (0b010, 0b01100) => OpCode::new(insn, insn_pc, "AMOAND.W", 0, 1),
-                (0b011, 0b00000) => OpCode::new(insn, insn_pc, "AMOADD.D", 1, 1),
-                (0b011, 0b00001) => OpCode::new(insn, insn_pc, "AMOSWAP.D", 2, 1),
-                (0b011, 0b00010) => OpCode::new(insn, insn_pc, "LR.D", 3, 1),
-                (0b011, 0b00011) => OpCode::new(insn, insn_pc, "SC.D", 4, 1),
-                _ => unreachable!(),
-            },
+            0b0101111 => {
+                let width = match funct3 {
+                    0b010 => 0,
+                    0b011 => 1,
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+                let (mnemonics, cycles) = match funct5 {
+                    0b00010 => (["LR.W", "LR.D"], 1),
+                    0b00011 => (["SC.W", "SC.D"], 1),
+                    0b00000 => (["AMOADD.W", "AMOADD.D"], 2),
+                    0b00001 => (["AMOSWAP.W", "AMOSWAP.D"], 2),
+                    0b00100 => (["AMOXOR.W", "AMOXOR.D"], 2),
+                    0b01000 => (["AMOOR.W", "AMOOR.D"], 2),
+                    0b01100 => (["AMOAND.W", "AMOAND.D"], 2),
+                    0b10000 => (["AMOMIN.W", "AMOMIN.D"], 2),
+                    0b10100 => (["AMOMAX.W", "AMOMAX.D"], 2),
+                    0b11000 => (["AMOMINU.W", "AMOMINU.D"], 2),
+                    0b11100 => (["AMOMAXU.W", "AMOMAXU.D"], 2),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+                // Atomics are memory operations of the width of LW or LD.
+                OpCode::new(insn, insn_pc, mnemonics[width], 26 + width as u32, cycles)
+            }
             0b0110111 => OpCode::new(insn, insn_pc, "LUI", 21, 1),
-            0b1100011 => match funct3 {
+            OPCODE_BRANCH => match funct3 {
                 0x0 => OpCode::new(insn, insn_pc, "BEQ", 13, 1),
                 0x1 => OpCode::new(insn, insn_pc, "BNE", 14, 1),
                 0x4 => OpCode::new(insn, insn_pc, "BLT", 15, 1),
@@ -174,21 +204,24 @@ impl OpCode {
                 0x7 => OpCode::new(insn, insn_pc, "BGEU", 18, 1),
                 _ => unreachable!(),
             },
-            0b1100111 => match funct3 {
+            OPCODE_JALR => match funct3 {
                 0x0 => OpCode::new(insn, insn_pc, "JALR", 20, 1),
                 _ => unreachable!(),
             },
-            0b0011011 => match funct3 {
+            OPCODE_OP_IMM_32 => match funct3 {
                 0b000 => OpCode::new(insn, insn_pc, "ADDIW", 0, 1),
                 _ => unreachable!(),
             },
-            0b0111011 => match (funct3, funct7) {
-                (0b000, 0b0000001) => OpCode::new(insn, insn_pc, "MULW", 0, 1),
-                (0b111, 0b0000001) => OpCode::new(insn, insn_pc, "REMUW", 1, 1),
+            OPCODE_OP_32 => match (funct3, funct7) {
+                (0b000, 0b0000001) => OpCode::new(insn, insn_pc, "MULW", 32, 1),
+                (0b100, 0b0000001) => OpCode::new(insn, insn_pc, "DIVW", 40, 2),
+                (0b101, 0b0000001) => OpCode::new(insn, insn_pc, "DIVUW", 41, 2),
+                (0b110, 0b0000001) => OpCode::new(insn, insn_pc, "REMW", 42, 2),
+                (0b111, 0b0000001) => OpCode::new(insn, insn_pc, "REMUW", 43, 2),
                 _ => unreachable!(),
             },
-            0b1101111 => OpCode::new(insn, insn_pc, "JAL", 19, 1),
-            0b1110011 => match funct3 {
+            OPCODE_JAL => OpCode::new(insn, insn_pc, "JAL", 19, 1),
+            OPCODE_SYSTEM => match funct3 {
                 0x0 => match (rs2, funct7) {
                     (0x0, 0x0) => {
                         OpCode::with_major_minor(insn, insn_pc, "ECALL", MajorType::ECall, 0, 1)
@@ -199,14 +232,112 @@ impl OpCode {
                     _ => unreachable!(),
                 },
                 // system call
-                0b010 => OpCode::new(insn, insn_pc, "RDTIME", 0, 1),
+                0b010 if insn >> 20 == 0xc00 => OpCode::new(insn, insn_pc, "RDCYCLE", 0, 1),
+                0b010 if insn >> 20 == 0xc01 => OpCode::new(insn, insn_pc, "RDTIME", 0, 1),
+                0b010 if insn >> 20 == 0xc02 => OpCode::new(insn, insn_pc, "RDINSTRET", 0, 1),
+                0b001 => OpCode::new(insn, insn_pc, "CSRRW", 0, 1),
+                0b010 => OpCode::new(insn, insn_pc, "CSRRS", 0, 1),
+                0b011 => OpCode::new(insn, insn_pc, "CSRRC", 0, 1),
+                0b101 => OpCode::new(insn, insn_pc, "CSRRWI", 0, 1),
+                0b110 => OpCode::new(insn, insn_pc, "CSRRSI", 0, 1),
+                0b111 => OpCode::new(insn, insn_pc, "CSRRCI", 0, 1),
                 _ => unreachable!(),
             },
-            0b0001111 => OpCode::new(insn, insn_pc, "FENCE", 0, 1),
+            0b0001111 => match funct3 {
+                0b000 => OpCode::new(insn, insn_pc, "FENCE", 0, 1),
+                0b001 => OpCode::new(insn, insn_pc, "FENCE.I", 0, 1),
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            },
+            0b0000111 => match funct3 {
+                0b010 => OpCode::new(insn, insn_pc, "FLW", 26, 1),
+                0b011 => OpCode::new(insn, insn_pc, "FLD", 27, 1),
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            },
+            0b0100111 => match funct3 {
+                0b010 => OpCode::new(insn, insn_pc, "FSW", 31, 1),
+                0b011 => OpCode::new(insn, insn_pc, "FSD", 31, 1),
+                _ => bail!("Illegal instruction: {insn:#010x}"),
+            },
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => {
+                let mnemonics = match opcode {
+                    0b1000011 => ["FMADD.S", "FMADD.D"],
+                    0b1000111 => ["FMSUB.S", "FMSUB.D"],
+                    0b1001011 => ["FNMSUB.S", "FNMSUB.D"],
+                    _ => ["FNMADD.S", "FNMADD.D"],
+                };
+                match funct7 & 0x3 {
+                    fmt @ (0 | 1) => OpCode::new(insn, insn_pc, mnemonics[fmt as usize], 0, 2),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                }
+            }
+            0b1010011 => {
+                let (mnemonics, cycles) = match (funct5, funct3, rs2) {
+                    (0b00000, _, _) => (["FADD.S", "FADD.D"], 1),
+                    (0b00001, _, _) => (["FSUB.S", "FSUB.D"], 1),
+                    (0b00010, _, _) => (["FMUL.S", "FMUL.D"], 1),
+                    (0b00011, _, _) => (["FDIV.S", "FDIV.D"], 2),
+                    (0b01011, _, 0) => (["FSQRT.S", "FSQRT.D"], 2),
+                    (0b00100, 0b000, _) => (["FSGNJ.S", "FSGNJ.D"], 1),
+                    (0b00100, 0b001, _) => (["FSGNJN.S", "FSGNJN.D"], 1),
+                    (0b00100, 0b010, _) => (["FSGNJX.S", "FSGNJX.D"], 1),
+                    (0b00101, 0b000, _) => (["FMIN.S", "FMIN.D"], 1),
+                    (0b00101, 0b001, _) => (["FMAX.S", "FMAX.D"], 1),
+                    (0b01000, _, _) => (["FCVT.S.D", "FCVT.D.S"], 1),
+                    (0b10100, 0b010, _) => (["FEQ.S", "FEQ.D"], 1),
+                    (0b10100, 0b001, _) => (["FLT.S", "FLT.D"], 1),
+                    (0b10100, 0b000, _) => (["FLE.S", "FLE.D"], 1),
+                    (0b11000, _, 0) => (["FCVT.W.S", "FCVT.W.D"], 1),
+                    (0b11000, _, 1) => (["FCVT.WU.S", "FCVT.WU.D"], 1),
+                    (0b11000, _, 2) => (["FCVT.L.S", "FCVT.L.D"], 1),
+                    (0b11000, _, 3) => (["FCVT.LU.S", "FCVT.LU.D"], 1),
+                    (0b11010, _, 0) => (["FCVT.S.W", "FCVT.D.W"], 1),
+                    (0b11010, _, 1) => (["FCVT.S.WU", "FCVT.D.WU"], 1),
+                    (0b11010, _, 2) => (["FCVT.S.L", "FCVT.D.L"], 1),
+                    (0b11010, _, 3) => (["FCVT.S.LU", "FCVT.D.LU"], 1),
+                    (0b11100, 0b000, 0) => (["FMV.X.W", "FMV.X.D"], 1),
+                    (0b11100, 0b001, 0) => (["FCLASS.S", "FCLASS.D"], 1),
+                    (0b11110, 0b000, 0) => (["FMV.W.X", "FMV.D.X"], 1),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                };
+                match funct7 & 0x3 {
+                    fmt @ (0 | 1) => OpCode::new(insn, insn_pc, mnemonics[fmt as usize], 0, cycles),
+                    _ => bail!("Illegal instruction: {insn:#010x}"),
+                }
+            }
+            0b0001011 => OpCode::new(insn, insn_pc, "CUSTOM-0", 0, 1),
+            0b0101011 => OpCode::new(insn, insn_pc, "CUSTOM-1", 0, 1),
+            0b1011011 => OpCode::new(insn, insn_pc, "CUSTOM-2", 0, 1),
+            0b1111011 => OpCode::new(insn, insn_pc, "CUSTOM-3", 0, 1),
             _ => bail!("Illegal opcode: 0b{opcode:07b}"),
         })
     }
 
+    /// Returns the ISA extension this instruction belongs to, e.g. `M` for
+    /// integer multiplication and division.
+    pub fn extension(&self) -> &'static str {
+        let opcode = self.insn & 0x0000007f;
+        let funct3 = (self.insn & 0x00007000) >> 12;
+        let funct7 = (self.insn & 0xfe000000) >> 25;
+        if let Some(op) = BitOp::decode(self.insn) {
+            return op.extension();
+        }
+        match opcode {
+            OPCODE_OP | OPCODE_OP_32 if funct7 == 0x01 => "M",
+            0b0101111 => "A",
+            0b0000111 | 0b0100111 if funct3 == 0b010 => "F",
+            // FCVT.S.D and FCVT.D.S are both part of D.
+            0b1010011 if funct7 >> 2 == 0b01000 => "D",
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 | 0b1010011 if funct7 & 1 == 0 => "F",
+            0b0000111 | 0b0100111 | 0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 | 0b1010011 => {
+                "D"
+            }
+            0b1110011 if funct3 != 0 => "Zicsr",
+            0b0001111 if funct3 == 0b001 => "Zifencei",
+            0b0001011 | 0b0101011 | 0b1011011 | 0b1111011 => "Xcustom",
+            _ => "I",
+        }
+    }
+
     #[allow(dead_code)]
     pub fn debug(&self, cycle: usize, insn_pc: u64) -> String {
         let mut outputter = InstructionStringOutputter { insn_pc };
@@ -230,3 +361,218 @@ impl core::fmt::Debug for OpCode {
         f.write_fmt(format_args!("{}", desc.unwrap_or(self.mnemonic.into())))
     }
 }
+
+/// A Zba, Zbb or Zbs bit manipulation instruction.
+///
+/// Several of these share their major opcode and funct3 with base
+/// instructions, such as `clz` with `slli`, so they must be decoded before the
+/// base instruction set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BitOp {
+    // Zba
+    AddUw,
+    Sh1add,
+    Sh1addUw,
+    Sh2add,
+    Sh2addUw,
+    Sh3add,
+    Sh3addUw,
+    SlliUw,
+    // Zbb
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Clzw,
+    Ctz,
+    Ctzw,
+    Cpop,
+    Cpopw,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Rolw,
+    Ror,
+    Rori,
+    Roriw,
+    Rorw,
+    OrcB,
+    Rev8,
+    // Zbs
+    Bclr,
+    Bclri,
+    Bext,
+    Bexti,
+    Binv,
+    Binvi,
+    Bset,
+    Bseti,
+}
+
+impl BitOp {
+    /// Decode `insn`, if it is a bit manipulation instruction.
+    pub(crate) fn decode(insn: u32) -> Option<Self> {
+        use BitOp::*;
+
+        let opcode = insn & 0x7f;
+        let funct3 = (insn >> 12) & 0x7;
+        let rs2 = (insn >> 20) & 0x1f;
+        let imm = insn >> 20;
+        let funct6 = insn >> 26;
+        let funct7 = insn >> 25;
+        Some(match (opcode, funct3) {
+            (OPCODE_OP, _) => match (funct7, funct3) {
+                (0b0010000, 0b010) => Sh1add,
+                (0b0010000, 0b100) => Sh2add,
+                (0b0010000, 0b110) => Sh3add,
+                (0b0100000, 0b111) => Andn,
+                (0b0100000, 0b110) => Orn,
+                (0b0100000, 0b100) => Xnor,
+                (0b0000101, 0b110) => Max,
+                (0b0000101, 0b111) => Maxu,
+                (0b0000101, 0b100) => Min,
+                (0b0000101, 0b101) => Minu,
+                (0b0110000, 0b001) => Rol,
+                (0b0110000, 0b101) => Ror,
+                (0b0100100, 0b001) => Bclr,
+                (0b0100100, 0b101) => Bext,
+                (0b0110100, 0b001) => Binv,
+                (0b0010100, 0b001) => Bset,
+                _ => return None,
+            },
+            (OPCODE_OP_32, _) => match (funct7, funct3) {
+                (0b0000100, 0b000) => AddUw,
+                (0b0000100, 0b100) if rs2 == 0 => ZextH,
+                (0b0010000, 0b010) => Sh1addUw,
+                (0b0010000, 0b100) => Sh2addUw,
+                (0b0010000, 0b110) => Sh3addUw,
+                (0b0110000, 0b001) => Rolw,
+                (0b0110000, 0b101) => Rorw,
+                _ => return None,
+            },
+            (OPCODE_OP_IMM, 0b001) => match (imm, funct6) {
+                (0x600, _) => Clz,
+                (0x601, _) => Ctz,
+                (0x602, _) => Cpop,
+                (0x604, _) => SextB,
+                (0x605, _) => SextH,
+                (_, 0b010010) => Bclri,
+                (_, 0b011010) => Binvi,
+                (_, 0b001010) => Bseti,
+                _ => return None,
+            },
+            (OPCODE_OP_IMM, 0b101) => match (imm, funct6) {
+                (0x287, _) => OrcB,
+                (0x6b8, _) => Rev8,
+                (_, 0b011000) => Rori,
+                (_, 0b010010) => Bexti,
+                _ => return None,
+            },
+            (OPCODE_OP_IMM_32, 0b001) => match (imm, funct6) {
+                (0x600, _) => Clzw,
+                (0x601, _) => Ctzw,
+                (0x602, _) => Cpopw,
+                (_, 0b000010) => SlliUw,
+                _ => return None,
+            },
+            (OPCODE_OP_IMM_32, 0b101) if funct7 == 0b0110000 => Roriw,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn mnemonic(self) -> &'static str {
+        use BitOp::*;
+
+        match self {
+            AddUw => "ADD.UW",
+            Sh1add => "SH1ADD",
+            Sh1addUw => "SH1ADD.UW",
+            Sh2add => "SH2ADD",
+            Sh2addUw => "SH2ADD.UW",
+            Sh3add => "SH3ADD",
+            Sh3addUw => "SH3ADD.UW",
+            SlliUw => "SLLI.UW",
+            Andn => "ANDN",
+            Orn => "ORN",
+            Xnor => "XNOR",
+            Clz => "CLZ",
+            Clzw => "CLZW",
+            Ctz => "CTZ",
+            Ctzw => "CTZW",
+            Cpop => "CPOP",
+            Cpopw => "CPOPW",
+            Max => "MAX",
+            Maxu => "MAXU",
+            Min => "MIN",
+            Minu => "MINU",
+            SextB => "SEXT.B",
+            SextH => "SEXT.H",
+            ZextH => "ZEXT.H",
+            Rol => "ROL",
+            Rolw => "ROLW",
+            Ror => "ROR",
+            Rori => "RORI",
+            Roriw => "RORIW",
+            Rorw => "RORW",
+            OrcB => "ORC.B",
+            Rev8 => "REV8",
+            Bclr => "BCLR",
+            Bclri => "BCLRI",
+            Bext => "BEXT",
+            Bexti => "BEXTI",
+            Binv => "BINV",
+            Binvi => "BINVI",
+            Bset => "BSET",
+            Bseti => "BSETI",
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        use BitOp::*;
+
+        match self {
+            AddUw | Sh1add | Sh1addUw | Sh2add | Sh2addUw | Sh3add | Sh3addUw | SlliUw => "Zba",
+            Bclr | Bclri | Bext | Bexti | Binv | Binvi | Bset | Bseti => "Zbs",
+            _ => "Zbb",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpCode;
+
+    #[test]
+    fn extensions() {
+        let extension = |insn| OpCode::decode(insn, 0).unwrap().extension();
+        // addi a0, a0, 1
+        assert_eq!(extension(0x00150513), "I");
+        // mul a0, a0, a1
+        assert_eq!(extension(0x02b50533), "M");
+        // remuw a0, a0, a1
+        assert_eq!(extension(0x02b5753b), "M");
+        // fadd.s fa0, fa0, fa1
+        assert_eq!(extension(0x00b57553), "F");
+        // fld fa0, 0(a0)
+        assert_eq!(extension(0x00053507), "D");
+        // fcvt.s.d fa0, fa0
+        assert_eq!(extension(0x40157553), "D");
+        // amoadd.w a0, a1, (a0)
+        assert_eq!(extension(0x00b5252f), "A");
+        // sh1add a0, a0, a1
+        assert_eq!(extension(0x20b52533), "Zba");
+        // fence.i
+        assert_eq!(extension(0x0000100f), "Zifencei");
+        // custom-0
+        assert_eq!(extension(0x00c5850b), "Xcustom");
+        // fence
+        assert_eq!(extension(0x0ff0000f), "I");
+        // ecall
+        assert_eq!(extension(0x00000073), "I");
+    }
+}
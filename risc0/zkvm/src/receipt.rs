@@ -0,0 +1,206 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Receipts, which carry the public claims of a session along with the proof
+//! of them that a verifier checks.
+
+use std::env;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    claim::{verify_segments, ExitCode, Journal, SegmentIo},
+    Digest,
+};
+
+/// The environment variable that enables dev mode, when set to `1` or
+/// `true`.
+pub const DEV_MODE_ENV: &str = "RISC0_DEV_MODE";
+
+/// Returns whether dev mode is enabled, with [DEV_MODE_ENV]. In dev mode,
+/// receipts with a [Seal::Fake] verify, so that guest developers can iterate
+/// on their application without proving. It must never be enabled where
+/// receipts are trusted.
+pub fn dev_mode() -> bool {
+    env::var(DEV_MODE_ENV).is_ok_and(|value| value == "1" || value == "true")
+}
+
+/// The version of the receipt format this release makes. It changes whenever
+/// the layout of a receipt, or what its seal proves, does.
+pub const RECEIPT_VERSION: u32 = 1;
+
+/// The oldest version of the receipt format this release still verifies.
+pub const MIN_RECEIPT_VERSION: u32 = 1;
+
+/// Check that this release can read and verify receipts of format `version`.
+pub(crate) fn check_version(version: u32) -> Result<()> {
+    if version > RECEIPT_VERSION {
+        bail!(
+            "Receipt has format version {version}, newer than version {RECEIPT_VERSION} \
+             of this release; upgrade to verify it"
+        );
+    }
+    if version < MIN_RECEIPT_VERSION {
+        bail!(
+            "Receipt has format version {version}, which this release no longer verifies; \
+             the oldest it verifies is {MIN_RECEIPT_VERSION}"
+        );
+    }
+    Ok(())
+}
+
+/// The proof a [Receipt] carries of the public IO of its segments
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Seal {
+    /// No proof at all, as made in dev mode by
+    /// [Session::dev_receipt](crate::Session::dev_receipt). Such a receipt
+    /// makes claims that nothing backs, and verifies only in
+    /// [dev_mode].
+    Fake,
+}
+
+/// The result of proving a session: what the guest committed and how it
+/// exited, the public IO of each of its segments, and the seal proving them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The version of the receipt format, [RECEIPT_VERSION] for receipts
+    /// made by this release
+    pub version: u32,
+
+    /// The public IO of the segments of the session, in order
+    pub segments: Vec<SegmentIo>,
+
+    /// The data publicly committed by the guest
+    pub journal: Journal,
+
+    /// How the session exited
+    pub exit_code: ExitCode,
+
+    /// The proof of the public IO of the segments
+    pub seal: Seal,
+}
+
+impl Receipt {
+    /// Check that the receipt proves a session of the guest with the given
+    /// image ID: that this release supports its format version, that its
+    /// segments chain from that image over the journal to
+    /// the exit code, as by [verify_segments](crate::verify_segments), that
+    /// they assumed no other claims, and that the seal proves them. A fake
+    /// seal passes only in [dev_mode].
+    pub fn verify(&self, image_id: Digest) -> Result<()> {
+        check_version(self.version)?;
+        verify_segments(&self.segments, &self.journal, self.exit_code)?;
+        if self.segments[0].pre_image_id != image_id {
+            bail!("Receipt is not of image {image_id}");
+        }
+        if let Some(idx) = self
+            .segments
+            .iter()
+            .position(|io| io.assumptions_digest != Digest::ZERO)
+        {
+            bail!("Segment {idx} assumed claims that the receipt does not prove");
+        }
+        match self.seal {
+            Seal::Fake if dev_mode() => Ok(()),
+            Seal::Fake => bail!(
+                "Receipt has a fake seal, which proves nothing; set {DEV_MODE_ENV}=1 to accept it"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::{Receipt, Seal, DEV_MODE_ENV, RECEIPT_VERSION};
+    use crate::{
+        binfmt::digest::sha256,
+        claim::{chain_journal_digest, ExitCode, Journal, SegmentIo},
+        Digest,
+    };
+
+    // A receipt for a single segment that halts after committing `journal`.
+    fn receipt(image_id: Digest, journal: &[u8]) -> Receipt {
+        let io = SegmentIo {
+            pre_image_id: image_id,
+            pre_pc: 0x1000,
+            pre_journal_len: 0,
+            pre_journal_digest: Digest::ZERO,
+            post_image_id: sha256(b"post"),
+            post_pc: 0x1100,
+            journal_len: journal.len() as u64,
+            journal_digest: chain_journal_digest(&Digest::ZERO, journal),
+            exit_code: ExitCode::Halted(0),
+            assumptions_digest: Digest::ZERO,
+        };
+        Receipt {
+            version: RECEIPT_VERSION,
+            segments: vec![io],
+            journal: Journal::new(journal.to_vec()),
+            exit_code: ExitCode::Halted(0),
+            seal: Seal::Fake,
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let image_id = sha256(b"image");
+        let receipt = receipt(image_id, b"public");
+        let err = |receipt: &Receipt, image_id| receipt.verify(image_id).unwrap_err().to_string();
+
+        assert!(err(&receipt, sha256(b"other")).contains("not of image"));
+
+        let mut tampered = receipt.clone();
+        tampered.journal = Journal::new(b"forged".to_vec());
+        assert!(err(&tampered, image_id).contains("journal"));
+
+        let mut tampered = receipt.clone();
+        tampered.exit_code = ExitCode::Halted(1);
+        assert!(err(&tampered, image_id).contains("not Halted(1)"));
+
+        let mut newer = receipt.clone();
+        newer.version = RECEIPT_VERSION + 1;
+        assert!(err(&newer, image_id).contains("upgrade"));
+        let mut older = receipt.clone();
+        older.version = 0;
+        assert!(err(&older, image_id).contains("no longer verifies"));
+
+        let mut conditional = receipt;
+        conditional.segments[0].assumptions_digest = sha256(b"assumption");
+        assert!(err(&conditional, image_id).contains("assumed"));
+    }
+
+    #[test]
+    fn dev_mode() {
+        let image_id = sha256(b"image");
+        let receipt = receipt(image_id, b"public");
+
+        // The public IO checks out, but nothing proves it.
+        env::remove_var(DEV_MODE_ENV);
+        let err = receipt.verify(image_id).unwrap_err().to_string();
+        assert!(err.contains("fake seal"));
+
+        env::set_var(DEV_MODE_ENV, "1");
+        receipt.verify(image_id).unwrap();
+        // Dev mode skips the seal, not the public IO.
+        assert!(receipt.verify(sha256(b"other")).is_err());
+
+        env::set_var(DEV_MODE_ENV, "0");
+        assert!(receipt.verify(image_id).is_err());
+        env::remove_var(DEV_MODE_ENV);
+    }
+}
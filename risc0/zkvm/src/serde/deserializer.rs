@@ -72,9 +72,30 @@ impl WordRead for &[u32] {
 /// Deserialize `slice` into type `T`. Returns an `Err` if deserialization isn't
 /// possible, such as if `slice` is not the serialized form of an object of type
 /// `T`.
+///
+/// `slice` need not be aligned to words: bytes, such as a journal, are copied
+/// into words first if they are not.
 pub fn from_slice<T: DeserializeOwned, P: Pod>(slice: &[P]) -> Result<T> {
-    let mut deserializer = Deserializer::new(bytemuck::cast_slice(slice));
-    T::deserialize(&mut deserializer)
+    match bytemuck::try_cast_slice(slice) {
+        Ok(words) => T::deserialize(&mut Deserializer::new(words)),
+        Err(_) => {
+            let words = to_words(bytemuck::cast_slice(slice));
+            T::deserialize(&mut Deserializer::new(words.as_slice()))
+        }
+    }
+}
+
+/// Pack `bytes` into little-endian words, padding the last with zeros, so that
+/// they can be deserialized whatever their alignment.
+pub(crate) fn to_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(WORD_SIZE)
+        .map(|chunk| {
+            let mut word = [0; WORD_SIZE];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect()
 }
 
 /// Enables deserializing from a WordRead
@@ -554,4 +575,14 @@ mod tests {
         };
         assert_eq!(expected, from_slice(&words).unwrap());
     }
+
+    #[test]
+    fn test_unaligned_bytes() {
+        let words: [u32; 4] = [1, 0x00000061, 7, 0];
+        let mut bytes = vec![0u8];
+        bytes.extend_from_slice(bytemuck::cast_slice(&words));
+        // Deserializing from an odd address copies the bytes into words.
+        let expected = (String::from("a"), 7u64);
+        assert_eq!(expected, from_slice(&bytes[1..]).unwrap());
+    }
 }
@@ -42,6 +42,8 @@ mod deserializer;
 mod err;
 mod serializer;
 
+#[cfg(feature = "verify")]
+pub(crate) use deserializer::to_words;
 pub use deserializer::{from_slice, Deserializer, WordRead};
 pub use err::{Error, Result};
 pub use serializer::{to_vec, to_vec_with_capacity, Serializer, WordWrite};
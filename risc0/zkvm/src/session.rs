@@ -15,27 +15,27 @@
 //! This module defines [Session] and [Segment] which provides a way to share
 //! execution traces between the execution phase and the proving phase.
 
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
 
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::{exec::SyscallRecord, MemoryImage};
-
-/// Indicates how a [Segment] or [Session]'s execution has terminated
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-pub enum ExitCode {
-    /// This indicates that the session limit has been reached.
-    SessionLimit,
-
-    /// This indicates normal termination of a program with an interior exit
-    /// code returned from the guest.
-    Halted(u32),
-}
+use crate::{
+    claim::{assumptions_digest, verify_segments, Assumption, ExitCode, Journal, SegmentIo},
+    exec::SyscallRecord,
+    receipt::{Receipt, Seal, RECEIPT_VERSION},
+    Digest,
+};
 
+/// The pages read and written during a [Segment], by page index.
+///
+/// Along with each accessed page, every page table page on its path up to the
+/// root is included, since those must be paged in (or out) to verify it.
 #[derive(Clone, Default, Serialize, Deserialize, Debug)]
 pub struct PageFaults {
-    pub(crate) reads: BTreeSet<u32>,
-    pub(crate) writes: BTreeSet<u32>,
+    pub(crate) reads: BTreeSet<u64>,
+    pub(crate) writes: BTreeSet<u64>,
 }
 
 /// The execution trace of a program.
@@ -53,10 +53,76 @@ pub struct Session {
     pub segments: Vec<Segment>,
 
     /// The data publicly committed by the guest program.
-    // pub journal: Vec<u8>,
+    pub journal: Journal,
 
     /// The [ExitCode] of the session.
     pub exit_code: ExitCode,
+
+    /// The instructions executed during the session.
+    pub opcode_stats: OpcodeStats,
+
+    /// The cycles spent in the regions the guest annotated, by name.
+    pub region_stats: BTreeMap<String, RegionStats>,
+
+    /// The claims about other guests that the guest assumed with
+    /// `env::verify`, in the order it first did. The session holds only if
+    /// they do.
+    pub assumptions: Vec<Assumption>,
+}
+
+/// Counts of the instructions executed during a [Session]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpcodeStats {
+    /// Executed instructions by mnemonic, e.g. `ADDI`
+    pub by_mnemonic: BTreeMap<String, u64>,
+
+    /// Executed instructions by ISA extension, e.g. `M` for integer
+    /// multiplication and division
+    pub by_extension: BTreeMap<String, u64>,
+}
+
+impl OpcodeStats {
+    /// Returns the total number of instructions executed.
+    pub fn total(&self) -> u64 {
+        self.by_mnemonic.values().sum()
+    }
+
+    /// Count `count` executions of an instruction.
+    pub(crate) fn record(&mut self, mnemonic: &str, extension: &str, count: u64) {
+        *self.by_mnemonic.entry(mnemonic.to_string()).or_default() += count;
+        *self.by_extension.entry(extension.to_string()).or_default() += count;
+    }
+}
+
+impl fmt::Display for OpcodeStats {
+    /// Lists each mnemonic with its count and share of the total, most
+    /// frequent first.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(1) as f64;
+        let mut by_count: Vec<_> = self.by_mnemonic.iter().collect();
+        by_count.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (mnemonic, count) in by_count {
+            let share = *count as f64 * 100.0 / total;
+            writeln!(f, "{mnemonic:<12} {count:>12} {share:>6.2}%")?;
+        }
+        for (extension, count) in self.by_extension.iter() {
+            let share = *count as f64 * 100.0 / total;
+            let label = format!("[{extension}]");
+            writeln!(f, "{label:<12} {count:>12} {share:>6.2}%")?;
+        }
+        Ok(())
+    }
+}
+
+/// The cycles spent in a region the guest annotated with
+/// `env::start_region` and `env::end_region`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionStats {
+    /// The number of times the guest left the region
+    pub count: u64,
+
+    /// The cycles spent in the region, including paging, in total
+    pub cycles: u64,
 }
 
 /// The execution trace of a portion of a program.
@@ -67,48 +133,210 @@ pub struct Session {
 /// call to the ZKP system. It does not necessarily represent an entire program;
 /// see [Session] for tracking memory transactions until a user-requested
 /// termination.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Segment {
-    // pub(crate) pre_image: MemoryImage,
-    // pub(crate) post_image_id: Digest,
+    /// The page table root of the memory image this segment starts from.
+    pub pre_image_id: Digest,
+    /// The page table root of the memory image this segment ends with.
+    pub post_image_id: Digest,
     pub(crate) pc: u64,
-    // pub(crate) faults: PageFaults,
-    // pub(crate) syscalls: Vec<SyscallRecord>,
+    pub(crate) post_pc: u64,
+    pub(crate) faults: PageFaults,
+    pub(crate) syscalls: Vec<SyscallRecord>,
     pub(crate) exit_code: ExitCode,
-    // The number of cycles in powers of 2.
-    // pub po2: usize,
+    /// The number of cycles in powers of 2.
+    pub po2: usize,
+    /// The number of cycles used, including paging.
+    pub cycles: usize,
+    /// The length of the journal committed before this segment.
+    pub pre_journal_len: u64,
+    /// The digest of the journal committed before this segment, that of the
+    /// previous segment or [Digest::ZERO] for the first.
+    pub pre_journal_digest: Digest,
+    /// The length of the journal committed up to the end of this segment.
+    pub journal_len: u64,
+    /// The digest of the journal committed up to the end of this segment,
+    /// which is its public output, chained as by
+    /// [chain_journal_digest](crate::chain_journal_digest).
+    pub journal_digest: Digest,
+    /// The claims the guest assumed with `env::verify` during this segment,
+    /// in the order it first did, which its proof holds only if they do.
+    pub assumptions: Vec<Assumption>,
 }
 
 impl Session {
     /// Construct a new [Session] from its constituent components.
-    pub fn new(segments: Vec<Segment>, exit_code: ExitCode) -> Self {
+    pub fn new(
+        segments: Vec<Segment>,
+        journal: Vec<u8>,
+        exit_code: ExitCode,
+        opcode_stats: OpcodeStats,
+        region_stats: BTreeMap<String, RegionStats>,
+        assumptions: Vec<Assumption>,
+    ) -> Self {
         Self {
             segments,
-            // journal,
+            journal: Journal::new(journal),
             exit_code,
+            opcode_stats,
+            region_stats,
+            assumptions,
+        }
+    }
+
+    /// Returns the claim this session makes, for another guest to assume.
+    ///
+    /// Fails unless the session halted with exit code 0.
+    pub fn assumption(&self) -> Result<Assumption> {
+        if self.exit_code != ExitCode::Halted(0) {
+            bail!("Session ended with {:?}, not Halted(0)", self.exit_code);
         }
+        let Some(first) = self.segments.first() else {
+            bail!("Session has no segments");
+        };
+        Ok(Assumption {
+            image_id: first.pre_image_id,
+            journal_digest: self.journal_digest(),
+        })
     }
+
+    /// Returns the journal digest the last segment binds, the public output
+    /// of the session, or [Digest::ZERO] if it has no segments.
+    /// [Session::verify_io] checks that it is [Journal::digest].
+    pub fn journal_digest(&self) -> Digest {
+        self.segments
+            .last()
+            .map_or(Digest::ZERO, |segment| segment.journal_digest)
+    }
+
+    /// Check that the public IO of the segments chains over the journal, as
+    /// by [verify_segments](crate::verify_segments), and that they assumed
+    /// the claims of the session.
+    pub fn verify_io(&self) -> Result<()> {
+        let ios: Vec<SegmentIo> = self.segments.iter().map(Segment::io).collect();
+        verify_segments(&ios, &self.journal, self.exit_code)?;
+        let mut assumptions = Vec::new();
+        for assumption in self
+            .segments
+            .iter()
+            .flat_map(|segment| &segment.assumptions)
+        {
+            if !assumptions.contains(assumption) {
+                assumptions.push(*assumption);
+            }
+        }
+        if assumptions != self.assumptions {
+            bail!("Session assumptions differ from those of its segments");
+        }
+        Ok(())
+    }
+
+    /// Returns a receipt for the session with a [Seal::Fake], without proving
+    /// it. The receipt carries the public IO of the session like a proven
+    /// one, but verifies only in [dev_mode](crate::dev_mode).
+    pub fn dev_receipt(&self) -> Result<Receipt> {
+        self.verify_io()?;
+        Ok(Receipt {
+            version: RECEIPT_VERSION,
+            segments: self.segments.iter().map(Segment::io).collect(),
+            journal: self.journal.clone(),
+            exit_code: self.exit_code,
+            seal: Seal::Fake,
+        })
+    }
+
+    /// Returns the host calls made during the session, in order, along with
+    /// the index of the segment each was made in.
+    pub fn syscalls(&self) -> impl Iterator<Item = (usize, &SyscallRecord)> {
+        self.segments
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, segment)| segment.syscalls.iter().map(move |syscall| (idx, syscall)))
+    }
+
+    /// Write the host calls made during the session to `writer` as CSV, one
+    /// per line, with a header.
+    pub fn write_syscalls(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        writeln!(writer, "index,segment,cycle,pc,number,a0,a1")?;
+        for (segment, syscall) in self.syscalls() {
+            let (a0, a1) = syscall.regs;
+            writeln!(
+                writer,
+                "{},{segment},{},0x{:x},{},0x{a0:x},0x{a1:x}",
+                syscall.index, syscall.cycle, syscall.pc, syscall.number
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The parts of a [Segment] that are not in its [SegmentIo].
+pub(crate) struct SegmentRecord {
+    pub faults: PageFaults,
+    pub syscalls: Vec<SyscallRecord>,
+    pub cycles: usize,
+    pub assumptions: Vec<Assumption>,
 }
 
 impl Segment {
-    /// Create a new [Segment] from its constituent components.
-    pub(crate) fn new(
-        pre_image: MemoryImage,
-        // post_image_id: Digest,
-        pc: u64,
-        // faults: PageFaults,
-        // syscalls: Vec<SyscallRecord>,
-        exit_code: ExitCode,
-        // po2: usize,
-    ) -> Self {
+    /// Create a new [Segment] from its public IO and what the prover needs
+    /// beyond it.
+    pub(crate) fn new(io: SegmentIo, record: SegmentRecord) -> Self {
+        debug_assert_eq!(
+            io.assumptions_digest,
+            assumptions_digest(&record.assumptions)
+        );
         Self {
-            // pre_image,
-            // post_image_id,
-            pc,
-            // faults,
-            // syscalls,
-            exit_code,
-            // po2,
+            pre_image_id: io.pre_image_id,
+            post_image_id: io.post_image_id,
+            pc: io.pre_pc,
+            post_pc: io.post_pc,
+            faults: record.faults,
+            syscalls: record.syscalls,
+            exit_code: io.exit_code,
+            po2: record.cycles.next_power_of_two().trailing_zeros() as usize,
+            cycles: record.cycles,
+            pre_journal_len: io.pre_journal_len,
+            pre_journal_digest: io.pre_journal_digest,
+            journal_len: io.journal_len,
+            journal_digest: io.journal_digest,
+            assumptions: record.assumptions,
+        }
+    }
+
+    /// Returns the program counter this segment starts at.
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    /// Returns the program counter this segment ends at.
+    pub fn post_pc(&self) -> u64 {
+        self.post_pc
+    }
+
+    /// Returns the public inputs and outputs of this segment.
+    pub fn io(&self) -> SegmentIo {
+        SegmentIo {
+            pre_image_id: self.pre_image_id,
+            pre_pc: self.pc,
+            pre_journal_len: self.pre_journal_len,
+            pre_journal_digest: self.pre_journal_digest,
+            post_image_id: self.post_image_id,
+            post_pc: self.post_pc,
+            journal_len: self.journal_len,
+            journal_digest: self.journal_digest,
+            exit_code: self.exit_code,
+            assumptions_digest: assumptions_digest(&self.assumptions),
         }
     }
+
+    /// Returns how this segment ended.
+    pub fn exit_code(&self) -> ExitCode {
+        self.exit_code
+    }
+
+    /// Returns the host calls made during this segment, in order.
+    pub fn syscalls(&self) -> &[SyscallRecord] {
+        &self.syscalls
+    }
 }
@@ -0,0 +1,65 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared by the unit tests.
+
+use risc0_zkvm_platform::memory::SYSTEM;
+#[cfg(feature = "prove")]
+use risc0_zkvm_platform::syscall::reg_abi::{REG_A0, REG_A7};
+
+#[cfg(feature = "prove")]
+use crate::Executor;
+use crate::{MemoryImage, Program, PAGE_SIZE};
+
+/// The address of the first instruction of a [program], and its entry point.
+pub const ENTRY: u64 = 0x1000;
+
+/// `ecall`
+pub const ECALL: u32 = 0x00000073;
+
+/// `ret`
+pub const RET: u32 = 0x00008067;
+
+/// A program of `insns`, laid out one after the other from [ENTRY].
+pub fn program(insns: &[u32]) -> Program {
+    Program {
+        entry: ENTRY,
+        image: (ENTRY..).step_by(4).zip(insns.iter().copied()).collect(),
+        segments: Vec::new(),
+    }
+}
+
+/// The memory image of the [program] of `insns`, which starts with the
+/// registers in `registers` set.
+pub fn image(insns: &[u32], registers: &[(usize, u64)]) -> MemoryImage {
+    let mut image = MemoryImage::new(&program(insns), PAGE_SIZE as u64, None);
+    for (idx, value) in registers {
+        image
+            .write_u64((SYSTEM.start() + idx * 8) as u64, *value)
+            .unwrap();
+    }
+    image
+}
+
+/// Make the host call `number` with `args` in a0 onwards, by stepping over the
+/// next instruction of `exec`, which must be an `ecall`. Returns a0.
+#[cfg(feature = "prove")]
+pub fn ecall(exec: &mut Executor, number: u64, args: &[u64]) -> u64 {
+    exec.monitor.set_register(REG_A7, number);
+    for (idx, arg) in args.iter().enumerate() {
+        exec.monitor.set_register(REG_A0 + idx, *arg);
+    }
+    exec.step().unwrap();
+    exec.monitor.register(REG_A0)
+}